@@ -0,0 +1,93 @@
+//! Throughput and latency of the rtc path, over a loopback pair.
+//!
+//! Run with `cargo bench --bench rtc`; `synch bench` gives a quicker
+//! table of the same measurements.
+
+use std::time::Duration;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use synch::rtc::{Loopback, DEFAULT_QUEUE_SIZE, MAX_MSG_SIZE_BYTES};
+
+/// messages sent per iteration of the throughput benchmarks
+const BATCH: usize = 256;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap()
+}
+
+/// Time `iters` batches of messages over `pair`, as criterion's `iter_custom` wants.
+async fn batches(pair: &Loopback, message_bytes: usize, iters: u64) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        total += pair.throughput(message_bytes, BATCH).await.unwrap();
+    }
+    total
+}
+
+fn message_size(c: &mut Criterion) {
+    let rt = runtime();
+    let pair = rt.block_on(Loopback::new(1, None)).unwrap();
+
+    let mut group = c.benchmark_group("message_size");
+    for size in [16, 256, 1024, MAX_MSG_SIZE_BYTES] {
+        group.throughput(Throughput::Bytes((size * BATCH) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.to_async(&rt).iter_custom(|iters| batches(&pair, size, iters));
+        });
+    }
+    group.finish();
+    rt.block_on(pair.close()).unwrap();
+}
+
+fn queue_size(c: &mut Criterion) {
+    let rt = runtime();
+
+    let mut group = c.benchmark_group("queue_size");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for queue in [1, DEFAULT_QUEUE_SIZE, 256] {
+        let pair = rt.block_on(Loopback::new(1, Some(queue))).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(queue), &queue, |b, _| {
+            b.to_async(&rt).iter_custom(|iters| batches(&pair, 256, iters));
+        });
+        rt.block_on(pair.close()).unwrap();
+    }
+    group.finish();
+}
+
+fn channel_count(c: &mut Criterion) {
+    let rt = runtime();
+
+    let mut group = c.benchmark_group("channel_count");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for channels in [1, 2, 4, 8] {
+        let pair = rt.block_on(Loopback::new(channels, None)).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(channels), &channels, |b, _| {
+            b.to_async(&rt).iter_custom(|iters| batches(&pair, 256, iters));
+        });
+        rt.block_on(pair.close()).unwrap();
+    }
+    group.finish();
+}
+
+fn round_trip(c: &mut Criterion) {
+    let rt = runtime();
+    let pair = rt.block_on(Loopback::new(1, None)).unwrap();
+
+    let mut group = c.benchmark_group("round_trip");
+    for size in [16, MAX_MSG_SIZE_BYTES] {
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.to_async(&rt).iter_custom(|iters| {
+                let pair = &pair;
+                async move {
+                    pair.round_trips(size, iters as usize).await.unwrap().into_iter().sum()
+                }
+            });
+        });
+    }
+    group.finish();
+    rt.block_on(pair.close()).unwrap();
+}
+
+criterion_group!(benches, message_size, queue_size, channel_count, round_trip);
+criterion_main!(benches);
@@ -0,0 +1,683 @@
+//! Simulated Swarms
+//!
+//! An in-memory stand-in for a swarm: a head and its children, passing
+//! tapes over links which can be partitioned and healed, with the head
+//! relaying between children the way [crate::rtc::Agent::sync] does.
+//! Use it to check your own document types converge; see
+//! [three_node_convergence] for a scenario to copy. [AgentSwarm] runs
+//! the same over real agents connected through loopback WebRTC, and
+//! [soak] uses it to edit, drop and reconnect peers for hours.
+//!
+//! Only built with the `harness` feature.
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use futures::FutureExt;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::rtc::{Agent, AgentConfig, ChannelHandle, PeerId, MAX_MSG_SIZE_BYTES};
+use crate::sync::prelude::*;
+
+/// channel an [AgentSwarm] syncs tapes over
+pub const SWARM_CHANNEL: &str = "synch-swarm";
+/// longest [AgentSwarm::converge] waits for every replica to agree
+pub const CONVERGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Tape<D> = Vec<<D as Taped>::Operation>;
+
+/// a head and its children, syncing over simulated links
+///
+/// # Notes
+/// Peer 0 is the head. Tapes sent over a partitioned link wait in the
+/// link until it heals, like a reconnecting peer's write queue.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::harness::SimSwarm;
+/// # use synch::sync::prelude::*;
+/// let mut swarm = SimSwarm::new(SyncedList::<u8>::new(), 2);
+/// swarm.peer(1).push(1);
+/// swarm.partition(2);
+/// swarm.peer(2).push(2);
+/// swarm.settle();
+/// swarm.heal(2);
+/// swarm.settle();
+/// assert!(swarm.converged(|x| x.clone().into_iter().collect::<Vec<_>>()));
+/// ```
+pub struct SimSwarm<D: Taped> {
+    peers: Vec<D>,
+    partitioned: Vec<bool>,
+    /// tapes on their way from each child to the head
+    up: Vec<VecDeque<Tape<D>>>,
+    /// tapes on their way from the head to each child
+    down: Vec<VecDeque<Tape<D>>>,
+}
+
+impl<D> SimSwarm<D>
+where D: Taped,
+      D::Operation: Clone {
+    /// a swarm whose head is `origin`, with `children` replicas of it
+    pub fn new(origin: D, children: usize) -> Self {
+        let mut peers = vec![origin];
+        for i in 0..children {
+            // each clone takes a fresh actor
+            let child = peers[i].clone();
+            peers.push(child);
+        }
+
+        SimSwarm {
+            partitioned: vec![false; peers.len()],
+            up: (0..peers.len()).map(|_| VecDeque::new()).collect(),
+            down: (0..peers.len()).map(|_| VecDeque::new()).collect(),
+            peers,
+        }
+    }
+
+    /// number of peers, counting the head
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// a peer's replica, to edit or read
+    pub fn peer(&mut self, peer: usize) -> &mut D {
+        &mut self.peers[peer]
+    }
+
+    /// cut a child off from the head
+    pub fn partition(&mut self, child: usize) {
+        self.partitioned[child] = true;
+    }
+
+    /// reconnect a child to the head
+    pub fn heal(&mut self, child: usize) {
+        self.partitioned[child] = false;
+    }
+
+    pub fn is_partitioned(&self, child: usize) -> bool {
+        self.partitioned[child]
+    }
+
+    /// number of tapes waiting in links
+    pub fn pending(&self) -> usize {
+        self.up.iter().chain(self.down.iter()).map(|x| x.len()).sum()
+    }
+
+    /// Publish every peer's tape, then deliver what can cross a link.
+    ///
+    /// # Return
+    /// Number of tapes delivered.
+    pub fn step(&mut self) -> usize {
+        let head_tape = self.peers[0].tape();
+        for child in 1..self.len() {
+            let tape = self.peers[child].tape();
+            if !tape.is_empty() {
+                self.up[child].push_back(tape);
+            }
+            if !head_tape.is_empty() {
+                self.down[child].push_back(head_tape.clone());
+            }
+        }
+
+        let mut delivered = 0;
+        for child in 1..self.len() {
+            if self.partitioned[child] {
+                continue;
+            }
+
+            while let Some(tape) = self.up[child].pop_front() {
+                self.peers[0].replay(tape.clone());
+                for sibling in (1..self.len()).filter(|x| *x != child) {
+                    self.down[sibling].push_back(tape.clone());
+                }
+                delivered += 1;
+            }
+        }
+        for child in 1..self.len() {
+            if self.partitioned[child] {
+                continue;
+            }
+
+            while let Some(tape) = self.down[child].pop_front() {
+                self.peers[child].replay(tape);
+                delivered += 1;
+            }
+        }
+
+        delivered
+    }
+
+    /// step until nothing more can be delivered
+    pub fn settle(&mut self) {
+        while self.step() > 0 {}
+    }
+
+    /// what every peer sees, through `view`
+    pub fn views<V>(&self, view: impl Fn(&D) -> V) -> Vec<V> {
+        self.peers.iter().map(view).collect()
+    }
+
+    /// whether every peer sees the same thing through `view`
+    pub fn converged<V: PartialEq>(&self, view: impl Fn(&D) -> V) -> bool {
+        let views = self.views(view);
+        views.windows(2).all(|x| x[0] == x[1])
+    }
+
+    /// like [SimSwarm::converged], but an error showing every view
+    pub fn check_converged<V: PartialEq + Debug>(&self, view: impl Fn(&D) -> V) -> Result<()> {
+        let views = self.views(view);
+        if views.windows(2).all(|x| x[0] == x[1]) {
+            Ok(())
+        } else {
+            Err(anyhow!("peers diverged: {views:?}"))
+        }
+    }
+}
+
+/// a child of an [AgentSwarm] while it is connected
+struct Link {
+    /// kept for its workers, which carry `channel`
+    _agent: Agent,
+    channel: ChannelHandle,
+    /// what the head calls this child
+    peer: PeerId,
+}
+
+/// a head and its children, syncing tapes over real agents
+///
+/// # Notes
+/// Peer 0 is the head. Every agent lives in this process and connects
+/// over host candidates only, so nothing leaves the machine. Partitioning
+/// a child kicks it, closing its connection; healing it connects a new
+/// agent, which is sent the tapes the head saw meanwhile, and the
+/// child's own edits, which waited in its replica's tape, go up.
+///
+/// Replicas need each peer's tapes in the order it made them, so both
+/// wait until every tape already sent has arrived: a tape lost with a
+/// closed connection, or relayed ahead of the backlog, would never be
+/// applied.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut swarm = AgentSwarm::new(SyncedList::<u8>::new(), 2).await?;
+/// swarm.peer(1).push(1);
+/// swarm.partition(2).await?;
+/// swarm.peer(2).push(2);
+/// swarm.heal(2).await?;
+/// swarm.converge(|x| x.clone().into_iter().collect::<Vec<_>>()).await?;
+/// ```
+pub struct AgentSwarm<D: Taped> {
+    peers: Vec<D>,
+    head: Agent,
+    channel: ChannelHandle,
+    /// each child's agent, or [None] while partitioned
+    links: Vec<Option<Link>>,
+    /// tapes the head saw while each child was partitioned
+    backlog: Vec<Vec<Vec<u8>>>,
+    /// tapes sent towards each peer
+    owed: Vec<u64>,
+    /// tapes each peer has applied
+    heard: Vec<u64>,
+}
+
+impl<D> AgentSwarm<D>
+where D: Taped,
+      D::Operation: Serialize + DeserializeOwned {
+    /// a swarm whose head is `origin`, with `children` replicas of it
+    pub async fn new(origin: D, children: usize) -> Result<Self> {
+        let mut head = Agent::new(AgentSwarm::<D>::config())?;
+        let channel = head.sync(SWARM_CHANNEL).await?;
+
+        let mut peers = vec![origin];
+        for i in 0..children {
+            // each clone takes a fresh actor
+            let child = peers[i].clone();
+            peers.push(child);
+        }
+
+        let mut swarm = AgentSwarm {
+            links: (0..peers.len()).map(|_| None).collect(),
+            backlog: vec![vec![]; peers.len()],
+            owed: vec![0; peers.len()],
+            heard: vec![0; peers.len()],
+            peers,
+            head,
+            channel,
+        };
+        for child in 1..swarm.len() {
+            swarm.connect(child).await?;
+        }
+
+        Ok(swarm)
+    }
+
+    fn config() -> AgentConfig {
+        AgentConfig {
+            stun_servers: vec![],
+            ..Default::default()
+        }
+    }
+
+    /// connect a new agent for `child` to the head
+    ///
+    /// # Notes
+    /// The head binds its channel to a new child in the background, and
+    /// relays nothing to it until then, so the child says hello with an
+    /// empty tape and we wait for the head to hear it.
+    async fn connect(&mut self, child: usize) -> Result<()> {
+        let mut offer = self.head.connect_child_with(&[SWARM_CHANNEL]).await?;
+        let mut agent = Agent::new(AgentSwarm::<D>::config())?;
+        let answer = agent.connect_parent(&offer.get()).await?;
+        offer.answer(&answer.get()).await?;
+        let peer = self.head.accept(offer)?;
+        let channel = agent.sync(SWARM_CHANNEL).await?;
+
+        let mut hello = vec![];
+        ciborium::into_writer(&Vec::<D::Operation>::new(), &mut hello)?;
+        channel.send(hello).await?;
+        self.links[child] = Some(Link { _agent: agent, channel, peer });
+        self.sent_by(child);
+
+        let start = Instant::now();
+        while self.heard[0] < self.owed[0] {
+            if start.elapsed() > CONVERGE_TIMEOUT {
+                return Err(anyhow!("child {child} was not bound after {CONVERGE_TIMEOUT:?}"));
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.deliver()?;
+        }
+
+        Ok(())
+    }
+
+    /// number of peers, counting the head
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// a peer's replica, to edit or read
+    pub fn peer(&mut self, peer: usize) -> &mut D {
+        &mut self.peers[peer]
+    }
+
+    /// the head's agent, to look at its health or traffic
+    pub fn head(&self) -> &Agent {
+        &self.head
+    }
+
+    /// cut a child off from the head, closing its connection
+    pub async fn partition(&mut self, child: usize) -> Result<()> {
+        if self.is_partitioned(child) {
+            return Ok(());
+        }
+        self.drain().await?;
+
+        let Some(link) = self.links[child].take() else { return Ok(()); };
+        self.head.kick(link.peer).await
+    }
+
+    /// reconnect a child to the head, sending it what it missed
+    pub async fn heal(&mut self, child: usize) -> Result<()> {
+        if !self.is_partitioned(child) {
+            return Ok(());
+        }
+        // whatever is on its way to the head belongs in the backlog
+        self.drain().await?;
+        self.connect(child).await?;
+
+        let peer = self.links[child].as_ref().map(|x| x.peer)
+            .ok_or(anyhow!("child {child} did not connect"))?;
+        for tape in std::mem::take(&mut self.backlog[child]) {
+            self.head.send_to(peer, SWARM_CHANNEL, tape).await?;
+            self.owed[child] += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_partitioned(&self, child: usize) -> bool {
+        child > 0 && self.links[child].is_none()
+    }
+
+    /// number of tapes the head holds for partitioned children
+    pub fn pending(&self) -> usize {
+        self.backlog.iter().map(|x| x.len()).sum()
+    }
+
+    /// Publish every connected peer's tape, then apply whatever has
+    /// arrived, without waiting for more.
+    ///
+    /// # Return
+    /// Number of tapes applied.
+    pub async fn step(&mut self) -> Result<usize> {
+        self.publish().await?;
+        self.deliver()
+    }
+
+    async fn publish(&mut self) -> Result<()> {
+        for peer in 0..self.len() {
+            if self.is_partitioned(peer) {
+                continue;
+            }
+
+            for tape in pack(&self.peers[peer].tape())? {
+                self.sent_by(peer);
+                if peer == 0 {
+                    self.hold(&tape);
+                    self.channel.send(tape).await?;
+                } else if let Some(link) = &self.links[peer] {
+                    link.channel.send(tape).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// apply whatever has arrived at every connected peer
+    fn deliver(&mut self) -> Result<usize> {
+        let mut delivered = 0;
+        for peer in 0..self.len() {
+            let channel = match &self.links[peer] {
+                _ if peer == 0 => &self.channel,
+                Some(link) => &link.channel,
+                None => continue,
+            };
+
+            let mut heard = vec![];
+            while let Some(Some(buf)) = channel.recv().now_or_never() {
+                heard.push(buf);
+            }
+            for buf in heard {
+                let tape: Vec<D::Operation> = ciborium::from_reader(&buf[..])?;
+                if peer == 0 {
+                    self.hold(&buf);
+                }
+                self.peers[peer].replay(tape);
+                self.heard[peer] += 1;
+                delivered += 1;
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// count a tape `peer` sent as owed to every other connected peer,
+    /// since the head relays what a child sends to its siblings
+    fn sent_by(&mut self, peer: usize) {
+        for other in 0..self.len() {
+            if other != peer && !self.is_partitioned(other) {
+                self.owed[other] += 1;
+            }
+        }
+    }
+
+    /// keep a tape the head saw for every partitioned child
+    fn hold(&mut self, tape: &[u8]) {
+        for child in 1..self.len() {
+            if self.is_partitioned(child) {
+                self.backlog[child].push(tape.to_vec());
+            }
+        }
+    }
+
+    /// step until every connected peer has applied every tape sent to it
+    async fn drain(&mut self) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            self.step().await?;
+            if (0..self.len()).all(|x| self.is_partitioned(x) || self.heard[x] >= self.owed[x]) {
+                return Ok(());
+            }
+            if start.elapsed() > CONVERGE_TIMEOUT {
+                return Err(anyhow!("tapes still in flight after {CONVERGE_TIMEOUT:?}: \
+                                    sent {:?}, applied {:?}", self.owed, self.heard));
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    /// what every peer sees, through `view`
+    pub fn views<V>(&self, view: impl Fn(&D) -> V) -> Vec<V> {
+        self.peers.iter().map(view).collect()
+    }
+
+    /// Step until every peer sees the same thing through `view`.
+    ///
+    /// # Notes
+    /// Fails, showing every view, if they still disagree after
+    /// [CONVERGE_TIMEOUT]; heal every child first.
+    pub async fn converge<V: PartialEq + Debug>(&mut self, view: impl Fn(&D) -> V) -> Result<()> {
+        let start = Instant::now();
+        loop {
+            self.step().await?;
+            let views = self.views(&view);
+            if views.windows(2).all(|x| x[0] == x[1]) {
+                return Ok(());
+            }
+            if start.elapsed() > CONVERGE_TIMEOUT {
+                return Err(anyhow!("peers never converged: {views:?}"));
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    }
+}
+
+/// encode `ops` as tapes which each fit in a message, in order
+fn pack<O: Serialize>(ops: &[O]) -> Result<Vec<Vec<u8>>> {
+    if ops.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut buf = vec![];
+    ciborium::into_writer(&ops, &mut buf)?;
+    if buf.len() <= MAX_MSG_SIZE_BYTES {
+        return Ok(vec![buf]);
+    }
+    if ops.len() == 1 {
+        return Err(anyhow!("an edit of {} bytes is too large to send", buf.len()));
+    }
+
+    let (first, rest) = ops.split_at(ops.len() / 2);
+    let mut tapes = pack(first)?;
+    tapes.extend(pack(rest)?);
+    Ok(tapes)
+}
+
+/// Head and two children edit a list and a map concurrently, one child
+/// is partitioned and keeps editing, then heals; everyone must agree.
+///
+/// # Notes
+/// `tests/three_node.rs` runs the same scenario over real agents
+/// connected through loopback WebRTC.
+pub fn three_node_convergence() -> Result<()> {
+    let mut lists = SimSwarm::new(SyncedList::<String>::new(), 2);
+    let mut maps = SimSwarm::new(SyncedMap::<String, u64>::new(), 2);
+    let read_list = |x: &SyncedList<String>| x.clone().into_iter().collect::<Vec<_>>();
+    let read_map = |x: &SyncedMap<String, u64>| {
+        ["0", "1", "2", "b", "c"].map(|k| x.get(&k.to_string()))
+    };
+
+    // concurrent writes to one key are kept as conflicting values, and
+    // `get` picks one of them, so each peer writes its own key here
+    for peer in 0..3 {
+        lists.peer(peer).push(format!("from {peer}"));
+        maps.peer(peer).insert(peer.to_string(), peer as u64);
+    }
+    lists.settle();
+    maps.settle();
+    lists.check_converged(read_list)?;
+    maps.check_converged(read_map)?;
+
+    lists.partition(2);
+    maps.partition(2);
+    lists.peer(2).remove(0);
+    lists.peer(1).insert(0, "first".into());
+    maps.peer(2).insert("b".into(), 2);
+    maps.peer(1).remove("0".into());
+    maps.peer(0).insert("c".into(), 0);
+    lists.settle();
+    maps.settle();
+    if lists.converged(read_list) {
+        return Err(anyhow!("a partitioned peer saw edits it shouldn't have"));
+    }
+
+    lists.heal(2);
+    maps.heal(2);
+    lists.settle();
+    maps.settle();
+    lists.check_converged(read_list)?;
+    maps.check_converged(read_map)?;
+
+    Ok(())
+}
+
+/// how long and how hard to run [soak]
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    pub duration: Duration,
+    /// children besides the head
+    pub children: usize,
+    /// rounds of random edits between full heals and convergence checks
+    pub rounds_per_check: u64,
+}
+
+impl Default for SoakConfig {
+    fn default() -> Self {
+        SoakConfig {
+            duration: Duration::from_secs(60 * 60),
+            children: 4,
+            rounds_per_check: 1000,
+        }
+    }
+}
+
+/// what a [soak] run has done so far
+#[derive(Debug, Clone, Default)]
+pub struct SoakReport {
+    pub rounds: u64,
+    /// times a child's connection was dropped, to reconnect later
+    pub partitions: u64,
+    /// times every peer was healed and found converged
+    pub checks: u64,
+    /// size of the head's list snapshot at the last check, for spotting growth
+    pub snapshot_bytes: usize,
+    /// tasks alive on the runtime at the last check, for spotting leaked workers
+    pub live_tasks: usize,
+    /// resident memory of the process at the last check, where the OS tells us
+    pub rss_bytes: Option<u64>,
+    /// most tapes ever held for partitioned children at once
+    pub peak_pending: usize,
+    pub elapsed: Duration,
+}
+
+/// resident memory of this process, from `/proc` on Linux
+fn rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|x| x.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Edit, drop and reconnect an [AgentSwarm] at random until time is up.
+///
+/// # Notes
+/// Every `rounds_per_check` rounds all children are reconnected and
+/// every replica must agree, or the soak stops with an error. `on_check`
+/// is called after each check, to log progress or watch the snapshot
+/// size, live tasks and memory for growth.
+///
+/// # Examples
+///
+/// ```ignore
+/// let report = soak(SoakConfig::default(), |x| println!("{x:?}")).await?;
+/// ```
+pub async fn soak(config: SoakConfig, mut on_check: impl FnMut(&SoakReport)) -> Result<SoakReport> {
+    // not thread_rng, which can't be held across an await
+    let mut rng = StdRng::from_entropy();
+    let mut lists = AgentSwarm::new(SyncedList::<u32>::new(), config.children).await?;
+    let mut maps = AgentSwarm::new(SyncedMap::<String, u32>::new(), config.children).await?;
+    let read_list = |x: &SyncedList<u32>| x.clone().into_iter().collect::<Vec<_>>();
+    let keys: Vec<String> = (0..lists.len())
+        .flat_map(|peer| (0..16).map(move |n| format!("{peer}-{n}")))
+        .collect();
+    let read_map = |x: &SyncedMap<String, u32>| keys.iter().map(|k| x.get(k)).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let mut report = SoakReport::default();
+
+    while start.elapsed() < config.duration {
+        let peer = rng.gen_range(0..lists.len());
+        let value: u32 = rng.gen();
+
+        let list = lists.peer(peer);
+        match rng.gen_range(0..4) {
+            // shrink faster than we grow once the list is long
+            _ if list.len() > 256 => list.remove(rng.gen_range(0..list.len())),
+            0 => list.push(value),
+            1 => list.insert(rng.gen_range(0..=list.len()), value),
+            2 if !list.is_empty() => list.remove(rng.gen_range(0..list.len())),
+            _ => if let Some(mut x) = list.lock(rng.gen_range(0..list.len().max(1))) {
+                *x = value;
+            },
+        }
+
+        // peers only write their own keys; see [three_node_convergence]
+        let key = format!("{peer}-{}", rng.gen_range(0..16));
+        if rng.gen_bool(0.3) {
+            maps.peer(peer).remove(key);
+        } else {
+            maps.peer(peer).insert(key, value);
+        }
+
+        if peer > 0 && rng.gen_bool(0.01) {
+            if lists.is_partitioned(peer) {
+                lists.heal(peer).await?;
+                maps.heal(peer).await?;
+            } else {
+                lists.partition(peer).await?;
+                maps.partition(peer).await?;
+                report.partitions += 1;
+            }
+        }
+
+        lists.step().await?;
+        maps.step().await?;
+        report.peak_pending = report.peak_pending.max(lists.pending() + maps.pending());
+        report.rounds += 1;
+
+        if report.rounds % config.rounds_per_check == 0 {
+            for child in 1..lists.len() {
+                lists.heal(child).await?;
+                maps.heal(child).await?;
+            }
+            lists.converge(read_list).await?;
+            maps.converge(&read_map).await?;
+
+            let mut snapshot = vec![];
+            ciborium::into_writer(lists.peer(0), &mut snapshot)?;
+            report.snapshot_bytes = snapshot.len();
+            report.live_tasks = tokio::runtime::Handle::current().metrics().num_alive_tasks();
+            report.rss_bytes = rss_bytes();
+            report.checks += 1;
+            report.elapsed = start.elapsed();
+            on_check(&report);
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
@@ -0,0 +1,194 @@
+//! Collision-free ID Generation
+//!
+//! Peers inserting items concurrently need keys that never collide
+//! without asking each other first. Two schemes are provided:
+//!
+//! - [SeqId]s are a counter prefixed by the peer's actor number: short,
+//!   and unique as long as actor numbers are.
+//! - [Ulid]s are a millisecond timestamp followed by 80 random bits:
+//!   longer, but they sort by creation time and need no coordination.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+/// an id made of an actor number and that actor's counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct SeqId {
+    pub actor: u64,
+    pub counter: u64,
+}
+
+impl fmt::Display for SeqId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}-{:x}", self.actor, self.counter)
+    }
+}
+
+impl FromStr for SeqId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (actor, counter) = s.split_once('-')
+            .ok_or_else(|| anyhow!("'{s}' is not an actor-counter id"))?;
+        Ok(SeqId {
+            actor: u64::from_str_radix(actor, 16)?,
+            counter: u64::from_str_radix(counter, 16)?
+        })
+    }
+}
+
+/// hands out [SeqId]s for one actor
+///
+/// # Notes
+/// Two generators must never share an actor number; use the same
+/// number the peer's documents use, or [SeqIdGenerator::random].
+/// Persist [SeqIdGenerator::counter] across restarts and resume with
+/// [SeqIdGenerator::resume], or ids will repeat.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::id::*;
+/// # use synch::sync::prelude::*;
+/// let mut todo: SyncedMap<SeqId, String> = SyncedMap::new();
+/// let actor = 7;
+/// let mut ids = SeqIdGenerator::new(actor);
+/// todo.insert(ids.next(), "milk".to_string());
+/// assert_eq!(ids.next(), SeqId { actor: 7, counter: 2 });
+/// ```
+#[derive(Debug, Clone)]
+pub struct SeqIdGenerator {
+    actor: u64,
+    counter: u64,
+}
+
+impl SeqIdGenerator {
+    pub fn new(actor: u64) -> Self {
+        SeqIdGenerator { actor, counter: 0 }
+    }
+
+    /// a generator with a random actor number
+    pub fn random() -> Self {
+        Self::new(rand::random())
+    }
+
+    /// a generator carrying on after `counter` ids were already handed out
+    pub fn resume(actor: u64, counter: u64) -> Self {
+        SeqIdGenerator { actor, counter }
+    }
+
+    pub fn actor(&self) -> u64 {
+        self.actor
+    }
+
+    /// how many ids were handed out so far
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> SeqId {
+        self.counter += 1;
+        SeqId { actor: self.actor, counter: self.counter }
+    }
+}
+
+/// crockford's base32 alphabet, as used by ULIDs
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// a 128 bit id: 48 bits of unix milliseconds, then 80 random bits
+///
+/// # Notes
+/// Written as 26 characters of crockford base32, which sort the same
+/// as the ids themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Ulid(pub u128);
+
+impl Ulid {
+    /// a fresh id for now
+    pub fn new() -> Self {
+        Self::from_parts(now_ms(), rand::random())
+    }
+
+    pub fn from_parts(timestamp_ms: u64, random: u128) -> Self {
+        Ulid(((timestamp_ms as u128) << 80) | (random & ((1 << 80) - 1)))
+    }
+
+    /// unix milliseconds when the id was made
+    pub fn timestamp_ms(&self) -> u64 {
+        (self.0 >> 80) as u64
+    }
+}
+
+impl Default for Ulid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Ulid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded: String = (0..26).rev()
+            .map(|i| CROCKFORD[((self.0 >> (i * 5)) & 0x1f) as usize] as char)
+            .collect();
+        f.write_str(&encoded)
+    }
+}
+
+impl FromStr for Ulid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.len() != 26 {
+            return Err(anyhow!("a ULID is 26 characters, not {}", s.len()));
+        }
+
+        let mut value: u128 = 0;
+        for (i, c) in s.bytes().enumerate() {
+            let digit = CROCKFORD.iter()
+                .position(|x| *x == c.to_ascii_uppercase())
+                .ok_or_else(|| anyhow!("'{}' is not a ULID character", c as char))?;
+            // the first character only holds the top 3 bits
+            if i == 0 && digit > 7 {
+                return Err(anyhow!("'{s}' overflows 128 bits"));
+            }
+            value = (value << 5) | digit as u128;
+        }
+
+        Ok(Ulid(value))
+    }
+}
+
+/// hands out [Ulid]s which strictly increase, even within a millisecond
+#[derive(Debug, Clone, Default)]
+pub struct UlidGenerator {
+    last: Option<Ulid>,
+}
+
+impl UlidGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Ulid {
+        let fresh = Ulid::new();
+        let id = match self.last {
+            // same millisecond (or the clock went back): count up instead
+            Some(last) if fresh.timestamp_ms() <= last.timestamp_ms() => Ulid(last.0 + 1),
+            _ => fresh,
+        };
+
+        self.last = Some(id);
+        id
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as u64)
+}
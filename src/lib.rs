@@ -0,0 +1,7 @@
+pub mod sync;
+pub mod rtc;
+pub mod id;
+pub mod pair;
+#[cfg(feature = "harness")]
+pub mod harness;
+pub use sync::prelude::*;
@@ -1,5 +1,4 @@
-pub mod sync;
-pub use sync::prelude::*;
+use synch::*;
 
 use anyhow::Result;
 
@@ -7,6 +6,73 @@ use anyhow::Result;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // synch inspect <offer or answer>
+    if std::env::args().nth(1).as_deref() == Some("inspect") {
+        let blob = match std::env::args().nth(2) {
+            Some(x) => x,
+            None => std::io::read_to_string(std::io::stdin())?,
+        };
+        print!("{}", rtc::inspect_offer(&blob)?);
+        return Ok(());
+    }
+
+    // synch bans <file> [ban <fingerprint> <reason> | unban <fingerprint>]
+    if std::env::args().nth(1).as_deref() == Some("bans") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        let path = args.first().ok_or(anyhow::anyhow!("usage: synch bans <file> [ban|unban ...]"))?;
+        let mut bans = rtc::BanList::open(path)?;
+
+        match args.get(1).map(|x| x.as_str()) {
+            None => (),
+            Some("ban") if args.len() >= 3 => bans.ban(&args[2], &args[3..].join(" "))?,
+            Some("unban") if args.len() == 3 => {
+                let lifted = bans.unban(&args[2])?;
+                if !lifted {
+                    println!("{} was not banned", args[2]);
+                }
+            }
+            Some(x) => return Err(anyhow::anyhow!("unknown bans command '{x}'")),
+        }
+        for (fingerprint, ban) in bans.list() {
+            println!("{fingerprint}\t{}\t{}", ban.since, ban.reason);
+        }
+        return Ok(());
+    }
+
+    // SYNCH_PASSPHRASE=... synch lock <key or identity file>...
+    if std::env::args().nth(1).as_deref() == Some("lock") {
+        let passphrase = rtc::AgentConfig::from_env()?.passphrase
+            .ok_or(anyhow::anyhow!("set {}PASSPHRASE to lock files with", rtc::ENV_PREFIX))?;
+        for path in std::env::args().skip(2) {
+            passphrase.lock_file(&path)?;
+        }
+        return Ok(());
+    }
+
+    // synch bench [messages]
+    if std::env::args().nth(1).as_deref() == Some("bench") {
+        let mut config = rtc::BenchConfig::default();
+        if let Some(messages) = std::env::args().nth(2) {
+            config.messages = messages.parse()?;
+        }
+
+        rtc::run_bench(&config, |x| println!("{x}")).await?;
+        return Ok(());
+    }
+
+    // synch soak [seconds]
+    #[cfg(feature = "harness")]
+    if std::env::args().nth(1).as_deref() == Some("soak") {
+        let mut config = harness::SoakConfig::default();
+        if let Some(secs) = std::env::args().nth(2) {
+            config.duration = std::time::Duration::from_secs(secs.parse()?);
+        }
+
+        let report = harness::soak(config, |x| println!("{x:?}")).await?;
+        println!("soak passed: {report:?}");
+        return Ok(());
+    }
+
     // let mut amy:SyncedList<u8> = SyncedList::new();
     // let mut bob:SyncedList<u8> = amy.clone();
     // amy.push(5);
@@ -0,0 +1,345 @@
+//! Two Peers, One Map
+//!
+//! The shortest way to share a [SyncedMap] between two peers: the
+//! host makes a code, the other peer joins with it, and both edit the
+//! map through their [Session]. Offers, answers, channels, document
+//! streams and tapes are taken care of.
+//!
+//! There is no signaling server, so the code is the host's whole
+//! WebRTC offer, and the joiner's [Session::reply] has to make its way
+//! back to the host's [Session::accept]; paste both through chat, a QR
+//! code, or whatever else the two share. For more than two peers, or
+//! more than one document, use [crate::rtc::Agent] directly.
+//!
+//! # Examples
+//!
+//! ```ignore
+//! // on the host
+//! let (code, mut session) = pair::host().await?;
+//! session.accept(&send_and_wait_for_reply(code)).await?;
+//! session.map_mut().insert("greeting".to_string(), "hi".to_string());
+//! session.sync().await?;
+//!
+//! // on the joiner
+//! let mut session = pair::join(&code).await?;
+//! send_back(session.reply().unwrap());
+//! session.changed().await?;
+//! println!("{:?}", session.map().get(&"greeting".to_string()));
+//! ```
+
+use std::sync::Arc;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::task::JoinHandle;
+use log::{debug, error};
+
+use crate::rtc::{Agent, AgentConfig, Connection, Offer, PeerId, Registry, Multiplexer, MuxStream,
+                 StreamId, SnapshotEnvelope, SnapshotAssembler, DocMessage, MAX_FRAME_PAYLOAD_BYTES,
+                 send_snapshot, recv_snapshot, send_tape, recv_message, spawn_named};
+use crate::sync::backend::MapOp;
+use crate::sync::map::{SyncedMap, MapKey, MapVal};
+use crate::sync::taped::Taped;
+
+/// data channel a pair's document is multiplexed over
+pub const PAIR_CHANNEL: &str = "synch-pair";
+/// name of the one document a pair shares
+pub const PAIR_DOCUMENT: &str = "pair";
+/// most bytes of tape sent per message, leaving room for the [DocMessage] around it
+const TAPE_BYTES: usize = MAX_FRAME_PAYLOAD_BYTES - 16;
+
+/// what the listener hears from the other peer
+enum Incoming {
+    /// the host's copy of the map, which the joiner starts from
+    Snapshot(SnapshotEnvelope),
+    Tape(Bytes),
+}
+
+/// the document's stream to the other peer, and what came down it
+struct Link {
+    /// kept for its read worker, which feeds `stream`
+    _mux: Multiplexer,
+    stream: MuxStream,
+    incoming: UnboundedReceiver<Incoming>,
+    listener: JoinHandle<()>,
+}
+
+impl Link {
+    /// Open the document's stream, then listen to it.
+    ///
+    /// # Notes
+    /// The host sends its `copy` before listening, since the joiner's
+    /// replies to it are read by [send_snapshot]; the joiner, giving
+    /// none, listens for the host's first.
+    async fn open(cnx: Arc<Connection>, id: StreamId, copy: Option<&SnapshotEnvelope>) -> Result<Link> {
+        let mux = Multiplexer::new(cnx, PAIR_CHANNEL, None);
+        let stream = mux.stream(id).await;
+        if let Some(copy) = copy {
+            send_snapshot(&stream, copy).await?;
+        }
+
+        let (sender, incoming) = unbounded_channel();
+        let listener = spawn_named("synch pair listener",
+                                   listen(mux.stream(id).await, copy.is_none(), sender));
+
+        Ok(Link {
+            _mux: mux,
+            stream,
+            incoming,
+            listener,
+        })
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.listener.abort();
+    }
+}
+
+/// pass what the other peer sends on to the [Link], until either is gone
+async fn listen(stream: MuxStream, joining: bool, incoming: UnboundedSender<Incoming>) {
+    if joining {
+        match recv_snapshot(&stream, &mut SnapshotAssembler::new()).await {
+            Ok(snapshot) => if incoming.send(Incoming::Snapshot(snapshot)).is_err() { return; },
+            Err(err) => {
+                error!("failed to recieve the host's copy of '{PAIR_DOCUMENT}': {err}");
+                return;
+            }
+        }
+    }
+
+    while let Some(message) = recv_message(&stream).await {
+        match message {
+            Ok(DocMessage::Tape(tape)) => if incoming.send(Incoming::Tape(tape)).is_err() { return; },
+            Ok(x) => debug!("ignoring {x:?} on '{PAIR_DOCUMENT}'"),
+            Err(err) => error!("dropping malformed message on '{PAIR_DOCUMENT}': {err}"),
+        }
+    }
+}
+
+/// One end of a map shared by two peers; see the [module](self) docs.
+///
+/// # Notes
+/// Edits made with [Session::map_mut] stay local until [Session::sync],
+/// which also applies whatever the other peer sent. The joiner's map is
+/// replaced by the host's copy once it arrives, with the joiner's own
+/// edits replayed on top, so add watchers and indexes once
+/// [Session::is_ready].
+///
+/// Each tape is split to fit a message, but a single edit can't be:
+/// values have to encode to under about 1.4kB.
+pub struct Session<K: MapKey, V: MapVal> {
+    agent: Agent,
+    map: SyncedMap<K, V>,
+    /// stream the document is carried on, from the registry
+    stream: StreamId,
+    /// the host's offer, until it is answered
+    offer: Option<Offer>,
+    /// the joiner's reply to the host's code
+    reply: Option<String>,
+    /// the other peer, as the agent knows it; [None] for the host, to the joiner
+    peer: Option<PeerId>,
+    link: Option<Link>,
+    /// whether both peers hold the document, so edits can go out
+    ready: bool,
+}
+
+/// Start a pair, to be joined with the code returned.
+///
+/// # Return
+/// The code to give the other peer's [join], and our [Session], which
+/// needs their reply given to [Session::accept].
+pub async fn host<K, V>() -> Result<(String, Session<K, V>)>
+where K: MapKey + Serialize + for<'de> Deserialize<'de>,
+      V: MapVal + Serialize + for<'de> Deserialize<'de> {
+    let agent = Agent::head()?;
+    let offer = agent.connect_child_with(&[PAIR_CHANNEL]).await?;
+    let code = offer.get();
+
+    Ok((code, Session::new(agent, Some(offer), None)?))
+}
+
+/// Join a pair with the code the host made.
+///
+/// # Notes
+/// Send [Session::reply] back to the host; nothing flows until they
+/// accept it.
+pub async fn join<K, V>(code: &str) -> Result<Session<K, V>>
+where K: MapKey + Serialize + for<'de> Deserialize<'de>,
+      V: MapVal + Serialize + for<'de> Deserialize<'de> {
+    let mut agent = Agent::new(AgentConfig::default())?;
+    let reply = agent.connect_parent(code.trim()).await?.get();
+    let cnx = agent.connection(None)?.clone();
+
+    let mut session = Session::new(agent, None, Some(reply))?;
+    session.link = Some(Link::open(cnx, session.stream, None).await?);
+    Ok(session)
+}
+
+impl<K, V> Session<K, V>
+where K: MapKey + Serialize + for<'de> Deserialize<'de>,
+      V: MapVal + Serialize + for<'de> Deserialize<'de> {
+    fn new(agent: Agent, offer: Option<Offer>, reply: Option<String>) -> Result<Self> {
+        let stream = Registry::new().create(PAIR_DOCUMENT)?;
+
+        Ok(Session {
+            agent,
+            map: SyncedMap::new(),
+            stream,
+            offer,
+            reply,
+            peer: None,
+            link: None,
+            ready: false,
+        })
+    }
+
+    /// the joiner's reply to the host's code, for the host to [Session::accept]
+    pub fn reply(&self) -> Option<&str> {
+        self.reply.as_deref()
+    }
+
+    /// Connect to the joiner with their [Session::reply], sending them our map.
+    ///
+    /// # Notes
+    /// Returns once the joiner has our map, so both must be running.
+    pub async fn accept(&mut self, reply: &str) -> Result<()> {
+        let mut offer = self.offer.take()
+            .ok_or(anyhow!("only the host accepts a reply, and only once"))?;
+        offer.answer(reply).await?;
+        let peer = self.agent.accept(offer)?;
+        self.peer = Some(peer);
+
+        // the copy carries every edit made so far
+        self.map.tape();
+        let mut copy = vec![];
+        ciborium::into_writer(&self.map, &mut copy)?;
+        let copy = SnapshotEnvelope::new(copy, self.map.actor(), 0);
+
+        let cnx = self.agent.connection(Some(peer))?.clone();
+        self.link = Some(Link::open(cnx, self.stream, Some(&copy)).await?);
+        self.ready = true;
+        Ok(())
+    }
+
+    /// whether both peers hold the map, so edits flow between them
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    pub fn map(&self) -> &SyncedMap<K, V> {
+        &self.map
+    }
+
+    /// the map, to edit; send the edits with [Session::sync]
+    pub fn map_mut(&mut self) -> &mut SyncedMap<K, V> {
+        &mut self.map
+    }
+
+    /// Apply what the other peer sent, and send them our edits.
+    ///
+    /// # Return
+    /// Whether the other peer sent anything. Fails once they are gone.
+    pub async fn sync(&mut self) -> Result<bool> {
+        let mut heard = false;
+        while let Some(incoming) = self.try_incoming()? {
+            self.absorb(incoming).await?;
+            heard = true;
+        }
+        self.flush().await?;
+
+        Ok(heard)
+    }
+
+    /// Wait for the other peer to send something, then [Session::sync].
+    pub async fn changed(&mut self) -> Result<()> {
+        let link = self.link.as_mut().ok_or(anyhow!("no peer to hear from yet"))?;
+        let incoming = link.incoming.recv().await.ok_or(anyhow!("the other peer is gone"))?;
+        self.absorb(incoming).await?;
+        self.sync().await?;
+
+        Ok(())
+    }
+
+    /// The code to compare with the other peer; see [Agent::pairing_code].
+    pub async fn pairing_code(&self) -> Result<String> {
+        if self.link.is_none() {
+            return Err(anyhow!("no peer to compare with yet"));
+        }
+        self.agent.pairing_code(self.peer).await
+    }
+
+    /// the agent underneath, for its health, traffic and such
+    pub fn agent(&self) -> &Agent {
+        &self.agent
+    }
+
+    fn try_incoming(&mut self) -> Result<Option<Incoming>> {
+        let Some(link) = self.link.as_mut() else { return Ok(None); };
+        match link.incoming.try_recv() {
+            Ok(x) => Ok(Some(x)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(anyhow!("the other peer is gone")),
+        }
+    }
+
+    async fn absorb(&mut self, incoming: Incoming) -> Result<()> {
+        match incoming {
+            Incoming::Snapshot(snapshot) => {
+                let host: SyncedMap<K, V> = ciborium::from_reader(snapshot.data())?;
+                let ours = self.map.tape();
+                self.map = host.with_actor(self.map.actor());
+                self.map.replay(ours.clone());
+                self.ready = true;
+                self.send(ours).await?;
+            }
+            Incoming::Tape(tape) => {
+                let ops: Vec<MapOp<K, V>> = ciborium::from_reader(&tape[..])?;
+                self.map.replay(ops);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// send our edits, if the other peer is there for them
+    async fn flush(&mut self) -> Result<()> {
+        if self.ready {
+            let ops = self.map.tape();
+            self.send(ops).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&self, ops: Vec<MapOp<K, V>>) -> Result<()> {
+        let link = self.link.as_ref().ok_or(anyhow!("no peer to send to yet"))?;
+        for tape in pack(&ops)? {
+            send_tape(&link.stream, tape).await?;
+        }
+        Ok(())
+    }
+}
+
+/// encode `ops` as tapes of at most [TAPE_BYTES], in order
+fn pack<K: MapKey + Serialize, V: MapVal + Serialize>(ops: &[MapOp<K, V>]) -> Result<Vec<Vec<u8>>> {
+    if ops.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut buf = vec![];
+    ciborium::into_writer(&ops, &mut buf)?;
+    if buf.len() <= TAPE_BYTES {
+        return Ok(vec![buf]);
+    }
+    if ops.len() == 1 {
+        return Err(anyhow!("an edit of {} bytes is too large to send", buf.len()));
+    }
+
+    let (first, rest) = ops.split_at(ops.len() / 2);
+    let mut tapes = pack(first)?;
+    tapes.extend(pack(rest)?);
+    Ok(tapes)
+}
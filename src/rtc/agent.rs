@@ -1,13 +1,40 @@
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
 use anyhow::{Result, anyhow};
+use bytes::Bytes;
 use webrtc::{api::API,
              peer_connection::configuration::RTCConfiguration};
-use tokio::sync::mpsc::{Sender, Receiver, channel};
-use futures::future::join_all;
+use tokio::sync::mpsc::{Sender, Receiver, UnboundedReceiver, channel, unbounded_channel};
+use tokio::sync::watch;
+use futures::future::{join_all, select_all};
+use log::{error, warn, debug};
 
 use super::utils::*;
-use super::DEFAULT_STUN_SERVERS;
-use super::connection::Connection;
+use super::config::AgentConfig;
+use super::connection::{Connection, Receipt};
+use super::headers::Message;
+use super::health::Health;
+use super::reload::ConfigChange;
+use super::span::relink;
+use super::mailbox::{Mailbox, MailboxMessage, MAILBOX_CHANNEL};
+use super::goodbye::{GoodbyeReason, ShutdownMessage, GOODBYE_CHANNEL, GOODBYE_TIMEOUT,
+                     SHUTDOWN_CHANNEL};
+use super::watermark::{QueueDepth, QueueStuck, Traffic};
+use super::bans::BanList;
+use super::invite::{Invite, Role, Permission};
+use super::storage::SecretKey;
+use super::inspect::inspect_offer;
+use super::parts::{split_parts, join_parts};
+use super::audit::{AuditLog, SecurityEvent};
+use super::handoff::{HandoffMessage, RosterEntry, Takeover, HANDOFF_CHANNEL, HANDOFF_TIMEOUT};
+use super::snapshot::SNAPSHOT_CHUNK_BYTES;
+use super::registry::{Registry, RegistryMessage};
+use super::control::ControlMessage;
+use super::noise::{NoiseKey, SecureControl};
+use super::mux::Multiplexer;
+use super::registry::CONTROL_STREAM;
 
 /// temporary Offer connection holder
 ///
@@ -17,7 +44,7 @@ use super::connection::Connection;
 ///
 /// # Examples
 ///
-/// ```
+/// ```ignore
 /// let mut agent = Agent::head()?;
 /// let mut offer = agent.offer().await?;
 /// tell_peer(offer.get());
@@ -27,7 +54,11 @@ use super::connection::Connection;
 pub struct Offer {
     cnx: Connection,
     offer: String,
-    validated: bool
+    validated: bool,
+    /// the answering peer's certificate fingerprints
+    fingerprints: Vec<String>,
+    /// the invite token sent with the answer, if any
+    invite: Option<String>
 }
 
 impl Offer {
@@ -36,27 +67,186 @@ impl Offer {
         self.offer.clone()
     }
     /// answer the offer, validating it
+    ///
+    /// # Notes
+    /// an invite attached with [Answer::with_invite] is kept for
+    /// [Agent::accept] to check.
     pub async fn answer(&mut self, answer: &str) -> Result<()> {
+        let (answer, invite) = match answer.trim().split_once('.') {
+            Some((answer, invite)) => (answer, Some(invite.to_owned())),
+            None => (answer, None),
+        };
+        self.invite = invite;
         self.validated = true;
+        self.fingerprints = inspect_offer(answer).map(|x| x.fingerprints).unwrap_or_default();
         self.cnx.accept(answer).await?;
         Ok(())
     }
+
+    /// the answering peer's DTLS certificate fingerprints, once answered
+    pub fn fingerprints(&self) -> &[String] {
+        &self.fingerprints
+    }
+
+    /// Split the offer string into parts of at most `max_len`
+    /// characters, for channels which cut long messages short; see
+    /// [split_parts].
+    pub fn to_parts(&self, max_len: usize) -> Result<Vec<String>> {
+        split_parts(&self.offer, max_len)
+    }
+
+    /// Put an offer string back together from its parts, to give to
+    /// [Agent::connect_parent].
+    pub fn from_parts<S: AsRef<str>>(parts: &[S]) -> Result<String> {
+        join_parts(parts)
+    }
 }
 
-pub struct Channel {
+/// our answer to a parent's offer, made by [Agent::connect_parent]
+///
+/// # Notes
+/// send it back to the parent, who gives it to [Offer::answer].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    answer: String
+}
+
+impl Answer {
+    /// get the answer string
+    pub fn get(&self) -> String {
+        self.answer.clone()
+    }
+
+    /// Split the answer string into parts, as [Offer::to_parts] does.
+    pub fn to_parts(&self, max_len: usize) -> Result<Vec<String>> {
+        split_parts(&self.answer, max_len)
+    }
+
+    /// Put an answer back together from its parts, to give to [Offer::answer].
+    pub fn from_parts<S: AsRef<str>>(parts: &[S]) -> Result<Answer> {
+        Ok(Answer { answer: join_parts(parts)? })
+    }
+
+    /// Present an invite token from the parent's head along with this answer.
+    pub fn with_invite(self, token: &str) -> Answer {
+        // base64url never contains a '.', so the two split apart cleanly
+        Answer { answer: format!("{}.{}", self.answer, token.trim()) }
+    }
+}
+
+/// identifier of a child peer, assigned when it is [Agent::accept]ed
+pub type PeerId = usize;
+
+/// children a synced channel is bound to, by id
+type Links = Arc<tokio::sync::Mutex<BTreeMap<PeerId, Arc<Connection>>>>;
+
+/// a channel created by [Agent::sync]
+struct Channel {
+    name: String,
+    handle: ChannelHandle,
+    links: Links,
+    /// where bound children's messages are gathered, with who sent them
+    hub: Sender<(Option<PeerId>, Vec<u8>)>,
+}
+
+/// publish to and recieve from a channel created by [Agent::sync]
+///
+/// # Notes
+/// A handle is not tied to any one connection: children accepted (or
+/// accepted again after reconnecting) later are bound to the channel
+/// by the [Agent], so a handle stays valid for as long as the agent
+/// lives. Clones share the same channel; each message is recieved by
+/// only one of them.
+///
+/// # Examples
+///
+/// ```ignore
+/// let todo = agent.sync("todo").await?;
+/// todo.send(encoded_tape).await?;
+/// // ... a child reconnects and is accepted again
+/// agent.accept(offer)?;
+/// let update = todo.recv().await; // still works, now hearing it too
+/// ```
+#[derive(Clone)]
+pub struct ChannelHandle {
     name: String,
     sender: Sender<Vec<u8>>,
-    reciever: Receiver<Vec<u8>>,
+    reciever: Arc<tokio::sync::Mutex<Receiver<Vec<u8>>>>,
+}
+
+impl ChannelHandle {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// publish a message to every peer on this channel
+    pub async fn send(&self, data: Vec<u8>) -> Result<()> {
+        self.sender.send(data).await?;
+        Ok(())
+    }
+
+    /// recieve the next message any peer published on this channel
+    ///
+    /// # Notes
+    /// returns [Option::None] once the agent is gone.
+    pub async fn recv(&self) -> Option<Vec<u8>> {
+        self.reciever.lock().await.recv().await
+    }
+
+    /// publish a [Message] to every peer on this channel, headers and all
+    ///
+    /// # Notes
+    /// relays forward messages untouched, so the headers reach every peer.
+    pub async fn send_message(&self, message: &Message) -> Result<()> {
+        self.send(message.encode()?).await
+    }
+
+    /// recieve the next [Message] any peer published on this channel
+    pub async fn recv_message(&self) -> Option<Message> {
+        Some(Message::decode(&self.recv().await?))
+    }
 }
 
 /// agent for handling RTC connections and propegating messages
+///
+/// # Notes
+/// Agents share nothing: each one has its own connections, channels
+/// and mailbox, so one process can host many isolated swarms by
+/// running one head agent per swarm.
 pub struct Agent {
-    parent: Option<Connection>,
-    children: Vec<Connection>,
+    parent: Option<Arc<Connection>>,
+    children: BTreeMap<PeerId, Arc<Connection>>,
+    next_peer: PeerId,
     api_instance: API,
     config: RTCConfiguration,
+    settings: AgentConfig,
+    /// run until we leave, so any which stopped has failed
     workers: Vec<tokio::task::JoinHandle<()>>,
-    channels: Vec<Channel>
+    /// stop once whoever they hear from is gone, each with whether it
+    /// returned rather than failed
+    listeners: Vec<(tokio::task::JoinHandle<()>, Arc<AtomicBool>)>,
+    channels: Vec<Channel>,
+    mailbox: Option<Arc<tokio::sync::Mutex<Mailbox>>>,
+    /// children which said goodbye, and why
+    departed: Arc<Mutex<BTreeMap<PeerId, GoodbyeReason>>>,
+    /// why our parent said goodbye, if it did
+    parent_departed: Arc<Mutex<Option<GoodbyeReason>>>,
+    bans: BanList,
+    /// certificate fingerprints of each child
+    fingerprints: BTreeMap<PeerId, Vec<String>>,
+    /// key invites are sealed with, if children need one to join
+    invite_key: Option<SecretKey>,
+    /// whether invites must be bound to the fingerprints of whoever answers with them
+    bound_invites: bool,
+    /// what we prove ourselves with on secured control streams
+    noise_key: NoiseKey,
+    /// what each child was invited to do
+    grants: BTreeMap<PeerId, Invite>,
+    audit: Option<Arc<Mutex<AuditLog>>>,
+    /// whether goodbyes were enabled, to hear them from a new parent too
+    goodbyes: bool,
+    /// our parent, for synced channels to follow as it changes
+    parent_changes: watch::Sender<Option<Arc<Connection>>>
 }
 
 impl Agent {
@@ -66,90 +256,1050 @@ impl Agent {
     //     which has .on_child() which calls self.child()
 
     /// synchronize a channel between parent and child
-    pub async fn sync(&mut self, channel_name: &str) -> Result<()> {
-        // create the channel in each of the children
-        join_all(self.children
-                 .iter()
-                 .map(|x| x.channel(channel_name))).await;
+    ///
+    /// # Notes
+    /// messages from one child are relayed to every other child, and up
+    /// to our parent if we have one; messages from the parent are
+    /// relayed to every child. Every child accepted now or later is
+    /// bound to the channel, and so is every parent we connect to; syncing
+    /// a name twice gives another handle to the same channel.
+    ///
+    /// The parent end of the channel is made when the parent syncs it
+    /// too; until then, what we publish waits to go up. Nothing is sent
+    /// back where it came from, so a tree never loops.
+    pub async fn sync(&mut self, channel_name: &str) -> Result<ChannelHandle> {
+        if let Some(channel) = self.channels.iter().find(|x| x.name == channel_name) {
+            return Ok(channel.handle.clone());
+        }
 
         // create channels to and from the sender
         // "sender" is the end to send stuff to publish to network
         // "reciever" is the end to recieve stuff that the network published
-        let (sender, publication_reciever) = channel(super::DEFAULT_QUEUE_SIZE);
+        let (sender, mut publication_reciever) = channel::<Vec<u8>>(super::DEFAULT_QUEUE_SIZE);
         let (publication_sender, reciever) = channel(super::DEFAULT_QUEUE_SIZE);
+        let (hub_sender, mut hub) = channel(super::DEFAULT_QUEUE_SIZE);
+        let links: Links = Arc::new(tokio::sync::Mutex::new(BTreeMap::new()));
 
-        let shared = Channel {
-            name: channel_name.to_owned(),
-            sender: sender,
-            reciever: reciever
-        };
+        // create the channel in each of the children which may see it
+        join_all(self.children
+                 .iter()
+                 .filter_map(|(id, cnx)| Some((id, cnx, self.admits(*id, channel_name)?)))
+                 .map(|(id, cnx, writable)| bind(links.clone(), hub_sender.clone(),
+                                                 channel_name.to_owned(), *id, cnx.clone(),
+                                                 writable, self.audit.clone()))).await;
+
+        // bridge to our parent, now and after any redirect
+        let (up, up_queue) = unbounded_channel();
+        self.workers.push(
+            spawn_named(&format!("synch upstream '{channel_name}'"),
+                        upstream(self.parent_changes.subscribe(), channel_name.to_owned(),
+                                 up_queue, hub_sender.clone()))
+        );
 
-        // bubble child events up
+        // push our publications up to our parent and down to children
+        let down = links.clone();
+        let name = channel_name.to_owned();
+        let parent = self.parent_changes.subscribe();
+        let publish_up = up.clone();
         self.workers.push(
-            tokio::spawn(async {
-                loop {
+            spawn_named(&format!("synch publish '{name}'"), async move {
+                while let Some(data) = publication_reciever.recv().await {
+                    if parent.borrow().is_some() {
+                        let _ = publish_up.send(data.clone());
+                    }
+                    send_all(&down, &name, None, data).await;
                 }
             })
         );
 
+        // bubble child events up, and parent events down
+        let across = links.clone();
+        let name = channel_name.to_owned();
+        let parent = self.parent_changes.subscribe();
+        self.workers.push(
+            spawn_named(&format!("synch relay '{name}'"), async move {
+                while let Some((from, data)) = hub.recv().await {
+                    // traced messages go on as a child of their sender's span
+                    let data = relink(data, &name, from);
+
+                    // what came from above never goes back up
+                    if from.is_some() && parent.borrow().is_some() {
+                        let _ = up.send(data.clone());
+                    }
+                    // relay to the siblings of whoever sent it
+                    send_all(&across, &name, from, data.clone()).await;
+
+                    if publication_sender.send(data).await.is_err() {
+                        return;
+                    }
+                }
+            })
+        );
+
+        let handle = ChannelHandle {
+            name: channel_name.to_owned(),
+            sender,
+            reciever: Arc::new(tokio::sync::Mutex::new(reciever)),
+        };
+        self.channels.push(Channel {
+            name: channel_name.to_owned(),
+            handle: handle.clone(),
+            links,
+            hub: hub_sender
+        });
+
+        Ok(handle)
+    }
+
+    /// publish a message onto a channel created by [Agent::sync]
+    pub async fn publish(&self, channel_name: &str, data: Vec<u8>) -> Result<()> {
+        let channel = self.channels.iter()
+            .find(|x| x.name == channel_name)
+            .ok_or(anyhow!("channel '{channel_name}' has not been synced"))?;
+        channel.handle.send(data).await
+    }
+
+    /// recieve a message published onto a channel created by [Agent::sync]
+    ///
+    /// # Notes
+    /// returns [Option::None] if the channel was never synced or has died.
+    pub async fn recv(&mut self, channel_name: &str) -> Option<Vec<u8>> {
+        let channel = self.channels.iter()
+            .find(|x| x.name == channel_name)?;
+        channel.handle.recv().await
+    }
+
+    /// create a channel scoped to only one child
+    ///
+    /// # Notes
+    /// unlike [Agent::sync], messages on this channel are not relayed
+    /// to any other peer, so the same name can be used to hold an
+    /// independent conversation with each child.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let amy = agent.accept(amy_offer)?;
+    /// let bob = agent.accept(bob_offer)?;
+    /// agent.channel_with(amy, "chat").await?;
+    /// agent.channel_with(bob, "chat").await?;
+    /// agent.send_to(amy, "chat", b"hi amy".to_vec()).await?;
+    /// let (from, msg) = agent.recv_any("chat").await.unwrap();
+    /// ```
+    pub async fn channel_with(&self, peer: PeerId, channel_name: &str) -> Result<()> {
+        self.peer(peer)?.channel(channel_name).await
+    }
+
+    /// send a message to exactly one child
+    pub async fn send_to(&self, peer: PeerId, channel_name: &str, data: Vec<u8>) -> Result<()> {
+        self.peer(peer)?.send(channel_name, data).await
+    }
+
+    /// send a message to exactly one child, waiting until it is written
+    ///
+    /// # Notes
+    /// see [Connection::send_tracked].
+    pub async fn send_tracked_to(&self, peer: PeerId, channel_name: &str,
+                                 data: Vec<u8>) -> Result<Receipt> {
+        self.peer(peer)?.send_tracked(channel_name, data).await
+    }
+
+    /// recieve a message from exactly one child
+    ///
+    /// # Notes
+    /// blocks until this channel exists on the peer; returns
+    /// [Option::None] if the peer is unknown or its queue is dead.
+    pub async fn recv_from(&self, peer: PeerId, channel_name: &str) -> Option<Vec<u8>> {
+        let (_, data) = self.children.get(&peer)?.recv(channel_name).await?;
+        Some(data)
+    }
+
+    /// recieve a message from whichever child sends one first
+    ///
+    /// # Return
+    /// The [PeerId] the message came from and the message, or
+    /// [Option::None] once every child's queue is dead.
+    pub async fn recv_any(&self, channel_name: &str) -> Option<(PeerId, Vec<u8>)> {
+        let children: Vec<(PeerId, Arc<Connection>)> = self.children
+            .iter()
+            .map(|(id, cnx)| (*id, cnx.clone()))
+            .collect();
+        recv_any(&children, channel_name).await
+    }
+
+    /// offer a store-and-forward [Mailbox] to our children
+    ///
+    /// # Notes
+    /// children talk to it with [MailboxMessage]s on [MAILBOX_CHANNEL];
+    /// like [Agent::sync], only children accepted before this call are
+    /// served.
+    pub async fn enable_mailbox(&mut self) -> Result<Arc<tokio::sync::Mutex<Mailbox>>> {
+        if let Some(ref mailbox) = self.mailbox {
+            return Ok(mailbox.clone());
+        }
+        self.restore_mailbox(Mailbox::new()).await
+    }
+
+    /// Offer `mailbox`, holding mail from elsewhere, to our children.
+    ///
+    /// # Notes
+    /// as [Agent::enable_mailbox], for the mail of [Takeover::mailbox];
+    /// fails if a mailbox is already offered.
+    pub async fn restore_mailbox(&mut self, mailbox: Mailbox) -> Result<Arc<tokio::sync::Mutex<Mailbox>>> {
+        if self.mailbox.is_some() {
+            return Err(anyhow!("this agent already offers a mailbox"));
+        }
+
+        join_all(self.children
+                 .values()
+                 .map(|x| x.channel(MAILBOX_CHANNEL))).await;
+
+        let mailbox = Arc::new(tokio::sync::Mutex::new(mailbox));
+        let children: Vec<(PeerId, Arc<Connection>)> = self.children
+            .iter()
+            .map(|(id, cnx)| (*id, cnx.clone()))
+            .collect();
+
+        let served = mailbox.clone();
+        self.listen("synch mailbox", async move {
+            while let Some((from, raw)) = recv_any(&children, MAILBOX_CHANNEL).await {
+                let request = match MailboxMessage::decode(&raw) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        error!("bad mailbox request from peer {from}: {err}");
+                        continue;
+                    }
+                };
+
+                let replies = served.lock().await.handle(from, request);
+                let Some((_, cnx)) = children.iter().find(|(id, _)| *id == from) else {
+                    continue;
+                };
+                for reply in replies {
+                    let sent = match reply.encode() {
+                        Ok(x) => cnx.send(MAILBOX_CHANNEL, x).await,
+                        Err(err) => Err(err),
+                    };
+                    if let Err(err) = sent {
+                        error!("failed to answer mailbox request from peer {from}: {err}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        self.mailbox = Some(mailbox.clone());
+        Ok(mailbox)
+    }
+
+    /// listen for [GoodbyeReason]s from our parent and children
+    ///
+    /// # Notes
+    /// a child which says goodbye is disconnected and dropped from
+    /// [Agent::peers] at once, rather than when its connection times
+    /// out; like [Agent::sync], only children accepted before this call
+    /// are heard.
+    pub async fn enable_goodbyes(&mut self) -> Result<()> {
+        join_all(self.children
+                 .values()
+                 .map(|x| x.channel(GOODBYE_CHANNEL))).await;
+
+        let children: Vec<(PeerId, Arc<Connection>)> = self.children
+            .iter()
+            .map(|(id, cnx)| (*id, cnx.clone()))
+            .collect();
+        let departed = self.departed.clone();
+        self.listen("synch goodbyes", async move {
+            while let Some((from, raw)) = recv_any(&children, GOODBYE_CHANNEL).await {
+                let reason = GoodbyeReason::decode(&raw).unwrap_or_else(|err| {
+                    GoodbyeReason::Other(format!("undecodable goodbye: {err}"))
+                });
+                debug!("peer {from} said goodbye: {reason:?}");
+
+                if let Ok(mut x) = departed.lock() {
+                    x.insert(from, reason);
+                }
+                if let Some((_, cnx)) = children.iter().find(|(id, _)| *id == from) {
+                    let _ = cnx.close().await;
+                }
+            }
+        });
+
+        self.goodbyes = true;
+        self.hear_parent_goodbye();
+
         Ok(())
     }
 
-    /// create a head node
-    pub fn head() -> Result<Agent> {
-        Agent::configure_manually(None, DEFAULT_STUN_SERVERS)
+    /// Say goodbye to every peer, then disconnect.
+    ///
+    /// # Notes
+    /// Peers which never enabled goodbyes, or don't answer within
+    /// [GOODBYE_TIMEOUT], are disconnected without one.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// agent.enable_goodbyes().await?;
+    /// // ... on quit
+    /// agent.leave(GoodbyeReason::Closed).await?;
+    /// ```
+    pub async fn leave(self, reason: GoodbyeReason) -> Result<()> {
+        let raw = reason.encode()?;
+        let peers: Vec<Arc<Connection>> = self.parent.iter()
+            .chain(self.children.values())
+            .cloned()
+            .collect();
+
+        join_all(peers.iter().map(|cnx| async {
+            let _ = tokio::time::timeout(GOODBYE_TIMEOUT,
+                                         cnx.send_tracked(GOODBYE_CHANNEL, raw.clone())).await;
+            cnx.close().await
+        })).await;
+
+        for worker in self.workers {
+            worker.abort();
+        }
+        for (listener, _) in self.listeners {
+            listener.abort();
+        }
+        Ok(())
     }
 
-    /// create a child by accepting a new offer
+    /// End the swarm without losing the last edits.
+    ///
+    /// # Notes
+    /// Every child is told to publish what it has left, with
+    /// [Agent::await_shutdown]. Once every child answers with
+    /// [Agent::ack_shutdown], or `grace` passes, `persist` is run to
+    /// save final snapshots of what was recieved, and then everyone is
+    /// sent [GoodbyeReason::Shutdown] and disconnected, as by
+    /// [Agent::leave]. Only direct children are told; a child with
+    /// children of its own should shut those down in turn before it
+    /// acknowledges.
     ///
     /// # Return
-    /// A response to the parent offer and an Agent
-    /// corresponding to the child.
-    pub async fn child(offer: &str) -> Result<(String, Agent)> {
-        let mut child = Agent::configure_manually(None, DEFAULT_STUN_SERVERS)?;
-        let mut parent_cnx = child.create_connection().await?;
+    /// The children which hadn't acknowledged within `grace`, whose last
+    /// edits may be lost.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the head; `notes` is a handle from sync, fed into `doc`
+    /// let late = head.shutdown_swarm(Duration::from_secs(5), || async {
+    ///     store.save("notes", &SnapshotEnvelope::new(serde_json::to_vec(&doc)?, doc.actor(), 0))
+    /// }).await?;
+    ///
+    /// // on each child
+    /// child.await_shutdown().await;
+    /// notes.send(serde_json::to_vec(&doc.tape())?).await?;
+    /// child.ack_shutdown().await?;
+    /// ```
+    pub async fn shutdown_swarm<F, Fut>(self, grace: std::time::Duration,
+                                        persist: F) -> Result<Vec<PeerId>>
+    where F: FnOnce() -> Fut, Fut: std::future::Future<Output = Result<()>> {
+        let notice = ShutdownMessage::Notice { grace }.encode()?;
+        let mut waiting: Vec<(PeerId, Arc<Connection>)> = self.children
+            .iter()
+            .map(|(id, cnx)| (*id, cnx.clone()))
+            .collect();
+
+        join_all(waiting.iter().map(|(id, cnx)| {
+            let notice = notice.clone();
+            async move {
+                let sent = tokio::time::timeout(GOODBYE_TIMEOUT, async {
+                    cnx.channel(SHUTDOWN_CHANNEL).await?;
+                    cnx.send(SHUTDOWN_CHANNEL, notice).await
+                }).await;
+                if !matches!(sent, Ok(Ok(()))) {
+                    warn!("failed to tell peer {id} the swarm is shutting down");
+                }
+            }
+        })).await;
+
+        // everything the children publish meanwhile still flows to the
+        // channel handles, as the workers are running until we leave
+        let _ = tokio::time::timeout(grace, async {
+            while let Some((from, raw)) = recv_any(&waiting, SHUTDOWN_CHANNEL).await {
+                if let Ok(ShutdownMessage::Flushed) = ShutdownMessage::decode(&raw) {
+                    debug!("peer {from} flushed for shutdown");
+                    waiting.retain(|(id, _)| *id != from);
+                }
+                if waiting.is_empty() {
+                    break;
+                }
+            }
+        }).await;
+
+        let persisted = persist().await;
+        self.leave(GoodbyeReason::Shutdown).await?;
+        persisted?;
+
+        Ok(waiting.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Wait for our parent to announce it is shutting down the swarm.
+    ///
+    /// # Return
+    /// How long we have to publish our last edits before
+    /// [Agent::ack_shutdown], or [None] if the parent left without notice.
+    pub async fn await_shutdown(&self) -> Option<std::time::Duration> {
+        let parent = self.parent.as_ref()?;
+        while let Some((_, raw)) = parent.recv(SHUTDOWN_CHANNEL).await {
+            if let Ok(ShutdownMessage::Notice { grace }) = ShutdownMessage::decode(&raw) {
+                return Some(grace);
+            }
+        }
+        None
+    }
+
+    /// Tell our parent everything we had to publish is sent.
+    ///
+    /// # Notes
+    /// Publish last edits before calling this. Channels are written
+    /// independently, so this waits, for up to [GOODBYE_TIMEOUT], for
+    /// what is queued to our parent to go out first.
+    pub async fn ack_shutdown(&self) -> Result<()> {
+        let parent = self.parent.as_ref().ok_or(anyhow!("this agent has no parent"))?;
+        if !matches!(tokio::time::timeout(GOODBYE_TIMEOUT, parent.flush()).await, Ok(Ok(()))) {
+            warn!("acknowledging shutdown before our last edits were sent");
+        }
+        parent.send_tracked(SHUTDOWN_CHANNEL, ShutdownMessage::Flushed.encode()?).await?;
+        Ok(())
+    }
+
+    /// Hand the swarm to a new head, then leave.
+    ///
+    /// # Notes
+    /// `successor` is the new head, joined as our child. It is sent
+    /// `documents`, the fingerprints and grant of every other child, our
+    /// bans and our mailbox's mail. Then each other child in turn is
+    /// sent an offer from the successor, and its answer carried back,
+    /// so nobody has to signal again. Children which don't answer within
+    /// [HANDOFF_TIMEOUT] are left behind, and are sent
+    /// [GoodbyeReason::Restarting] as we [Agent::leave].
+    ///
+    /// Edits published while this runs may miss the successor, so
+    /// children should publish theirs again once moved.
+    ///
+    /// # Return
+    /// The children which could not be moved.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the old head, once the new process joined as `successor`
+    /// let documents = BTreeMap::from([("notes".to_owned(), serde_json::to_vec(&doc)?)]);
+    /// let stranded = old.hand_off(successor, documents).await?;
+    ///
+    /// // on the new head
+    /// let takeover = new.take_over().await?;
+    /// let doc: SyncedList<Note> = serde_json::from_slice(&takeover.documents["notes"])?;
+    /// new.restore_mailbox(takeover.mailbox).await?;
+    ///
+    /// // on each child
+    /// child.follow_redirect().await?;
+    /// notes.send(serde_json::to_vec(&doc.tape())?).await?;
+    /// ```
+    pub async fn hand_off(self, successor: PeerId,
+                          documents: BTreeMap<String, Vec<u8>>) -> Result<Vec<PeerId>> {
+        let heir = self.peer(successor)?.clone();
+        heir.channel(HANDOFF_CHANNEL).await?;
+
+        for (name, snapshot) in documents {
+            let mut parts: Vec<&[u8]> = snapshot.chunks(SNAPSHOT_CHUNK_BYTES).collect();
+            if parts.is_empty() {
+                parts.push(&[]);
+            }
+            let count = parts.len();
+            for (i, part) in parts.into_iter().enumerate() {
+                tell(&heir, &HandoffMessage::Document { name: name.clone(),
+                                                        part: Bytes::copy_from_slice(part),
+                                                        last: i + 1 == count }).await?;
+            }
+        }
+
+        let moving: Vec<(PeerId, Arc<Connection>)> = self.children
+            .iter()
+            .filter(|(id, _)| **id != successor)
+            .map(|(id, cnx)| (*id, cnx.clone()))
+            .collect();
+        for (peer, _) in moving.iter() {
+            tell(&heir, &HandoffMessage::Peer(RosterEntry {
+                peer: *peer,
+                fingerprints: self.fingerprints.get(peer).cloned().unwrap_or_default(),
+                grant: self.grants.get(peer).cloned(),
+            })).await?;
+        }
+        for (fingerprint, ban) in self.bans.list() {
+            tell(&heir, &HandoffMessage::Ban { fingerprint: fingerprint.to_owned(),
+                                               ban: ban.clone() }).await?;
+        }
+        if let Some(ref mailbox) = self.mailbox {
+            let letters = mailbox.lock().await.letters();
+            for letter in letters {
+                tell(&heir, &HandoffMessage::Letter(letter)).await?;
+            }
+        }
+
+        let mut stranded = vec![];
+        for (peer, cnx) in moving {
+            if let Err(err) = redirect(&heir, peer, &cnx).await {
+                warn!("failed to move peer {peer} to the new head: {err}");
+                stranded.push(peer);
+            }
+        }
+
+        heir.send_tracked(HANDOFF_CHANNEL, HandoffMessage::Done.encode()?).await?;
+        self.leave(GoodbyeReason::Restarting).await?;
+
+        Ok(stranded)
+    }
+
+    /// Become the head of our parent's swarm, as it runs [Agent::hand_off].
+    ///
+    /// # Notes
+    /// The old head vouches for the children it moves, so each is let
+    /// in without an invite, keeping the grant it had there. A moved
+    /// child answers with a new certificate, and is known here by the
+    /// fingerprints of that; it is still refused if banned by either.
+    /// Once the old head is done it is disconnected, leaving us the
+    /// head; if it drops partway, we keep whoever was moved.
+    ///
+    /// Like [Agent::accept], moved children are bound to synced
+    /// channels, but mailboxes and goodbyes should be enabled after
+    /// this.
+    pub async fn take_over(&mut self) -> Result<Takeover> {
+        let old = self.parent.clone().ok_or(anyhow!("this agent has no parent to take over from"))?;
+        let mut takeover = Takeover::default();
+        let mut partial: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        let mut roster: BTreeMap<PeerId, RosterEntry> = BTreeMap::new();
+        let mut offers: BTreeMap<PeerId, Offer> = BTreeMap::new();
+        let mut letters = vec![];
+        let mut done = false;
+
+        while let Some((_, raw)) = old.recv(HANDOFF_CHANNEL).await {
+            match HandoffMessage::decode(&raw)? {
+                HandoffMessage::Document { name, part, last } => {
+                    let mut snapshot = partial.remove(&name).unwrap_or_default();
+                    snapshot.extend_from_slice(&part);
+                    if last {
+                        takeover.documents.insert(name, snapshot);
+                    } else {
+                        partial.insert(name, snapshot);
+                    }
+                }
+                HandoffMessage::Peer(entry) => {
+                    roster.insert(entry.peer, entry);
+                }
+                HandoffMessage::Ban { fingerprint, ban } => self.bans.restore(&fingerprint, ban)?,
+                HandoffMessage::Letter(letter) => letters.push(letter),
+                HandoffMessage::OfferWanted { peer } => {
+                    let mut cnx = self.create_connection().await?;
+                    cnx.channel(HANDOFF_CHANNEL).await?;
+                    let offer = cnx.offer().await?;
+                    tell(&old, &HandoffMessage::Offer { peer, offer: offer.clone() }).await?;
+
+                    offers.insert(peer, Offer { cnx, offer, validated: false,
+                                                fingerprints: vec![], invite: None });
+                }
+                HandoffMessage::Answered { peer, answer } => {
+                    let (Some(mut offer), Some(entry)) = (offers.remove(&peer), roster.remove(&peer)) else {
+                        warn!("old head answered for peer {peer}, which it never announced");
+                        continue;
+                    };
+                    if let Err(err) = offer.answer(&answer).await {
+                        warn!("failed to connect peer {peer} from the old head: {err}");
+                        continue;
+                    }
+                    if self.bans.is_banned(&offer.fingerprints) || self.bans.is_banned(&entry.fingerprints) {
+                        self.audit(SecurityEvent::Refused { fingerprints: offer.fingerprints,
+                                                            reason: "banned".to_owned() });
+                        let _ = offer.cnx.close().await;
+                        continue;
+                    }
+
+                    let id = self.adopt(offer.cnx, offer.fingerprints, entry.grant);
+                    debug!("peer {peer} of the old head is now peer {id}");
+                    takeover.peers.insert(peer, id);
+                }
+                HandoffMessage::Done => {
+                    done = true;
+                    break;
+                }
+                other => debug!("ignored {other:?} while taking over"),
+            }
+        }
+        if !done {
+            warn!("old head left partway through the handoff");
+        }
+
+        // mail from peers who weren't moved is charged to ids nobody
+        // here has, so their storage stays their own
+        let mut strangers: BTreeMap<PeerId, PeerId> = BTreeMap::new();
+        for mut letter in letters {
+            letter.owner = match takeover.peers.get(&letter.owner) {
+                Some(id) => *id,
+                None => *strangers.entry(letter.owner).or_insert_with(|| {
+                    self.next_peer += 1;
+                    self.next_peer - 1
+                }),
+            };
+            takeover.mailbox.restore(letter);
+        }
+
+        let _ = old.close().await;
+        self.set_parent(None);
+        if let Ok(mut x) = self.parent_departed.lock() {
+            *x = None;
+        }
+
+        Ok(takeover)
+    }
+
+    /// Move to a new head as our parent hands the swarm off.
+    ///
+    /// # Notes
+    /// Waits for our parent's [HandoffMessage::Redirect], answers it and
+    /// makes the new head our parent, listening for its goodbye if
+    /// goodbyes are enabled. The new head may have missed recent edits,
+    /// so publish them again after this.
+    pub async fn follow_redirect(&mut self) -> Result<()> {
+        let old = self.parent.clone().ok_or(anyhow!("this agent has no parent"))?;
+        let offer = loop {
+            let (_, raw) = old.recv(HANDOFF_CHANNEL).await
+                .ok_or(anyhow!("parent left without redirecting us"))?;
+            if let HandoffMessage::Redirect { offer } = HandoffMessage::decode(&raw)? {
+                break offer;
+            }
+        };
+
+        let mut cnx = self.create_connection().await?;
+        let answer = cnx.answer(&offer).await?;
+        // the answer has arrived once this returns, so closing can't lose it
+        tokio::time::timeout(GOODBYE_TIMEOUT, old.send_tracked(
+            HANDOFF_CHANNEL, HandoffMessage::Answer { answer }.encode()?
+        )).await.map_err(|_| anyhow!("timed out answering our parent's redirect"))??;
+        let _ = old.close().await;
+        self.set_parent(Some(Arc::new(cnx)));
+        if let Ok(mut x) = self.parent_departed.lock() {
+            *x = None;
+        }
+        if self.goodbyes {
+            self.hear_parent_goodbye();
+        }
+
+        Ok(())
+    }
+
+    /// why a child said goodbye, if it did
+    pub fn departed(&self, peer: PeerId) -> Option<GoodbyeReason> {
+        self.departed.lock().ok()?.get(&peer).cloned()
+    }
+
+    /// why our parent said goodbye, if it did
+    ///
+    /// # Notes
+    /// check [GoodbyeReason::should_reconnect] before trying to find a
+    /// new way back to the swarm.
+    pub fn parent_departed(&self) -> Option<GoodbyeReason> {
+        self.parent_departed.lock().ok()?.clone()
+    }
+
+    /// the ids of all accepted children which haven't said goodbye
+    pub fn peers(&self) -> Vec<PeerId> {
+        let departed = self.departed.lock()
+            .map(|x| x.keys().copied().collect())
+            .unwrap_or(vec![]);
+        self.children.keys()
+            .filter(|x| !departed.contains(x))
+            .copied()
+            .collect()
+    }
+
+    /// Whether this agent is working, for liveness and readiness probes.
+    ///
+    /// # Notes
+    /// An agent is ready when it is reachable by the swarm (it is the
+    /// head, or its parent connection is up) and its queues hold at most
+    /// [AgentConfig::max_backlog] messages, so it is keeping up.
+    pub fn health(&self) -> Health {
+        let connections = self.parent.iter().chain(self.children.values());
+        let backlog = connections
+            .flat_map(|x| x.queue_depths())
+            .map(|x| x.current)
+            .sum();
+
+        let is_head = self.parent.is_none();
+        let parent_connected = self.parent.as_ref().is_some_and(|x| x.is_connected())
+            && self.parent_departed().is_none();
+        let peers = self.peers().iter()
+            .filter(|x| self.children.get(x).is_some_and(|x| x.is_connected()))
+            .count();
+
+        Health {
+            live: self.workers.iter().all(|x| !x.is_finished())
+                && self.listeners.iter().all(|(x, returned)| {
+                    !x.is_finished() || returned.load(Ordering::Acquire)
+                }),
+            ready: (is_head || parent_connected) && backlog <= self.settings.max_backlog,
+            is_head,
+            parent_connected,
+            peers,
+            backlog,
+        }
+    }
+
+    /// Create an agent with no connections yet.
+    ///
+    /// # Notes
+    /// Nothing touches the network until [Agent::connect_parent] or
+    /// [Agent::connect_child] is called; an agent which never connects
+    /// to a parent is the head of its swarm.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut agent = Agent::new(AgentConfig::default())?;
+    /// let answer = agent.connect_parent(&offer_from_parent).await?;
+    /// send_to_parent(answer.get());
+    /// ```
+    pub fn new(settings: AgentConfig) -> Result<Agent> {
+        settings.apply_log_level()?;
+        let stun_servers: Vec<&str> = settings.stun_servers.iter().map(|x| x.as_str()).collect();
+        let mut config = get_config_from_stun_servers(&stun_servers);
+        config.certificates = settings.identity()?.into_iter().collect();
+
+        Ok(Agent {
+            parent: None,
+            children: BTreeMap::new(),
+            next_peer: 0,
+            api_instance: get_api()?,
+            config,
+            settings,
+            workers: vec![],
+            listeners: vec![],
+            channels: vec![],
+            mailbox: None,
+            departed: Arc::new(Mutex::new(BTreeMap::new())),
+            parent_departed: Arc::new(Mutex::new(None)),
+            bans: BanList::new(),
+            fingerprints: BTreeMap::new(),
+            invite_key: None,
+            bound_invites: false,
+            noise_key: NoiseKey::generate()?,
+            grants: BTreeMap::new(),
+            audit: None,
+            goodbyes: false,
+            parent_changes: watch::Sender::new(None)
+        })
+    }
+
+    /// the configuration this agent is running with
+    pub fn settings(&self) -> &AgentConfig {
+        &self.settings
+    }
+
+    /// Switch to a new configuration, applying what can be applied live.
+    ///
+    /// # Notes
+    /// - `queue_size` resizes every open queue
+    /// - `max_backlog` and `log_level` apply at once
+    /// - `stun_servers`, `identity_file` and `passphrase` apply to new
+    ///   connections only
+    /// - `snapshot_dir`, `key_file` and `documents` are not used by the
+    ///   agent; rebuild whatever was made from them
+    ///
+    /// # Return
+    /// What became of every setting which changed.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for change in agent.reload(AgentConfig::from_file("synch.toml")?).await {
+    ///     if let ConfigChange::NeedsReconnect(setting) = change {
+    ///         warn!("{setting} changed; reconnect peers to use it");
+    ///     }
+    /// }
+    /// ```
+    pub async fn reload(&mut self, settings: AgentConfig) -> Vec<ConfigChange> {
+        let old = std::mem::replace(&mut self.settings, settings);
+        let mut changes = vec![];
+
+        if self.settings.stun_servers != old.stun_servers {
+            let stun_servers: Vec<&str> = self.settings.stun_servers.iter()
+                .map(|x| x.as_str()).collect();
+            let certificates = std::mem::take(&mut self.config.certificates);
+            self.config = get_config_from_stun_servers(&stun_servers);
+            self.config.certificates = certificates;
+            changes.push(ConfigChange::NeedsReconnect("stun_servers".into()));
+        }
 
+        if self.settings.identity_file != old.identity_file
+            || self.settings.passphrase != old.passphrase {
+            match self.settings.identity() {
+                Ok(x) => {
+                    self.config.certificates = x.into_iter().collect();
+                    changes.push(ConfigChange::NeedsReconnect("identity_file".into()));
+                }
+                Err(err) => warn!("{err}"),
+            }
+        }
+
+        if self.settings.queue_size != old.queue_size {
+            for cnx in self.parent.iter().chain(self.children.values()) {
+                let channels: BTreeSet<String> = cnx.queue_depths().into_iter()
+                    .map(|x| x.channel).collect();
+                for channel in channels {
+                    if let Err(err) = cnx.resize_queue(&channel, self.settings.queue_size).await {
+                        warn!("failed to resize queue of '{channel}': {err}");
+                    }
+                }
+            }
+            changes.push(ConfigChange::Applied("queue_size".into()));
+        }
+
+        if self.settings.max_backlog != old.max_backlog {
+            changes.push(ConfigChange::Applied("max_backlog".into()));
+        }
+
+        if self.settings.log_level != old.log_level {
+            match self.settings.apply_log_level() {
+                Ok(()) => changes.push(ConfigChange::Applied("log_level".into())),
+                Err(err) => warn!("{err}"),
+            }
+        }
+
+        for (setting, changed) in [
+            ("snapshot_dir", self.settings.snapshot_dir != old.snapshot_dir),
+            ("key_file", self.settings.key_file != old.key_file),
+            ("documents", self.settings.documents != old.documents),
+        ] {
+            if changed {
+                changes.push(ConfigChange::NeedsRestart(setting.into()));
+            }
+        }
+
+        changes
+    }
+
+    /// Join a swarm by answering an offer from a parent.
+    ///
+    /// # Arguments
+    ///
+    /// * `offer` - Base64 encoded offer from [Agent::connect_child] on the parent.
+    pub async fn connect_parent(&mut self, offer: &str) -> Result<Answer> {
+        if self.parent.is_some() {
+            return Err(anyhow!("this agent already has a parent"));
+        }
+
+        let mut parent_cnx = self.create_connection().await?;
         let answer = parent_cnx.answer(offer).await?;
-        child.parent = Some(parent_cnx);
+        self.set_parent(Some(Arc::new(parent_cnx)));
 
-        Ok((answer, child))
+        Ok(Answer { answer })
     }
 
-    /// offer a new connection to a possible child
-    pub async fn offer(&self) -> Result<Offer> {
+    /// offer a new connection to a possible child, to [Agent::accept] once answered
+    pub async fn connect_child(&self) -> Result<Offer> {
+        self.connect_child_with(&[]).await
+    }
+
+    /// Offer a new connection to a possible child with `channels`
+    /// already made on it.
+    ///
+    /// # Notes
+    /// An offer only negotiates what the connection has when it is
+    /// made, so one made before any channel leaves nothing to carry
+    /// data over until renegotiated.
+    pub async fn connect_child_with(&self, channels: &[&str]) -> Result<Offer> {
         let mut child_cnx = self.create_connection().await?;
+        for name in channels {
+            child_cnx.channel(name).await?;
+        }
         let offer = child_cnx.offer().await?;
 
         Ok(Offer {
             cnx: child_cnx,
-            offer: offer,
-            validated: false
+            offer,
+            validated: false,
+            fingerprints: vec![],
+            invite: None
         })
     }
 
+    /// create a head node
+    pub fn head() -> Result<Agent> {
+        Agent::new(AgentConfig::default())
+    }
+
+    /// Turn wire-level frame tracing on or off for one connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - the child to trace, or [None] for our parent.
+    /// * `enabled` - whether to log its frames to [super::TRACE_TARGET].
+    pub fn trace(&self, peer: Option<PeerId>, enabled: bool) -> Result<()> {
+        let (cnx, label) = match peer {
+            Some(id) => (self.peer(id)?, format!("child-{id}")),
+            None => (self.parent.as_ref().ok_or(anyhow!("this agent has no parent"))?,
+                     "parent".to_owned()),
+        };
+
+        cnx.set_trace(if enabled { Some(label) } else { None });
+        Ok(())
+    }
+
+    /// The code to compare with a child (or the parent, if `None`); see
+    /// [Connection::pairing_code].
+    pub async fn pairing_code(&self, peer: Option<PeerId>) -> Result<String> {
+        let cnx = match peer {
+            Some(id) => self.peer(id)?,
+            None => self.parent.as_ref().ok_or(anyhow!("this agent has no parent"))?,
+        };
+
+        cnx.pairing_code().await
+    }
+
+    /// how full the queues to a peer (or the parent, if `None`) are, and have been
+    ///
+    /// # Notes
+    /// use this to pick [AgentConfig::queue_size]; a queue whose high
+    /// watermark sits at its capacity is too small.
+    pub fn queue_depths(&self, peer: Option<PeerId>) -> Result<Vec<QueueDepth>> {
+        let cnx = match peer {
+            Some(id) => self.peer(id)?,
+            None => self.parent.as_ref().ok_or(anyhow!("this agent has no parent"))?,
+        };
+
+        Ok(cnx.queue_depths())
+    }
+
+    /// Find stuck queues on every connection; see [Connection::watchdog].
+    ///
+    /// # Return
+    /// Each stuck queue, with the child it is to, or [None] for our parent.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// loop {
+    ///     for (peer, stuck) in agent.watchdog(Duration::from_secs(30)) {
+    ///         error!("peer {peer:?} stuck: {stuck:?}");
+    ///     }
+    ///     tokio::time::sleep(Duration::from_secs(5)).await;
+    /// }
+    /// ```
+    pub fn watchdog(&self, after: std::time::Duration) -> Vec<(Option<PeerId>, QueueStuck)> {
+        let parent = self.parent.iter().map(|x| (None, x));
+        let children = self.children.iter().map(|(id, x)| (Some(*id), x));
+
+        parent.chain(children)
+            .flat_map(|(peer, cnx)| cnx.watchdog(after).into_iter().map(move |x| (peer, x)))
+            .inspect(|(peer, x)| warn!("queue to {peer:?} is stuck: {x:?}"))
+            .collect()
+    }
+
+    /// bytes moved over every connection, to our parent and children alike
+    pub fn traffic(&self) -> Traffic {
+        self.parent.iter().chain(self.children.values())
+            .map(|x| x.traffic())
+            .fold(Traffic::default(), |a, b| a + b)
+    }
+
+    /// names of every channel synced with [Agent::sync]
+    pub fn channels(&self) -> Vec<&str> {
+        self.channels.iter().map(|x| x.name.as_str()).collect()
+    }
+
+    /// create a child by accepting a new offer
+    ///
+    /// # Return
+    /// A response to the parent offer and an Agent
+    /// corresponding to the child.
+    pub async fn child(offer: &str) -> Result<(String, Agent)> {
+        let mut child = Agent::new(AgentConfig::default())?;
+        let answer = child.connect_parent(offer).await?;
+
+        Ok((answer.get(), child))
+    }
+
+    /// offer a new connection to a possible child
+    pub async fn offer(&self) -> Result<Offer> {
+        self.connect_child().await
+    }
+
     /// accept a child connection
-    pub fn accept(&mut self, validated_offer: Offer) -> Result<()> {
+    ///
+    /// # Return
+    /// The [PeerId] now identifying this child.
+    pub fn accept(&mut self, validated_offer: Offer) -> Result<PeerId> {
         if !validated_offer.validated {
             return Err(anyhow!("offer given to accept has not been answered yet!"));
         }
+        let fingerprints = validated_offer.fingerprints;
+        if self.bans.is_banned(&fingerprints) {
+            self.audit(SecurityEvent::Refused { fingerprints: fingerprints.clone(),
+                                                reason: "banned".to_owned() });
+            return Err(anyhow!("peer {fingerprints:?} is banned"));
+        }
 
-        self.children.push(validated_offer.cnx);
+        let grant = match (&self.invite_key, validated_offer.invite) {
+            (None, _) if self.bound_invites => {
+                return Err(anyhow!("bound invites are required, but there is no invite key"));
+            }
+            (None, _) => None,
+            (Some(key), Some(token)) => match Invite::open(key, &token) {
+                Ok(x) if x.fingerprints.is_none() && !self.bound_invites => Some(x),
+                Ok(x) if x.binds(&fingerprints) => Some(x),
+                Ok(_) => {
+                    let reason = "invite is not bound to this peer's certificate";
+                    self.audit(SecurityEvent::BadInvite { fingerprints, reason: reason.to_owned() });
+                    return Err(anyhow!(reason));
+                }
+                Err(err) => {
+                    self.audit(SecurityEvent::BadInvite { fingerprints, reason: err.to_string() });
+                    return Err(err);
+                }
+            },
+            (Some(_), None) => {
+                self.audit(SecurityEvent::Refused { fingerprints,
+                                                    reason: "no invite".to_owned() });
+                return Err(anyhow!("this agent only accepts invited peers"));
+            }
+        };
 
-        Ok(())
+        Ok(self.adopt(validated_offer.cnx, fingerprints, grant))
+    }
+
+    /// make an answered connection our child, binding it to every channel it may see
+    fn adopt(&mut self, cnx: Connection, fingerprints: Vec<String>, grant: Option<Invite>) -> PeerId {
+        let id = self.next_peer;
+        self.next_peer += 1;
+        let cnx = Arc::new(cnx);
+        self.children.insert(id, cnx.clone());
+        self.fingerprints.insert(id, fingerprints);
+        if let Some(grant) = grant {
+            self.grants.insert(id, grant);
+        }
+
+        // bind every synced channel the newcomer may see to it
+        for channel in self.channels.iter() {
+            let Some(writable) = self.admits(id, &channel.name) else { continue; };
+            spawn_named(&format!("synch bind '{}' peer {id}", channel.name),
+                        bind(channel.links.clone(), channel.hub.clone(),
+                             channel.name.clone(), id, cnx.clone(), writable,
+                             self.audit.clone()));
+        }
+
+        id
     }
 
     pub fn configure_manually(parent: Option<Connection>, stun_servers: &[&str]) -> Result<Agent> {
-        let api = get_api()?;
-        let config = get_config_from_stun_servers(stun_servers);
+        let mut agent = Agent::new(AgentConfig::with_stun_servers(stun_servers))?;
+        agent.set_parent(parent.map(Arc::new));
 
-        Ok(Agent {
-            parent: parent,
-            children: vec![],
-            api_instance: api,
-            config: config,
-            workers: vec![],
-            channels: vec![]
-        })
+        Ok(agent)
     }
 
     async fn create_connection(&self) -> Result<Connection> {
@@ -158,9 +1308,522 @@ impl Agent {
                 self.api_instance
                     .new_peer_connection(self.config.clone())
                     .await?
-            ), None
+            ), Some(self.settings.queue_size)
         ))
     }
+
+    /// the peers this agent refuses
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    /// Use another ban list, disconnecting any child it bans.
+    pub async fn set_bans(&mut self, bans: BanList) {
+        self.bans = bans;
+        self.kick_banned().await;
+    }
+
+    /// a child's DTLS certificate fingerprints, which [BanList]s are keyed by
+    pub fn fingerprints(&self, peer: PeerId) -> Option<&[String]> {
+        self.fingerprints.get(&peer).map(|x| x.as_slice())
+    }
+
+    /// Ban a child by its fingerprints, disconnecting it.
+    ///
+    /// # Notes
+    /// The ban outlives the connection: the peer is refused by
+    /// [Agent::accept] whenever it comes back with the same certificate.
+    pub async fn ban(&mut self, peer: PeerId, reason: &str) -> Result<()> {
+        let fingerprints = self.fingerprints.get(&peer)
+            .filter(|x| !x.is_empty())
+            .ok_or(anyhow!("no fingerprint known for peer {peer}"))?;
+        for fingerprint in fingerprints.iter() {
+            self.bans.ban(fingerprint, reason)?;
+        }
+        self.audit(SecurityEvent::Banned { peer, fingerprints: fingerprints.clone(),
+                                           reason: reason.to_owned() });
+
+        self.kick_banned().await;
+        Ok(())
+    }
+
+    /// lift a ban; see [BanList::unban]
+    pub fn unban(&mut self, fingerprint: &str) -> Result<bool> {
+        self.bans.unban(fingerprint)
+    }
+
+    /// say goodbye to and drop every child which is banned
+    async fn kick_banned(&mut self) {
+        let banned: Vec<PeerId> = self.fingerprints.iter()
+            .filter(|(_, x)| self.bans.is_banned(x))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in banned {
+            self.drop_child(id).await;
+        }
+    }
+
+    /// Disconnect a child, telling it it was kicked.
+    ///
+    /// # Notes
+    /// Unlike [Agent::ban], this lets the peer come back.
+    pub async fn kick(&mut self, peer: PeerId) -> Result<()> {
+        if !self.children.contains_key(&peer) {
+            return Err(anyhow!("no child with peer id {peer}"));
+        }
+        self.audit(SecurityEvent::Kicked { peer });
+        self.drop_child(peer).await;
+
+        Ok(())
+    }
+
+    /// say [GoodbyeReason::Kicked] to a child and forget it
+    async fn drop_child(&mut self, id: PeerId) {
+        self.fingerprints.remove(&id);
+        self.grants.remove(&id);
+        let Some(cnx) = self.children.remove(&id) else { return; };
+        for channel in self.channels.iter() {
+            channel.links.lock().await.remove(&id);
+        }
+        if let Ok(mut x) = self.departed.lock() {
+            x.insert(id, GoodbyeReason::Kicked);
+        }
+
+        if let Ok(raw) = GoodbyeReason::Kicked.encode() {
+            let _ = tokio::time::timeout(GOODBYE_TIMEOUT, cnx.send_tracked(GOODBYE_CHANNEL, raw)).await;
+        }
+        if let Err(err) = cnx.close().await {
+            warn!("failed to disconnect peer {id}: {err}");
+        }
+    }
+
+    /// Only accept children which answer with an invite sealed by `key`.
+    ///
+    /// # Notes
+    /// Children already accepted keep what they have. Use the same key
+    /// across restarts for outstanding invites to stay good.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the head
+    /// head.require_invites(SecretKey::generate());
+    /// let token = head.invite(Role::Viewer, Duration::from_secs(3600))?;
+    /// let offer = head.offer().await?;
+    /// send_to_child(offer.get(), token);
+    ///
+    /// // on the child
+    /// let answer = child.connect_parent(&offer).await?.with_invite(&token);
+    /// send_to_head(answer.get());
+    /// ```
+    pub fn require_invites(&mut self, key: SecretKey) {
+        self.invite_key = Some(key);
+    }
+
+    /// Only accept invites bound with [Invite::bound_to] to the
+    /// fingerprints of the peer answering with them.
+    ///
+    /// # Notes
+    /// For swarms open to anyone, where a [BanList] alone is no use: a
+    /// banned peer can answer again with a new certificate, but not
+    /// with a new bound invite. An invite which is bound is always
+    /// checked; this refuses the ones which aren't. Needs
+    /// [Agent::require_invites] as well.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the child, with an identity_file so its fingerprints stay put
+    /// send_to_head(config.fingerprints()?);
+    ///
+    /// // on the head
+    /// head.require_bound_invites(true);
+    /// let token = head.issue(&Invite::new(Role::Editor, Duration::from_secs(600))
+    ///     .bound_to(&fingerprints_from_child))?;
+    /// ```
+    pub fn require_bound_invites(&mut self, bound: bool) {
+        self.bound_invites = bound;
+    }
+
+    /// Seal invites with a fresh key from now on, so outstanding ones stop working.
+    ///
+    /// # Notes
+    /// Children already accepted keep what they have.
+    ///
+    /// # Return
+    /// The new key, to give [Agent::require_invites] after a restart;
+    /// giving it the old one would make its invites good again.
+    pub fn rotate_invite_key(&mut self) -> Result<SecretKey> {
+        if self.invite_key.is_none() {
+            return Err(anyhow!("invites are not required, so there is no key to rotate"));
+        }
+        let key = SecretKey::generate();
+        self.invite_key = Some(key.clone());
+        self.audit(SecurityEvent::KeysRotated);
+
+        Ok(key)
+    }
+
+    /// the key invites are sealed with, if children need one to join
+    pub fn invite_key(&self) -> Option<&SecretKey> {
+        self.invite_key.as_ref()
+    }
+
+    /// an invite token for `role` to every document, good for `expiry`
+    pub fn invite(&self, role: Role, expiry: std::time::Duration) -> Result<String> {
+        self.issue(&Invite::new(role, expiry))
+    }
+
+    /// the token for an invite, such as one limited to some documents
+    pub fn issue(&self, invite: &Invite) -> Result<String> {
+        let key = self.invite_key.as_ref()
+            .ok_or(anyhow!("call require_invites before handing out invites"))?;
+        invite.seal(key)
+    }
+
+    /// what a child was invited to do, if it joined with an invite
+    pub fn grant(&self, peer: PeerId) -> Option<&Invite> {
+        self.grants.get(&peer)
+    }
+
+    /// Check a child may do something; [Agent::control] does so for every control message.
+    ///
+    /// # Notes
+    /// Children which joined without an invite, because none was
+    /// required, may do anything, as before invites existed.
+    pub fn authorize(&self, peer: PeerId, permission: Permission) -> Result<()> {
+        if !self.children.contains_key(&peer) {
+            return Err(anyhow!("no child with peer id {peer}"));
+        }
+        match self.grants.get(&peer) {
+            Some(grant) if !grant.role.allows(permission) => {
+                self.audit(SecurityEvent::Denied { peer, permission });
+                Err(anyhow!("peer {peer} is a {:?}, which may not {permission:?}", grant.role))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Act on a control message from a child, if its role allows it.
+    ///
+    /// # Notes
+    /// This is how a head takes control messages: each is checked with
+    /// [Agent::authorize] first, and refused if the sender may not do
+    /// it. Registry changes are applied to `registry`, which forgets
+    /// what kicked children advertised. A [ControlMessage::RotateKeys]
+    /// leaves the new key in [Agent::invite_key], to store as
+    /// [Agent::rotate_invite_key] explains.
+    ///
+    /// # Return
+    /// Messages to broadcast to every peer in response.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let message = ControlMessage::decode(&raw)?;
+    /// match agent.control(peer, message, &mut registry).await {
+    ///     Ok(replies) => for reply in replies {
+    ///         broadcast(reply.encode()?);
+    ///     },
+    ///     Err(err) => warn!("refused control message from peer {peer}: {err}"),
+    /// }
+    /// ```
+    pub async fn control(&mut self, peer: PeerId, message: ControlMessage,
+                         registry: &mut Registry) -> Result<Vec<RegistryMessage>> {
+        self.authorize(peer, message.permission())?;
+
+        match message {
+            ControlMessage::Registry(x) => return Ok(registry.handle(peer, x)),
+            ControlMessage::Kick { peer: target, ban: Some(reason) } => {
+                self.ban(target, &reason).await?;
+                registry.forget_peer(target);
+            }
+            ControlMessage::Kick { peer: target, ban: None } => {
+                self.kick(target).await?;
+                registry.forget_peer(target);
+            }
+            ControlMessage::RotateKeys => {
+                self.rotate_invite_key()?;
+            }
+        }
+
+        Ok(vec![])
+    }
+
+    /// Open a control stream to a peer on `channel`, secured as
+    /// [SecureControl] explains.
+    ///
+    /// # Notes
+    /// Both ends must call this at once: a child with [None] for its
+    /// parent, which starts the handshake, and the parent with the
+    /// child's [PeerId]. `channel` must have been offered to the child
+    /// with [Agent::connect_child_with]. A child must prove the
+    /// fingerprints it has in [Agent::fingerprints], and a parent the
+    /// ones in its offer. Nothing else should use `channel`, as the
+    /// session multiplexes it on [CONTROL_STREAM].
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the child
+    /// let control = agent.secure_control(None, "control").await?;
+    /// control.send_message(&ControlMessage::RotateKeys).await?;
+    /// // on the head
+    /// let control = agent.secure_control(Some(peer), "control").await?;
+    /// let message = control.recv_message().await.unwrap()?;
+    /// agent.control(peer, message, &mut registry).await?;
+    /// ```
+    pub async fn secure_control(&self, peer: Option<PeerId>, channel: &str) -> Result<SecureControl> {
+        let cnx = self.connection(peer)?;
+        let (local, remote) = cnx.fingerprints().await?;
+        if let Some(id) = peer {
+            let known = self.fingerprints(id).unwrap_or_default();
+            if known != remote.as_slice() {
+                return Err(anyhow!("peer {id} answered with {known:?}, but connected with {remote:?}"));
+            }
+        }
+
+        let mux = Multiplexer::new(cnx.clone(), channel, None);
+        let control = SecureControl::handshake(mux.stream(CONTROL_STREAM).await, peer.is_none(),
+                                               &self.noise_key, &local, &remote).await?;
+        Ok(control.with_mux(mux))
+    }
+
+    /// the public half of the key we prove ourselves with on secured control streams
+    pub fn noise_public_key(&self) -> &[u8] {
+        self.noise_key.public()
+    }
+
+    /// Record refused peers, bad invites, denied requests, bans, kicks and key rotations to `log`.
+    ///
+    /// # Notes
+    /// Channels already bound to children keep logging to the old log,
+    /// if there was one.
+    pub fn set_audit_log(&mut self, log: AuditLog) {
+        self.audit = Some(Arc::new(Mutex::new(log)));
+    }
+
+    fn audit(&self, event: SecurityEvent) {
+        record(self.audit.as_ref(), event);
+    }
+
+    /// listen for a goodbye from our parent, as [Agent::enable_goodbyes] does
+    fn hear_parent_goodbye(&mut self) {
+        let Some(ref parent) = self.parent else { return; };
+        let parent = parent.clone();
+        let parent_departed = self.parent_departed.clone();
+        self.listen("synch parent goodbye", async move {
+            let Some((_, raw)) = parent.recv(GOODBYE_CHANNEL).await else { return; };
+            let reason = GoodbyeReason::decode(&raw).unwrap_or_else(|err| {
+                GoodbyeReason::Other(format!("undecodable goodbye: {err}"))
+            });
+            debug!("parent said goodbye: {reason:?}");
+
+            if let Ok(mut x) = parent_departed.lock() {
+                *x = Some(reason);
+            }
+            let _ = parent.close().await;
+        });
+    }
+
+    /// spawn a worker which may stop on its own, without [Health::live] going false
+    fn listen<F>(&mut self, name: &str, work: F)
+    where F: std::future::Future<Output = ()> + Send + 'static {
+        let returned = Arc::new(AtomicBool::new(false));
+        let done = returned.clone();
+        let handle = spawn_named(name, async move {
+            work.await;
+            done.store(true, Ordering::Release);
+        });
+        self.listeners.push((handle, returned));
+    }
+
+    /// whether a child may see `channel`, and if so whether it may publish to it
+    fn admits(&self, peer: PeerId, channel: &str) -> Option<bool> {
+        match self.grants.get(&peer) {
+            Some(grant) => grant.can_see(channel).then_some(grant.role.can_write()),
+            None => Some(true),
+        }
+    }
+
+    /// make `parent` our parent, binding synced channels to it
+    fn set_parent(&mut self, parent: Option<Arc<Connection>>) {
+        self.parent = parent.clone();
+        self.parent_changes.send_replace(parent);
+    }
+
+    fn peer(&self, peer: PeerId) -> Result<&Arc<Connection>> {
+        self.children.get(&peer)
+            .ok_or(anyhow!("no child with peer id {peer}"))
+    }
+
+    /// the connection to a child, or to our parent if `None`
+    pub(crate) fn connection(&self, peer: Option<PeerId>) -> Result<&Arc<Connection>> {
+        match peer {
+            Some(id) => self.peer(id),
+            None => self.parent.as_ref().ok_or(anyhow!("this agent has no parent")),
+        }
+    }
+}
+
+/// create `channel` on a child, and forward what it sends into `hub`
+///
+/// # Notes
+/// the child stays in `links` until its queue dies.
+async fn bind(links: Links, hub: Sender<(Option<PeerId>, Vec<u8>)>, channel: String,
+              peer: PeerId, cnx: Arc<Connection>, writable: bool,
+              mut audit: Option<Arc<Mutex<AuditLog>>>) {
+    if let Err(err) = cnx.channel(&channel).await {
+        error!("failed to bind channel '{channel}' to peer {peer}: {err}");
+        return;
+    }
+    links.lock().await.insert(peer, cnx.clone());
+
+    let links = links.clone();
+    spawn_named(&format!("synch forward '{channel}' peer {peer}"), async move {
+        while let Some((_, data)) = cnx.recv(&channel).await {
+            if !writable {
+                debug!("dropped a message on '{channel}' from read-only peer {peer}");
+                // once is enough to know
+                record(audit.take().as_ref(),
+                       SecurityEvent::ReadOnlyWrite { peer, channel: channel.clone() });
+                continue;
+            }
+            if hub.send((Some(peer), data)).await.is_err() {
+                break;
+            }
+        }
+        links.lock().await.remove(&peer);
+    });
+}
+
+/// Bridge `channel` to whichever parent `parent` holds: send it what
+/// comes through `up`, and forward what it sends into `hub`.
+///
+/// # Notes
+/// without a parent, what comes through `up` is dropped.
+async fn upstream(mut parent: watch::Receiver<Option<Arc<Connection>>>, channel: String,
+                  mut up: UnboundedReceiver<Vec<u8>>, hub: Sender<(Option<PeerId>, Vec<u8>)>) {
+    let mut cnx = parent.borrow_and_update().clone();
+
+    loop {
+        tokio::select! {
+            changed = parent.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                cnx = parent.borrow_and_update().clone();
+            }
+            data = up.recv() => {
+                let Some(data) = data else { return; };
+                let Some(ref to) = cnx else { continue; };
+                if let Err(err) = to.send(&channel, data).await {
+                    debug!("failed to send on '{channel}' to our parent: {err}");
+                    cnx = None;
+                }
+            }
+            heard = async {
+                match cnx {
+                    Some(ref x) => x.recv(&channel).await,
+                    None => std::future::pending().await,
+                }
+            } => match heard {
+                Some((_, data)) => {
+                    if hub.send((None, data)).await.is_err() {
+                        return;
+                    }
+                }
+                // our parent is gone; wait for the next
+                None => cnx = None,
+            }
+        }
+    }
+}
+
+/// write `event` to the audit log, if there is one
+fn record(audit: Option<&Arc<Mutex<AuditLog>>>, event: SecurityEvent) {
+    warn!("security event: {event:?}");
+    let Some(log) = audit else { return; };
+    if let Err(err) = log.lock().map_err(|_| anyhow!("audit log poisoned"))
+        .and_then(|mut x| x.record(event)) {
+        error!("failed to write audit log: {err}");
+    }
+}
+
+/// send `data` on `channel` to every linked child but `except`,
+/// unlinking those whose queues are dead
+async fn send_all(links: &Links, channel: &str, except: Option<PeerId>, data: Vec<u8>) {
+    let targets: Vec<(PeerId, Arc<Connection>)> = links.lock().await
+        .iter()
+        .filter(|(id, _)| Some(**id) != except)
+        .map(|(id, cnx)| (*id, cnx.clone()))
+        .collect();
+
+    let sent = join_all(targets.iter()
+                        .map(|(_, x)| x.send(channel, data.clone()))).await;
+
+    let mut links = links.lock().await;
+    for ((id, _), res) in targets.iter().zip(sent) {
+        if res.is_err() {
+            links.remove(id);
+        }
+    }
+}
+
+/// send a [HandoffMessage] to `cnx`
+async fn tell(cnx: &Connection, message: &HandoffMessage) -> Result<()> {
+    cnx.send(HANDOFF_CHANNEL, message.encode()?).await
+}
+
+/// wait up to [HANDOFF_TIMEOUT] for a [HandoffMessage] from `cnx`
+async fn hear(cnx: &Connection) -> Result<HandoffMessage> {
+    let (_, raw) = tokio::time::timeout(HANDOFF_TIMEOUT, cnx.recv(HANDOFF_CHANNEL)).await
+        .map_err(|_| anyhow!("timed out during the handoff"))?
+        .ok_or(anyhow!("handoff channel closed"))?;
+    HandoffMessage::decode(&raw)
+}
+
+/// move child `peer` to the new head `heir`, carrying its offer and answer
+async fn redirect(heir: &Connection, peer: PeerId, cnx: &Connection) -> Result<()> {
+    cnx.channel(HANDOFF_CHANNEL).await?;
+    tell(heir, &HandoffMessage::OfferWanted { peer }).await?;
+
+    let offer = loop {
+        match hear(heir).await? {
+            HandoffMessage::Offer { peer: x, offer } if x == peer => break offer,
+            // a late offer for a child we already gave up on
+            HandoffMessage::Offer { .. } => continue,
+            other => return Err(anyhow!("expected an offer from the new head, got {other:?}")),
+        }
+    };
+    tell(cnx, &HandoffMessage::Redirect { offer }).await?;
+
+    let answer = match hear(cnx).await? {
+        HandoffMessage::Answer { answer } => answer,
+        other => return Err(anyhow!("expected an answer from peer {peer}, got {other:?}")),
+    };
+    tell(heir, &HandoffMessage::Answered { peer, answer }).await
+}
+
+/// recieve from whichever of `peers` yields a message on `channel` first
+async fn recv_any(peers: &[(PeerId, Arc<Connection>)],
+                  channel: &str) -> Option<(PeerId, Vec<u8>)> {
+    let mut pending: Vec<_> = peers
+        .iter()
+        .map(|(id, cnx)| Box::pin(async move { (*id, cnx.recv(channel).await) }))
+        .collect();
+
+    // drop dead queues until someone gives us something
+    while !pending.is_empty() {
+        let ((id, res), _, rest) = select_all(pending).await;
+        if let Some((_, data)) = res {
+            return Some((id, data));
+        }
+        pending = rest;
+    }
+
+    None
 }
 
 
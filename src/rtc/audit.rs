@@ -0,0 +1,164 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+
+use super::agent::PeerId;
+use super::invite::Permission;
+
+/// something security relevant an [super::Agent] saw
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SecurityEvent {
+    /// a peer was turned away by [super::Agent::accept]
+    Refused { fingerprints: Vec<String>, reason: String },
+    /// an invite token did not open: forged, altered, sealed with
+    /// another key, or expired
+    BadInvite { fingerprints: Vec<String>, reason: String },
+    /// a child asked for something its role does not allow
+    Denied { peer: PeerId, permission: Permission },
+    /// a read-only child published to a channel; logged once per channel
+    ReadOnlyWrite { peer: PeerId, channel: String },
+    /// a child was banned by [super::Agent::ban]
+    Banned { peer: PeerId, fingerprints: Vec<String>, reason: String },
+    /// a child was disconnected by [super::Agent::kick]
+    Kicked { peer: PeerId },
+    /// invites are sealed with a new key; see [super::Agent::rotate_invite_key]
+    KeysRotated,
+}
+
+/// one line of an [AuditLog]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// position in the log, from 0
+    pub seq: u64,
+    /// seconds since the unix epoch
+    pub time: u64,
+    pub event: SecurityEvent,
+    /// hash of the record before, in a chained log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev: Option<String>,
+    /// hash of this record, without this field, in a chained log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl AuditRecord {
+    fn digest(&self) -> Result<String> {
+        let unhashed = AuditRecord { hash: None, ..self.clone() };
+        Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(&unhashed)?)))
+    }
+}
+
+/// Append-only log of [SecurityEvent]s, one JSON record per line.
+///
+/// # Notes
+/// In a chained log every record carries the hash of the one before,
+/// so editing, dropping or reordering records breaks the chain from
+/// that point on, which [AuditLog::verify] finds. Chaining shows
+/// tampering; it can't stop someone who can write the file from
+/// rewriting all of it, so ship the log somewhere else too if that
+/// matters.
+///
+/// # Examples
+///
+/// ```ignore
+/// agent.set_audit_log(AuditLog::open("/var/log/synch/audit.jsonl", true)?);
+/// // ... later, investigating
+/// let records = AuditLog::verify("/var/log/synch/audit.jsonl")?;
+/// ```
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    chained: bool,
+    next_seq: u64,
+    last_hash: Option<String>,
+}
+
+impl AuditLog {
+    /// Append to the log at `path`, which is created on the first record.
+    ///
+    /// # Notes
+    /// A chained log picks up the chain where the file ends.
+    pub fn open(path: impl AsRef<Path>, chained: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let last = match fs::read_to_string(path) {
+            Ok(text) => match text.lines().rfind(|x| !x.trim().is_empty()) {
+                Some(line) => Some(serde_json::from_str::<AuditRecord>(line)
+                    .map_err(|err| anyhow!("failed to read audit log '{}': {err}", path.display()))?),
+                None => None,
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(AuditLog {
+            path: path.to_owned(),
+            chained,
+            next_seq: last.as_ref().map_or(0, |x| x.seq + 1),
+            last_hash: last.and_then(|x| x.hash),
+        })
+    }
+
+    /// Write an event to the end of the log.
+    pub fn record(&mut self, event: SecurityEvent) -> Result<()> {
+        let mut record = AuditRecord {
+            seq: self.next_seq,
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            event,
+            prev: None,
+            hash: None,
+        };
+        if self.chained {
+            record.prev = self.last_hash.clone();
+            record.hash = Some(record.digest()?);
+        }
+
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        OpenOptions::new().create(true).append(true).open(&self.path)?
+            .write_all(line.as_bytes())?;
+
+        self.next_seq += 1;
+        self.last_hash = record.hash;
+        Ok(())
+    }
+
+    /// Read every record of a log, checking its chain.
+    ///
+    /// # Notes
+    /// Fails at the first record which is out of sequence, or whose hashes
+    /// don't match the records before it. Unchained records are only
+    /// checked for sequence, and may not follow chained ones.
+    pub fn verify(path: impl AsRef<Path>) -> Result<Vec<AuditRecord>> {
+        let text = fs::read_to_string(path.as_ref())?;
+        let mut records: Vec<AuditRecord> = vec![];
+
+        for (line, raw) in text.lines().enumerate().filter(|(_, x)| !x.trim().is_empty()) {
+            let record: AuditRecord = serde_json::from_str(raw)
+                .map_err(|err| anyhow!("line {}: {err}", line + 1))?;
+            let last = records.last();
+
+            if record.seq != last.map_or(0, |x| x.seq + 1) {
+                return Err(anyhow!("line {}: record {} is out of sequence", line + 1, record.seq));
+            }
+            if record.hash.is_none() && last.is_some_and(|x| x.hash.is_some()) {
+                return Err(anyhow!("line {}: record {} breaks the chain", line + 1, record.seq));
+            }
+            if let Some(ref hash) = record.hash {
+                if record.prev != last.and_then(|x| x.hash.clone()) {
+                    return Err(anyhow!("line {}: record {} does not follow the one before",
+                                       line + 1, record.seq));
+                }
+                if *hash != record.digest()? {
+                    return Err(anyhow!("line {}: record {} was altered", line + 1, record.seq));
+                }
+            }
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+}
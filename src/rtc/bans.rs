@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+
+/// why and when a peer was banned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    pub reason: String,
+    /// seconds since the unix epoch
+    pub since: u64,
+}
+
+/// Peers refused by an [super::Agent], by DTLS certificate fingerprint.
+///
+/// # Notes
+/// A peer's fingerprint is the `algorithm value` pair from the
+/// `a=fingerprint` line of its answer, as shown by
+/// [super::inspect_offer]. It identifies the peer only for as long as
+/// the peer chooses to keep the same certificate: an
+/// [super::AgentConfig::identity_file] pins our own certificate, not
+/// anyone else's, so a banned peer can come back with a new one. A ban
+/// holds only where peers can't join under a certificate of their
+/// choosing, such as swarms requiring invites bound to fingerprints;
+/// see [super::Agent::require_bound_invites].
+///
+/// A list opened from a file is written back on every change.
+///
+/// # Examples
+///
+/// ```ignore
+/// let bans = BanList::open("/var/lib/synch/bans.json")?;
+/// agent.set_bans(bans).await;
+/// // ... a peer misbehaves
+/// agent.ban(peer, "spamming the chat").await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BanList {
+    bans: BTreeMap<String, Ban>,
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    /// an empty list, kept only in memory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a list kept in the JSON file at `path`, which is created on the first ban
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bans = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map_err(|err| anyhow!("failed to read ban list '{}': {err}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(BanList { bans, path: Some(path.to_owned()) })
+    }
+
+    /// Ban a fingerprint, replacing any earlier reason.
+    pub fn ban(&mut self, fingerprint: &str, reason: &str) -> Result<()> {
+        let since = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.bans.insert(fingerprint.to_owned(), Ban { reason: reason.to_owned(), since });
+        self.save()
+    }
+
+    /// Ban a fingerprint as some other list did, keeping its reason and time.
+    pub fn restore(&mut self, fingerprint: &str, ban: Ban) -> Result<()> {
+        self.bans.insert(fingerprint.to_owned(), ban);
+        self.save()
+    }
+
+    /// Lift a ban.
+    ///
+    /// # Return
+    /// Whether the fingerprint was banned.
+    pub fn unban(&mut self, fingerprint: &str) -> Result<bool> {
+        let banned = self.bans.remove(fingerprint).is_some();
+        if banned {
+            self.save()?;
+        }
+        Ok(banned)
+    }
+
+    pub fn get(&self, fingerprint: &str) -> Option<&Ban> {
+        self.bans.get(fingerprint)
+    }
+
+    /// whether any of a peer's fingerprints is banned
+    pub fn is_banned(&self, fingerprints: &[String]) -> bool {
+        fingerprints.iter().any(|x| self.bans.contains_key(x))
+    }
+
+    pub fn list(&self) -> Vec<(&str, &Ban)> {
+        self.bans.iter().map(|(x, ban)| (x.as_str(), ban)).collect()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(ref path) = self.path else { return Ok(()); };
+
+        // write then rename, so a crash never leaves half a list
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.bans)?)?;
+        fs::rename(&tmp, path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use futures::future::{try_join, try_join_all};
+
+use super::{MAX_MSG_SIZE_BYTES, DEFAULT_QUEUE_SIZE};
+use super::connection::Connection;
+use super::utils::{get_api, get_config_from_stun_servers};
+
+/// how long a [Loopback] may take to connect
+pub const LOOPBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Two connected [Connection]s within this process, for measuring the
+/// rtc path without a network.
+///
+/// # Notes
+/// The pair uses host candidates only, so nothing leaves the machine;
+/// numbers include the whole stack, from the write queue through DTLS
+/// and SCTP to the read queue. Build with `--release` for numbers worth
+/// comparing.
+///
+/// # Examples
+///
+/// ```ignore
+/// let pair = Loopback::new(4, Some(64)).await?;
+/// let elapsed = pair.throughput(1024, 10_000).await?;
+/// pair.close().await?;
+/// ```
+pub struct Loopback {
+    pub head: Connection,
+    pub child: Connection,
+    /// the data channels opened between them
+    pub channels: Vec<String>,
+}
+
+impl Loopback {
+    /// connect a pair with `channels` data channels of `queue_size` messages each
+    pub async fn new(channels: usize, queue_size: Option<usize>) -> Result<Loopback> {
+        let api = get_api()?;
+        let config = get_config_from_stun_servers(&[]);
+        let mut head = Connection::new(Arc::new(api.new_peer_connection(config.clone()).await?), queue_size);
+        let mut child = Connection::new(Arc::new(api.new_peer_connection(config).await?), queue_size);
+
+        let channels: Vec<String> = (0..channels.max(1)).map(|x| format!("synch-bench-{x}")).collect();
+        for name in channels.iter() {
+            head.channel(name).await?;
+        }
+        let offer = head.offer().await?;
+        let answer = child.answer(&offer).await?;
+        head.accept(&answer).await?;
+
+        tokio::time::timeout(LOOPBACK_TIMEOUT, async {
+            while !(head.is_connected() && child.is_connected()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.map_err(|_| anyhow!("loopback did not connect within {LOOPBACK_TIMEOUT:?}"))?;
+
+        Ok(Loopback { head, child, channels })
+    }
+
+    /// Send `messages` messages of `message_bytes` from head to child,
+    /// spread over every channel.
+    ///
+    /// # Return
+    /// How long it took for the child to read them all.
+    pub async fn throughput(&self, message_bytes: usize, messages: usize) -> Result<Duration> {
+        check_size(message_bytes)?;
+        let share = |i: usize| messages / self.channels.len() + usize::from(i < messages % self.channels.len());
+
+        let start = Instant::now();
+        let sends = self.channels.iter().enumerate().map(|(i, name)| async move {
+            for _ in 0..share(i) {
+                self.head.send(name, vec![0u8; message_bytes]).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        });
+        let recvs = self.channels.iter().enumerate().map(|(i, name)| async move {
+            for _ in 0..share(i) {
+                self.child.recv(name).await.ok_or(anyhow!("channel '{name}' closed"))?;
+            }
+            Ok::<_, anyhow::Error>(())
+        });
+        try_join(try_join_all(sends), try_join_all(recvs)).await?;
+
+        Ok(start.elapsed())
+    }
+
+    /// Bounce `rounds` messages of `message_bytes` off the child, one at a time.
+    ///
+    /// # Return
+    /// The round trip time of each.
+    pub async fn round_trips(&self, message_bytes: usize, rounds: usize) -> Result<Vec<Duration>> {
+        check_size(message_bytes)?;
+        let name = &self.channels[0];
+
+        let echo = async {
+            for _ in 0..rounds {
+                let (_, data) = self.child.recv(name).await.ok_or(anyhow!("channel '{name}' closed"))?;
+                self.child.send(name, data).await?;
+            }
+            Ok::<_, anyhow::Error>(())
+        };
+        let ping = async {
+            let mut times = Vec::with_capacity(rounds);
+            for _ in 0..rounds {
+                let start = Instant::now();
+                self.head.send(name, vec![0u8; message_bytes]).await?;
+                self.head.recv(name).await.ok_or(anyhow!("channel '{name}' closed"))?;
+                times.push(start.elapsed());
+            }
+            Ok(times)
+        };
+
+        Ok(try_join(echo, ping).await?.1)
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.head.close().await?;
+        self.child.close().await
+    }
+}
+
+fn check_size(message_bytes: usize) -> Result<()> {
+    match message_bytes {
+        1..=MAX_MSG_SIZE_BYTES => Ok(()),
+        _ => Err(anyhow!("messages must be 1 to {MAX_MSG_SIZE_BYTES} bytes, not {message_bytes}")),
+    }
+}
+
+/// what [run_bench] measures
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub message_sizes: Vec<usize>,
+    pub queue_sizes: Vec<usize>,
+    pub channel_counts: Vec<usize>,
+    /// messages sent to measure throughput
+    pub messages: usize,
+    /// round trips made to measure latency
+    pub rounds: usize,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            message_sizes: vec![64, 512, MAX_MSG_SIZE_BYTES],
+            queue_sizes: vec![1, DEFAULT_QUEUE_SIZE, 256],
+            channel_counts: vec![1, 4],
+            messages: 2000,
+            rounds: 200,
+        }
+    }
+}
+
+/// the numbers for one combination of a [BenchConfig]
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub message_bytes: usize,
+    pub queue_size: usize,
+    pub channels: usize,
+    pub messages: usize,
+    /// time to send and read every message
+    pub elapsed: Duration,
+    /// median round trip
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+impl BenchResult {
+    pub fn messages_per_sec(&self) -> f64 {
+        self.messages as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.messages_per_sec() * self.message_bytes as f64
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>6} B  queue {:>4}  channels {:>2}  {:>9.0} msg/s  {:>8.2} MB/s  rtt p50 {:?} p99 {:?}",
+               self.message_bytes, self.queue_size, self.channels, self.messages_per_sec(),
+               self.bytes_per_sec() / 1e6, self.p50, self.p99)
+    }
+}
+
+/// Measure throughput and latency over a [Loopback] for every
+/// combination in `config`, passing each result to `progress` as it comes.
+///
+/// # Notes
+/// A fresh pair is connected for each queue size and channel count.
+pub async fn run_bench<F: FnMut(&BenchResult)>(config: &BenchConfig, mut progress: F) -> Result<Vec<BenchResult>> {
+    let mut results = vec![];
+    for &queue_size in config.queue_sizes.iter() {
+        for &channels in config.channel_counts.iter() {
+            let pair = Loopback::new(channels, Some(queue_size)).await?;
+            for &message_bytes in config.message_sizes.iter() {
+                let elapsed = pair.throughput(message_bytes, config.messages).await?;
+                let mut times = pair.round_trips(message_bytes, config.rounds.max(1)).await?;
+                times.sort();
+
+                let result = BenchResult {
+                    message_bytes,
+                    queue_size,
+                    channels: pair.channels.len(),
+                    messages: config.messages,
+                    elapsed,
+                    p50: times[times.len() / 2],
+                    p99: times[(times.len() * 99 / 100).min(times.len() - 1)],
+                };
+                progress(&result);
+                results.push(result);
+            }
+            pair.close().await?;
+        }
+    }
+
+    Ok(results)
+}
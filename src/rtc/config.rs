@@ -0,0 +1,349 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use anyhow::{Result, anyhow};
+use base64::prelude::{BASE64_URL_SAFE, BASE64_URL_SAFE_NO_PAD, Engine as _};
+use serde::{Serialize, Deserialize};
+use serde_json::{Value, Map};
+use webrtc::peer_connection::certificate::RTCCertificate;
+
+use super::{DEFAULT_STUN_SERVERS, DEFAULT_QUEUE_SIZE, DEFAULT_MAX_BACKLOG};
+use super::storage::{SecretKey, SnapshotStore, Retention};
+use super::keystore::{Passphrase, is_sealed, write_private};
+
+/// prefix of every environment variable read by [AgentConfig::with_env]
+pub const ENV_PREFIX: &str = "SYNCH_";
+
+/// how one document is handled
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentPolicy {
+    pub retention: Retention,
+}
+
+/// everything needed to build an [super::Agent]
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut agent = Agent::new(AgentConfig {
+///     queue_size: 64,
+///     ..Default::default()
+/// })?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// STUN servers used to find our public address
+    pub stun_servers: Vec<String>,
+    /// messages buffered per data channel, each way
+    pub queue_size: usize,
+    /// most queued messages, over all connections, while still ready;
+    /// see [super::Agent::health]
+    pub max_backlog: usize,
+    /// where document snapshots are kept; see [AgentConfig::snapshot_store]
+    pub snapshot_dir: Option<PathBuf>,
+    /// file holding the key snapshots are encrypted with; see [AgentConfig::secret_key]
+    pub key_file: Option<PathBuf>,
+    /// file holding the certificate every connection presents; see [AgentConfig::identity]
+    pub identity_file: Option<PathBuf>,
+    /// per document settings, by document name
+    pub documents: BTreeMap<String, DocumentPolicy>,
+    /// most verbose level logged, such as `info`; see [AgentConfig::apply_log_level]
+    pub log_level: Option<String>,
+    /// unlocks `key_file` and `identity_file` if they were sealed with it;
+    /// never read from a file, only from `SYNCH_PASSPHRASE` or code
+    #[serde(skip)]
+    pub passphrase: Option<Passphrase>,
+}
+
+impl AgentConfig {
+    /// the default configuration, but with other STUN servers
+    pub fn with_stun_servers(stun_servers: &[&str]) -> Self {
+        AgentConfig {
+            stun_servers: stun_servers.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Read a configuration file; anything it leaves out is the default.
+    ///
+    /// # Notes
+    /// Files ending in `.json` are read as JSON, and anything else as
+    /// TOML. Only the TOML this configuration needs is understood:
+    /// tables, and keys set to strings, integers, booleans or one-line
+    /// arrays of those.
+    ///
+    /// # Examples
+    ///
+    /// ```toml
+    /// stun_servers = ["stun:stun.example.com:3478"]
+    /// queue_size = 64
+    /// snapshot_dir = "/var/lib/synch"
+    /// key_file = "/etc/synch/key"
+    /// identity_file = "/etc/synch/identity.pem"
+    ///
+    /// [documents.scratch]
+    /// retention = "Ephemeral"
+    /// ```
+    ///
+    /// ```ignore
+    /// let config = AgentConfig::from_file("synch.toml")?.with_env()?;
+    /// ```
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read '{}': {err}", path.display()))?;
+
+        let value = if path.extension().is_some_and(|x| x == "json") {
+            serde_json::from_str(&text)?
+        } else {
+            parse_toml(&text)?
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// the default configuration, overridden by the environment; see [AgentConfig::with_env]
+    pub fn from_env() -> Result<Self> {
+        AgentConfig::default().with_env()
+    }
+
+    /// Override settings from `SYNCH_*` environment variables.
+    ///
+    /// # Notes
+    /// Every top level setting can be set by its name in capitals, such
+    /// as `SYNCH_QUEUE_SIZE=64`; lists are comma separated. Document
+    /// policies can only be set in a file, and the passphrase only here.
+    pub fn with_env(mut self) -> Result<Self> {
+        let var = |name: &str| std::env::var(format!("{ENV_PREFIX}{name}")).ok();
+        let number = |name: &str, value: String| value.trim().parse()
+            .map_err(|err| anyhow!("{ENV_PREFIX}{name}='{value}' is not a number: {err}"));
+
+        if let Some(x) = var("STUN_SERVERS") {
+            self.stun_servers = x.split(',').map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty()).collect();
+        }
+        if let Some(x) = var("QUEUE_SIZE") {
+            self.queue_size = number("QUEUE_SIZE", x)?;
+        }
+        if let Some(x) = var("MAX_BACKLOG") {
+            self.max_backlog = number("MAX_BACKLOG", x)?;
+        }
+        if let Some(x) = var("SNAPSHOT_DIR") {
+            self.snapshot_dir = Some(x.into());
+        }
+        if let Some(x) = var("KEY_FILE") {
+            self.key_file = Some(x.into());
+        }
+        if let Some(x) = var("IDENTITY_FILE") {
+            self.identity_file = Some(x.into());
+        }
+        if let Some(x) = var("LOG_LEVEL") {
+            self.log_level = Some(x);
+        }
+        if let Some(x) = var("PASSPHRASE") {
+            self.passphrase = Some(Passphrase::new(x));
+        }
+
+        Ok(self)
+    }
+
+    /// Set the most verbose level logged to [AgentConfig::log_level], if set.
+    ///
+    /// # Notes
+    /// This can only narrow what the installed logger was built to
+    /// allow; with env_logger, that is what `RUST_LOG` lets through.
+    pub fn apply_log_level(&self) -> Result<()> {
+        let Some(ref level) = self.log_level else { return Ok(()); };
+        let level: log::LevelFilter = level.parse()
+            .map_err(|_| anyhow!("'{level}' is not a log level"))?;
+        log::set_max_level(level);
+
+        Ok(())
+    }
+
+    /// `data` read from `path`, unlocked with [AgentConfig::passphrase] if it was sealed
+    fn unlock(&self, path: &Path, data: Vec<u8>) -> Result<Vec<u8>> {
+        if !is_sealed(&data) {
+            return Ok(data);
+        }
+
+        self.passphrase.as_ref()
+            .ok_or(anyhow!("'{}' is sealed; set {ENV_PREFIX}PASSPHRASE to unlock it", path.display()))?
+            .open(&data)
+            .map_err(|err| anyhow!("failed to unlock '{}': {err}", path.display()))
+    }
+
+    /// Read the key in [AgentConfig::key_file], if there is one.
+    ///
+    /// # Notes
+    /// The file holds the 32 key bytes, either raw or base64url encoded,
+    /// or either of those sealed with [Passphrase::lock_file]. A plain
+    /// file is only as safe as its permissions, so keep it readable only
+    /// by the user running the agent, or seal it.
+    pub fn secret_key(&self) -> Result<Option<SecretKey>> {
+        let Some(ref path) = self.key_file else { return Ok(None); };
+        let data = fs::read(path)
+            .map_err(|err| anyhow!("failed to read key file '{}': {err}", path.display()))?;
+        let data = self.unlock(path, data)?;
+
+        let key = match <[u8; 32]>::try_from(data.as_slice()) {
+            Ok(x) => x,
+            Err(_) => {
+                let text = String::from_utf8(data)?;
+                let text = text.trim();
+                let bytes = BASE64_URL_SAFE_NO_PAD.decode(text)
+                    .or_else(|_| BASE64_URL_SAFE.decode(text))?;
+                <[u8; 32]>::try_from(bytes.as_slice())
+                    .map_err(|_| anyhow!("key in '{}' is not 32 bytes", path.display()))?
+            }
+        };
+
+        Ok(Some(SecretKey::new(key)))
+    }
+
+    /// Read the certificate in [AgentConfig::identity_file], if there is
+    /// one, making it first if the file does not exist yet.
+    ///
+    /// # Notes
+    /// Peers know us by this certificate's fingerprint, which is what
+    /// their [super::BanList]s are keyed by, and invites can be bound to
+    /// with [super::Invite::bound_to]. Without one, every connection
+    /// gets a new certificate, and so a new fingerprint.
+    /// The file holds the private key as well, and is made readable only
+    /// by the user running the agent; with a [AgentConfig::passphrase]
+    /// it is also sealed, and a sealed file is unlocked with it.
+    pub fn identity(&self) -> Result<Option<RTCCertificate>> {
+        let Some(ref path) = self.identity_file else { return Ok(None); };
+
+        match fs::read(path) {
+            Ok(data) => {
+                let pem = String::from_utf8(self.unlock(path, data)?)
+                    .map_err(|_| anyhow!("identity '{}' is not PEM", path.display()))?;
+                return RTCCertificate::from_pem(&pem).map(Some)
+                    .map_err(|err| anyhow!("failed to read identity '{}': {err}", path.display()));
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(anyhow!("failed to read identity '{}': {err}", path.display())),
+        }
+
+        let key = rcgen::KeyPair::generate_for(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+        let certificate = RTCCertificate::from_key_pair(key)?;
+        let pem = certificate.serialize_pem().into_bytes();
+        let data = match self.passphrase {
+            Some(ref passphrase) => passphrase.seal(&pem)?,
+            None => pem,
+        };
+        write_private(path, &data)
+            .map_err(|err| anyhow!("failed to create identity: {err}"))?;
+
+        Ok(Some(certificate))
+    }
+
+    /// The fingerprints peers see for [AgentConfig::identity], as
+    /// `algorithm value`; empty without an identity file.
+    pub fn fingerprints(&self) -> Result<Vec<String>> {
+        Ok(self.identity()?
+           .map(|x| x.get_fingerprints().into_iter()
+                // as an SDP gives them, in upper case
+                .map(|f| format!("{} {}", f.algorithm, f.value.to_uppercase()))
+                .collect())
+           .unwrap_or_default())
+    }
+
+    /// Open a [SnapshotStore] in [AgentConfig::snapshot_dir], if there is
+    /// one, with the key and document retentions set here.
+    pub fn snapshot_store(&self) -> Result<Option<SnapshotStore>> {
+        let Some(ref dir) = self.snapshot_dir else { return Ok(None); };
+
+        let mut store = SnapshotStore::new(dir, self.secret_key()?)?;
+        for (name, policy) in self.documents.iter() {
+            store.set_retention(name, policy.retention)?;
+        }
+
+        Ok(Some(store))
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            stun_servers: DEFAULT_STUN_SERVERS.iter().map(|x| x.to_string()).collect(),
+            queue_size: DEFAULT_QUEUE_SIZE,
+            max_backlog: DEFAULT_MAX_BACKLOG,
+            snapshot_dir: None,
+            key_file: None,
+            identity_file: None,
+            documents: BTreeMap::new(),
+            log_level: None,
+            passphrase: None,
+        }
+    }
+}
+
+/// read the TOML described in [AgentConfig::from_file] into a json value
+fn parse_toml(text: &str) -> Result<Value> {
+    let mut root = Map::new();
+    let mut table: Vec<String> = vec![];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        let malformed = || anyhow!("line {}: cannot read '{line}'", number + 1);
+
+        if line.is_empty() {
+            continue;
+        } else if let Some(name) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+            table = name.split('.').map(|x| x.trim().trim_matches('"').to_owned()).collect();
+            if table.iter().any(|x| x.is_empty()) {
+                return Err(malformed());
+            }
+        } else {
+            let (key, value) = line.split_once('=').ok_or_else(malformed)?;
+            let key = key.trim().trim_matches('"').to_owned();
+            let value = parse_toml_value(value.trim()).ok_or_else(malformed)?;
+
+            let mut map = &mut root;
+            for name in table.iter() {
+                map = map.entry(name.clone()).or_insert_with(|| Value::Object(Map::new()))
+                    .as_object_mut().ok_or_else(malformed)?;
+            }
+            map.insert(key, value);
+        }
+    }
+
+    Ok(Value::Object(root))
+}
+
+fn parse_toml_value(value: &str) -> Option<Value> {
+    if let Some(items) = value.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        return items.split(',')
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(parse_toml_value)
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array);
+    }
+    if let Some(text) = value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        return Some(Value::String(text.to_owned()));
+    }
+
+    match value {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => value.replace('_', "").parse::<i64>().ok().map(Value::from),
+    }
+}
+
+/// a line without its `#` comment, leaving any `#` in a string alone
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
@@ -1,12 +1,14 @@
 use bytes::Bytes;
 use std::pin::Pin;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use std::sync::Arc;
-use tokio::sync::{Mutex, Notify};
+use tokio::sync::{Mutex, Notify, oneshot};
 use std::future::Future;
-use std::collections::HashMap;
-use tokio::sync::mpsc::{Sender, Receiver};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{Sender, Receiver, UnboundedSender, UnboundedReceiver};
 use base64::prelude::{BASE64_URL_SAFE, Engine as _};
+use sha2::{Sha256, Digest};
 use webrtc::{data_channel::RTCDataChannel,
              data::data_channel::DataChannel,
              peer_connection::{peer_connection_state::RTCPeerConnectionState,
@@ -14,7 +16,13 @@ use webrtc::{data_channel::RTCDataChannel,
                                RTCPeerConnection}};
 use log::{error, debug};
 
-use crate::MAX_MSG_SIZE_BYTES;
+use super::MAX_MSG_SIZE_BYTES;
+use super::inspect::fingerprints;
+use super::utils::spawn_named;
+use super::headers::Message;
+use super::trace::{TraceLabel, Direction, trace_frame};
+use super::watermark::{QueueMonitor, QueueDirection, QueueDepth, QueueStall, QueueStuck,
+                       QueueExpiry, Traffic};
 
 #[derive(Debug)]
 pub enum ConnectionType {
@@ -22,7 +30,72 @@ pub enum ConnectionType {
     CHILD
 }
 
+/// longest a [Receipt] waits on the peer's acks before giving up
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
 type QueueTuple = (String, Vec<u8>);
+type ReadQueue = Arc<Mutex<Receiver<QueueTuple>>>;
+// oldest first; a channel has more than one only while a resize drains
+type ReadQueues = Arc<Mutex<HashMap<String, VecDeque<ReadQueue>>>>;
+type WriteQueues = Arc<Mutex<HashMap<String, Sender<Outgoing>>>>;
+type Monitor = Arc<QueueMonitor>;
+type Ended = Arc<Mutex<HashSet<String>>>;
+// where to hand a channel's workers their replacement queues
+type Resizers = Arc<Mutex<HashMap<String, (UnboundedSender<Sender<QueueTuple>>,
+                                           UnboundedSender<Receiver<Outgoing>>)>>>;
+
+/// proof that a message crossed its data channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub channel: String,
+    /// bytes written
+    pub bytes: usize,
+    /// how long the message waited in the write queue
+    pub queued: Duration,
+}
+
+/// what [Connection::recv_checked] got from a channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Received {
+    Data(Vec<u8>),
+    /// the peer called [Connection::end_stream]; nothing more will come
+    StreamEnded,
+    /// the channel or connection died
+    Closed,
+}
+
+/// what the write worker does with an [Outgoing]
+#[derive(PartialEq, Eq)]
+enum Frame {
+    Data,
+    /// send the end of stream marker instead
+    End,
+    /// write nothing; the receipt says everything queued before is written
+    Flush,
+}
+
+/// a message waiting in a write queue
+struct Outgoing {
+    channel: String,
+    data: Vec<u8>,
+    frame: Frame,
+    enqueued: Instant,
+    /// dropped instead of sent if still queued after this
+    deadline: Option<Instant>,
+    receipt: Option<oneshot::Sender<Result<Receipt>>>,
+}
+
+/// every queue of a connection, by channel name
+#[derive(Clone)]
+struct Queues {
+    read: ReadQueues,
+    write: WriteQueues,
+    resizers: Resizers,
+    /// channels we ended with [Connection::end_stream]
+    ended_here: Ended,
+    /// channels the peer ended
+    ended_there: Ended
+}
 
 pub struct Connection {
     cnx: Arc<RTCPeerConnection>,
@@ -32,9 +105,10 @@ pub struct Connection {
 
     // the first mutex is for insertions to the map; the second mutex
     // is for the reciever itself, blocking each data channel queue 
-    read_queues: Arc<Mutex<HashMap<String, Arc<Mutex<Receiver<QueueTuple>>>>>>,
-    write_queues: Arc<Mutex<HashMap<String, Sender<QueueTuple>>>>,
-    queue_size: usize
+    queues: Queues,
+    queue_size: usize,
+    trace: TraceLabel,
+    monitor: Monitor
 }
 
 impl Connection {
@@ -47,12 +121,153 @@ impl Connection {
             cnx: connection,
             cnx_type: None,
             new_channel_notify: Arc::new(Notify::new()),
-            read_queues: Arc::new(Mutex::new(HashMap::new())),
-            write_queues: Arc::new(Mutex::new(HashMap::new())),
-            queue_size: qs
+            queues: Queues {
+                read: Arc::new(Mutex::new(HashMap::new())),
+                write: Arc::new(Mutex::new(HashMap::new())),
+                resizers: Arc::new(Mutex::new(HashMap::new())),
+                ended_here: Arc::new(Mutex::new(HashSet::new())),
+                ended_there: Arc::new(Mutex::new(HashSet::new())),
+            },
+            queue_size: qs,
+            trace: Arc::new(std::sync::RwLock::new(None)),
+            monitor: Arc::new(QueueMonitor::new())
+        }
+    }
+
+    /// whether the peer connection is up
+    pub fn is_connected(&self) -> bool {
+        self.cnx.connection_state() == RTCPeerConnectionState::Connected
+    }
+
+    /// Short numeric code to compare with the peer, out loud or side by side.
+    ///
+    /// # Notes
+    /// Both ends derive the same six digits from the DTLS certificate
+    /// fingerprints of the connection. Someone who rewrote the offer or
+    /// answer in transit to sit in the middle holds different
+    /// certificates on each side, so the two codes differ. Only
+    /// meaningful once connected, as DTLS has then proven the peer
+    /// holds its certificate.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// println!("confirm your friend sees {}", cnx.pairing_code().await?);
+    /// ```
+    pub async fn pairing_code(&self) -> Result<String> {
+        if !self.is_connected() {
+            return Err(anyhow!("pairing codes are only meaningful once connected"));
+        }
+        let (local, remote) = self.fingerprints().await?;
+
+        // sorted, so both ends hash the same thing
+        let mut sides = [local.join(","), remote.join(",")];
+        sides.sort();
+        let digest = Sha256::digest(sides.join("|").as_bytes());
+        let code = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) % 1_000_000;
+
+        Ok(format!("{code:06}"))
+    }
+
+    /// our and the peer's DTLS certificate fingerprints, from the session descriptions
+    pub(crate) async fn fingerprints(&self) -> Result<(Vec<String>, Vec<String>)> {
+        let (Some(local), Some(remote)) = (self.cnx.local_description().await,
+                                           self.cnx.remote_description().await) else {
+            return Err(anyhow!("connection has no session descriptions"));
+        };
+
+        Ok((fingerprints(&local.sdp), fingerprints(&remote.sdp)))
+    }
+
+    /// how full every read and write queue is now, and at most has been
+    pub fn queue_depths(&self) -> Vec<QueueDepth> {
+        self.monitor.depths()
+    }
+
+    /// bytes moved over this connection so far
+    pub fn traffic(&self) -> Traffic {
+        self.monitor.traffic()
+    }
+
+    /// get a [QueueStall] whenever a queue stays full longer than the stall threshold
+    pub fn subscribe_stalls(&self) -> tokio::sync::mpsc::UnboundedReceiver<QueueStall> {
+        self.monitor.subscribe()
+    }
+
+    /// Find queues which have held messages, with none taken, for at least `after`.
+    ///
+    /// # Notes
+    /// Progress is measured between calls, so call this periodically,
+    /// more often than `after`; a queue is only reported once it has
+    /// been seen stuck across calls spanning `after`.
+    pub fn watchdog(&self, after: std::time::Duration) -> Vec<QueueStuck> {
+        self.monitor.stuck(after)
+    }
+
+    /// how long a queue may stay full before it is reported as stalled
+    pub fn set_stall_threshold(&self, threshold: std::time::Duration) {
+        self.monitor.set_stall_threshold(threshold);
+    }
+
+    /// Swap both queues of `channel` for ones holding `capacity` messages.
+    ///
+    /// # Notes
+    /// Messages already in the old queues are still delivered, in order,
+    /// before anything sent through the new ones. The new queues start
+    /// with a fresh high watermark in [Connection::queue_depths].
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// for depth in cnx.queue_depths() {
+    ///     if depth.high_watermark == depth.capacity {
+    ///         cnx.resize_queue(&depth.channel, depth.capacity * 2).await?;
+    ///     }
+    /// }
+    /// ```
+    pub async fn resize_queue(&self, channel: &str, capacity: usize) -> Result<()> {
+        if capacity == 0 {
+            return Err(anyhow!("queue capacity must be positive"));
+        }
+
+        // held throughout, so resizes of one channel happen one at a time
+        let resizers = self.queues.resizers.lock().await;
+        let (read, write) = resizers.get(channel)
+            .ok_or(anyhow!("no data channel named '{channel}'"))?;
+
+        // the write worker takes up the new queue once the old one drains,
+        // which happens when the last old sender (ours, in the map) is gone
+        let (snd, recv) = tokio::sync::mpsc::channel(capacity);
+        write.send(recv).map_err(|_| anyhow!("data channel '{channel}' is closed"))?;
+        self.monitor.watch(channel, QueueDirection::Write, &snd);
+        self.queues.write.lock().await.insert(channel.to_owned(), snd);
+
+        // the read worker drops the old sender when it takes up the new one,
+        // and [Connection::recv] moves on once the old reciever drains
+        let (snd, recv) = tokio::sync::mpsc::channel(capacity);
+        self.monitor.watch(channel, QueueDirection::Read, &snd);
+        self.queues.read.lock().await.entry(channel.to_owned()).or_default()
+            .push_back(Arc::new(Mutex::new(recv)));
+        read.send(snd).map_err(|_| anyhow!("data channel '{channel}' is closed"))?;
+
+        Ok(())
+    }
+
+    /// Log every frame on this connection, labelled with `peer`; [None] stops.
+    ///
+    /// # Notes
+    /// Frames are logged at info level to [super::TRACE_TARGET] with their
+    /// direction, channel, size and decoded type, but never their payload.
+    pub fn set_trace(&self, peer: Option<String>) {
+        if let Ok(mut label) = self.trace.write() {
+            *label = peer;
         }
     }
 
+    pub fn is_traced(&self) -> bool {
+        self.trace.read().is_ok_and(|x| x.is_some())
+    }
+
     /// read from a channel, if exists and is non empty
     ///
     /// # Notes
@@ -60,26 +275,125 @@ impl Connection {
     /// returns [Option::None] if nothing is there.
     /// blocks until this channel you want exists
     pub async fn recv(&self, channel: &str) -> Option<QueueTuple> {
-        let queuetex: Arc<Mutex<Receiver<QueueTuple>>> = {
-            loop {
-                // lock the global mutex briefly to get the correct
-                // channel, checking if it exists (if it doesn't,
-                // wait for our Semiphore)
-                let queuetable = self.read_queues.lock();
-                if let Some(n) = queuetable.await.get(channel) {
-                    break n.clone();
+        loop {
+            let queuetex: ReadQueue = {
+                loop {
+                    // lock the global mutex briefly to get the oldest
+                    // queue of the channel, checking if it exists (if it
+                    // doesn't, wait for our Semiphore)
+                    let queuetable = self.queues.read.lock();
+                    if let Some(n) = queuetable.await.get(channel).and_then(|x| x.front()) {
+                        break n.clone();
+                    }
+
+                    self.new_channel_notify.notified().await;
                 }
+            };
 
-                self.new_channel_notify.notified().await;
+            // now. lock the second lock until we got something; this
+            // means we will lock every other member trying to read
+            // from this channel (which shouldn't be more than 1 thread
+            // anyway.)
+            let mut queue = queuetex.lock().await;
+            if let Some(n) = queue.recv().await {
+                return Some(n);
             }
-        };
 
-        // now. lock the second lock until we got something; this
-        // means we will lock every other member trying to read
-        // from this channel (which shouldn't be more than 1 thread
-        // anyway.)
-        let mut queue = queuetex.lock().await;
-        queue.recv().await
+            // the queue is dead; carry on with the next only if it was resized
+            let mut queuetable = self.queues.read.lock().await;
+            let queues = queuetable.get_mut(channel)?;
+            if queues.front().is_some_and(|x| Arc::ptr_eq(x, &queuetex)) {
+                if queues.len() == 1 {
+                    return None;
+                }
+                queues.pop_front();
+            }
+        }
+    }
+
+    /// Read from a channel, telling an orderly end from a dead channel.
+    ///
+    /// # Notes
+    /// blocks like [Connection::recv]. Messages sent before the peer
+    /// called [Connection::end_stream] are all read before
+    /// [Received::StreamEnded].
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let mut file = vec![];
+    /// loop {
+    ///     match cnx.recv_checked("upload").await {
+    ///         Received::Data(chunk) => file.extend(chunk),
+    ///         Received::StreamEnded => break,
+    ///         Received::Closed => return Err(anyhow!("upload cut short")),
+    ///     }
+    /// }
+    /// cnx.send("upload", b"got it".to_vec()).await?;
+    /// ```
+    pub async fn recv_checked(&self, channel: &str) -> Received {
+        match self.recv(channel).await {
+            Some((_, data)) => Received::Data(data),
+            None if self.queues.ended_there.lock().await.contains(channel) => Received::StreamEnded,
+            None => Received::Closed,
+        }
+    }
+
+    /// Stop writing to a channel, while still reading from it.
+    ///
+    /// # Notes
+    /// Messages already queued are sent first; the peer then gets
+    /// [Received::StreamEnded]. Sending on the channel afterwards fails.
+    pub async fn end_stream(&self, channel: &str) -> Result<()> {
+        if !self.queues.ended_here.lock().await.insert(channel.to_owned()) {
+            return Ok(());
+        }
+        self.push(channel, Outgoing {
+            channel: channel.into(), data: vec![], frame: Frame::End,
+            enqueued: Instant::now(), deadline: None, receipt: None
+        }).await
+    }
+
+    /// Wait until everything queued so far, on every channel, has arrived.
+    ///
+    /// # Notes
+    /// Like [Connection::send_tracked], this says nothing of whether
+    /// the remote application read it.
+    pub async fn flush(&self) -> Result<()> {
+        let channels: Vec<String> = self.queues.write.lock().await.keys().cloned().collect();
+
+        let mut receipts = vec![];
+        for channel in channels {
+            let (sender, reciever) = oneshot::channel();
+            self.push(&channel, Outgoing {
+                channel: channel.clone(), data: vec![], frame: Frame::Flush,
+                enqueued: Instant::now(), deadline: None, receipt: Some(sender)
+            }).await?;
+            receipts.push((channel, reciever));
+        }
+
+        for (channel, reciever) in receipts {
+            reciever.await
+                .map_err(|_| anyhow!("data channel '{channel}' closed before it was flushed"))??;
+        }
+        Ok(())
+    }
+
+    /// read a [Message] from a channel, with whatever headers it carries
+    ///
+    /// # Notes
+    /// blocks like [Connection::recv].
+    pub async fn recv_message(&self, channel: &str) -> Option<Message> {
+        let (_, data) = self.recv(channel).await?;
+        Some(Message::decode(&data))
+    }
+
+    /// write a [Message] to a channel, headers and all
+    ///
+    /// # Notes
+    /// blocks like [Connection::send].
+    pub async fn send_message(&self, channel: &str, message: &Message) -> Result<()> {
+        self.send(channel, message.encode()?).await
     }
 
     /// write to a channel, if exists and has capacity
@@ -87,12 +401,75 @@ impl Connection {
     /// # Notes
     /// blocks until this channel you want exists
     pub async fn send(&self, channel: &str, data: Vec<u8>) -> Result<()> {
-        let queue: Sender<QueueTuple> = {
+        self.enqueue(channel, data, None, None).await
+    }
+
+    /// write to a channel, unless the message is still queued after `ttl`
+    ///
+    /// # Notes
+    /// For messages which are worthless once stale, like presence or
+    /// cursor positions: after a stall, the write worker drops expired
+    /// messages instead of sending them, and reports each one through
+    /// [Connection::subscribe_expiries].
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// cnx.send_with_ttl("presence", cursor, Duration::from_millis(500)).await?;
+    /// ```
+    pub async fn send_with_ttl(&self, channel: &str, data: Vec<u8>, ttl: Duration) -> Result<()> {
+        self.enqueue(channel, data, None, Some(Instant::now() + ttl)).await
+    }
+
+    /// get a [QueueExpiry] whenever a message sent with a ttl is dropped
+    pub fn subscribe_expiries(&self) -> tokio::sync::mpsc::UnboundedReceiver<QueueExpiry> {
+        self.monitor.subscribe_expiries()
+    }
+
+    /// write to a channel, then wait until the message actually arrives
+    ///
+    /// # Notes
+    /// [Connection::send] returns once the message is queued, and it
+    /// may yet be lost if the connection dies before the queue drains.
+    /// This only returns [Ok] once the peer's end acknowledged the
+    /// message, so it is safe to close right after, and critical
+    /// messages can be retried until they get a [Receipt].
+    /// It says nothing of whether the remote application read it.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// let receipt = cnx.send_tracked("control", msg).await?;
+    /// debug!("sent after {:?} in the queue", receipt.queued);
+    /// ```
+    pub async fn send_tracked(&self, channel: &str, data: Vec<u8>) -> Result<Receipt> {
+        let (sender, reciever) = oneshot::channel();
+        self.enqueue(channel, data, Some(sender), None).await?;
+
+        reciever.await
+            .map_err(|_| anyhow!("data channel '{channel}' closed before the message was sent"))?
+    }
+
+    async fn enqueue(&self, channel: &str, data: Vec<u8>,
+                     receipt: Option<oneshot::Sender<Result<Receipt>>>,
+                     deadline: Option<Instant>) -> Result<()> {
+        if self.queues.ended_here.lock().await.contains(channel) {
+            return Err(anyhow!("data channel '{channel}' was ended"));
+        }
+
+        // whoosh
+        self.push(channel, Outgoing {
+            channel: channel.into(), data, frame: Frame::Data, enqueued: Instant::now(), deadline, receipt
+        }).await
+    }
+
+    async fn push(&self, channel: &str, message: Outgoing) -> Result<()> {
+        let queue: Sender<Outgoing> = {
             loop {
                 // lock the global mutex briefly to get the correct
                 // channel, checking if it exists (if it doesn't,
                 // wait for our Semiphore)
-                let queuetable = self.write_queues.lock();
+                let queuetable = self.queues.write.lock();
                 if let Some(n) = queuetable.await.get(channel) {
                     break n.clone();
                 }
@@ -101,8 +478,8 @@ impl Connection {
             }
         };
 
-        // whoosh
-        queue.send((channel.into(), data)).await?;
+        self.monitor.send(channel, QueueDirection::Write, &queue, message).await
+            .map_err(|_| anyhow!("data channel '{channel}' is closed"))?;
 
         Ok(())
     }
@@ -115,58 +492,157 @@ impl Connection {
 
     pub async fn channel(&self, name: &str) -> Result<()> {
         let channel = self.cnx.create_data_channel(name, None).await?;
-        let read_queues = self.read_queues.clone();
-        let write_queues = self.write_queues.clone();
+        let queues = self.queues.clone();
         let new_channel = self.new_channel_notify.clone();
         let capacity = self.queue_size;
+        let trace = self.trace.clone();
+        let monitor = self.monitor.clone();
 
-        let _ = Connection::register_channel(channel.clone(), new_channel, read_queues,
-                                             write_queues, capacity).await;
+        let _ = Connection::register_channel(channel.clone(), new_channel, queues,
+                                             capacity, trace, monitor).await;
 
         Ok(())
     }
 
-    async fn _read_worker(d: Arc<DataChannel>, queue: Sender<QueueTuple>, name: String) {
+    async fn _read_worker(d: Arc<DataChannel>, mut queue: Sender<QueueTuple>,
+                          mut resized: UnboundedReceiver<Sender<QueueTuple>>, name: String,
+                          trace: TraceLabel, monitor: Monitor, ended: Ended) {
         let mut buffer = vec![0u8; MAX_MSG_SIZE_BYTES];
 
         loop {
-            let n = match d.read(&mut buffer).await {
+            let received = d.messages_received();
+            let n = match d.read_data_channel(&mut buffer).await {
+                // we only ever send binary messages, so a string
+                // message is the end of stream marker; dropping our
+                // queue lets readers drain it, then see the end
+                Ok((_, true)) => {
+                    debug!("data channel ended by peer: name '{name}'");
+                    ended.lock().await.insert(name);
+                    return;
+                }
+                // a reset stream also reads as empty, but unlike an
+                // empty message it is not counted as received
+                Ok((0, false)) if d.messages_received() == received => {
+                    return;
+                }
                 // number of bytes read
-                Ok(n) => n,
+                Ok((n, false)) => n,
                 // data channel exited
                 Err(_) => {
                     return;
                 }
             };
 
+            trace_frame(&trace, Direction::In, &name, &buffer[..n]);
+            monitor.moved(QueueDirection::Read, n);
+
+            // take up the newest queue, if we were resized
+            while let Ok(x) = resized.try_recv() {
+                queue = x;
+            }
+
             // push to our queue
-            let _ = queue.send((name.clone(), buffer[..n].to_vec())).await;
+            let _ = monitor.send(&name, QueueDirection::Read, &queue,
+                                 (name.clone(), buffer[..n].to_vec())).await;
         }
     }
 
-    async fn _write_worker(d: Arc<DataChannel>, mut queue: Receiver<QueueTuple>) {
+    async fn _write_worker(d: Arc<DataChannel>, mut queue: Receiver<Outgoing>,
+                           mut resized: UnboundedReceiver<Receiver<Outgoing>>,
+                           trace: TraceLabel, monitor: Monitor) {
+        // the peer's acks release buffered bytes; when none are left,
+        // everything we wrote has arrived
+        let drained = Arc::new(Notify::new());
+        let on_low = drained.clone();
+        d.on_buffered_amount_low(Box::new(move || {
+            let on_low = on_low.clone();
+            Box::pin(async move { on_low.notify_waiters(); })
+        }));
+
         loop {
-            let (_, data) = match queue.recv().await {
+            let message = match queue.recv().await {
                 // number of bytes read
                 Some(n) => n,
-                // data channel exited
-                None => {
-                    return;
+                // every sender is gone: either we were resized, or the
+                // connection is dead
+                None => match resized.try_recv() {
+                    Ok(x) => {
+                        queue = x;
+                        continue;
+                    }
+                    Err(_) => return
                 }
             };
 
+            // stale by now; better dropped than sent late
+            if let Some(late_by) = message.deadline
+                .and_then(|x| Instant::now().checked_duration_since(x)) {
+                monitor.expired(QueueExpiry {
+                    channel: message.channel.clone(),
+                    bytes: message.data.len(),
+                    late_by
+                });
+                if let Some(receipt) = message.receipt {
+                    let _ = receipt.send(Err(anyhow!("message expired in the write queue")));
+                }
+                continue;
+            }
+
+            if message.frame != Frame::Flush {
+                trace_frame(&trace, Direction::Out, &message.channel, &message.data);
+            }
+
             // push to rtc; if error, our channel closed
-            if let Err(_) = d.write(&Bytes::from(data)).await {
+            let written = match message.frame {
+                Frame::Data => d.write(&Bytes::from(message.data)).await,
+                Frame::End => d.write_data_channel(&Bytes::new(), true).await,
+                Frame::Flush => Ok(0),
+            };
+            if let Ok(bytes) = written {
+                monitor.moved(QueueDirection::Write, bytes);
+            }
+            if let Some(receipt) = message.receipt {
+                let result = match written {
+                    // whoever holds a receipt wants to know it arrived,
+                    // not just that it is buffered; so wait for acks
+                    Ok(bytes) => match tokio::time::timeout(
+                        DRAIN_TIMEOUT, Connection::drain(&d, &drained)).await {
+                        Ok(()) => Ok(Receipt {
+                            channel: message.channel,
+                            bytes,
+                            queued: message.enqueued.elapsed()
+                        }),
+                        Err(_) => Err(anyhow!("peer did not acknowledge within {DRAIN_TIMEOUT:?}"))
+                    },
+                    Err(ref err) => Err(anyhow!("data channel write failed: {err}"))
+                };
+                let _ = receipt.send(result);
+            }
+            if written.is_err() {
                 return;
             }
         }
     }
 
+    /// wait until nothing written to `d` is waiting on an ack
+    async fn drain(d: &DataChannel, drained: &Notify) {
+        loop {
+            let notified = drained.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if d.buffered_amount() == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+
     fn register_channel(d: Arc<RTCDataChannel>,
                         notify: Arc<Notify>,
-                        read_queues: Arc<Mutex<HashMap<String, Arc<Mutex<Receiver<QueueTuple>>>>>>,
-                        write_queues: Arc<Mutex<HashMap<String, Sender<QueueTuple>>>>,
-                        capacity:usize) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>
+                        queues: Queues,
+                        capacity:usize,
+                        trace: TraceLabel,
+                        monitor: Monitor) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>
     {
         debug!("data channel connected: name '{}'", d.label());
         let channel = d.clone();
@@ -175,8 +651,9 @@ impl Connection {
             // create a new channel to write to this channel
             // and write the send end down for others' use
             let reciever = {
-                let mut guarded_write_hmp = write_queues.lock().await;
+                let mut guarded_write_hmp = queues.write.lock().await;
                 let (snd, recv) = tokio::sync::mpsc::channel(capacity);
+                monitor.watch(channel.label(), QueueDirection::Write, &snd);
                 guarded_write_hmp.insert(
                     channel.label().to_owned(),
                     snd
@@ -185,15 +662,24 @@ impl Connection {
             };
 
             let sender = {
-                let mut guarded_write_hmp = read_queues.lock().await;
+                let mut guarded_write_hmp = queues.read.lock().await;
                 let (snd, recv) = tokio::sync::mpsc::channel(capacity);
+                monitor.watch(channel.label(), QueueDirection::Read, &snd);
                 guarded_write_hmp.insert(
                     channel.label().to_owned(),
-                    Arc::new(Mutex::new(recv))
+                    VecDeque::from([Arc::new(Mutex::new(recv))])
                 );
                 snd
             };
 
+            let (read_resized, write_resized) = {
+                let (read_snd, read_recv) = tokio::sync::mpsc::unbounded_channel();
+                let (write_snd, write_recv) = tokio::sync::mpsc::unbounded_channel();
+                queues.resizers.lock().await.insert(channel.label().to_owned(),
+                                                    (read_snd, write_snd));
+                (read_recv, write_recv)
+            };
+
             channel.clone().on_open(Box::new(move || {
                 Box::pin(async move {
                     let raw = match channel.detach().await {
@@ -206,13 +692,22 @@ impl Connection {
                     };
 
                     let rc = raw.clone();
-                    tokio::spawn(async move {
-                        Connection::_read_worker(rc, sender,
-                                                 channel.label().to_owned()).await;
+                    let raw_label = channel.label().to_owned();
+                    let read_trace = trace.clone();
+                    let read_monitor = monitor.clone();
+                    let monitor = monitor.clone();
+                    let ended = queues.ended_there.clone();
+                    let peer = trace.read().ok().and_then(|x| x.clone())
+                        .map_or(String::new(), |x| format!(" peer {x}"));
+                    spawn_named(&format!("synch read '{}'{peer}", channel.label()), async move {
+                        Connection::_read_worker(rc, sender, read_resized,
+                                                 channel.label().to_owned(),
+                                                 read_trace, read_monitor, ended).await;
                     });
 
-                    tokio::spawn(async move {
-                        Connection::_write_worker(raw, reciever).await;
+                    spawn_named(&format!("synch write '{raw_label}'{peer}"), async move {
+                        Connection::_write_worker(raw, reciever, write_resized,
+                                                  trace, monitor).await;
                     });
 
                     notify.notify_waiters();
@@ -224,19 +719,21 @@ impl Connection {
 
     async fn listen(&self, desc: RTCSessionDescription) -> Result<String> {
         // keep a pointer to the queue
-        let read_queue = self.read_queues.clone();
-        let write_queues = self.write_queues.clone();
+        let queues = self.queues.clone();
         let new_channel = self.new_channel_notify.clone();
         let capacity = self.queue_size;
+        let trace = self.trace.clone();
+        let monitor = self.monitor.clone();
 
         // create a handler for new data channels
         self.cnx.on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
             // copy the queue pointers to share with the registration function 
-            let read_queue = read_queue.clone();
-            let write_queues = write_queues.clone();
+            let queues = queues.clone();
             let new_channel = new_channel.clone();
+            let trace = trace.clone();
+            let monitor = monitor.clone();
 
-            Connection::register_channel(d, new_channel, read_queue, write_queues, capacity)
+            Connection::register_channel(d, new_channel, queues, capacity, trace, monitor)
         }));
 
         // wait for ICE gather; TODO this disables trickle ICE, which should
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use super::agent::PeerId;
+use super::registry::RegistryMessage;
+use super::invite::Permission;
+
+/// A request a child makes of the head, given to [super::Agent::control].
+///
+/// # Notes
+/// The head checks every one against the sender's [super::Role] before
+/// acting on it; see [ControlMessage::permission].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlMessage {
+    /// a change to the swarm's documents, for the head's [super::Registry]
+    Registry(RegistryMessage),
+    /// disconnect a child of the head, banning it too if a reason is given
+    Kick { peer: PeerId, ban: Option<String> },
+    /// seal invites with a new key, so outstanding ones stop working
+    RotateKeys,
+}
+
+impl ControlMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<ControlMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+
+    /// what a peer must be allowed to do for the head to act on this
+    pub fn permission(&self) -> Permission {
+        match self {
+            ControlMessage::Registry(x) => x.permission(),
+            ControlMessage::Kick { .. } => Permission::KickPeers,
+            ControlMessage::RotateKeys => Permission::RotateKeys,
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::{BTreeSet, HashMap};
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use log::debug;
+
+use super::agent::PeerId;
+use super::registry::Registry;
+
+/// data channel federated heads talk on
+pub const FEDERATION_CHANNEL: &str = "synch-federation";
+
+/// identifier of a swarm, picked at random by its head
+pub type SwarmId = u64;
+
+/// message between two federated heads
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FederationMessage {
+    /// first message on a federation link: who we are
+    Hello(SwarmId),
+    /// the sender's swarm subscribes to these documents (in addition to any before)
+    Subscribe(Vec<String>),
+    /// the sender's swarm no longer subscribes to these documents
+    Unsubscribe(Vec<String>),
+    /// a tape for `doc`, made in swarm `origin` and bridged through `via`
+    Tape { doc: String, origin: SwarmId, via: Vec<SwarmId>, tape: Bytes },
+}
+
+impl FederationMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<FederationMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Link {
+    swarm: Option<SwarmId>,
+    subscribed: BTreeSet<String>,
+}
+
+/// what to do with a message from a federated head
+#[derive(Debug, Default)]
+pub struct Bridged {
+    /// a tape to replay into our own swarm, as `(document, tape)`
+    pub local: Option<(String, Bytes)>,
+    /// messages to pass on to other federated heads
+    pub forward: Vec<(PeerId, FederationMessage)>,
+}
+
+/// bridges documents between this head's swarm and other swarms' heads
+///
+/// # Notes
+/// Heads connect like any other peers and exchange [FederationMessage]s
+/// on [FEDERATION_CHANNEL]. Tapes only cross a link for documents both
+/// sides subscribe to, and carry the swarms they went through so they
+/// are never bridged back into a swarm that already saw them.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut federation = Federation::new();
+/// send_to(other_head, federation.hello().encode()?);
+/// send_to(other_head, federation.subscriptions(&registry).encode()?);
+///
+/// // a tape was made in our swarm
+/// for (head, message) in federation.bridge(&registry, "todo", tape) {
+///     send_to(head, message.encode()?);
+/// }
+///
+/// // a message came from another head
+/// let bridged = federation.handle(&registry, from, FederationMessage::decode(&raw)?);
+/// ```
+#[derive(Debug)]
+pub struct Federation {
+    swarm: SwarmId,
+    links: HashMap<PeerId, Link>,
+}
+
+impl Federation {
+    pub fn new() -> Self {
+        Federation { swarm: rand::random(), links: HashMap::new() }
+    }
+
+    pub fn swarm(&self) -> SwarmId {
+        self.swarm
+    }
+
+    pub fn hello(&self) -> FederationMessage {
+        FederationMessage::Hello(self.swarm)
+    }
+
+    /// tell other heads every document our swarm subscribes to
+    pub fn subscriptions(&self, registry: &Registry) -> FederationMessage {
+        FederationMessage::Subscribe(
+            registry.list()
+                .into_iter()
+                .filter(|x| x.subscribed)
+                .map(|x| x.name.clone())
+                .collect()
+        )
+    }
+
+    /// stop bridging with a head which went away
+    pub fn forget(&mut self, head: PeerId) {
+        self.links.remove(&head);
+    }
+
+    /// bridge a tape made in our own swarm out to other swarms
+    ///
+    /// # Return
+    /// The messages to send, and to which federated heads.
+    pub fn bridge(&self, registry: &Registry, doc: &str, tape: Bytes) -> Vec<(PeerId, FederationMessage)> {
+        self.route(registry, None, FederationMessage::Tape {
+            doc: doc.to_owned(),
+            origin: self.swarm,
+            via: vec![],
+            tape
+        })
+    }
+
+    /// apply a message from the federated head `from`
+    pub fn handle(&mut self, registry: &Registry, from: PeerId, message: FederationMessage) -> Bridged {
+        let link = self.links.entry(from).or_default();
+
+        match message {
+            FederationMessage::Hello(swarm) => {
+                link.swarm = Some(swarm);
+            }
+            FederationMessage::Subscribe(docs) => {
+                link.subscribed.extend(docs);
+            }
+            FederationMessage::Unsubscribe(docs) => {
+                for doc in docs {
+                    link.subscribed.remove(&doc);
+                }
+            }
+            FederationMessage::Tape { doc, origin, via, tape } => {
+                if origin == self.swarm || via.contains(&self.swarm) {
+                    debug!("dropping tape for '{doc}' which already went through us");
+                    return Bridged::default();
+                }
+                if !registry.get(&doc).is_some_and(|x| x.subscribed) {
+                    return Bridged::default();
+                }
+
+                let mut via = via;
+                via.push(self.swarm);
+                let forward = self.route(registry, Some(from), FederationMessage::Tape {
+                    doc: doc.clone(),
+                    origin,
+                    via,
+                    tape: tape.clone()
+                });
+
+                return Bridged { local: Some((doc, tape)), forward };
+            }
+        }
+
+        Bridged::default()
+    }
+
+    /// send a tape to every head subscribing to its document, except
+    /// where it came from and swarms it already went through
+    fn route(&self, registry: &Registry, from: Option<PeerId>,
+             message: FederationMessage) -> Vec<(PeerId, FederationMessage)> {
+        let FederationMessage::Tape { ref doc, origin, ref via, .. } = message else {
+            return vec![];
+        };
+        if !registry.get(doc).is_some_and(|x| x.subscribed) {
+            return vec![];
+        }
+
+        self.links.iter()
+            .filter(|(head, _)| Some(**head) != from)
+            .filter(|(_, link)| link.subscribed.contains(doc))
+            .filter(|(_, link)| link.swarm.is_none_or(|x| x != origin && !via.contains(&x)))
+            .map(|(head, _)| (*head, message.clone()))
+            .collect()
+    }
+}
+
+impl Default for Federation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+/// data channel goodbyes are sent on
+pub const GOODBYE_CHANNEL: &str = "synch-goodbye";
+
+/// how long [super::Agent::leave] waits for each goodbye to be sent
+pub const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// data channel [ShutdownMessage]s are sent on
+pub const SHUTDOWN_CHANNEL: &str = "synch-shutdown";
+
+/// why a peer disconnected on purpose
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GoodbyeReason {
+    /// the user closed the app
+    Closed,
+    /// the node is shutting down for good
+    Shutdown,
+    /// the node is restarting and will be back shortly
+    Restarting,
+    /// the head removed this peer from the swarm
+    Kicked,
+    /// anything else, in the application's words
+    Other(String),
+}
+
+impl GoodbyeReason {
+    /// whether it is worth trying to reconnect to a peer which left for this reason
+    pub fn should_reconnect(&self) -> bool {
+        matches!(self, GoodbyeReason::Restarting)
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<GoodbyeReason> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+/// how a head and its children agree to end a swarm; see
+/// [super::Agent::shutdown_swarm]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShutdownMessage {
+    /// from the head: publish your last edits within `grace`
+    Notice { grace: Duration },
+    /// from a child: everything it had to publish is sent
+    Flushed,
+}
+
+impl ShutdownMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<ShutdownMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use super::agent::PeerId;
+use super::bans::Ban;
+use super::invite::Invite;
+use super::mailbox::{Mailbox, PendingLetter};
+
+/// data channel [HandoffMessage]s are sent on
+pub const HANDOFF_CHANNEL: &str = "synch-handoff";
+
+/// how long a handoff waits on each step of moving one child
+pub const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// a child of the old head, as the new head should know it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RosterEntry {
+    /// its id on the old head
+    pub peer: PeerId,
+    pub fingerprints: Vec<String>,
+    /// what its invite let it do, if it had one
+    pub grant: Option<Invite>,
+}
+
+/// how an old head, its successor and its children move a swarm;
+/// see [super::Agent::hand_off]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandoffMessage {
+    /// from the old head: part of a document's snapshot, in order
+    Document { name: String, part: Bytes, last: bool },
+    /// from the old head: a child about to be moved
+    Peer(RosterEntry),
+    /// from the old head: a fingerprint it had banned
+    Ban { fingerprint: String, ban: Ban },
+    /// from the old head: a batch waiting in its mailbox
+    Letter(PendingLetter),
+    /// from the old head: make an offer for its child `peer`
+    OfferWanted { peer: PeerId },
+    /// from the new head: the offer for the old head's child `peer`
+    Offer { peer: PeerId, offer: String },
+    /// from the old head to a child: answer `offer` to move to the new head
+    Redirect { offer: String },
+    /// from a child: its answer to the redirect
+    Answer { answer: String },
+    /// from the old head: what its child `peer` answered
+    Answered { peer: PeerId, answer: String },
+    /// from the old head: every child was moved or given up on
+    Done,
+}
+
+impl HandoffMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<HandoffMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+/// what a new head got from [super::Agent::take_over]
+#[derive(Debug, Default)]
+pub struct Takeover {
+    /// the snapshots the old head handed over, by name
+    pub documents: BTreeMap<String, Vec<u8>>,
+    /// the old head's id for each child moved, and its id now
+    pub peers: BTreeMap<PeerId, PeerId>,
+    /// the old head's mail, for [super::Agent::restore_mailbox]
+    pub mailbox: Mailbox,
+}
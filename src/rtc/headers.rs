@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+/// largest the encoded headers of one [Message] may be
+pub const MAX_HEADER_BYTES: usize = 4096;
+
+/// small key/value metadata carried alongside a payload
+pub type Headers = BTreeMap<String, String>;
+
+/// what [Message] looks like on the wire
+#[derive(Serialize, Deserialize)]
+enum Wire {
+    Envelope { headers: Headers, payload: Bytes },
+}
+
+/// a payload with headers, such as its content type, schema version or trace id
+///
+/// # Notes
+/// Headers are opt in: a message without any is sent as its bare
+/// payload, and anything recieved which is not an envelope decodes as a
+/// message without headers. So one channel can carry both, and peers
+/// which never use headers need not change.
+///
+/// # Examples
+///
+/// ```ignore
+/// let msg = Message::new(payload)
+///     .with_header("content-type", "application/cbor")
+///     .with_header("schema-version", "2");
+/// cnx.send_message("todo", &msg).await?;
+///
+/// let msg = cnx.recv_message("todo").await.unwrap();
+/// match msg.header("schema-version") { ... }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Message {
+    pub headers: Headers,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(payload: Vec<u8>) -> Self {
+        Message { headers: Headers::new(), payload }
+    }
+
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.get(key).map(|x| x.as_str())
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if self.headers.is_empty() {
+            return Ok(self.payload.clone());
+        }
+
+        let size: usize = self.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        if size > MAX_HEADER_BYTES {
+            return Err(anyhow!("headers of {size} bytes exceed the limit of {MAX_HEADER_BYTES}"));
+        }
+
+        let mut buf = vec![];
+        ciborium::into_writer(&Wire::Envelope {
+            headers: self.headers.clone(),
+            payload: Bytes::copy_from_slice(&self.payload)
+        }, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// decode a recieved message; this never fails, see the notes on [Message]
+    pub fn decode(buf: &[u8]) -> Message {
+        Message::decode_envelope(buf).unwrap_or_else(|| Message::new(buf.to_vec()))
+    }
+
+    /// decode `buf` only if it is an envelope, so bare payloads are not copied
+    pub(crate) fn decode_envelope(buf: &[u8]) -> Option<Message> {
+        match ciborium::from_reader(buf) {
+            Ok(Wire::Envelope { headers, payload }) => Some(Message { headers, payload: payload.into() }),
+            Err(_) => None,
+        }
+    }
+}
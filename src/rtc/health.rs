@@ -0,0 +1,27 @@
+/// whether an [super::Agent] is working, for orchestrators to poll
+///
+/// # Examples
+///
+/// ```ignore
+/// // in a daemon's status loop
+/// let health = agent.health();
+/// if !health.live {
+///     std::process::exit(1); // let the supervisor restart us
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// no background worker has failed; ones which stop once the peers
+    /// they listen to are gone have not
+    pub live: bool,
+    /// reachable by the swarm and keeping up with it; see the notes on [super::Agent::health]
+    pub ready: bool,
+    /// this agent has no parent, so it is the head of its swarm
+    pub is_head: bool,
+    /// the connection to our parent is up; always false for a head
+    pub parent_connected: bool,
+    /// children which are connected and have not said goodbye
+    pub peers: usize,
+    /// messages waiting in every queue of every connection
+    pub backlog: usize,
+}
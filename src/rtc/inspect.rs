@@ -0,0 +1,140 @@
+use std::fmt;
+use anyhow::{Result, anyhow};
+use base64::prelude::{BASE64_URL_SAFE, Engine as _};
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+/// one way an offer or answer says it can be reached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateInfo {
+    /// udp or tcp
+    pub transport: String,
+    pub address: String,
+    pub port: u16,
+    /// host, srflx (seen by a STUN server), prflx or relay
+    pub kind: String,
+}
+
+/// what a pasted offer or answer contains
+///
+/// # Notes
+/// Offers carry no expiry of their own: one stays usable for as long as
+/// the [super::Agent] that made it keeps the connection open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OfferInfo {
+    /// "offer" or "answer"
+    pub kind: String,
+    /// SDP protocol version, from the `v=` line
+    pub protocol_version: String,
+    /// session id and version, from the `o=` line
+    pub session: String,
+    pub ice_ufrag: Option<String>,
+    /// DTLS certificate fingerprints, as `algorithm value`
+    pub fingerprints: Vec<String>,
+    pub candidates: Vec<CandidateInfo>,
+    /// the media sections, e.g. `application 9 UDP/DTLS/SCTP webrtc-datachannel`
+    pub media: Vec<String>,
+}
+
+impl OfferInfo {
+    /// whether anyone outside our network could reach this peer
+    ///
+    /// # Notes
+    /// An offer with only host candidates usually means STUN failed,
+    /// and it will only connect on the same network.
+    pub fn has_public_candidates(&self) -> bool {
+        self.candidates.iter().any(|x| x.kind != "host")
+    }
+}
+
+impl fmt::Display for OfferInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} (sdp v{}, session {})", self.kind, self.protocol_version, self.session)?;
+        if let Some(ref ufrag) = self.ice_ufrag {
+            writeln!(f, "ice ufrag: {ufrag}")?;
+        }
+        for fingerprint in self.fingerprints.iter() {
+            writeln!(f, "fingerprint: {fingerprint}")?;
+        }
+        for media in self.media.iter() {
+            writeln!(f, "media: {media}")?;
+        }
+        writeln!(f, "{} candidates:", self.candidates.len())?;
+        for x in self.candidates.iter() {
+            writeln!(f, "  {} {}:{} ({})", x.transport, x.address, x.port, x.kind)?;
+        }
+        if !self.has_public_candidates() {
+            writeln!(f, "warning: only host candidates; this will only connect on the same network")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decode a pasted offer or answer, as made by [super::Agent::offer] or
+/// [super::Agent::child], to see what is in it.
+///
+/// # Examples
+///
+/// ```ignore
+/// let info = inspect_offer(&pasted)?;
+/// println!("{info}");
+/// ```
+pub fn inspect_offer(blob: &str) -> Result<OfferInfo> {
+    let description: RTCSessionDescription = serde_json::from_str(
+        &String::from_utf8(BASE64_URL_SAFE.decode(blob.trim())?)?
+    )?;
+
+    let mut info = OfferInfo {
+        kind: description.sdp_type.to_string(),
+        protocol_version: String::new(),
+        session: String::new(),
+        ice_ufrag: None,
+        fingerprints: vec![],
+        candidates: vec![],
+        media: vec![],
+    };
+
+    for line in description.sdp.lines() {
+        if let Some(version) = line.strip_prefix("v=") {
+            info.protocol_version = version.to_owned();
+        } else if let Some(origin) = line.strip_prefix("o=") {
+            // username, session id, session version, ...
+            let fields: Vec<_> = origin.split_whitespace().collect();
+            info.session = fields.get(1..3).map_or(origin.to_owned(), |x| x.join(" v"));
+        } else if let Some(media) = line.strip_prefix("m=") {
+            info.media.push(media.to_owned());
+        } else if let Some(ufrag) = line.strip_prefix("a=ice-ufrag:") {
+            info.ice_ufrag = Some(ufrag.to_owned());
+        } else if let Some(candidate) = line.strip_prefix("a=candidate:") {
+            info.candidates.push(parse_candidate(candidate)?);
+        }
+    }
+
+    info.fingerprints = fingerprints(&description.sdp);
+    Ok(info)
+}
+
+/// the distinct `a=fingerprint` values of an SDP, in order
+pub(crate) fn fingerprints(sdp: &str) -> Vec<String> {
+    let mut out: Vec<String> = vec![];
+    for fingerprint in sdp.lines().filter_map(|x| x.strip_prefix("a=fingerprint:")) {
+        if !out.iter().any(|x| x == fingerprint) {
+            out.push(fingerprint.to_owned());
+        }
+    }
+    out
+}
+
+/// `foundation component transport priority address port typ kind ...`
+fn parse_candidate(candidate: &str) -> Result<CandidateInfo> {
+    let fields: Vec<_> = candidate.split_whitespace().collect();
+    if fields.len() < 8 || fields[6] != "typ" {
+        return Err(anyhow!("malformed ICE candidate '{candidate}'"));
+    }
+
+    Ok(CandidateInfo {
+        transport: fields[2].to_lowercase(),
+        address: fields[4].to_owned(),
+        port: fields[5].parse()?,
+        kind: fields[7].to_owned(),
+    })
+}
@@ -0,0 +1,131 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::{Result, anyhow};
+use base64::prelude::{BASE64_URL_SAFE_NO_PAD, Engine as _};
+use serde::{Serialize, Deserialize};
+
+use super::storage::SecretKey;
+
+/// what a peer let in by an [Invite] may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    /// recieves documents, but anything it publishes is dropped
+    Viewer,
+    /// recieves and publishes documents
+    Editor,
+    /// an editor which may also run the swarm
+    Admin,
+}
+
+/// something a peer may be allowed to ask the head to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    /// publish to the documents it can see
+    Publish,
+    CreateDocument,
+    DeleteDocument,
+    /// take or give up a document's lease
+    LeaseDocument,
+    /// disconnect or ban other peers
+    KickPeers,
+    RotateKeys,
+    /// change who may see or edit what, such as by granting leases
+    ChangeAcls,
+    /// hand out invites
+    Invite,
+}
+
+impl Role {
+    /// Whether this role allows `permission`.
+    ///
+    /// # Notes
+    /// Viewers may do nothing but read; editors may publish and create
+    /// and lease documents; admins may do everything.
+    pub fn allows(&self, permission: Permission) -> bool {
+        match self {
+            Role::Viewer => false,
+            Role::Editor => matches!(permission, Permission::Publish
+                                     | Permission::CreateDocument
+                                     | Permission::LeaseDocument),
+            Role::Admin => true,
+        }
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.allows(Permission::Publish)
+    }
+}
+
+/// A grant to join a swarm, handed out by its head with [super::Agent::invite].
+///
+/// # Notes
+/// The head seals invites with a key only it holds, so a token cannot
+/// be forged or altered; it can be shared, so keep expiries short.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invite {
+    pub role: Role,
+    /// seconds since the unix epoch after which the invite is refused
+    pub expires: u64,
+    /// the channels the peer may see; [None] for all of them
+    pub documents: Option<BTreeSet<String>>,
+    /// the certificate fingerprints which may redeem it; [None] for anyone
+    #[serde(default)]
+    pub fingerprints: Option<BTreeSet<String>>,
+}
+
+impl Invite {
+    /// an invite to every document, valid for `ttl` from now
+    pub fn new(role: Role, ttl: Duration) -> Self {
+        Invite { role, expires: now() + ttl.as_secs(), documents: None, fingerprints: None }
+    }
+
+    /// only let the peer see `documents`
+    pub fn with_documents(mut self, documents: &[&str]) -> Self {
+        self.documents = Some(documents.iter().map(|x| x.to_string()).collect());
+        self
+    }
+
+    /// Only let a peer presenting `fingerprints` redeem the invite,
+    /// such as ones from [super::AgentConfig::fingerprints] on its end.
+    pub fn bound_to<S: AsRef<str>>(mut self, fingerprints: &[S]) -> Self {
+        self.fingerprints = Some(fingerprints.iter().map(|x| x.as_ref().to_owned()).collect());
+        self
+    }
+
+    /// whether the invite is bound to a peer presenting `fingerprints`
+    pub fn binds(&self, fingerprints: &[String]) -> bool {
+        self.fingerprints.as_ref().is_some_and(|x| {
+            !fingerprints.is_empty() && fingerprints.iter().all(|f| x.contains(f))
+        })
+    }
+
+    pub fn can_see(&self, document: &str) -> bool {
+        self.documents.as_ref().is_none_or(|x| x.contains(document))
+    }
+
+    /// the token to give to the invited peer
+    pub fn seal(&self, key: &SecretKey) -> Result<String> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(BASE64_URL_SAFE_NO_PAD.encode(key.seal(&buf)?))
+    }
+
+    /// Read a token made by [Invite::seal] with the same key.
+    ///
+    /// # Notes
+    /// fails if the token was not sealed with `key`, or has expired.
+    pub fn open(key: &SecretKey, token: &str) -> Result<Invite> {
+        let sealed = BASE64_URL_SAFE_NO_PAD.decode(token.trim())
+            .map_err(|err| anyhow!("malformed invite: {err}"))?;
+        let invite: Invite = ciborium::from_reader(key.open(&sealed)?.as_slice())?;
+
+        if invite.expires < now() {
+            return Err(anyhow!("invite expired at {}", invite.expires));
+        }
+        Ok(invite)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs())
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use anyhow::{Result, anyhow};
+use argon2::{Argon2, Algorithm, Version, Params};
+use aes_gcm::aead::{OsRng, rand_core::RngCore};
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use super::storage::SecretKey;
+
+/// first bytes of every file sealed with a [Passphrase]
+pub const KEYSTORE_MAGIC: &[u8] = b"synch-keystore\n";
+/// version of the sealed file layout
+const KEYSTORE_VERSION: u8 = 1;
+/// bytes of random salt each file's key is derived with
+const SALT_BYTES: usize = 16;
+
+/// how a sealed file's key was derived, and what it sealed
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    version: u8,
+    salt: Bytes,
+    /// argon2id memory, in KiB
+    memory: u32,
+    iterations: u32,
+    lanes: u32,
+    /// the contents, sealed with the derived [SecretKey]
+    data: Bytes,
+}
+
+/// passphrase protecting the files an agent keeps its secrets in
+///
+/// # Notes
+/// Each file gets its own random salt, which argon2id stretches the
+/// passphrase with into a [SecretKey]; the contents are then sealed
+/// with it like any other data at rest. The cost of the derivation is
+/// kept in the file, so it can be raised later without breaking files
+/// already written.
+///
+/// # Examples
+///
+/// ```ignore
+/// let passphrase = Passphrase::new(std::env::var("SYNCH_PASSPHRASE")?);
+/// passphrase.lock_file("/etc/synch/key")?;
+/// let config = AgentConfig {
+///     key_file: Some("/etc/synch/key".into()),
+///     passphrase: Some(passphrase),
+///     ..Default::default()
+/// };
+/// let key = config.secret_key()?;
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Passphrase(String);
+
+impl Passphrase {
+    pub fn new(passphrase: impl Into<String>) -> Passphrase {
+        Passphrase(passphrase.into())
+    }
+
+    /// stretch the passphrase into a key with `salt`, at the given cost
+    fn derive(&self, salt: &[u8], memory: u32, iterations: u32, lanes: u32) -> Result<SecretKey> {
+        let params = Params::new(memory, iterations, lanes, Some(32))
+            .map_err(|err| anyhow!("bad key derivation parameters: {err}"))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.0.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("failed to derive a key from the passphrase: {err}"))?;
+
+        Ok(SecretKey::new(key))
+    }
+
+    /// encrypt `plain` so only holders of this passphrase can read it
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let mut salt = [0u8; SALT_BYTES];
+        OsRng.fill_bytes(&mut salt);
+        let (memory, iterations, lanes) = (Params::DEFAULT_M_COST, Params::DEFAULT_T_COST,
+                                           Params::DEFAULT_P_COST);
+        let key = self.derive(&salt, memory, iterations, lanes)?;
+
+        let mut buf = KEYSTORE_MAGIC.to_vec();
+        ciborium::into_writer(&Sealed {
+            version: KEYSTORE_VERSION,
+            salt: Bytes::copy_from_slice(&salt),
+            memory,
+            iterations,
+            lanes,
+            data: key.seal(plain)?.into(),
+        }, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// decrypt something made by [Passphrase::seal]
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        let body = sealed.strip_prefix(KEYSTORE_MAGIC)
+            .ok_or(anyhow!("not sealed with a passphrase"))?;
+        let sealed: Sealed = ciborium::from_reader(body)
+            .map_err(|err| anyhow!("unreadable keystore: {err}"))?;
+        if sealed.version != KEYSTORE_VERSION {
+            return Err(anyhow!("keystore is version {}, expected {KEYSTORE_VERSION}", sealed.version));
+        }
+
+        self.derive(&sealed.salt, sealed.memory, sealed.iterations, sealed.lanes)?
+            .open(&sealed.data)
+            .map_err(|_| anyhow!("failed to decrypt: wrong passphrase or corrupted keystore"))
+    }
+
+    /// Seal a plain key or identity file in place.
+    ///
+    /// # Notes
+    /// The file is rewritten readable only by the user running this,
+    /// through a temporary file next to it, so a crash leaves either
+    /// the old file or the sealed one. Files already sealed are left alone.
+    pub fn lock_file(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let plain = fs::read(path)
+            .map_err(|err| anyhow!("failed to read '{}': {err}", path.display()))?;
+        if is_sealed(&plain) {
+            return Ok(());
+        }
+
+        let temp = path.with_extension("locking");
+        write_private(&temp, &self.seal(&plain)?)?;
+        fs::rename(&temp, path)
+            .map_err(|err| anyhow!("failed to replace '{}': {err}", path.display()))
+    }
+}
+
+impl std::fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Passphrase(..)")
+    }
+}
+
+/// whether `data` was made by [Passphrase::seal]
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(KEYSTORE_MAGIC)
+}
+
+/// create `path` readable only by the user running this, holding `data`
+pub(crate) fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    let mut file = fs::OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    file.open(path)
+        .map_err(|err| anyhow!("failed to create '{}': {err}", path.display()))?
+        .write_all(data)?;
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+use std::time::{Duration, Instant};
+use std::collections::{HashMap, VecDeque};
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use log::debug;
+
+use super::agent::PeerId;
+use super::quota::{Quota, QuotaLedger};
+
+/// data channel mailbox requests travel on
+pub const MAILBOX_CHANNEL: &str = "synch-mailbox";
+
+/// message between a peer and a node running a [Mailbox]
+///
+/// # Notes
+/// Mailboxes are named by an address the owning peer picks; anyone who
+/// knows it can read and acknowledge its mail, so it should be
+/// unguessable. Batches are sealed by the peers with a
+/// [super::SecretKey], so the mailbox never sees their contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MailboxMessage {
+    /// leave a sealed batch for `to`, dropped after `ttl_ms` if never acknowledged
+    Deposit { to: String, ttl_ms: u64, sealed: Bytes },
+    /// send me everything waiting at `address`
+    Fetch { address: String },
+    /// a batch waiting at the fetched address
+    Deliver { id: u64, sealed: Bytes },
+    /// batch `id` was recieved and may be deleted
+    Ack { address: String, id: u64 },
+    /// the request went over the sender's [Quota] and was dropped
+    Refused(String),
+}
+
+impl MailboxMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<MailboxMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+/// a batch waiting in a [Mailbox], as moved to another one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingLetter {
+    pub to: String,
+    /// who deposited it
+    pub owner: PeerId,
+    /// how long it has left
+    pub ttl_ms: u64,
+    pub sealed: Bytes,
+}
+
+#[derive(Debug)]
+struct Letter {
+    id: u64,
+    /// who deposited it, and is charged for storing it
+    owner: PeerId,
+    expires: Instant,
+    sealed: Bytes,
+}
+
+/// per-peer queues of sealed batches, held for peers who are offline
+///
+/// # Notes
+/// Fetching does not delete anything: a batch stays until it is
+/// acknowledged or its TTL runs out, so a peer which drops mid-fetch
+/// simply fetches again on reconnect.
+///
+/// Depositors are charged for what they store and everyone for what
+/// they move, against a per-peer [Quota].
+///
+/// # Examples
+///
+/// ```ignore
+/// // while bob is offline
+/// send(MailboxMessage::Deposit { to: bob_address, ttl_ms: 86_400_000,
+///                                sealed: key.seal(&tape)?.into() }.encode()?);
+/// // bob, on reconnect
+/// send(MailboxMessage::Fetch { address: bob_address }.encode()?);
+/// // ... for each Deliver { id, sealed } recieved
+/// send(MailboxMessage::Ack { address: bob_address, id }.encode()?);
+/// ```
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    boxes: HashMap<String, VecDeque<Letter>>,
+    next_id: u64,
+    ledger: QuotaLedger,
+}
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a mailbox whose peers get `quota` unless given their own
+    pub fn with_quota(quota: Quota) -> Self {
+        Mailbox { ledger: QuotaLedger::new(quota), ..Default::default() }
+    }
+
+    /// per-peer accounting, to inspect usage or set quotas
+    pub fn ledger(&mut self) -> &mut QuotaLedger {
+        &mut self.ledger
+    }
+
+    /// number of batches waiting at `address`
+    pub fn pending(&self, address: &str) -> usize {
+        self.boxes.get(address).map_or(0, |x| x.len())
+    }
+
+    /// drop every batch whose TTL ran out
+    pub fn expire(&mut self) {
+        let now = Instant::now();
+        for letters in self.boxes.values_mut() {
+            Mailbox::remove(letters, &mut self.ledger, |x| x.expires <= now);
+        }
+        self.boxes.retain(|_, x| !x.is_empty());
+    }
+
+    /// every batch still waiting, to move to another mailbox
+    pub fn letters(&mut self) -> Vec<PendingLetter> {
+        self.expire();
+        let now = Instant::now();
+
+        self.boxes.iter()
+            .flat_map(|(to, letters)| letters.iter().map(move |x| PendingLetter {
+                to: to.clone(),
+                owner: x.owner,
+                ttl_ms: x.expires.saturating_duration_since(now).as_millis() as u64,
+                sealed: x.sealed.clone(),
+            }))
+            .collect()
+    }
+
+    /// Hold a batch moved from another mailbox.
+    ///
+    /// # Notes
+    /// The batch was already let in, so it is kept even if it puts its
+    /// owner over their storage quota.
+    pub fn restore(&mut self, letter: PendingLetter) {
+        let _ = self.ledger.charge_storage(letter.owner, letter.sealed.len());
+
+        self.next_id += 1;
+        self.boxes.entry(letter.to).or_default().push_back(Letter {
+            id: self.next_id,
+            owner: letter.owner,
+            expires: Instant::now() + Duration::from_millis(letter.ttl_ms),
+            sealed: letter.sealed
+        });
+    }
+
+    /// apply a message from a peer
+    ///
+    /// # Return
+    /// The messages to send back to that peer.
+    pub fn handle(&mut self, from: PeerId, message: MailboxMessage) -> Vec<MailboxMessage> {
+        self.expire();
+
+        match message {
+            MailboxMessage::Deposit { to, ttl_ms, sealed } => {
+                let charged = self.ledger.charge_traffic(from, sealed.len())
+                    .and_then(|_| self.ledger.charge_storage(from, sealed.len()));
+                if let Err(err) = charged {
+                    return vec![MailboxMessage::Refused(err.to_string())];
+                }
+
+                self.next_id += 1;
+                debug!("holding batch {} for '{to}'", self.next_id);
+                self.boxes.entry(to).or_default().push_back(Letter {
+                    id: self.next_id,
+                    owner: from,
+                    expires: Instant::now() + Duration::from_millis(ttl_ms),
+                    sealed
+                });
+                vec![]
+            }
+            MailboxMessage::Fetch { address } => {
+                let Some(letters) = self.boxes.get(&address) else { return vec![]; };
+
+                // deliver what fits in the bandwidth quota; the rest
+                // stays for the next fetch
+                let mut delivered = vec![];
+                for letter in letters {
+                    if let Err(err) = self.ledger.charge_traffic(from, letter.sealed.len()) {
+                        if delivered.is_empty() {
+                            delivered.push(MailboxMessage::Refused(err.to_string()));
+                        }
+                        break;
+                    }
+                    delivered.push(MailboxMessage::Deliver { id: letter.id, sealed: letter.sealed.clone() });
+                }
+                delivered
+            }
+            MailboxMessage::Ack { address, id } => {
+                if let Some(letters) = self.boxes.get_mut(&address) {
+                    Mailbox::remove(letters, &mut self.ledger, |x| x.id == id);
+                }
+                vec![]
+            }
+            // mailboxes don't recieve mail themselves
+            MailboxMessage::Deliver { .. } | MailboxMessage::Refused(_) => vec![],
+        }
+    }
+
+    fn remove(letters: &mut VecDeque<Letter>, ledger: &mut QuotaLedger,
+              doomed: impl Fn(&Letter) -> bool) {
+        letters.retain(|x| {
+            if doomed(x) {
+                ledger.release_storage(x.owner, x.sealed.len());
+                return false;
+            }
+            true
+        });
+    }
+}
@@ -9,12 +9,69 @@ pub const DEFAULT_STUN_SERVERS:&[&str] = &[
 ];
 /// default size of a queue before we block
 pub const DEFAULT_QUEUE_SIZE: usize = 16;
+/// default most queued messages an agent may have and still be ready
+pub const DEFAULT_MAX_BACKLOG: usize = 256;
 
 mod utils;
 mod connection;
 mod agent;
+mod mux;
+mod snapshot;
+mod storage;
+mod keystore;
+mod registry;
+mod control;
+mod noise;
+mod relay;
+mod mailbox;
+mod quota;
+mod federation;
+mod trace;
+mod inspect;
+mod goodbye;
+mod config;
+mod watermark;
+mod headers;
+mod span;
+mod health;
+mod reload;
+mod rooms;
+mod bans;
+mod invite;
+mod audit;
+mod handoff;
+mod parts;
+mod bench;
 
 pub use utils::*;
 pub use connection::*;
 pub use agent::*;
+pub use mux::*;
+pub use snapshot::*;
+pub use storage::*;
+pub use keystore::*;
+pub use registry::*;
+pub use control::*;
+pub use noise::*;
+pub use relay::*;
+pub use mailbox::*;
+pub use quota::*;
+pub use federation::*;
+pub use trace::{TRACE_TARGET, Direction, describe};
+pub use inspect::*;
+pub use goodbye::*;
+pub use config::*;
+pub use headers::*;
+pub use health::*;
+pub use reload::*;
+pub use rooms::*;
+pub use bans::*;
+pub use invite::*;
+pub use audit::*;
+pub use handoff::*;
+pub use parts::*;
+pub use bench::*;
+pub use span::{TRACEPARENT_HEADER, SPAN_TARGET, TraceContext};
+pub use watermark::{DEFAULT_STALL_THRESHOLD, QueueDirection, QueueDepth, QueueStall, QueueStuck,
+                    QueueExpiry, Traffic};
 
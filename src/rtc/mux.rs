@@ -0,0 +1,292 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::mpsc::{Sender, Receiver, channel};
+use tokio::task::JoinHandle;
+use log::{error, debug};
+
+use super::connection::Connection;
+use super::utils::spawn_named;
+use super::{MAX_MSG_SIZE_BYTES, DEFAULT_QUEUE_SIZE};
+
+/// identifier of a logical stream within a [Multiplexer]
+pub type StreamId = u32;
+
+/// size of a [Frame] header on the wire: stream id + kind
+pub const FRAME_HEADER_BYTES: usize = 5;
+/// largest payload which fits into a single [Frame]
+pub const MAX_FRAME_PAYLOAD_BYTES: usize = MAX_MSG_SIZE_BYTES - FRAME_HEADER_BYTES;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// application payload for a stream
+    Data,
+    /// the remote consumed messages; payload is a u32 count of credits returned
+    Credit,
+}
+
+impl FrameKind {
+    fn tag(self) -> u8 {
+        match self {
+            FrameKind::Data => 0,
+            FrameKind::Credit => 1,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<FrameKind> {
+        match tag {
+            0 => Ok(FrameKind::Data),
+            1 => Ok(FrameKind::Credit),
+            x => Err(anyhow!("unknown frame kind {x}"))
+        }
+    }
+}
+
+/// a single message on a multiplexed data channel
+///
+/// # Notes
+/// The wire layout is a big endian u32 stream id, a one byte
+/// [FrameKind], and then the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub stream: StreamId,
+    pub kind: FrameKind,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FRAME_HEADER_BYTES + self.payload.len());
+        buf.extend_from_slice(&self.stream.to_be_bytes());
+        buf.push(self.kind.tag());
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Frame> {
+        if buf.len() < FRAME_HEADER_BYTES {
+            return Err(anyhow!("frame of {} bytes is shorter than its header", buf.len()));
+        }
+
+        Ok(Frame {
+            stream: StreamId::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            kind: FrameKind::from_tag(buf[4])?,
+            payload: buf[FRAME_HEADER_BYTES..].to_vec(),
+        })
+    }
+}
+
+struct StreamState {
+    credits: Arc<Semaphore>,
+    sender: Sender<Vec<u8>>,
+    reciever: Arc<Mutex<Receiver<Vec<u8>>>>,
+}
+
+/// streams of a [Multiplexer] by id; [Option::None] once the channel is dead
+type StreamTable = Arc<Mutex<Option<HashMap<StreamId, StreamState>>>>;
+
+/// carries many logical streams over a single data channel
+///
+/// # Notes
+/// Each stream has a window of `window` messages: a sender may only
+/// have that many messages outstanding until the remote reads them and
+/// returns credit, so one busy stream can't starve the others.
+///
+/// Both ends must be created with the same `window`, and the data
+/// channel must already have been created with [Connection::channel].
+///
+/// # Examples
+///
+/// ```ignore
+/// cnx.channel("docs").await?;
+/// let mux = Multiplexer::new(cnx.clone(), "docs", None);
+/// let todo = mux.stream(1).await;
+/// let notes = mux.stream(2).await;
+/// todo.send(b"hello".to_vec()).await?;
+/// let msg = notes.recv().await;
+/// ```
+pub struct Multiplexer {
+    cnx: Arc<Connection>,
+    channel: String,
+    streams: StreamTable,
+    window: usize,
+    worker: JoinHandle<()>,
+}
+
+impl Multiplexer {
+    pub fn new(cnx: Arc<Connection>, channel: &str, window: Option<usize>) -> Multiplexer {
+        let window = window.unwrap_or(DEFAULT_QUEUE_SIZE);
+        let streams: StreamTable = Arc::new(Mutex::new(Some(HashMap::new())));
+
+        let worker = spawn_named(&format!("synch mux '{channel}'"), Multiplexer::_read_worker(
+            cnx.clone(), channel.to_owned(), streams.clone(), window
+        ));
+
+        Multiplexer {
+            cnx,
+            channel: channel.to_owned(),
+            streams,
+            window,
+            worker,
+        }
+    }
+
+    /// open (or get) a logical stream
+    ///
+    /// # Notes
+    /// once the underlying channel is dead, the stream given is already
+    /// closed: [MuxStream::recv] returns [Option::None] and
+    /// [MuxStream::send] fails.
+    pub async fn stream(&self, id: StreamId) -> MuxStream {
+        let mut streams = self.streams.lock().await;
+        let mut closed = HashMap::new();
+        let state = match streams.as_mut() {
+            Some(streams) => Multiplexer::entry(streams, id, self.window),
+            None => {
+                let state = Multiplexer::entry(&mut closed, id, self.window);
+                state.credits.close();
+                state
+            }
+        };
+
+        MuxStream {
+            id,
+            cnx: self.cnx.clone(),
+            channel: self.channel.clone(),
+            credits: state.credits.clone(),
+            reciever: state.reciever.clone(),
+            consumed: AtomicUsize::new(0),
+            window: self.window,
+        }
+    }
+
+    fn entry(streams: &mut HashMap<StreamId, StreamState>,
+             id: StreamId, window: usize) -> &StreamState {
+        streams.entry(id).or_insert_with(|| {
+            let (sender, reciever) = channel(window);
+            StreamState {
+                credits: Arc::new(Semaphore::new(window)),
+                sender,
+                reciever: Arc::new(Mutex::new(reciever)),
+            }
+        })
+    }
+
+    async fn _read_worker(cnx: Arc<Connection>, channel: String,
+                          streams: StreamTable, window: usize) {
+        while let Some((_, raw)) = cnx.recv(&channel).await {
+            let frame = match Frame::decode(&raw) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    error!("dropping malformed frame on '{channel}': {err}");
+                    continue;
+                }
+            };
+
+            let mut streams = streams.lock().await;
+            let Some(streams) = streams.as_mut() else { break };
+            let state = Multiplexer::entry(streams, frame.stream, window);
+
+            match frame.kind {
+                FrameKind::Data => {
+                    // never block here, otherwise one full stream would
+                    // stall every other stream on the channel
+                    if state.sender.try_send(frame.payload).is_err() {
+                        error!("stream {} on '{channel}' overran its window, dropping",
+                               frame.stream);
+                    }
+                }
+                FrameKind::Credit => {
+                    let credit = match <[u8; 4]>::try_from(frame.payload.as_slice()) {
+                        Ok(x) => u32::from_be_bytes(x) as usize,
+                        Err(_) => {
+                            error!("malformed credit frame for stream {}", frame.stream);
+                            continue;
+                        }
+                    };
+                    debug!("stream {} on '{channel}' got {credit} credits", frame.stream);
+                    state.credits.add_permits(credit);
+                }
+            }
+        }
+
+        // dropping every sender ends each stream's reciever, and closing
+        // the credits wakes up anyone still waiting to send
+        debug!("channel '{channel}' is dead, closing its streams");
+        if let Some(streams) = streams.lock().await.take() {
+            for state in streams.values() {
+                state.credits.close();
+            }
+        }
+    }
+}
+
+impl Drop for Multiplexer {
+    fn drop(&mut self) {
+        self.worker.abort();
+    }
+}
+
+/// one logical stream of a [Multiplexer]
+pub struct MuxStream {
+    id: StreamId,
+    cnx: Arc<Connection>,
+    channel: String,
+    credits: Arc<Semaphore>,
+    reciever: Arc<Mutex<Receiver<Vec<u8>>>>,
+    consumed: AtomicUsize,
+    window: usize,
+}
+
+impl MuxStream {
+    pub fn id(&self) -> StreamId {
+        self.id
+    }
+
+    /// send a message on this stream
+    ///
+    /// # Notes
+    /// blocks until the remote has room in its window for us.
+    pub async fn send(&self, data: Vec<u8>) -> Result<()> {
+        if data.len() > MAX_FRAME_PAYLOAD_BYTES {
+            return Err(anyhow!("payload of {} bytes exceeds the {} byte frame limit",
+                               data.len(), MAX_FRAME_PAYLOAD_BYTES));
+        }
+
+        // the permit is given back by the remote's credit frame, not on drop
+        self.credits.acquire().await?.forget();
+
+        self.cnx.send(&self.channel, Frame {
+            stream: self.id,
+            kind: FrameKind::Data,
+            payload: data
+        }.encode()).await
+    }
+
+    /// read a message from this stream
+    ///
+    /// # Notes
+    /// blocks until something is given; returns [Option::None]
+    /// once the underlying channel is dead.
+    pub async fn recv(&self) -> Option<Vec<u8>> {
+        let data = self.reciever.lock().await.recv().await?;
+
+        // hand credit back in batches of half a window
+        let consumed = self.consumed.fetch_add(1, Ordering::SeqCst) + 1;
+        if consumed >= (self.window / 2).max(1) {
+            self.consumed.fetch_sub(consumed, Ordering::SeqCst);
+            let credit = Frame {
+                stream: self.id,
+                kind: FrameKind::Credit,
+                payload: (consumed as u32).to_be_bytes().to_vec()
+            };
+            if let Err(err) = self.cnx.send(&self.channel, credit.encode()).await {
+                error!("failed to return credit on stream {}: {err}", self.id);
+            }
+        }
+
+        Some(data)
+    }
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+use anyhow::{Result, anyhow};
+use snow::{Builder, HandshakeState, TransportState};
+use tokio::sync::Mutex;
+
+use super::control::ControlMessage;
+use super::mux::{Multiplexer, MuxStream, MAX_FRAME_PAYLOAD_BYTES};
+
+/// Noise handshake and ciphers the control stream is secured with
+pub const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// longest a [SecureControl] handshake may take
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// bytes a Noise transport message adds to what it carries
+const NOISE_TAG_BYTES: usize = 16;
+/// largest control message which fits into a single frame once encrypted
+pub const MAX_CONTROL_BYTES: usize = MAX_FRAME_PAYLOAD_BYTES - NOISE_TAG_BYTES;
+/// start of every handshake prologue, so other protocols' handshakes never match
+const PROLOGUE: &[u8] = b"synch control v1|";
+
+/// long-term Noise key an agent proves itself with on the control stream
+#[derive(Clone)]
+pub struct NoiseKey {
+    private: Vec<u8>,
+    public: Vec<u8>,
+}
+
+impl NoiseKey {
+    /// make a fresh random key
+    pub fn generate() -> Result<NoiseKey> {
+        let keypair = Builder::new(NOISE_PATTERN.parse()?).generate_keypair()?;
+        Ok(NoiseKey { private: keypair.private, public: keypair.public })
+    }
+
+    /// the public half, which peers see as our remote static key
+    pub fn public(&self) -> &[u8] {
+        &self.public
+    }
+}
+
+impl std::fmt::Debug for NoiseKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("NoiseKey(..)")
+    }
+}
+
+/// the control stream to one peer, encrypted and mutually authenticated
+///
+/// # Notes
+/// Made by [super::Agent::secure_control] with a Noise XX handshake
+/// on top of the connection's DTLS. The handshake's prologue is both
+/// ends' DTLS certificate fingerprints, and each end sends its own
+/// fingerprints under encryption, checked against the ones the
+/// connection was made with; someone who rewrote the offer or answer to
+/// sit in the middle sees other fingerprints on each side, and fails
+/// the handshake. What it proves beyond DTLS is that the peer holds
+/// [SecureControl::remote_static], which can be pinned across
+/// connections.
+///
+/// Messages are encrypted with keys only this session knows, and must
+/// arrive in the order they were sent, as a [MuxStream] keeps them.
+pub struct SecureControl {
+    /// kept for its read worker, which feeds `stream`, if we made it
+    _mux: Option<Multiplexer>,
+    stream: MuxStream,
+    transport: std::sync::Mutex<TransportState>,
+    /// held while encrypting and sending, so messages go out in nonce order
+    sending: Mutex<()>,
+    remote_static: Vec<u8>,
+}
+
+impl SecureControl {
+    /// Run the handshake over `stream`, as the initiator if we dialled.
+    ///
+    /// # Arguments
+    ///
+    /// * `local` - our DTLS fingerprints on this connection.
+    /// * `remote` - the peer's DTLS fingerprints, as the connection saw them.
+    pub async fn handshake(stream: MuxStream, initiator: bool, key: &NoiseKey,
+                           local: &[String], remote: &[String]) -> Result<SecureControl> {
+        // sorted, so both ends agree on the prologue
+        let mut sides = [local.join(","), remote.join(",")];
+        sides.sort();
+        let prologue = [PROLOGUE, sides.join("|").as_bytes()].concat();
+
+        let builder = Builder::new(NOISE_PATTERN.parse()?)
+            .local_private_key(&key.private)
+            .prologue(&prologue);
+        let state = match initiator {
+            true => builder.build_initiator()?,
+            false => builder.build_responder()?,
+        };
+
+        let proof = local.join(",");
+        let transport = tokio::time::timeout(HANDSHAKE_TIMEOUT,
+                                             exchange(&stream, state, initiator, proof.as_bytes(),
+                                                      remote.join(",").as_bytes())).await
+            .map_err(|_| anyhow!("control handshake timed out after {HANDSHAKE_TIMEOUT:?}"))??;
+        let remote_static = transport.get_remote_static()
+            .ok_or(anyhow!("peer sent no static key"))?
+            .to_vec();
+
+        Ok(SecureControl {
+            _mux: None,
+            stream,
+            transport: std::sync::Mutex::new(transport),
+            sending: Mutex::new(()),
+            remote_static,
+        })
+    }
+
+    /// keep `mux` alive for as long as this session
+    pub(crate) fn with_mux(mut self, mux: Multiplexer) -> SecureControl {
+        self._mux = Some(mux);
+        self
+    }
+
+    /// the Noise static key the peer proved it holds
+    pub fn remote_static(&self) -> &[u8] {
+        &self.remote_static
+    }
+
+    /// encrypt and send a message of at most [MAX_CONTROL_BYTES]
+    pub async fn send(&self, plain: &[u8]) -> Result<()> {
+        if plain.len() > MAX_CONTROL_BYTES {
+            return Err(anyhow!("control message of {} bytes exceeds the {MAX_CONTROL_BYTES} byte limit",
+                               plain.len()));
+        }
+
+        let _sending = self.sending.lock().await;
+        let mut sealed = vec![0u8; plain.len() + NOISE_TAG_BYTES];
+        let n = self.transport.lock()
+            .map_err(|_| anyhow!("control transport poisoned"))?
+            .write_message(plain, &mut sealed)?;
+        sealed.truncate(n);
+        self.stream.send(sealed).await
+    }
+
+    /// Recieve and decrypt the next message.
+    ///
+    /// # Notes
+    /// returns [Option::None] once the stream is dead; a message which
+    /// fails to decrypt is an error, and the session is unusable after it.
+    pub async fn recv(&self) -> Option<Result<Vec<u8>>> {
+        let sealed = self.stream.recv().await?;
+        let mut plain = vec![0u8; sealed.len()];
+        let opened = self.transport.lock()
+            .map_err(|_| anyhow!("control transport poisoned"))
+            .and_then(|mut x| Ok(x.read_message(&sealed, &mut plain)?));
+
+        Some(opened.map(|n| {
+            plain.truncate(n);
+            plain
+        }))
+    }
+
+    pub async fn send_message(&self, message: &ControlMessage) -> Result<()> {
+        self.send(&message.encode()?).await
+    }
+
+    pub async fn recv_message(&self) -> Option<Result<ControlMessage>> {
+        Some(self.recv().await?.and_then(|x| ControlMessage::decode(&x)))
+    }
+}
+
+/// Trade the three XX messages, each end proving its fingerprints.
+///
+/// # Return
+/// The session, once the peer's proof matches `expected`.
+async fn exchange(stream: &MuxStream, mut state: HandshakeState, initiator: bool,
+                  proof: &[u8], expected: &[u8]) -> Result<TransportState> {
+    let mut buf = vec![0u8; MAX_FRAME_PAYLOAD_BYTES];
+
+    // -> e
+    // <- e, ee, s, es + responder's proof
+    // -> s, se + initiator's proof
+    let heard = if initiator {
+        let n = state.write_message(&[], &mut buf)?;
+        stream.send(buf[..n].to_vec()).await?;
+        let heard = read(stream, &mut state).await?;
+        let n = state.write_message(proof, &mut buf)?;
+        stream.send(buf[..n].to_vec()).await?;
+        heard
+    } else {
+        read(stream, &mut state).await?;
+        let n = state.write_message(proof, &mut buf)?;
+        stream.send(buf[..n].to_vec()).await?;
+        read(stream, &mut state).await?
+    };
+
+    if heard != expected {
+        return Err(anyhow!("peer proved fingerprints {:?}, but connected with {:?}",
+                           String::from_utf8_lossy(&heard), String::from_utf8_lossy(expected)));
+    }
+    Ok(state.into_transport_mode()?)
+}
+
+/// read the next handshake message, giving its payload
+async fn read(stream: &MuxStream, state: &mut HandshakeState) -> Result<Vec<u8>> {
+    let message = stream.recv().await
+        .ok_or(anyhow!("control stream closed during the handshake"))?;
+    let mut payload = vec![0u8; message.len()];
+    let n = state.read_message(&message, &mut payload)
+        .map_err(|err| anyhow!("control handshake failed: {err}"))?;
+    payload.truncate(n);
+
+    Ok(payload)
+}
@@ -0,0 +1,82 @@
+use anyhow::{Result, anyhow};
+use sha2::{Sha256, Digest};
+
+/// hex digits of the checksum carried by every part
+const CHECKSUM_LEN: usize = 8;
+
+fn checksum(blob: &str) -> String {
+    let mut sum = format!("{:x}", Sha256::digest(blob.as_bytes()));
+    sum.truncate(CHECKSUM_LEN);
+    sum
+}
+
+/// Split an offer or answer into parts of at most `max_len` characters.
+///
+/// # Notes
+/// Each part reads `index/count/checksum:text`, counting from 1, so
+/// [join_parts] can put them back in any order and tell when one is
+/// missing, truncated or from another blob.
+///
+/// # Examples
+///
+/// ```ignore
+/// for part in split_parts(&offer.get(), 500)? {
+///     chat.send(part);
+/// }
+/// ```
+pub fn split_parts(blob: &str, max_len: usize) -> Result<Vec<String>> {
+    let sum = checksum(blob);
+    let chars: Vec<char> = blob.chars().collect();
+
+    // the header grows with the number of parts, which depends on the
+    // room the header leaves; settle on a count which fits itself
+    let mut count = 1;
+    loop {
+        let header = format!("{count}/{count}/{sum}:").len();
+        let room = max_len.checked_sub(header).filter(|x| *x > 0)
+            .ok_or(anyhow!("parts of {max_len} characters leave no room after the header"))?;
+        let needed = chars.len().div_ceil(room).max(1);
+        if needed <= count {
+            return Ok(chars.chunks(room).chain(chars.is_empty().then_some(&[][..]))
+                      .enumerate()
+                      .map(|(i, x)| format!("{}/{needed}/{sum}:{}", i + 1, x.iter().collect::<String>()))
+                      .collect());
+        }
+        count = needed;
+    }
+}
+
+/// Put an offer or answer back together from the parts made by [split_parts].
+///
+/// # Notes
+/// Parts may be given in any order, and with surrounding whitespace.
+pub fn join_parts<S: AsRef<str>>(parts: &[S]) -> Result<String> {
+    let mut parsed: Vec<(usize, usize, &str, &str)> = parts.iter()
+        .map(|part| {
+            let part = part.as_ref().trim();
+            let (header, text) = part.split_once(':')
+                .ok_or(anyhow!("part '{part}' has no header"))?;
+            let mut fields = header.splitn(3, '/');
+            let (Some(index), Some(count), Some(sum)) = (fields.next(), fields.next(), fields.next()) else {
+                return Err(anyhow!("part header '{header}' is malformed"));
+            };
+            Ok((index.parse()?, count.parse()?, sum, text))
+        })
+        .collect::<Result<_>>()?;
+    parsed.sort_by_key(|x| x.0);
+
+    let (_, count, sum, _) = *parsed.first().ok_or(anyhow!("no parts given"))?;
+    if parsed.iter().any(|x| x.1 != count || x.2 != sum) {
+        return Err(anyhow!("parts come from more than one blob"));
+    }
+    let indices: Vec<usize> = parsed.iter().map(|x| x.0).collect();
+    if indices != (1..=count).collect::<Vec<_>>() {
+        return Err(anyhow!("expected parts 1 to {count}, got {indices:?}"));
+    }
+
+    let blob: String = parsed.iter().map(|x| x.3).collect();
+    if checksum(&blob) != sum {
+        return Err(anyhow!("parts do not match their checksum; one may be truncated"));
+    }
+    Ok(blob)
+}
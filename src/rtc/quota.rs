@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver, unbounded_channel};
+use log::warn;
+
+use super::agent::PeerId;
+
+/// window over which bandwidth is measured
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+/// limits on what one peer may use of a relay or mailbox node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    /// bytes this peer may have stored at once
+    pub max_stored_bytes: usize,
+    /// bytes this peer may send and be sent per second
+    pub max_bytes_per_sec: usize,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Quota {
+            max_stored_bytes: 64 * 1024 * 1024,
+            max_bytes_per_sec: 1024 * 1024,
+        }
+    }
+}
+
+/// what one peer is currently using
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub stored_bytes: usize,
+    /// bytes moved in the current one second window
+    pub window_bytes: usize,
+    /// bytes moved since accounting started
+    pub total_bytes: u64,
+    window_start: Instant,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Usage {
+            stored_bytes: 0,
+            window_bytes: 0,
+            total_bytes: 0,
+            window_start: Instant::now()
+        }
+    }
+}
+
+/// a peer tried to go over its [Quota]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaEvent {
+    StorageExceeded { peer: PeerId, requested: usize, limit: usize },
+    BandwidthExceeded { peer: PeerId, requested: usize, limit: usize },
+}
+
+/// per-peer storage and bandwidth accounting
+///
+/// # Notes
+/// Every peer gets the default [Quota] unless given its own with
+/// [QuotaLedger::set_quota]. Charges which would go over a quota are
+/// refused, and a [QuotaEvent] is sent to every subscriber.
+#[derive(Debug, Default)]
+pub struct QuotaLedger {
+    default: Quota,
+    quotas: HashMap<PeerId, Quota>,
+    usage: HashMap<PeerId, Usage>,
+    subscribers: Vec<UnboundedSender<QuotaEvent>>,
+}
+
+impl QuotaLedger {
+    pub fn new(default: Quota) -> Self {
+        QuotaLedger { default, ..Default::default() }
+    }
+
+    /// give one peer a quota other than the default
+    pub fn set_quota(&mut self, peer: PeerId, quota: Quota) {
+        self.quotas.insert(peer, quota);
+    }
+
+    pub fn quota(&self, peer: PeerId) -> Quota {
+        self.quotas.get(&peer).copied().unwrap_or(self.default)
+    }
+
+    pub fn usage(&self, peer: PeerId) -> Usage {
+        self.usage.get(&peer).copied().unwrap_or_default()
+    }
+
+    /// every peer we have accounted for, and what they use
+    pub fn usages(&self) -> impl Iterator<Item = (PeerId, Usage)> + '_ {
+        self.usage.iter().map(|(peer, usage)| (*peer, *usage))
+    }
+
+    /// get [QuotaEvent]s as peers go over their quotas
+    pub fn subscribe(&mut self) -> UnboundedReceiver<QuotaEvent> {
+        let (sender, reciever) = unbounded_channel();
+        self.subscribers.push(sender);
+        reciever
+    }
+
+    /// account for `bytes` sent to or from `peer`
+    pub fn charge_traffic(&mut self, peer: PeerId, bytes: usize) -> Result<()> {
+        let limit = self.quota(peer).max_bytes_per_sec;
+        let usage = self.usage.entry(peer).or_default();
+
+        if usage.window_start.elapsed() >= BANDWIDTH_WINDOW {
+            usage.window_start = Instant::now();
+            usage.window_bytes = 0;
+        }
+        if usage.window_bytes + bytes > limit {
+            return self.exceeded(QuotaEvent::BandwidthExceeded { peer, requested: bytes, limit });
+        }
+
+        usage.window_bytes += bytes;
+        usage.total_bytes += bytes as u64;
+        Ok(())
+    }
+
+    /// account for `bytes` stored on behalf of `peer`
+    pub fn charge_storage(&mut self, peer: PeerId, bytes: usize) -> Result<()> {
+        let limit = self.quota(peer).max_stored_bytes;
+        let usage = self.usage.entry(peer).or_default();
+
+        if usage.stored_bytes + bytes > limit {
+            return self.exceeded(QuotaEvent::StorageExceeded { peer, requested: bytes, limit });
+        }
+
+        usage.stored_bytes += bytes;
+        Ok(())
+    }
+
+    /// `bytes` stored on behalf of `peer` were deleted
+    pub fn release_storage(&mut self, peer: PeerId, bytes: usize) {
+        if let Some(usage) = self.usage.get_mut(&peer) {
+            usage.stored_bytes = usage.stored_bytes.saturating_sub(bytes);
+        }
+    }
+
+    fn exceeded(&mut self, event: QuotaEvent) -> Result<()> {
+        warn!("quota exceeded: {event:?}");
+        self.subscribers.retain(|x| x.send(event.clone()).is_ok());
+        Err(anyhow!("quota exceeded: {event:?}"))
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use log::debug;
+
+use super::agent::PeerId;
+use super::mux::StreamId;
+use super::invite::Permission;
+
+/// Stream reserved for [RegistryMessage]s; never assigned to a document.
+///
+/// # Notes
+/// Besides the connection's DTLS, control messages can be secured end
+/// to end with [super::Agent::secure_control], which runs a Noise XX
+/// handshake on this stream bound to the fingerprints
+/// [super::Agent::fingerprints] gives.
+pub const CONTROL_STREAM: StreamId = 0;
+/// number of document names sent per [RegistryMessage::Advertise]
+const NAMES_PER_ADVERTISEMENT: usize = 16;
+
+/// lifecycle state of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DocState {
+    /// being synced
+    Active,
+    /// kept locally but no longer synced
+    Archived,
+}
+
+/// advisory exclusive hold on a document, granted by the head
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    /// the peer holding it, as the head knows it
+    pub holder: PeerId,
+    pub expires: Instant,
+}
+
+/// what we know about a document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentInfo {
+    pub name: String,
+    pub stream: StreamId,
+    /// our own copy of it, if we have one
+    pub state: Option<DocState>,
+    /// whether we want to recieve its tapes
+    pub subscribed: bool,
+    /// peers who told us they hold it
+    pub advertised_by: BTreeSet<PeerId>,
+    /// who currently has the document leased, as last told by the head
+    pub lease: Option<Lease>,
+}
+
+/// message exchanged between registries on [CONTROL_STREAM]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegistryMessage {
+    /// the sender holds these documents (in addition to any advertised before)
+    Advertise(Vec<String>),
+    /// this document was deleted swarm-wide; stop advertising it
+    Tombstone(String),
+    /// to the head: grant (or renew) the sender a lease for `ttl_ms`
+    AcquireLease { name: String, ttl_ms: u64 },
+    /// to the head: give up the sender's lease early
+    ReleaseLease { name: String },
+    /// from the head: who holds a document's lease, and for how much longer
+    Lease { name: String, holder: Option<PeerId>, ttl_ms: u64 },
+    /// from the head, to one child: the [PeerId] the head knows it by
+    Assign(PeerId),
+}
+
+impl RegistryMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<RegistryMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+
+    /// what a peer must be allowed to do for the head to act on this
+    pub fn permission(&self) -> Permission {
+        match self {
+            RegistryMessage::Advertise(_) => Permission::CreateDocument,
+            RegistryMessage::Tombstone(_) => Permission::DeleteDocument,
+            RegistryMessage::AcquireLease { .. } | RegistryMessage::ReleaseLease { .. } => {
+                Permission::LeaseDocument
+            }
+            // only the head hands out leases and ids
+            RegistryMessage::Lease { .. } | RegistryMessage::Assign(_) => Permission::ChangeAcls,
+        }
+    }
+}
+
+/// the [PeerId] a head passes to its own [Registry::handle], as it is no child of itself
+pub const HEAD_PEER: PeerId = PeerId::MAX;
+
+/// stream a document named `name` is carried on
+///
+/// # Notes
+/// derived from the name so every peer agrees without negotiating.
+pub fn stream_for(name: &str) -> StreamId {
+    let digest = Sha256::digest(name.as_bytes());
+    match StreamId::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) {
+        CONTROL_STREAM => 1,
+        x => x
+    }
+}
+
+/// swarm-wide view of which documents exist
+///
+/// # Notes
+/// The registry only keeps state; the application sends the
+/// [RegistryMessage]s it returns to peers (usually over a
+/// [super::Multiplexer] on [CONTROL_STREAM]) and feeds recieved ones to
+/// [Registry::handle].
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut registry = Registry::new();
+/// registry.create("todo")?;
+/// for message in registry.advertisements() {
+///     broadcast(message.encode()?);
+/// }
+/// // ... later
+/// let tombstone = registry.delete("todo")?;
+/// broadcast(tombstone.encode()?);
+/// ```
+#[derive(Debug)]
+pub struct Registry {
+    docs: BTreeMap<String, DocumentInfo>,
+    tombstones: BTreeSet<String>,
+    /// who the head knows us as, once told
+    peer: Option<PeerId>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            docs: BTreeMap::new(),
+            tombstones: BTreeSet::new(),
+            peer: None,
+        }
+    }
+
+    /// create a document locally, subscribing to it
+    ///
+    /// # Return
+    /// The stream the document is carried on.
+    pub fn create(&mut self, name: &str) -> Result<StreamId> {
+        if self.tombstones.contains(name) {
+            return Err(anyhow!("document '{name}' was deleted"));
+        }
+
+        let stream = stream_for(name);
+        if let Some(other) = self.docs.values().find(|x| x.stream == stream && x.name != name) {
+            return Err(anyhow!("document '{name}' collides with '{}' on stream {stream}",
+                               other.name));
+        }
+
+        let doc = self.entry(name);
+        doc.state = Some(DocState::Active);
+        doc.subscribed = true;
+
+        Ok(stream)
+    }
+
+    /// every document we hold or a peer advertised
+    pub fn list(&self) -> Vec<&DocumentInfo> {
+        self.docs.values().collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DocumentInfo> {
+        self.docs.get(name)
+    }
+
+    /// start recieving a document's tapes
+    pub fn subscribe(&mut self, name: &str) -> Result<StreamId> {
+        let doc = self.known(name)?;
+        doc.subscribed = true;
+        if doc.state.is_none() {
+            doc.state = Some(DocState::Active);
+        }
+
+        Ok(doc.stream)
+    }
+
+    /// stop recieving a document's tapes, keeping whatever we have
+    pub fn unsubscribe(&mut self, name: &str) -> Result<()> {
+        self.known(name)?.subscribed = false;
+
+        Ok(())
+    }
+
+    /// stop syncing a document but keep its state
+    pub fn archive(&mut self, name: &str) -> Result<()> {
+        let doc = self.known(name)?;
+        if doc.state.is_none() {
+            return Err(anyhow!("document '{name}' is not held locally"));
+        }
+        doc.state = Some(DocState::Archived);
+        doc.subscribed = false;
+
+        Ok(())
+    }
+
+    /// delete a document swarm-wide
+    ///
+    /// # Return
+    /// The tombstone to send to every peer.
+    pub fn delete(&mut self, name: &str) -> Result<RegistryMessage> {
+        self.known(name)?;
+        self.tombstone(name);
+
+        Ok(RegistryMessage::Tombstone(name.to_owned()))
+    }
+
+    /// whether a document was deleted swarm-wide
+    pub fn is_deleted(&self, name: &str) -> bool {
+        self.tombstones.contains(name)
+    }
+
+    /// advertisements of everything we hold, to send to peers
+    pub fn advertisements(&self) -> Vec<RegistryMessage> {
+        let names: Vec<String> = self.docs.values()
+            .filter(|x| x.state.is_some())
+            .map(|x| x.name.clone())
+            .collect();
+
+        names.chunks(NAMES_PER_ADVERTISEMENT)
+            .map(|x| RegistryMessage::Advertise(x.to_vec()))
+            .collect()
+    }
+
+    /// a peer went away; drop everything it advertised
+    pub fn forget_peer(&mut self, peer: PeerId) {
+        for doc in self.docs.values_mut() {
+            doc.advertised_by.remove(&peer);
+        }
+        self.docs.retain(|_, x| x.state.is_some() || !x.advertised_by.is_empty());
+    }
+
+    /// Set who the head knows us as, for [Registry::holds_lease].
+    ///
+    /// # Notes
+    /// A child learns this from the head's [RegistryMessage::Assign],
+    /// which [Registry::handle] applies; a head sets [HEAD_PEER] itself.
+    pub fn set_peer(&mut self, peer: PeerId) {
+        self.peer = Some(peer);
+    }
+
+    /// who the head knows us as, if we've been told
+    pub fn peer(&self) -> Option<PeerId> {
+        self.peer
+    }
+
+    /// ask the head for an exclusive lease on a document, or renew ours
+    ///
+    /// # Notes
+    /// The head grants leases to the [PeerId] a request came from, so
+    /// no peer can renew or release another's lease. A head sends each
+    /// new child a [RegistryMessage::Assign] with its id first, and
+    /// passes its own requests to its [Registry::handle] as [HEAD_PEER].
+    ///
+    /// # Return
+    /// The request to send to the head. The lease is ours once the head
+    /// answers and [Registry::holds_lease] says so.
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// // on the head, once a child is accepted
+    /// send_to(peer, RegistryMessage::Assign(peer).encode()?);
+    /// // on the child
+    /// send_to_head(registry.acquire_lease("todo", Duration::from_secs(30))?.encode()?);
+    /// // ... once the head's answer went through registry.handle()
+    /// if registry.holds_lease("todo") {
+    ///     migrate_schema();
+    ///     send_to_head(registry.release_lease("todo")?.encode()?);
+    /// }
+    /// ```
+    pub fn acquire_lease(&mut self, name: &str, ttl: Duration) -> Result<RegistryMessage> {
+        self.known(name)?;
+
+        Ok(RegistryMessage::AcquireLease {
+            name: name.to_owned(),
+            ttl_ms: ttl.as_millis() as u64
+        })
+    }
+
+    /// give up our lease on a document
+    pub fn release_lease(&mut self, name: &str) -> Result<RegistryMessage> {
+        self.known(name)?;
+
+        Ok(RegistryMessage::ReleaseLease { name: name.to_owned() })
+    }
+
+    /// whether we hold an unexpired lease on a document
+    pub fn holds_lease(&self, name: &str) -> bool {
+        self.docs.get(name)
+            .and_then(|x| x.lease.as_ref())
+            .is_some_and(|x| Some(x.holder) == self.peer && x.expires > Instant::now())
+    }
+
+    /// on the head: drop leases which ran out
+    ///
+    /// # Return
+    /// Messages to broadcast so every peer sees the leases are free.
+    pub fn expire_leases(&mut self) -> Vec<RegistryMessage> {
+        let now = Instant::now();
+
+        self.docs.values_mut()
+            .filter(|x| x.lease.as_ref().is_some_and(|l| l.expires <= now))
+            .map(|x| {
+                x.lease = None;
+                RegistryMessage::Lease { name: x.name.clone(), holder: None, ttl_ms: 0 }
+            })
+            .collect()
+    }
+
+    /// apply a message recieved from `peer`
+    ///
+    /// # Notes
+    /// A head takes messages from its children through
+    /// [super::Agent::control], which checks the sender's role before
+    /// passing them here; other peers apply what the head broadcasts.
+    /// Leases are granted to and released by `peer` alone.
+    ///
+    /// # Return
+    /// Messages to broadcast to every peer in response; only the head
+    /// answers lease requests.
+    pub fn handle(&mut self, peer: PeerId, message: RegistryMessage) -> Vec<RegistryMessage> {
+        match message {
+            RegistryMessage::Advertise(names) => {
+                for name in names {
+                    if self.tombstones.contains(&name) {
+                        debug!("peer {peer} advertised deleted document '{name}'");
+                        continue;
+                    }
+                    self.entry(&name).advertised_by.insert(peer);
+                }
+            }
+            RegistryMessage::Tombstone(name) => {
+                debug!("peer {peer} deleted document '{name}'");
+                self.tombstone(&name);
+            }
+            RegistryMessage::AcquireLease { name, ttl_ms } => {
+                let now = Instant::now();
+                let Ok(doc) = self.known(&name) else { return vec![]; };
+
+                // grant if free, expired, or a renewal by the holder
+                let free = doc.lease.as_ref()
+                    .is_none_or(|x| x.holder == peer || x.expires <= now);
+                if free {
+                    debug!("leasing '{name}' to peer {peer} for {ttl_ms}ms");
+                    doc.lease = Some(Lease {
+                        holder: peer,
+                        expires: now + Duration::from_millis(ttl_ms)
+                    });
+                }
+
+                return vec![Registry::lease_message(doc, now)];
+            }
+            RegistryMessage::ReleaseLease { name } => {
+                let now = Instant::now();
+                let Ok(doc) = self.known(&name) else { return vec![]; };
+
+                if doc.lease.as_ref().is_some_and(|x| x.holder == peer) {
+                    doc.lease = None;
+                    return vec![Registry::lease_message(doc, now)];
+                }
+            }
+            RegistryMessage::Lease { name, holder, ttl_ms } => {
+                if self.tombstones.contains(&name) {
+                    return vec![];
+                }
+                self.entry(&name).lease = holder.map(|holder| Lease {
+                    holder,
+                    expires: Instant::now() + Duration::from_millis(ttl_ms)
+                });
+            }
+            RegistryMessage::Assign(id) => {
+                debug!("the head knows us as peer {id}");
+                self.peer = Some(id);
+            }
+        }
+
+        vec![]
+    }
+
+    fn lease_message(doc: &DocumentInfo, now: Instant) -> RegistryMessage {
+        RegistryMessage::Lease {
+            name: doc.name.clone(),
+            holder: doc.lease.as_ref().map(|x| x.holder),
+            ttl_ms: doc.lease.as_ref()
+                .map_or(0, |x| x.expires.saturating_duration_since(now).as_millis() as u64)
+        }
+    }
+
+    fn tombstone(&mut self, name: &str) {
+        self.docs.remove(name);
+        self.tombstones.insert(name.to_owned());
+    }
+
+    fn entry(&mut self, name: &str) -> &mut DocumentInfo {
+        self.docs.entry(name.to_owned()).or_insert_with(|| DocumentInfo {
+            name: name.to_owned(),
+            stream: stream_for(name),
+            state: None,
+            subscribed: false,
+            advertised_by: BTreeSet::new(),
+            lease: None,
+        })
+    }
+
+    fn known(&mut self, name: &str) -> Result<&mut DocumentInfo> {
+        self.docs.get_mut(name)
+            .ok_or(anyhow!("no document named '{name}'"))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use log::debug;
+
+use super::agent::PeerId;
+use super::quota::{Quota, QuotaLedger};
+
+/// message between a peer and a [Relay]
+///
+/// # Notes
+/// Everything under `sealed` is encrypted by the peers with a
+/// [super::SecretKey] the relay never sees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayMessage {
+    /// to the relay: store a sealed snapshot, superseding every entry before it
+    Snapshot { doc: String, sealed: Bytes },
+    /// to the relay: store a sealed tape after everything already stored
+    Tape { doc: String, sealed: Bytes },
+    /// to the relay: send every entry of `doc` after sequence number `since`
+    Fetch { doc: String, since: u64 },
+    /// from the relay: one stored entry
+    Entry { doc: String, seq: u64, snapshot: bool, sealed: Bytes },
+    /// from the relay: the request went over the sender's [Quota] and was dropped
+    Refused(String),
+}
+
+impl RelayMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<RelayMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+#[derive(Debug)]
+struct LogEntry {
+    seq: u64,
+    snapshot: bool,
+    /// who stored it, and is charged for it
+    owner: PeerId,
+    sealed: Bytes,
+}
+
+#[derive(Debug, Default)]
+struct DocLog {
+    entries: VecDeque<LogEntry>,
+    /// sequence number of the newest entry; the first entry is 1
+    last: u64,
+}
+
+/// store-and-forward service run by an always-on super-peer
+///
+/// # Notes
+/// The relay keeps, per document, the latest sealed snapshot and every
+/// sealed tape after it, so two peers that are never online at the same
+/// time still converge: one stores, the other fetches later. It can't
+/// read what it stores; it only orders it.
+///
+/// Peers remember the last sequence number they fetched (starting at
+/// 0) and ask for everything after it when they come back; a peer which
+/// fell behind the latest snapshot gets the snapshot first.
+///
+/// Peers are charged for what they store and move against a per-peer
+/// [Quota].
+///
+/// # Examples
+///
+/// ```ignore
+/// // amy, before going offline
+/// let sealed = key.seal(&tape)?;
+/// send(RelayMessage::Tape { doc: "todo".into(), sealed: sealed.into() }.encode()?);
+///
+/// // on the super-peer
+/// for reply in relay.handle(from, RelayMessage::decode(&msg)?) {
+///     reply_to_sender(reply.encode()?);
+/// }
+///
+/// // bob, much later
+/// send(RelayMessage::Fetch { doc: "todo".into(), since: last_seen }.encode()?);
+/// ```
+#[derive(Debug, Default)]
+pub struct Relay {
+    docs: HashMap<String, DocLog>,
+    ledger: QuotaLedger,
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// a relay whose peers get `quota` unless given their own
+    pub fn with_quota(quota: Quota) -> Self {
+        Relay { ledger: QuotaLedger::new(quota), ..Default::default() }
+    }
+
+    /// per-peer accounting, to inspect usage or set quotas
+    pub fn ledger(&mut self) -> &mut QuotaLedger {
+        &mut self.ledger
+    }
+
+    /// total bytes of sealed payloads stored for a document
+    pub fn stored_bytes(&self, doc: &str) -> usize {
+        self.docs.get(doc)
+            .map_or(0, |x| x.entries.iter().map(|x| x.sealed.len()).sum())
+    }
+
+    /// apply a message from a peer
+    ///
+    /// # Return
+    /// The messages to send back to that peer.
+    pub fn handle(&mut self, from: PeerId, message: RelayMessage) -> Vec<RelayMessage> {
+        match message {
+            RelayMessage::Snapshot { doc, sealed } => {
+                if let Err(err) = self.charge(from, sealed.len()) {
+                    return vec![err];
+                }
+
+                let log = self.docs.entry(doc).or_default();
+                // everything before a snapshot is folded into it
+                for old in log.entries.drain(..) {
+                    self.ledger.release_storage(old.owner, old.sealed.len());
+                }
+                log.last += 1;
+                log.entries.push_back(LogEntry { seq: log.last, snapshot: true, owner: from, sealed });
+                vec![]
+            }
+            RelayMessage::Tape { doc, sealed } => {
+                if let Err(err) = self.charge(from, sealed.len()) {
+                    return vec![err];
+                }
+
+                let log = self.docs.entry(doc).or_default();
+                log.last += 1;
+                log.entries.push_back(LogEntry { seq: log.last, snapshot: false, owner: from, sealed });
+                vec![]
+            }
+            RelayMessage::Fetch { doc, since } => {
+                let Some(log) = self.docs.get(&doc) else { return vec![]; };
+                debug!("serving '{doc}' after {since}");
+
+                // a peer ahead of us saw a relay that lost its state, so
+                // it gets everything we have. Send what fits in the
+                // bandwidth quota; the peer asks again for the rest.
+                let mut sent = vec![];
+                for entry in log.entries.iter().filter(|x| x.seq > since || since > log.last) {
+                    if let Err(err) = self.ledger.charge_traffic(from, entry.sealed.len()) {
+                        if sent.is_empty() {
+                            sent.push(RelayMessage::Refused(err.to_string()));
+                        }
+                        break;
+                    }
+                    sent.push(RelayMessage::Entry {
+                        doc: doc.clone(),
+                        seq: entry.seq,
+                        snapshot: entry.snapshot,
+                        sealed: entry.sealed.clone()
+                    });
+                }
+                sent
+            }
+            // relays don't fetch from anyone
+            RelayMessage::Entry { .. } | RelayMessage::Refused(_) => vec![],
+        }
+    }
+
+    fn charge(&mut self, peer: PeerId, bytes: usize) -> Result<(), RelayMessage> {
+        self.ledger.charge_traffic(peer, bytes)
+            .and_then(|_| self.ledger.charge_storage(peer, bytes))
+            .map_err(|err| RelayMessage::Refused(err.to_string()))
+    }
+}
@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{UnboundedReceiver, unbounded_channel};
+use log::{warn, debug};
+
+use super::config::AgentConfig;
+use super::utils::spawn_named;
+
+/// how often [watch_config] checks its file by default
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// what became of one changed setting, from [super::Agent::reload]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// in effect now
+    Applied(String),
+    /// in effect for new connections; existing ones keep the old value until they reconnect
+    NeedsReconnect(String),
+    /// not used by the agent itself: whatever was built from it must be rebuilt
+    NeedsRestart(String),
+}
+
+/// Re-read the configuration file at `path` whenever it changes.
+///
+/// # Notes
+/// The file is checked every `interval`, and on unix immediately on
+/// SIGHUP. Each new configuration is read as
+/// `AgentConfig::from_file(path)?.with_env()`; one which fails to read
+/// is logged and skipped. Watching stops once the reciever is dropped.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut reloads = watch_config("synch.toml", DEFAULT_RELOAD_INTERVAL);
+/// while let Some(config) = reloads.recv().await {
+///     for change in agent.reload(config).await {
+///         info!("{change:?}");
+///     }
+/// }
+/// ```
+pub fn watch_config(path: impl Into<PathBuf>, interval: Duration) -> UnboundedReceiver<AgentConfig> {
+    let path = path.into();
+    let (sender, reciever) = unbounded_channel();
+
+    spawn_named("synch watch config", async move {
+        let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|x| x.modified()).ok();
+        let mut last: Option<SystemTime> = modified(&path);
+        let mut ticks = tokio::time::interval(interval);
+
+        #[cfg(unix)]
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).ok();
+
+        loop {
+            #[cfg(unix)]
+            let forced = tokio::select! {
+                _ = ticks.tick() => false,
+                Some(_) = async { hangups.as_mut()?.recv().await } => true,
+            };
+            #[cfg(not(unix))]
+            let forced = { ticks.tick().await; false };
+
+            if sender.is_closed() {
+                return;
+            }
+
+            let now = modified(&path);
+            if !forced && now == last {
+                continue;
+            }
+            last = now;
+
+            match AgentConfig::from_file(&path).and_then(|x| x.with_env()) {
+                Ok(config) => {
+                    debug!("reloaded configuration from '{}'", path.display());
+                    if sender.send(config).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => warn!("keeping the old configuration: {err}"),
+            }
+        }
+    });
+
+    reciever
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use anyhow::{Result, anyhow};
+use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver, unbounded_channel};
+
+use super::agent::{Agent, ChannelHandle, Offer, PeerId};
+use super::config::AgentConfig;
+use super::goodbye::GoodbyeReason;
+use super::watermark::Traffic;
+
+/// limits on one room of [Rooms]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoomPolicy {
+    /// most peers connected at once; [None] for no limit
+    pub max_peers: Option<usize>,
+    /// how long a room may sit empty before [Rooms::expire] closes it;
+    /// [None] to keep it until it is closed
+    pub idle_ttl: Option<Duration>,
+    /// most channels synced with [Rooms::sync]
+    pub max_documents: Option<usize>,
+    /// new peers are refused while the room moves more than this
+    pub max_bytes_per_sec: Option<u64>,
+    /// only admit peers with an invite bound to their fingerprints, so
+    /// bans hold in a room anyone may answer; see
+    /// [Agent::require_bound_invites]
+    pub bound_invites: bool,
+}
+
+/// what one room is using, from [Rooms::usage]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoomUsage {
+    /// peers connected and not departed
+    pub peers: usize,
+    /// channels synced in the room
+    pub documents: usize,
+    /// messages waiting in the room's queues, which is what holds its memory
+    pub backlog: usize,
+    /// bytes moved since the room was created
+    pub traffic: Traffic,
+    /// bytes moved per second, as of the last [Rooms::expire]
+    pub bytes_per_sec: u64,
+}
+
+/// something which happened to a room, from [Rooms::subscribe]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomEvent {
+    /// the last peer left the room
+    Emptied(String),
+    /// the room was closed, by [Rooms::close] or for sitting idle
+    Closed(String),
+}
+
+struct Room {
+    agent: Agent,
+    policy: RoomPolicy,
+    /// when the room was last seen empty, if it still is
+    idle_since: Option<Instant>,
+    /// when [Rooms::expire] last looked, and how many bytes had moved then
+    sampled: (Instant, u64),
+    bytes_per_sec: u64,
+}
+
+/// Many independent swarms hosted by one process, each headed by its own [Agent].
+///
+/// # Notes
+/// Rooms share nothing but the [AgentConfig] they were created with.
+/// Nothing runs in the background: call [Rooms::expire] periodically
+/// to notice rooms emptying out and close idle ones.
+///
+/// # Examples
+///
+/// ```ignore
+/// let mut rooms = Rooms::new(AgentConfig::default());
+/// rooms.create("standup", RoomPolicy {
+///     max_peers: Some(8),
+///     idle_ttl: Some(Duration::from_secs(600)),
+///     ..Default::default()
+/// })?;
+/// let notes = rooms.sync("standup", "notes").await?;
+///
+/// let mut offer = rooms.get("standup").unwrap().offer().await?;
+/// offer.answer(&answer_from_peer).await?;
+/// rooms.accept("standup", offer)?;
+///
+/// loop {
+///     rooms.expire().await;
+///     tokio::time::sleep(Duration::from_secs(10)).await;
+/// }
+/// ```
+pub struct Rooms {
+    settings: AgentConfig,
+    rooms: BTreeMap<String, Room>,
+    subscribers: Vec<UnboundedSender<RoomEvent>>,
+}
+
+impl Rooms {
+    pub fn new(settings: AgentConfig) -> Self {
+        Rooms { settings, rooms: BTreeMap::new(), subscribers: vec![] }
+    }
+
+    /// Open a room, with a new head agent.
+    ///
+    /// # Notes
+    /// A new room counts as empty, so it expires if nobody joins it
+    /// within its idle TTL.
+    pub fn create(&mut self, name: &str, policy: RoomPolicy) -> Result<&mut Agent> {
+        if self.rooms.contains_key(name) {
+            return Err(anyhow!("room '{name}' already exists"));
+        }
+
+        let mut agent = Agent::new(self.settings.clone())?;
+        agent.require_bound_invites(policy.bound_invites);
+        let room = Room {
+            agent,
+            policy,
+            idle_since: Some(Instant::now()),
+            sampled: (Instant::now(), 0),
+            bytes_per_sec: 0,
+        };
+        Ok(&mut self.rooms.entry(name.to_owned()).or_insert(room).agent)
+    }
+
+    /// the names of every open room
+    pub fn list(&self) -> Vec<&str> {
+        self.rooms.keys().map(|x| x.as_str()).collect()
+    }
+
+    /// the head agent of a room
+    pub fn get(&self, name: &str) -> Option<&Agent> {
+        self.rooms.get(name).map(|x| &x.agent)
+    }
+
+    /// the head agent of a room
+    ///
+    /// # Notes
+    /// channels synced on it directly are not held to `max_documents`;
+    /// use [Rooms::sync] for that.
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Agent> {
+        self.rooms.get_mut(name).map(|x| &mut x.agent)
+    }
+
+    pub fn policy(&self, name: &str) -> Option<RoomPolicy> {
+        self.rooms.get(name).map(|x| x.policy)
+    }
+
+    /// Change a room's limits; peers and documents already in a room
+    /// over its new limits stay, but no more are let in.
+    pub fn set_policy(&mut self, name: &str, policy: RoomPolicy) -> Result<()> {
+        let room = self.room(name)?;
+        room.agent.require_bound_invites(policy.bound_invites);
+        room.policy = policy;
+        Ok(())
+    }
+
+    /// Let an answered offer into a room, if it has space.
+    ///
+    /// # Notes
+    /// A refused offer is dropped, which closes its connection.
+    pub fn accept(&mut self, name: &str, offer: Offer) -> Result<PeerId> {
+        let room = self.room(name)?;
+        let peers = room.agent.health().peers;
+        if room.policy.max_peers.is_some_and(|x| peers >= x) {
+            return Err(anyhow!("room '{name}' is full with {peers} peers"));
+        }
+        if room.policy.max_bytes_per_sec.is_some_and(|x| room.bytes_per_sec > x) {
+            return Err(anyhow!("room '{name}' is busy, moving {} bytes per second",
+                               room.bytes_per_sec));
+        }
+
+        let id = room.agent.accept(offer)?;
+        room.idle_since = None;
+        Ok(id)
+    }
+
+    /// Sync a channel in a room, if it has space for another document.
+    ///
+    /// # Notes
+    /// Syncing a channel the room already has always succeeds; see
+    /// [Agent::sync].
+    pub async fn sync(&mut self, name: &str, channel_name: &str) -> Result<ChannelHandle> {
+        let room = self.room(name)?;
+        let documents = room.agent.channels();
+        if !documents.contains(&channel_name)
+            && room.policy.max_documents.is_some_and(|x| documents.len() >= x) {
+            return Err(anyhow!("room '{name}' is full with {} documents", documents.len()));
+        }
+
+        room.agent.sync(channel_name).await
+    }
+
+    /// what a room is using now
+    pub fn usage(&self, name: &str) -> Option<RoomUsage> {
+        let room = self.rooms.get(name)?;
+        let health = room.agent.health();
+
+        Some(RoomUsage {
+            peers: health.peers,
+            documents: room.agent.channels().len(),
+            backlog: health.backlog,
+            traffic: room.agent.traffic(),
+            bytes_per_sec: room.bytes_per_sec,
+        })
+    }
+
+    /// Close a room, saying goodbye to everyone in it.
+    pub async fn close(&mut self, name: &str, reason: GoodbyeReason) -> Result<()> {
+        let room = self.rooms.remove(name).ok_or(anyhow!("no room named '{name}'"))?;
+        room.agent.leave(reason).await?;
+        self.notify(RoomEvent::Closed(name.to_owned()));
+
+        Ok(())
+    }
+
+    /// Notice rooms which have emptied out, close those which have been
+    /// empty for longer than their idle TTL, and measure how fast every
+    /// room is moving bytes.
+    ///
+    /// # Return
+    /// The names of the rooms closed.
+    pub async fn expire(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let mut emptied = vec![];
+        let mut idle = vec![];
+
+        for (name, room) in self.rooms.iter_mut() {
+            let traffic = room.agent.traffic();
+            let total = traffic.bytes_in + traffic.bytes_out;
+            let elapsed = now.duration_since(room.sampled.0).as_secs_f64();
+            if elapsed > 0.0 {
+                room.bytes_per_sec = (total.saturating_sub(room.sampled.1) as f64 / elapsed) as u64;
+                room.sampled = (now, total);
+            }
+
+            if room.agent.health().peers > 0 {
+                room.idle_since = None;
+                continue;
+            }
+
+            let since = *room.idle_since.get_or_insert_with(|| {
+                emptied.push(name.clone());
+                now
+            });
+            if room.policy.idle_ttl.is_some_and(|x| now.duration_since(since) >= x) {
+                idle.push(name.clone());
+            }
+        }
+
+        emptied.into_iter().for_each(|x| self.notify(RoomEvent::Emptied(x)));
+        for name in idle.iter() {
+            let _ = self.close(name, GoodbyeReason::Shutdown).await;
+        }
+
+        idle
+    }
+
+    /// hear about rooms emptying out and closing
+    pub fn subscribe(&mut self) -> UnboundedReceiver<RoomEvent> {
+        let (sender, reciever) = unbounded_channel();
+        self.subscribers.push(sender);
+        reciever
+    }
+
+    fn room(&mut self, name: &str) -> Result<&mut Room> {
+        self.rooms.get_mut(name).ok_or(anyhow!("no room named '{name}'"))
+    }
+
+    fn notify(&mut self, event: RoomEvent) {
+        self.subscribers.retain(|x| x.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::io::Read;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Digest};
+use log::{debug, error};
+
+use super::mux::{Multiplexer, MuxStream, StreamId};
+
+/// largest number of snapshot bytes carried per chunk, leaving room for framing
+pub const SNAPSHOT_CHUNK_BYTES: usize = 1024;
+/// smallest chunk cut by [chunks], unless the snapshot ends first
+const MIN_CHUNK_BYTES: usize = SNAPSHOT_CHUNK_BYTES / 4;
+/// cut a chunk when the rolling hash has these bits clear (~512 byte chunks)
+const CHUNK_MASK: u64 = (1 << 8) - 1;
+/// number of chunk hashes sent per [DocMessage::Hashes]
+const HASHES_PER_MESSAGE: usize = 32;
+/// number of ranges sent per [DocMessage::Want]
+const RANGES_PER_MESSAGE: usize = 64;
+/// version of the format written by [SnapshotEnvelope::encode]
+pub const ENVELOPE_VERSION: u32 = 1;
+/// zstd level snapshots and their chunks are compressed at
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// random table for the gear rolling hash, generated with splitmix64
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+fn hash(data: &[u8]) -> Bytes {
+    Bytes::copy_from_slice(&Sha256::digest(data))
+}
+
+/// split a snapshot into content-defined chunks
+///
+/// # Notes
+/// Chunk boundaries depend only on the nearby bytes, so an edit in the
+/// middle of a document only changes the chunks around it; the rest
+/// keep their hashes and can be reused from an older snapshot. An
+/// empty snapshot is one empty chunk.
+fn chunks(snapshot: &[u8]) -> Vec<&[u8]> {
+    let mut out = vec![];
+    let mut start = 0;
+    let mut rolling: u64 = 0;
+
+    for (i, byte) in snapshot.iter().enumerate() {
+        rolling = (rolling << 1).wrapping_add(GEAR[*byte as usize]);
+        let len = i + 1 - start;
+
+        if (len >= MIN_CHUNK_BYTES && rolling & CHUNK_MASK == 0) || len >= SNAPSHOT_CHUNK_BYTES {
+            out.push(&snapshot[start..=i]);
+            start = i + 1;
+            rolling = 0;
+        }
+    }
+    if start < snapshot.len() || snapshot.is_empty() {
+        out.push(&snapshot[start..]);
+    }
+
+    out
+}
+
+/// what a snapshot is, and who made it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotHeader {
+    /// [ENVELOPE_VERSION] of the envelope the snapshot came in
+    pub format: u32,
+    /// version of the document, as the application counts them
+    pub version: u64,
+    /// actor of the replica which took the snapshot
+    pub actor: usize,
+    /// hash of the uncompressed snapshot, identifying it
+    pub digest: Bytes,
+    /// length of the uncompressed snapshot
+    pub size: u64,
+}
+
+impl SnapshotHeader {
+    /// describe `snapshot`, taken by `actor` at `version`
+    pub fn new(snapshot: &[u8], actor: usize, version: u64) -> SnapshotHeader {
+        SnapshotHeader {
+            format: ENVELOPE_VERSION,
+            version,
+            actor,
+            digest: hash(snapshot),
+            size: snapshot.len() as u64,
+        }
+    }
+
+    /// check that `snapshot` is the one this header describes
+    pub fn verify(&self, snapshot: &[u8]) -> Result<()> {
+        if self.format != ENVELOPE_VERSION {
+            return Err(anyhow!("snapshot envelope is version {}, expected {ENVELOPE_VERSION}", self.format));
+        }
+        if snapshot.len() as u64 != self.size {
+            return Err(anyhow!("snapshot is {} bytes, expected {}", snapshot.len(), self.size));
+        }
+        if hash(snapshot) != self.digest {
+            return Err(anyhow!("snapshot does not match its digest"));
+        }
+
+        Ok(())
+    }
+}
+
+/// envelope as written by [SnapshotEnvelope::encode]
+#[derive(Serialize, Deserialize)]
+struct SealedEnvelope {
+    header: SnapshotHeader,
+    /// the snapshot, compressed with zstd
+    body: Bytes,
+}
+
+/// A snapshot, with what is needed to check it arrived intact.
+///
+/// # Notes
+/// This is what snapshots travel in, both over the wire with
+/// [send_snapshot] and on disk with [super::storage::SnapshotStore]. It
+/// is encoded compressed, and every way of reading one back checks the
+/// snapshot against its [SnapshotHeader], so a corrupted or truncated
+/// snapshot is an error rather than a broken replica.
+///
+/// # Examples
+///
+/// ```ignore
+/// let snapshot = SnapshotEnvelope::new(doc.export_state()?, doc.actor(), revision);
+/// fs::write("todo.snapshot", snapshot.encode()?)?;
+/// // later
+/// let snapshot = SnapshotEnvelope::decode(&fs::read("todo.snapshot")?)?;
+/// let doc = SyncedList::import_state(snapshot.data())?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotEnvelope {
+    header: SnapshotHeader,
+    data: Vec<u8>,
+}
+
+impl SnapshotEnvelope {
+    /// wrap `snapshot`, taken by `actor` at `version`
+    pub fn new(snapshot: Vec<u8>, actor: usize, version: u64) -> SnapshotEnvelope {
+        SnapshotEnvelope {
+            header: SnapshotHeader::new(&snapshot, actor, version),
+            data: snapshot,
+        }
+    }
+
+    pub fn header(&self) -> &SnapshotHeader {
+        &self.header
+    }
+
+    /// the snapshot itself, uncompressed
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// compress the snapshot and encode it with its header
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let body = zstd::bulk::compress(&self.data, COMPRESSION_LEVEL)?;
+        let mut buf = vec![];
+        ciborium::into_writer(&SealedEnvelope {
+            header: self.header.clone(),
+            body: body.into(),
+        }, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read back an envelope written by [SnapshotEnvelope::encode].
+    ///
+    /// # Notes
+    /// Fails unless the decompressed snapshot matches its header.
+    pub fn decode(buf: &[u8]) -> Result<SnapshotEnvelope> {
+        let sealed: SealedEnvelope = ciborium::from_reader(buf)
+            .map_err(|err| anyhow!("unreadable snapshot envelope: {err}"))?;
+        if sealed.header.format != ENVELOPE_VERSION {
+            return Err(anyhow!("snapshot envelope is version {}, expected {ENVELOPE_VERSION}",
+                               sealed.header.format));
+        }
+
+        // not bulk::decompress, which would trust the size in the header
+        // for its allocation; read one byte past it so a body which
+        // inflates further is caught without inflating all of it
+        let mut data = vec![];
+        zstd::stream::read::Decoder::new(&sealed.body[..])
+            .and_then(|x| x.take(sealed.header.size.saturating_add(1)).read_to_end(&mut data))
+            .map_err(|err| anyhow!("snapshot failed to decompress: {err}"))?;
+        if data.len() as u64 > sealed.header.size {
+            return Err(anyhow!("snapshot decompresses past its {} byte header size",
+                               sealed.header.size));
+        }
+        sealed.header.verify(&data)?;
+
+        Ok(SnapshotEnvelope { header: sealed.header, data })
+    }
+}
+
+/// description of a snapshot sent ahead of its chunks
+///
+/// # Notes
+/// Per-chunk hashes follow in [DocMessage::Hashes] rather than living
+/// here, so the manifest always fits in a single frame.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub header: SnapshotHeader,
+    /// number of chunks the snapshot is split into
+    pub total: u32,
+}
+
+/// message sent on a document's stream
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DocMessage {
+    /// sender → reciever: the snapshot about to be sent
+    Manifest(Manifest),
+    /// sender → reciever: hashes of chunks `offset..offset + hashes.len()`
+    Hashes { offset: u32, hashes: Vec<Bytes> },
+    /// reciever → sender: send these half-open ranges of chunks; more follow unless `done`
+    Want { ranges: Vec<(u32, u32)>, done: bool },
+    /// one piece of a snapshot, compressed with zstd
+    Chunk { index: u32, data: Bytes },
+    /// an encoded tape for a document whose snapshot was already sent
+    Tape(Bytes),
+}
+
+impl DocMessage {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<DocMessage> {
+        Ok(ciborium::from_reader(buf)?)
+    }
+}
+
+/// order in which snapshots of several documents are sent
+#[derive(Debug, Clone, Default)]
+pub enum SnapshotOrder {
+    /// send the smallest snapshots first, so the most documents become usable soonest
+    #[default]
+    SmallestFirst,
+    /// send these streams first, in order, then the rest smallest first
+    Priority(Vec<StreamId>),
+}
+
+impl SnapshotOrder {
+    /// sort `snapshots` into the order they should be sent
+    pub fn arrange(&self, snapshots: &mut [(StreamId, SnapshotEnvelope)]) {
+        snapshots.sort_by_key(|(id, snapshot)| {
+            let rank = match self {
+                SnapshotOrder::SmallestFirst => None,
+                SnapshotOrder::Priority(ids) => ids.iter().position(|x| x == id),
+            };
+            // ranked streams go before unranked ones
+            (rank.is_none(), rank, snapshot.data().len())
+        });
+    }
+}
+
+/// send one snapshot, chunked, down a stream
+///
+/// # Notes
+/// The reciever answers the chunk hashes with the chunks it is still
+/// missing, so a snapshot interrupted by a dropped connection resumes
+/// after what was already verified, and a peer holding an older copy
+/// of the document only gets the chunks which changed.
+///
+/// Chunks are compressed one by one, rather than the snapshot as a
+/// whole, so that an edit still only changes the chunks around it.
+pub async fn send_snapshot(stream: &MuxStream, snapshot: &SnapshotEnvelope) -> Result<()> {
+    let pieces = chunks(snapshot.data());
+    let manifest = Manifest {
+        header: snapshot.header().clone(),
+        total: pieces.len() as u32
+    };
+    stream.send(DocMessage::Manifest(manifest).encode()?).await?;
+
+    for (n, batch) in pieces.chunks(HASHES_PER_MESSAGE).enumerate() {
+        stream.send(DocMessage::Hashes {
+            offset: (n * HASHES_PER_MESSAGE) as u32,
+            hashes: batch.iter().map(|x| hash(x)).collect()
+        }.encode()?).await?;
+    }
+
+    let mut wanted = vec![];
+    loop {
+        match recv_message(stream).await {
+            Some(Ok(DocMessage::Want { ranges, done })) => {
+                wanted.extend(ranges);
+                if done { break; }
+            }
+            Some(Ok(x)) => return Err(anyhow!("expected wanted chunks, got {x:?}")),
+            Some(Err(err)) => return Err(err),
+            None => return Err(anyhow!("stream {} died before asking for chunks", stream.id())),
+        }
+    }
+    debug!("stream {} wants {} of {} chunks", stream.id(),
+           wanted.iter().map(|(a, b)| b.saturating_sub(*a)).sum::<u32>(), pieces.len());
+
+    for (start, end) in wanted {
+        for index in start..end {
+            let data = pieces.get(index as usize)
+                .ok_or(anyhow!("reciever wants chunk {index} which does not exist"))?;
+            stream.send(DocMessage::Chunk {
+                index,
+                data: zstd::bulk::compress(data, COMPRESSION_LEVEL)?.into()
+            }.encode()?).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// send the snapshots of several documents, one document at a time
+///
+/// # Notes
+/// Every document has its own stream, so tapes sent with [send_tape]
+/// for documents that already finished keep flowing between the
+/// chunks of the ones still being transferred.
+///
+/// # Examples
+///
+/// ```ignore
+/// let snapshots = vec![(1, big_doc), (2, small_doc)];
+/// send_snapshots(&mux, snapshots, SnapshotOrder::SmallestFirst).await?;
+/// ```
+pub async fn send_snapshots(mux: &Multiplexer,
+                            mut snapshots: Vec<(StreamId, SnapshotEnvelope)>,
+                            order: SnapshotOrder) -> Result<()> {
+    order.arrange(&mut snapshots);
+
+    for (id, snapshot) in snapshots {
+        debug!("sending snapshot for stream {id}: {} bytes", snapshot.data().len());
+        send_snapshot(&mux.stream(id).await, &snapshot).await?;
+    }
+
+    Ok(())
+}
+
+/// send an encoded tape down a document's stream
+pub async fn send_tape(stream: &MuxStream, tape: Vec<u8>) -> Result<()> {
+    stream.send(DocMessage::Tape(tape.into()).encode()?).await
+}
+
+/// read the next message from a document's stream
+///
+/// # Notes
+/// returns [Option::None] once the stream is dead.
+pub async fn recv_message(stream: &MuxStream) -> Option<Result<DocMessage>> {
+    let raw = stream.recv().await?;
+    Some(DocMessage::decode(&raw))
+}
+
+/// reassembles a snapshot from its chunks
+///
+/// # Notes
+/// Chunks are kept by hash, so anything the assembler has already
+/// seen---chunks of an older copy given to [SnapshotAssembler::with_previous],
+/// or verified chunks of a transfer cut short by a dropped
+/// connection---is reused rather than sent again. Keep the assembler
+/// around across reconnections to benefit.
+#[derive(Debug, Default)]
+pub struct SnapshotAssembler {
+    known: HashMap<Bytes, Bytes>,
+    manifest: Option<Manifest>,
+    hashes: Vec<Bytes>,
+    chunks: Vec<Option<Bytes>>,
+}
+
+impl SnapshotAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// create an assembler which reuses the chunks of a stale local snapshot
+    pub fn with_previous(snapshot: &[u8]) -> Self {
+        let mut assembler = Self::default();
+        assembler.remember(snapshot);
+        assembler
+    }
+
+    /// make the chunks of `snapshot` available for reuse
+    pub fn remember(&mut self, snapshot: &[u8]) {
+        for piece in chunks(snapshot) {
+            self.known.insert(hash(piece), Bytes::copy_from_slice(piece));
+        }
+    }
+
+    /// start recieving the snapshot described by `manifest`
+    pub fn begin(&mut self, manifest: Manifest) {
+        // keep whatever we verified last time around
+        for chunk in self.chunks.drain(..).flatten() {
+            self.known.insert(hash(&chunk), chunk);
+        }
+        // total is the remote's word, so grow as hashes actually arrive
+        self.hashes = Vec::new();
+        self.manifest = Some(manifest);
+    }
+
+    /// record the hashes of chunks `offset..`
+    ///
+    /// # Return
+    /// Whether the hashes of every chunk are now known.
+    pub fn hashes(&mut self, offset: u32, hashes: Vec<Bytes>) -> Result<bool> {
+        let manifest = self.manifest.as_ref()
+            .ok_or(anyhow!("got chunk hashes before a manifest"))?;
+
+        if offset as usize != self.hashes.len()
+            || self.hashes.len() + hashes.len() > manifest.total as usize {
+            return Err(anyhow!("unexpected hashes for chunks {offset}..{}",
+                               offset as usize + hashes.len()));
+        }
+        self.hashes.extend(hashes);
+
+        if self.hashes.len() < manifest.total as usize {
+            return Ok(false);
+        }
+
+        self.chunks = self.hashes.iter()
+            .map(|x| self.known.get(x).cloned())
+            .collect();
+        Ok(true)
+    }
+
+    /// half-open ranges of chunks which we still need
+    pub fn missing(&self) -> Vec<(u32, u32)> {
+        let mut ranges: Vec<(u32, u32)> = vec![];
+
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            if chunk.is_some() { continue; }
+            let i = i as u32;
+            match ranges.last_mut() {
+                Some((_, end)) if *end == i => *end += 1,
+                _ => ranges.push((i, i + 1)),
+            }
+        }
+
+        ranges
+    }
+
+    /// add a compressed chunk to the snapshot, verifying it against its hash
+    pub fn push(&mut self, index: u32, data: Bytes) -> Result<()> {
+        let data: Bytes = zstd::bulk::decompress(&data, SNAPSHOT_CHUNK_BYTES)
+            .map_err(|err| anyhow!("chunk {index} failed to decompress: {err}"))?
+            .into();
+        match self.hashes.get(index as usize) {
+            Some(x) if *x == hash(&data) => {},
+            _ => return Err(anyhow!("chunk {index} does not match its hash")),
+        }
+        // chunks has no slots until every hash is in
+        let slot = self.chunks.get_mut(index as usize)
+            .ok_or(anyhow!("got chunk {index} before all hashes"))?;
+        *slot = Some(data);
+
+        Ok(())
+    }
+
+    /// the whole snapshot, once every chunk has arrived and it matches
+    /// the manifest's header
+    pub fn finish(&mut self) -> Result<Option<SnapshotEnvelope>> {
+        let manifest = self.manifest.as_ref()
+            .ok_or(anyhow!("no snapshot is being recieved"))?;
+
+        if self.chunks.len() != manifest.total as usize
+            || self.chunks.iter().any(|x| x.is_none()) {
+            return Ok(None);
+        }
+
+        let snapshot: Vec<u8> = self.chunks.iter().flatten().flat_map(|x| x.iter()).copied().collect();
+        if let Err(err) = manifest.header.verify(&snapshot) {
+            self.chunks.clear();
+            return Err(anyhow!("reassembled snapshot is broken: {err}"));
+        }
+
+        let header = manifest.header.clone();
+        self.manifest = None;
+        self.hashes.clear();
+        self.chunks.clear();
+        self.known.clear();
+        Ok(Some(SnapshotEnvelope { header, data: snapshot }))
+    }
+}
+
+/// read a whole snapshot from a document's stream
+///
+/// # Examples
+///
+/// ```ignore
+/// // seed with whatever we persisted last session
+/// let mut assembler = SnapshotAssembler::with_previous(stale.data());
+/// let snapshot = loop {
+///     let stream = reconnect().await?.stream(1).await;
+///     match recv_snapshot(&stream, &mut assembler).await {
+///         Ok(x) => break x,
+///         // verified chunks are kept in the assembler
+///         Err(_) => continue
+///     }
+/// };
+/// ```
+pub async fn recv_snapshot(stream: &MuxStream,
+                           assembler: &mut SnapshotAssembler) -> Result<SnapshotEnvelope> {
+    loop {
+        match recv_message(stream).await {
+            Some(Ok(DocMessage::Manifest(manifest))) => {
+                if manifest.header.format != ENVELOPE_VERSION {
+                    return Err(anyhow!("stream {} sent a version {} snapshot, expected {ENVELOPE_VERSION}",
+                                       stream.id(), manifest.header.format));
+                }
+                // every chunk but the last is at least MIN_CHUNK_BYTES long
+                if manifest.total as u64 > manifest.header.size / MIN_CHUNK_BYTES as u64 + 1 {
+                    return Err(anyhow!("stream {} claimed {} chunks for a {} byte snapshot",
+                                       stream.id(), manifest.total, manifest.header.size));
+                }
+                assembler.begin(manifest);
+            }
+            Some(Ok(DocMessage::Hashes { offset, hashes })) => {
+                if !assembler.hashes(offset, hashes)? {
+                    continue;
+                }
+
+                let missing = assembler.missing();
+                let batches: Vec<_> = missing.chunks(RANGES_PER_MESSAGE).collect();
+                for (n, ranges) in batches.iter().enumerate() {
+                    stream.send(DocMessage::Want {
+                        ranges: ranges.to_vec(),
+                        done: n + 1 == batches.len()
+                    }.encode()?).await?;
+                }
+                if batches.is_empty() {
+                    stream.send(DocMessage::Want { ranges: vec![], done: true }.encode()?).await?;
+                }
+            }
+            Some(Ok(DocMessage::Chunk { index, data })) => {
+                if let Err(err) = assembler.push(index, data) {
+                    error!("bad snapshot chunk on stream {}: {err}", stream.id());
+                    return Err(err);
+                }
+            }
+            Some(Ok(x)) => {
+                return Err(anyhow!("stream {} sent {x:?} mid-snapshot", stream.id()));
+            }
+            Some(Err(err)) => return Err(err),
+            None => return Err(anyhow!("stream {} died mid-snapshot", stream.id())),
+        }
+
+        if let Some(snapshot) = assembler.finish()? {
+            return Ok(snapshot);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{Result, anyhow};
+use log::info;
+
+use super::headers::Message;
+
+/// header a [TraceContext] travels in, as in W3C trace context
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// log target every span is written to
+pub const SPAN_TARGET: &str = "synch::span";
+
+/// where a message sits in a distributed trace: a W3C `traceparent`
+///
+/// # Notes
+/// The editing peer starts a trace with [TraceContext::root] and sends
+/// it with the edit. Every [super::Agent] which relays the message logs
+/// a span to [SPAN_TARGET] as a child of the one it came from, and
+/// passes its own span on, so one edit can be followed through every
+/// hop to every replica.
+///
+/// # Examples
+///
+/// ```ignore
+/// let edit = Message::new(tape).with_trace(TraceContext::root());
+/// todo.send_message(&edit).await?;
+///
+/// // on another replica
+/// let edit = todo.recv_message().await.unwrap();
+/// if let Some(ctx) = edit.trace() {
+///     info!("applying edit {ctx}");
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    /// the span which sent this message
+    pub span_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceContext {
+    /// start a new trace
+    pub fn root() -> Self {
+        TraceContext {
+            trace_id: nonzero(rand::random()),
+            span_id: nonzero(rand::random()),
+            sampled: true,
+        }
+    }
+
+    /// a new span in the same trace, whose parent is this one
+    pub fn child(&self) -> Self {
+        TraceContext { span_id: nonzero(rand::random()), ..*self }
+    }
+}
+
+/// W3C forbids all-zero ids
+fn nonzero<T: Default + PartialEq + From<u8>>(x: T) -> T {
+    if x == T::default() { T::from(1) } else { x }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-{:032x}-{:016x}-{:02x}", self.trace_id, self.span_id, self.sampled as u8)
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = anyhow::Error;
+
+    /// `version-traceid-spanid-flags`, all lowercase hex
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<_> = s.trim().split('-').collect();
+        let [version, trace_id, span_id, flags] = fields[..] else {
+            return Err(anyhow!("traceparent '{s}' should have four fields"));
+        };
+        if version.len() != 2 || version == "ff" || trace_id.len() != 32
+            || span_id.len() != 16 || flags.len() != 2 {
+            return Err(anyhow!("malformed traceparent '{s}'"));
+        }
+
+        let context = TraceContext {
+            trace_id: u128::from_str_radix(trace_id, 16)?,
+            span_id: u64::from_str_radix(span_id, 16)?,
+            sampled: u8::from_str_radix(flags, 16)? & 1 == 1,
+        };
+        if context.trace_id == 0 || context.span_id == 0 {
+            return Err(anyhow!("traceparent '{s}' has an all-zero id"));
+        }
+
+        Ok(context)
+    }
+}
+
+impl Message {
+    pub fn with_trace(self, context: TraceContext) -> Self {
+        self.with_header(TRACEPARENT_HEADER, &context.to_string())
+    }
+
+    /// the trace this message is part of, if it carries a valid one
+    pub fn trace(&self) -> Option<TraceContext> {
+        self.header(TRACEPARENT_HEADER)?.parse().ok()
+    }
+}
+
+/// Log a span for relaying `data`, linked to the span that sent it.
+///
+/// # Arguments
+///
+/// * `from` - the child it came from, or [None] for our parent.
+///
+/// # Return
+/// The message to pass on, carrying our span as its parent; `data`
+/// itself if it is not traced.
+pub(crate) fn relink(data: Vec<u8>, channel: &str, from: Option<usize>) -> Vec<u8> {
+    let Some(message) = Message::decode_envelope(&data) else { return data; };
+    let Some(parent) = message.trace() else { return data; };
+
+    let span = parent.child();
+    if span.sampled {
+        let from = from.map_or("parent".to_owned(), |x| x.to_string());
+        info!(target: SPAN_TARGET, "trace={:032x} span={:016x} parent={:016x} channel={channel} from={from}",
+              span.trace_id, span.span_id, parent.span_id);
+    }
+
+    message.with_trace(span).encode().unwrap_or(data)
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use anyhow::{Result, anyhow};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use base64::prelude::{BASE64_URL_SAFE_NO_PAD, Engine as _};
+use serde::{Serialize, Deserialize};
+
+use super::snapshot::SnapshotEnvelope;
+
+/// bytes of nonce prepended to every encrypted file
+const NONCE_BYTES: usize = 12;
+
+/// key used to encrypt data at rest or end to end
+///
+/// # Notes
+/// Sealed data is AES-256-GCM with a random nonce prepended, so it is
+/// both private and tamper evident.
+#[derive(Clone)]
+pub struct SecretKey(Key<Aes256Gcm>);
+
+impl SecretKey {
+    /// use a 256 bit key supplied by the application
+    pub fn new(key: [u8; 32]) -> SecretKey {
+        SecretKey(key.into())
+    }
+
+    /// make a fresh random key; the application must keep it somewhere safe
+    pub fn generate() -> SecretKey {
+        SecretKey(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// the raw key, for the application to store
+    pub fn bytes(&self) -> [u8; 32] {
+        self.0.into()
+    }
+
+    /// encrypt `plain` so only holders of this key can read it
+    pub fn seal(&self, plain: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(Aes256Gcm::new(&self.0)
+                      .encrypt(&nonce, plain)
+                      .map_err(|_| anyhow!("failed to encrypt"))?);
+        Ok(sealed)
+    }
+
+    /// decrypt something made by [SecretKey::seal]
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_BYTES {
+            return Err(anyhow!("encrypted file is too short"));
+        }
+        let (nonce, cipher) = sealed.split_at(NONCE_BYTES);
+        Aes256Gcm::new(&self.0)
+            .decrypt(Nonce::from_slice(nonce), cipher)
+            .map_err(|_| anyhow!("failed to decrypt: wrong key or corrupted data"))
+    }
+}
+
+impl std::fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+/// how long a document's state is kept around
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Retention {
+    /// never written to disk; gone once the peer disconnects
+    Ephemeral,
+    /// written to disk until [SnapshotStore::end_session]
+    Session,
+    /// written to disk until explicitly removed
+    #[default]
+    Durable,
+}
+
+/// on-disk store of document snapshots, optionally encrypted at rest
+///
+/// # Notes
+/// Snapshots are written as their encoded [SnapshotEnvelope], so a
+/// file which was truncated or corrupted on disk fails to load rather
+/// than resuming a broken replica.
+///
+/// With a [SecretKey], every file is sealed with AES-256-GCM so other
+/// users of the machine can't read cached collaborative data; a file
+/// written with one key fails to load with any other.
+///
+/// Documents are [Retention::Durable] unless told otherwise with
+/// [SnapshotStore::set_retention].
+///
+/// # Examples
+///
+/// ```ignore
+/// let key = SecretKey::new(key_from_app);
+/// let store = SnapshotStore::new("./synch", Some(key))?;
+/// store.save("todo", &SnapshotEnvelope::new(doc.export_state()?, doc.actor(), revision))?;
+/// let stale = store.load("todo")?.map(|x| x.into_data()).unwrap_or_default();
+/// let mut assembler = SnapshotAssembler::with_previous(&stale);
+/// ```
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    key: Option<SecretKey>,
+    retention: HashMap<String, Retention>,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: impl AsRef<Path>, key: Option<SecretKey>) -> Result<SnapshotStore> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(SnapshotStore {
+            dir: dir.as_ref().to_owned(),
+            key,
+            retention: HashMap::new()
+        })
+    }
+
+    /// set how long a document is kept
+    ///
+    /// # Notes
+    /// making a document [Retention::Ephemeral] also removes anything
+    /// already written for it.
+    pub fn set_retention(&mut self, name: &str, retention: Retention) -> Result<()> {
+        if retention == Retention::Ephemeral {
+            self.remove(name)?;
+        }
+        self.retention.insert(name.to_owned(), retention);
+
+        Ok(())
+    }
+
+    pub fn retention(&self, name: &str) -> Retention {
+        self.retention.get(name).copied().unwrap_or_default()
+    }
+
+    /// write a document's snapshot, replacing any older one
+    ///
+    /// # Notes
+    /// does nothing for [Retention::Ephemeral] documents.
+    pub fn save(&self, name: &str, snapshot: &SnapshotEnvelope) -> Result<()> {
+        if self.retention(name) == Retention::Ephemeral {
+            return Ok(());
+        }
+
+        let data = match self.key {
+            Some(ref key) => key.seal(&snapshot.encode()?)?,
+            None => snapshot.encode()?,
+        };
+
+        // write then rename, so a crash never leaves half a snapshot
+        let path = self.path(name);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+
+    /// Read a document's snapshot, if one was saved.
+    ///
+    /// # Notes
+    /// Fails if the snapshot doesn't match its header.
+    pub fn load(&self, name: &str) -> Result<Option<SnapshotEnvelope>> {
+        let data = match fs::read(self.path(name)) {
+            Ok(x) => x,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let data = match self.key {
+            Some(ref key) => key.open(&data)?,
+            None => data,
+        };
+
+        SnapshotEnvelope::decode(&data)
+            .map(Some)
+            .map_err(|err| anyhow!("snapshot of {name} is broken: {err}"))
+    }
+
+    /// the swarm ended: forget every [Retention::Session] document
+    pub fn end_session(&self) -> Result<()> {
+        for (name, _) in self.retention.iter()
+            .filter(|(_, x)| **x == Retention::Session) {
+            self.remove(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// forget a document's snapshot
+    pub fn remove(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(())
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        // document names may contain anything, so encode them
+        self.dir.join(format!("{}.snapshot", BASE64_URL_SAFE_NO_PAD.encode(name)))
+    }
+}
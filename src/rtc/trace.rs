@@ -0,0 +1,69 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use ciborium::Value;
+use log::info;
+
+use super::mux::{Frame, FrameKind};
+
+/// log target every traced frame is written to
+pub const TRACE_TARGET: &str = "synch::wire";
+
+/// whether a traced frame was sent or recieved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::In => f.write_str("in"),
+            Direction::Out => f.write_str("out"),
+        }
+    }
+}
+
+/// per-connection trace switch: the peer's label while tracing, else [None]
+pub(crate) type TraceLabel = Arc<RwLock<Option<String>>>;
+
+/// log one frame if tracing is on for its connection
+pub(crate) fn trace_frame(label: &TraceLabel, direction: Direction, channel: &str, data: &[u8]) {
+    let Ok(label) = label.read() else { return; };
+    let Some(ref peer) = *label else { return; };
+
+    info!(target: TRACE_TARGET, "dir={direction} peer={peer} channel={channel} size={} type={}",
+          data.len(), describe(data));
+}
+
+/// Best guess at what a frame is, without its payload.
+///
+/// # Notes
+/// Every message enum in this crate is CBOR, so a frame decoding to a
+/// one-entry map or a string is named after that variant. Multiplexed
+/// frames are named after their stream and kind, and what they carry.
+pub fn describe(data: &[u8]) -> String {
+    if let Some(variant) = variant_of(data) {
+        return variant;
+    }
+
+    match Frame::decode(data) {
+        Ok(Frame { stream, kind: FrameKind::Data, payload }) => {
+            let inner = variant_of(&payload).unwrap_or_else(|| "opaque".into());
+            format!("mux/{stream}/data/{inner}")
+        }
+        Ok(Frame { stream, kind: FrameKind::Credit, .. }) => format!("mux/{stream}/credit"),
+        Err(_) => "opaque".into(),
+    }
+}
+
+fn variant_of(data: &[u8]) -> Option<String> {
+    match ciborium::from_reader::<Value, _>(data).ok()? {
+        Value::Text(unit) => Some(unit),
+        Value::Map(entries) if entries.len() == 1 => match entries[0].0 {
+            Value::Text(ref variant) => Some(variant.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -1,4 +1,6 @@
 use anyhow::Result;
+use std::future::Future;
+use tokio::task::JoinHandle;
 
 use std::default::Default;
 
@@ -39,16 +41,37 @@ pub fn get_api() -> Result<API> {
 
 pub fn get_config_from_stun_servers(stun_servers: &[&str]) -> RTCConfiguration {
     let ice_servers = stun_servers
-        .into_iter()
+        .iter()
         .map(|x| RTCIceServer {
             urls: vec![x.to_string()],
             ..Default::default()
         }).collect();
 
     RTCConfiguration {
-        ice_servers: ice_servers,
+        ice_servers,
         ..Default::default()
     }
 }
 
 
+
+/// Spawn a task called `name`, so tools like tokio-console can tell
+/// which channel or peer a worker is for.
+///
+/// # Notes
+/// Tasks are only named with the `console` feature, in builds with
+/// `RUSTFLAGS="--cfg tokio_unstable"`, which tokio-console needs anyway;
+/// otherwise this is [tokio::spawn].
+pub(crate) fn spawn_named<F>(name: &str, future: F) -> JoinHandle<F::Output>
+where F: Future + Send + 'static, F::Output: Send + 'static {
+    #[cfg(all(tokio_unstable, feature = "console"))]
+    {
+        tokio::task::Builder::new().name(name).spawn(future)
+            .expect("failed to spawn task")
+    }
+    #[cfg(not(all(tokio_unstable, feature = "console")))]
+    {
+        let _ = name;
+        tokio::spawn(future)
+    }
+}
@@ -0,0 +1,266 @@
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::BTreeMap;
+use tokio::sync::mpsc::{Sender, UnboundedSender, UnboundedReceiver,
+                        unbounded_channel, error::SendError};
+use log::{warn, debug};
+
+/// how long a queue may stay full before a [QueueStall] is reported
+pub const DEFAULT_STALL_THRESHOLD: Duration = Duration::from_millis(250);
+
+/// which way a queue carries messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum QueueDirection {
+    /// from the data channel to the application
+    Read,
+    /// from the application to the data channel
+    Write,
+}
+
+/// how full one queue is, and has been
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueDepth {
+    pub channel: String,
+    pub direction: QueueDirection,
+    /// messages waiting now
+    pub current: usize,
+    /// most messages ever waiting at once
+    pub high_watermark: usize,
+    pub capacity: usize,
+}
+
+/// a queue stayed full for longer than the stall threshold
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueStall {
+    pub channel: String,
+    pub direction: QueueDirection,
+    /// how long it had been full when this was reported
+    pub waited: Duration,
+}
+
+/// A queue holding messages which nobody has taken for a while, from
+/// [super::Connection::watchdog].
+///
+/// # Notes
+/// A stuck write queue means its worker is blocked writing to the data
+/// channel; a stuck read queue means the application stopped reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueStuck {
+    pub channel: String,
+    pub direction: QueueDirection,
+    /// messages waiting
+    pub waiting: usize,
+    /// how long since a message was last taken
+    pub idle: Duration,
+}
+
+/// a message outlived its deadline in a write queue, and was dropped unsent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueExpiry {
+    pub channel: String,
+    /// size of the dropped message
+    pub bytes: usize,
+    /// how long past its deadline it was when it reached the front
+    pub late_by: Duration,
+}
+
+/// bytes a connection has moved since it opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Traffic {
+    /// read off its data channels
+    pub bytes_in: u64,
+    /// written to its data channels
+    pub bytes_out: u64,
+}
+
+impl std::ops::Add for Traffic {
+    type Output = Traffic;
+
+    fn add(self, other: Traffic) -> Traffic {
+        Traffic {
+            bytes_in: self.bytes_in + other.bytes_in,
+            bytes_out: self.bytes_out + other.bytes_out,
+        }
+    }
+}
+
+type QueueKey = (String, QueueDirection);
+// (current depth, capacity) of a queue, if it is still alive
+type Gauge = Box<dyn Fn() -> Option<(usize, usize)> + Send>;
+
+struct Watched {
+    gauge: Gauge,
+    high_watermark: usize,
+    /// messages ever sent into the queue
+    sent: u64,
+    /// messages taken out of it as of `progressed`
+    taken: u64,
+    progressed: Instant,
+}
+
+/// watches every queue of a connection without keeping any of them open
+pub(crate) struct QueueMonitor {
+    queues: Mutex<BTreeMap<QueueKey, Watched>>,
+    stall_threshold: Mutex<Duration>,
+    subscribers: Mutex<Vec<UnboundedSender<QueueStall>>>,
+    expiry_subscribers: Mutex<Vec<UnboundedSender<QueueExpiry>>>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl QueueMonitor {
+    pub(crate) fn new() -> Self {
+        QueueMonitor {
+            queues: Mutex::new(BTreeMap::new()),
+            stall_threshold: Mutex::new(DEFAULT_STALL_THRESHOLD),
+            subscribers: Mutex::new(vec![]),
+            expiry_subscribers: Mutex::new(vec![]),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+        }
+    }
+
+    /// start watching a queue, forgetting its high watermark
+    pub(crate) fn watch<T: Send + 'static>(&self, channel: &str, direction: QueueDirection,
+                                           sender: &Sender<T>) {
+        let weak = sender.downgrade();
+        let gauge: Gauge = Box::new(move || {
+            let x = weak.upgrade()?;
+            Some((x.max_capacity() - x.capacity(), x.max_capacity()))
+        });
+
+        if let Ok(mut queues) = self.queues.lock() {
+            queues.insert((channel.to_owned(), direction), Watched {
+                gauge, high_watermark: 0, sent: 0, taken: 0, progressed: Instant::now()
+            });
+        }
+    }
+
+    pub(crate) fn set_stall_threshold(&self, threshold: Duration) {
+        if let Ok(mut x) = self.stall_threshold.lock() {
+            *x = threshold;
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> UnboundedReceiver<QueueStall> {
+        let (sender, reciever) = unbounded_channel();
+        if let Ok(mut x) = self.subscribers.lock() {
+            x.push(sender);
+        }
+        reciever
+    }
+
+    pub(crate) fn subscribe_expiries(&self) -> UnboundedReceiver<QueueExpiry> {
+        let (sender, reciever) = unbounded_channel();
+        if let Ok(mut x) = self.expiry_subscribers.lock() {
+            x.push(sender);
+        }
+        reciever
+    }
+
+    /// every queue still alive, and how full it is
+    pub(crate) fn depths(&self) -> Vec<QueueDepth> {
+        let Ok(queues) = self.queues.lock() else { return vec![]; };
+
+        queues.iter()
+            .filter_map(|((channel, direction), queue)| {
+                let (current, capacity) = (queue.gauge)()?;
+                Some(QueueDepth {
+                    channel: channel.clone(),
+                    direction: *direction,
+                    current,
+                    high_watermark: queue.high_watermark,
+                    capacity,
+                })
+            })
+            .collect()
+    }
+
+    /// every queue which has had messages waiting, untaken, for at least `after`
+    pub(crate) fn stuck(&self, after: Duration) -> Vec<QueueStuck> {
+        let Ok(mut queues) = self.queues.lock() else { return vec![]; };
+        let now = Instant::now();
+
+        queues.iter_mut()
+            .filter_map(|((channel, direction), queue)| {
+                let (current, _) = (queue.gauge)()?;
+                let taken = queue.sent.saturating_sub(current as u64);
+                if current == 0 || taken != queue.taken {
+                    queue.taken = taken;
+                    queue.progressed = now;
+                    return None;
+                }
+
+                let idle = now.duration_since(queue.progressed);
+                (idle >= after).then(|| QueueStuck {
+                    channel: channel.clone(),
+                    direction: *direction,
+                    waiting: current,
+                    idle,
+                })
+            })
+            .collect()
+    }
+
+    /// send into a watched queue, reporting it if it stays full too long
+    pub(crate) async fn send<T>(&self, channel: &str, direction: QueueDirection,
+                             sender: &Sender<T>, item: T) -> Result<(), SendError<T>> {
+        let threshold = self.stall_threshold.lock().map_or(DEFAULT_STALL_THRESHOLD, |x| *x);
+        let start = Instant::now();
+
+        let permit = match tokio::time::timeout(threshold, sender.reserve()).await {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.stalled(QueueStall {
+                    channel: channel.to_owned(),
+                    direction,
+                    waited: start.elapsed()
+                });
+                sender.reserve().await
+            }
+        };
+        let Ok(permit) = permit else { return Err(SendError(item)); };
+        permit.send(item);
+
+        let depth = sender.max_capacity() - sender.capacity();
+        if let Ok(mut queues) = self.queues.lock() {
+            if let Some(queue) = queues.get_mut(&(channel.to_owned(), direction)) {
+                queue.high_watermark = queue.high_watermark.max(depth);
+                queue.sent += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// count bytes moved through a data channel
+    pub(crate) fn moved(&self, direction: QueueDirection, bytes: usize) {
+        let counter = match direction {
+            QueueDirection::Read => &self.bytes_in,
+            QueueDirection::Write => &self.bytes_out,
+        };
+        counter.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn traffic(&self) -> Traffic {
+        Traffic {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+        }
+    }
+
+    fn stalled(&self, stall: QueueStall) {
+        warn!("queue stalled: {stall:?}");
+        if let Ok(mut x) = self.subscribers.lock() {
+            x.retain(|x| x.send(stall.clone()).is_ok());
+        }
+    }
+
+    pub(crate) fn expired(&self, expiry: QueueExpiry) {
+        debug!("queued message expired: {expiry:?}");
+        if let Ok(mut x) = self.expiry_subscribers.lock() {
+            x.retain(|x| x.send(expiry.clone()).is_ok());
+        }
+    }
+}
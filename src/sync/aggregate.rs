@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+/// Summary of a number extracted from every element of a collection
+///
+/// # Notes
+/// See [super::list::SyncedList::add_aggregate] and
+/// [super::map::SyncedMap::add_aggregate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Aggregate {
+    pub count: usize,
+    pub sum: i64,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+impl Aggregate {
+    /// the mean of every extracted number, if there are any
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum as f64 / self.count as f64)
+        }
+    }
+}
+
+pub(crate) type Extractor<V> = Box<dyn Fn(&V) -> i64 + Send>;
+
+/// An [Aggregate] kept up to date one element at a time
+///
+/// # Notes
+/// Elements are told apart by `Id`, so seeing the same element twice
+/// replaces its contribution rather than counting it again.
+pub(crate) struct Aggregator<Id, V> {
+    extract: Extractor<V>,
+    contributions: BTreeMap<Id, i64>,
+    /// how many elements contributed each number, for min and max
+    values: BTreeMap<i64, usize>,
+    sum: i64,
+}
+
+impl<Id: Ord + Clone, V> Aggregator<Id, V> {
+    pub(crate) fn new(extract: Extractor<V>) -> Self {
+        Aggregator {
+            extract,
+            contributions: BTreeMap::new(),
+            values: BTreeMap::new(),
+            sum: 0
+        }
+    }
+
+    /// the element `id` now holds `value`, or is gone
+    pub(crate) fn observe(&mut self, id: &Id, value: Option<&V>) {
+        if let Some(old) = self.contributions.remove(id) {
+            self.sum -= old;
+            if let Some(count) = self.values.get_mut(&old) {
+                *count -= 1;
+                if *count == 0 {
+                    self.values.remove(&old);
+                }
+            }
+        }
+
+        if let Some(value) = value {
+            let new = (self.extract)(value);
+            self.sum += new;
+            *self.values.entry(new).or_default() += 1;
+            self.contributions.insert(id.clone(), new);
+        }
+    }
+
+    pub(crate) fn read(&self) -> Aggregate {
+        Aggregate {
+            count: self.contributions.len(),
+            sum: self.sum,
+            min: self.values.first_key_value().map(|(x, _)| *x),
+            max: self.values.last_key_value().map(|(x, _)| *x),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Storage behind [super::list::SyncedList] and [super::map::SyncedMap].
+//!
+//! Everything the two know about how their contents are stored goes
+//! through [SeqBackend] and [MapBackend], so a document can be given
+//! another backend without its API changing. The ops a backend makes
+//! are the tape ops which travel between replicas: every backend must
+//! make and merge them exactly as the defaults, [Rope] and [CrdtsMap],
+//! do, or replicas with different backends will drift apart. The
+//! defaults are where the `crdts` crate is used.
+
+use std::borrow::Borrow;
+use std::collections::BTreeSet;
+use std::ops::{Bound, RangeBounds};
+use crdts::{CmRDT, Identifier, MVReg, OrdDot};
+use crdts::map::Map;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::de::value::{SeqDeserializer, Error as ValueError};
+
+use super::map::{MapCell, MapKey, MapVal};
+
+pub use super::rope::Rope;
+
+/// identifier of an element of a sequence; see [super::list::ElementId]
+pub type SeqId = Identifier<OrdDot<usize>>;
+/// an insert or delete of one element of a sequence
+pub type SeqOp<T> = crdts::list::Op<T, usize>;
+/// what a map keeps for one key: every value written concurrently
+pub type MapReg<V> = MVReg<MapCell<V>, usize>;
+/// a write to, or remove of, keys of a map
+pub type MapOp<K, V> = crdts::map::Op<K, MapReg<V>, usize>;
+
+/// The identifier with no path, which sorts after every other.
+///
+/// # Notes
+/// No backend makes one, but a peer's tape may hold it. `crdts` keeps
+/// the path private, so this decodes one from an empty path, in memory.
+pub fn empty_seq_id() -> SeqId {
+    let path = SeqDeserializer::<_, ValueError>::new(std::iter::once(Vec::<()>::new()));
+    SeqId::deserialize(path).expect("an empty path is an identifier")
+}
+
+/// A sequence CRDT, as a [super::list::SyncedList] keeps its elements.
+///
+/// # Notes
+/// Elements are kept in order of their identifiers. Methods making ops
+/// don't apply them; the list applies them with [SeqBackend::apply],
+/// which must ignore an op it has already seen.
+pub trait SeqBackend<T>: Clone + Default {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// an op inserting `val` at `ix`, or at the end if `ix` is past it
+    fn insert_index(&self, ix: usize, val: T, actor: usize) -> SeqOp<T>;
+
+    fn append(&self, val: T, actor: usize) -> SeqOp<T> {
+        self.insert_index(self.len(), val, actor)
+    }
+
+    /// An op appending `val` as `actor`'s edit numbered `counter`.
+    ///
+    /// # Notes
+    /// For appending many elements in a row; `counter` is one past the
+    /// last op `actor` made, which must already be applied.
+    fn append_numbered(&self, val: T, actor: usize, counter: u64) -> SeqOp<T>;
+
+    /// an op deleting the element at `ix`, if there is one
+    fn delete_index(&self, ix: usize, actor: usize) -> Option<SeqOp<T>>;
+
+    /// an op deleting the element with identifier `id`, if there is one
+    fn delete_id(&self, id: &SeqId, actor: usize) -> Option<SeqOp<T>>;
+
+    /// apply an op, ignoring it if it was already seen
+    fn apply(&mut self, op: SeqOp<T>);
+
+    /// Drop the element with identifier `id` without making an op.
+    ///
+    /// # Notes
+    /// For the places a moved element left, which every replica drops
+    /// alike; see [super::list::SyncedList::move_item]. Replaying the
+    /// insert which made `id` must not bring it back.
+    fn forget(&mut self, id: &SeqId);
+
+    /// every element with its identifier, in order
+    fn iter_entries<'a>(&'a self) -> impl Iterator<Item = (&'a SeqId, &'a T)>
+    where T: 'a;
+
+    /// the element at `ix`, with its identifier
+    fn entry(&self, ix: usize) -> Option<(&SeqId, &T)>;
+
+    /// every element from `ix` on
+    fn entries_from<'a>(&'a self, ix: usize) -> impl Iterator<Item = (&'a SeqId, &'a T)>
+    where T: 'a;
+
+    /// the index of the element with identifier `id`
+    fn position_entry(&self, id: &SeqId) -> Option<usize>;
+
+    fn get(&self, id: &SeqId) -> Option<&T>;
+
+    /// How many elements come before `id`, whether or not it is still there.
+    ///
+    /// # Notes
+    /// By default this walks [SeqBackend::iter_entries]; backends which
+    /// can seek to `id` should.
+    fn rank(&self, id: &SeqId) -> usize {
+        self.iter_entries().take_while(|(x, _)| *x < id).count()
+    }
+
+    fn last_entry(&self) -> Option<(&SeqId, &T)>;
+}
+
+/// A map CRDT, as a [super::map::SyncedMap] keeps its entries.
+///
+/// # Notes
+/// Each key holds every [MapCell] written to it concurrently, leaving
+/// it to the map to settle on a value. Like [SeqBackend], methods
+/// making ops don't apply them.
+pub trait MapBackend<K: MapKey, V: MapVal>: Clone + Default {
+    /// what is written under `key`, empty if nothing is
+    fn cells(&self, key: &K) -> Vec<MapCell<V>>;
+
+    /// How many keys have anything written under them.
+    ///
+    /// # Notes
+    /// By default this walks [MapBackend::entries], reading every
+    /// cell; backends which can count their keys should.
+    fn len(&self) -> usize {
+        self.keys().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// whether anything is written under `key`
+    fn contains(&self, key: &K) -> bool {
+        !self.cells(key).is_empty()
+    }
+
+    /// Every key with anything written under it, in order.
+    ///
+    /// # Notes
+    /// By default this walks [MapBackend::entries]; backends which can
+    /// list keys without reading their cells should.
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where K: 'a {
+        self.entries().map(|(k, _)| k)
+    }
+
+    /// every key with what is written under it, in order of keys
+    fn entries<'a>(&'a self) -> impl Iterator<Item = (&'a K, Vec<MapCell<V>>)>
+    where K: 'a;
+
+    /// Every key in `range` with what is written under it, in order of keys.
+    ///
+    /// # Notes
+    /// By default this walks [MapBackend::entries] from the first key;
+    /// backends which can seek to the start of the range should.
+    fn range<'a, Q, R>(&'a self, range: R) -> impl Iterator<Item = (&'a K, Vec<MapCell<V>>)>
+    where K: 'a + Borrow<Q>, Q: Ord + ?Sized, R: RangeBounds<Q> {
+        let mut entries = self.entries();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            while !done {
+                let (key, cells) = entries.next()?;
+                done = past(range.end_bound(), key.borrow());
+                if !done && range.contains(key.borrow()) {
+                    return Some((key, cells));
+                }
+            }
+            None
+        })
+    }
+
+    /// an op writing `cell` to `key` over every write to it seen so far
+    fn write(&self, key: K, cell: MapCell<V>, actor: usize) -> MapOp<K, V>;
+
+    /// an op removing every write to `key` seen so far
+    fn remove(&self, key: K) -> MapOp<K, V>;
+
+    fn apply(&mut self, op: MapOp<K, V>);
+}
+
+/// whether `key` is after a range ending at `end`
+fn past<Q: Ord + ?Sized>(end: Bound<&Q>, key: &Q) -> bool {
+    match end {
+        Bound::Included(x) => key > x,
+        Bound::Excluded(x) => key >= x,
+        Bound::Unbounded => false,
+    }
+}
+
+/// whether a range has any room between its bounds; [BTreeSet::range]
+/// panics on one that doesn't
+fn valid<Q: Ord + ?Sized>(start: Bound<&Q>, end: Bound<&Q>) -> bool {
+    match (start, end) {
+        (Bound::Excluded(a), Bound::Excluded(b)) => a < b,
+        (Bound::Included(a) | Bound::Excluded(a), Bound::Included(b) | Bound::Excluded(b)) => a <= b,
+        _ => true,
+    }
+}
+
+/// the keys an op to a map touches
+pub fn keys_of<K: MapKey, V: MapVal>(op: &MapOp<K, V>) -> Vec<K> {
+    match op {
+        MapOp::Rm { keyset, .. } => keyset.iter().cloned().collect(),
+        MapOp::Up { key, .. } => vec![key.clone()],
+    }
+}
+
+/// The default [MapBackend]: a `crdts::Map` of multi-value registers.
+///
+/// # Notes
+/// Encodes just as the `crdts::Map` does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "K: Serialize, V: Serialize",
+              deserialize = "K: Deserialize<'de>, V: Deserialize<'de>"))]
+pub struct CrdtsMap<K: MapKey, V: MapVal>(Map<K, MapReg<V>, usize>);
+
+impl<K: MapKey, V: MapVal> Default for CrdtsMap<K, V> {
+    fn default() -> Self {
+        CrdtsMap(Map::new())
+    }
+}
+
+impl<K: MapKey, V: MapVal> MapBackend<K, V> for CrdtsMap<K, V> {
+    fn cells(&self, key: &K) -> Vec<MapCell<V>> {
+        self.0.get(key).val.map_or(vec![], |x| x.read().val)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len().val
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.0.get(key).val.is_some()
+    }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where K: 'a {
+        self.0.keys().map(|x| x.val)
+    }
+
+    fn entries<'a>(&'a self) -> impl Iterator<Item = (&'a K, Vec<MapCell<V>>)>
+    where K: 'a {
+        self.0.iter().map(|x| {
+            let (key, reg) = x.val;
+            (key, reg.read().val)
+        })
+    }
+
+    fn write(&self, key: K, cell: MapCell<V>, actor: usize) -> MapOp<K, V> {
+        let add_ctx = self.0.get(&key).derive_add_ctx(actor);
+        self.0.update(key, add_ctx, |v, a| v.write(cell, a))
+    }
+
+    fn remove(&self, key: K) -> MapOp<K, V> {
+        let rm_ctx = self.0.get(&key).derive_rm_ctx();
+        self.0.rm(key, rm_ctx)
+    }
+
+    fn apply(&mut self, op: MapOp<K, V>) {
+        self.0.apply(op)
+    }
+}
+
+/// A [CrdtsMap] which also keeps its keys in order, so that
+/// [MapBackend::range] seeks to the start of the range instead of
+/// walking every key before it.
+///
+/// # Notes
+/// Worth it for maps scanned by range or prefix, such as ones keyed by
+/// paths like `"room/42/users"`; each key is kept twice. Encodes just
+/// as [CrdtsMap] does, so either can load what the other saved.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # use synch::sync::backend::RangeMap;
+/// let mut rooms: SyncedMap<String, u32, RangeMap<String, u32>> = SyncedMap::default();
+/// rooms.insert("room/42/amy".into(), 1);
+/// rooms.insert("room/43/bob".into(), 2);
+/// for (key, user) in rooms.scan_prefix("room/42/") {
+///     assert_eq!((key.as_str(), user), ("room/42/amy", 1));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RangeMap<K: MapKey, V: MapVal> {
+    map: CrdtsMap<K, V>,
+    keys: BTreeSet<K>,
+}
+
+impl<K: MapKey, V: MapVal> Default for RangeMap<K, V> {
+    fn default() -> Self {
+        RangeMap { map: CrdtsMap::default(), keys: BTreeSet::new() }
+    }
+}
+
+impl<K: MapKey, V: MapVal> MapBackend<K, V> for RangeMap<K, V> {
+    fn cells(&self, key: &K) -> Vec<MapCell<V>> {
+        self.map.cells(key)
+    }
+
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.keys.contains(key)
+    }
+
+    fn keys<'a>(&'a self) -> impl Iterator<Item = &'a K>
+    where K: 'a {
+        self.keys.iter()
+    }
+
+    fn entries<'a>(&'a self) -> impl Iterator<Item = (&'a K, Vec<MapCell<V>>)>
+    where K: 'a {
+        self.map.entries()
+    }
+
+    fn range<'a, Q, R>(&'a self, range: R) -> impl Iterator<Item = (&'a K, Vec<MapCell<V>>)>
+    where K: 'a + Borrow<Q>, Q: Ord + ?Sized, R: RangeBounds<Q> {
+        valid(range.start_bound(), range.end_bound())
+            .then(|| self.keys.range::<Q, R>(range))
+            .into_iter()
+            .flatten()
+            .map(|k| (k, self.map.cells(k)))
+    }
+
+    fn write(&self, key: K, cell: MapCell<V>, actor: usize) -> MapOp<K, V> {
+        self.map.write(key, cell, actor)
+    }
+
+    fn remove(&self, key: K) -> MapOp<K, V> {
+        self.map.remove(key)
+    }
+
+    fn apply(&mut self, op: MapOp<K, V>) {
+        let keys = keys_of(&op);
+        self.map.apply(op);
+
+        for key in keys {
+            if self.map.0.get(&key).val.is_some() {
+                self.keys.insert(key);
+            } else {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
+
+impl<K: MapKey + Serialize, V: MapVal + Serialize> Serialize for RangeMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for RangeMap<K, V>
+where K: MapKey + Deserialize<'de>, V: MapVal + Deserialize<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map: CrdtsMap<K, V> = CrdtsMap::deserialize(deserializer)?;
+        let keys = map.entries().map(|(k, _)| k.clone()).collect();
+        Ok(RangeMap { map, keys })
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+
+use super::taped::Taped;
+use super::map::MapKey;
+
+/// one actor's part of a count
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Share {
+    /// how many increments the actor has made to the key
+    seq: u64,
+    /// the sum of those increments
+    total: i64,
+    /// `seq` and `total` as the latest remove of the key saw them
+    removed_seq: u64,
+    removed_total: i64,
+}
+
+impl Share {
+    /// what this share adds to the count
+    fn live(&self) -> Option<i64> {
+        (self.seq > self.removed_seq).then(|| self.total.wrapping_sub(self.removed_total))
+    }
+}
+
+/// An edit to a [SyncedCounterMap].
+///
+/// # Notes
+/// Ops carry running totals rather than deltas, so they give the same
+/// count however often, and in whatever order, they are replayed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CounterOp<K> {
+    /// the `seq`th increment of `key` by `actor` brought its sum to `total`
+    Add { key: K, actor: usize, seq: u64, total: i64 },
+    /// forget the increments of `key` up to each actor's `seq`, which summed to `total`
+    Remove { key: K, seen: BTreeMap<usize, (u64, i64)> },
+}
+
+/// Map of counters, whose concurrent increments add up.
+///
+/// # Notes
+/// Where [super::map::SyncedMap] keeps the values written concurrently
+/// to a key side by side, each replica here only ever adds to or takes
+/// from a count, so two peers adding 1 to the same key leave it at 2
+/// everywhere. Use it for metrics, votes and the like. Counts wrap
+/// around at the bounds of an `i64`.
+///
+/// A missing key counts as 0. Removing a key forgets the increments
+/// this replica has seen; one made concurrently elsewhere survives,
+/// leaving just that increment. Each key keeps a few numbers per actor
+/// which ever incremented it, even once removed.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// let mut hits: SyncedCounterMap<String> = SyncedCounterMap::new();
+/// let mut peer = hits.clone();
+/// hits.increment("/index".to_string(), 1);
+/// peer.increment("/index".to_string(), 2);
+/// peer.replay(hits.tape());
+/// assert_eq!(peer.get(&"/index".to_string()), 3);
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: Deserialize<'de>"))]
+pub struct SyncedCounterMap<K: MapKey> {
+    counts: BTreeMap<K, BTreeMap<usize, Share>>,
+    #[serde(skip, default = "rand::random")]
+    actor: usize,
+    #[serde(skip)]
+    tape: Vec<CounterOp<K>>,
+}
+
+impl<K: MapKey> Taped<usize> for SyncedCounterMap<K> {
+    type Operation = CounterOp<K>;
+
+    /// Synchronize your map against a tape
+    fn replay(&mut self, tape: Vec<Self::Operation>) {
+        tape.into_iter().for_each(|x| self.absorb(x));
+    }
+
+    fn tape(&mut self) -> Vec<Self::Operation> {
+        std::mem::take(&mut self.tape)
+    }
+}
+
+impl<K: MapKey> SyncedCounterMap<K> {
+    pub fn new() -> Self {
+        SyncedCounterMap {
+            counts: BTreeMap::new(),
+            actor: rand::random(),
+            tape: vec![],
+        }
+    }
+
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on; see [super::map::SyncedMap::with_actor].
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// the count of `key`, or 0 if it has none
+    pub fn get(&self, key: &K) -> i64 {
+        self.counts.get(key).map_or(0, count)
+    }
+
+    /// Add `delta` to the count of `key`, which may be negative.
+    ///
+    /// # Return
+    /// The new count.
+    pub fn increment(&mut self, key: K, delta: i64) -> i64 {
+        if delta != 0 {
+            let share = self.counts.get(&key)
+                .and_then(|x| x.get(&self.actor))
+                .copied()
+                .unwrap_or_default();
+            self.apply(CounterOp::Add {
+                key: key.clone(),
+                actor: self.actor,
+                seq: share.seq + 1,
+                total: share.total.wrapping_add(delta),
+            });
+        }
+
+        self.get(&key)
+    }
+
+    /// take `delta` from the count of `key`, returning the new count
+    pub fn decrement(&mut self, key: K, delta: i64) -> i64 {
+        self.increment(key, delta.wrapping_neg())
+    }
+
+    /// remove a key, returning its count
+    pub fn remove(&mut self, k: K) -> Option<i64> {
+        if !self.contains_key(&k) {
+            return None;
+        }
+
+        let old = self.get(&k);
+        let seen = self.counts[&k].iter()
+            .map(|(actor, x)| (*actor, (x.seq, x.total)))
+            .collect();
+        self.apply(CounterOp::Remove { key: k, seen });
+
+        Some(old)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.counts.get(key).is_some_and(|x| x.values().any(|x| x.live().is_some()))
+    }
+
+    /// every key in the map, in order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Iterate over every key and its count, in order of keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, i64)> {
+        self.counts.iter()
+            .filter(|(_, x)| x.values().any(|x| x.live().is_some()))
+            .map(|(k, x)| (k, count(x)))
+    }
+
+    fn apply(&mut self, op: CounterOp<K>) {
+        self.absorb(op.clone());
+        self.tape.push(op);
+    }
+
+    fn absorb(&mut self, op: CounterOp<K>) {
+        match op {
+            CounterOp::Add { key, actor, seq, total } => {
+                let share = self.counts.entry(key).or_default().entry(actor).or_default();
+                if seq > share.seq {
+                    share.seq = seq;
+                    share.total = total;
+                }
+            }
+            CounterOp::Remove { key, seen } => {
+                let shares = self.counts.entry(key).or_default();
+                for (actor, (seq, total)) in seen {
+                    let share = shares.entry(actor).or_default();
+                    if seq > share.removed_seq {
+                        share.removed_seq = seq;
+                        share.removed_total = total;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// the count of a key, from every actor's share
+fn count(shares: &BTreeMap<usize, Share>) -> i64 {
+    shares.values().filter_map(|x| x.live()).fold(0, i64::wrapping_add)
+}
+
+impl<K: MapKey> Default for SyncedCounterMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: MapKey> Clone for SyncedCounterMap<K> {
+    fn clone(&self) -> Self {
+        SyncedCounterMap {
+            counts: self.counts.clone(),
+            actor: rand::random(),
+            tape: vec![],
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use anyhow::Result;
+use serde::Serialize;
+
+type Update<S, O, C> = Box<dyn Fn(&mut O, &S, &C) + Send>;
+
+/// A document computed from other documents
+///
+/// # Notes
+/// `Derived` owns its sources, so it knows when they change. A change
+/// made with [Derived::apply] (a local edit, or replaying a tape from a
+/// peer) is passed to the update closure given with
+/// [Derived::with_update], which brings the output up to date from the
+/// change alone. Without one, or after any mutable access through
+/// [Derived::sources_mut], the output is marked stale and recomputed in
+/// full, once, on the next read.
+///
+/// Peers who don't want to compute it themselves can be sent the
+/// output with [Derived::publish] instead; it is CBOR, as produced by
+/// [ciborium], and read-only on their end.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # use synch::sync::backend::SeqOp;
+/// # fn main() -> anyhow::Result<()> {
+/// let chat: SyncedList<String> = SyncedList::new();
+/// let mut peer = chat.clone();
+/// let mut letters = Derived::new(chat, |chat| {
+///     chat.iter().map(|x| x.len()).sum::<usize>()
+/// }).with_update(|letters, _, tape: &Vec<ListOp<String>>| {
+///     // only messages arrive in this chat; none are edited or removed
+///     for op in tape {
+///         if let ListOp::Seq(SeqOp::Insert { val, .. }) = op {
+///             *letters += val.len();
+///         }
+///     }
+/// });
+/// println!("{} letters", letters.get());
+///
+/// peer.push("hello".into());
+/// letters.apply(peer.tape(), |chat, tape| chat.replay(tape.clone()));
+/// assert!(!letters.is_stale());
+/// if let Some(update) = letters.publish()? {
+///     let letters: usize = ciborium::from_reader(&update[..])?;
+///     assert_eq!(letters, 5);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Derived<S, O, C = ()> {
+    sources: S,
+    compute: Box<dyn Fn(&S) -> O + Send>,
+    /// brings the output up to date with a change, if it can be
+    update: Option<Update<S, O, C>>,
+    output: Option<O>,
+    /// the output as last published, to avoid republishing it unchanged
+    published: Option<Vec<u8>>,
+}
+
+impl<S, O> Derived<S, O> {
+    pub fn new<F>(sources: S, compute: F) -> Self
+    where F: Fn(&S) -> O + Send + 'static {
+        Derived { sources, compute: Box::new(compute), update: None, output: None, published: None }
+    }
+
+    /// Keep the output up to date from each change given to
+    /// [Derived::apply], rather than recomputing it.
+    ///
+    /// # Notes
+    /// `update` gets the output as it was, the sources with the change
+    /// applied, and the change. It must leave the output as `compute`
+    /// would make it.
+    pub fn with_update<C, F>(self, update: F) -> Derived<S, O, C>
+    where F: Fn(&mut O, &S, &C) + Send + 'static {
+        Derived {
+            sources: self.sources,
+            compute: self.compute,
+            update: Some(Box::new(update)),
+            output: self.output,
+            published: self.published,
+        }
+    }
+}
+
+impl<S, O, C> Derived<S, O, C> {
+    pub fn sources(&self) -> &S {
+        &self.sources
+    }
+
+    /// change the sources, marking the output stale
+    pub fn sources_mut(&mut self) -> &mut S {
+        self.output = None;
+        &mut self.sources
+    }
+
+    /// Change the sources with `apply`, such as by replaying the tape
+    /// `change`, then update the output from it.
+    ///
+    /// # Notes
+    /// Falls back to marking the output stale, as
+    /// [Derived::sources_mut] does, if there is no update closure.
+    pub fn apply<F>(&mut self, change: C, apply: F)
+    where F: FnOnce(&mut S, &C) {
+        apply(&mut self.sources, &change);
+
+        match (&self.update, &mut self.output) {
+            (Some(update), Some(output)) => update(output, &self.sources, &change),
+            _ => self.output = None,
+        }
+    }
+
+    /// whether the output must be recomputed before it is read
+    pub fn is_stale(&self) -> bool {
+        self.output.is_none()
+    }
+
+    /// the output, recomputed first if any source changed
+    pub fn get(&mut self) -> &O {
+        self.output.get_or_insert_with(|| (self.compute)(&self.sources))
+    }
+
+    /// give the sources back, dropping the output
+    pub fn into_sources(self) -> S {
+        self.sources
+    }
+}
+
+impl<S, O: Serialize, C> Derived<S, O, C> {
+    /// Encode the output for peers, if it changed since the last publish.
+    ///
+    /// # Return
+    /// [None] if peers already have the current output.
+    pub fn publish(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut buf = vec![];
+        ciborium::into_writer(self.get(), &mut buf)?;
+
+        if self.published.as_ref() == Some(&buf) {
+            return Ok(None);
+        }
+        self.published = Some(buf.clone());
+        Ok(Some(buf))
+    }
+}
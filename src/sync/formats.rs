@@ -0,0 +1,127 @@
+//! Import and Export
+//!
+//! Reading and writing synced types as CSV, JSON lines and JSON.
+//!
+//! Imports go through the usual edits, so the tape holds an op for
+//! everything read in; publish it and the data is editable by the
+//! swarm like anything else. A malformed record fails the whole import
+//! before anything is recorded.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read, Write};
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::list::SyncedList;
+use super::map::{MapKey, MapVal, SyncedMap};
+
+impl<T: Clone + Serialize> SyncedList<T> {
+    /// Write the list as CSV, one row per element under a header of field names.
+    ///
+    /// # Notes
+    /// Elements should be flat structs (or tuples, which get no
+    /// header); CSV has no place for nested fields.
+    pub fn export_csv(&self, writer: impl Write) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        for element in self.iter() {
+            writer.serialize(element)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the list as JSON lines, one element per line.
+    pub fn export_jsonl(&self, mut writer: impl Write) -> Result<()> {
+        for element in self.iter() {
+            serde_json::to_writer(&mut writer, element)?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Clone + DeserializeOwned> SyncedList<T> {
+    /// Push every row of a CSV with a header row, as written by [SyncedList::export_csv].
+    ///
+    /// # Return
+    /// How many elements were pushed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # use serde::Deserialize;
+    /// # fn main() -> anyhow::Result<()> {
+    /// #[derive(Clone, Deserialize)]
+    /// struct Person { name: String, age: u32 }
+    ///
+    /// let csv = "name,age\namy,31\nbob,27\n";
+    /// let mut people: SyncedList<Person> = SyncedList::new();
+    /// assert_eq!(people.import_csv(csv.as_bytes())?, 2);
+    /// assert_eq!(people.tape().len(), 2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn import_csv(&mut self, reader: impl Read) -> Result<usize> {
+        let rows = csv::Reader::from_reader(reader)
+            .deserialize()
+            .collect::<Result<Vec<T>, _>>()?;
+
+        let count = rows.len();
+        self.extend(rows);
+        Ok(count)
+    }
+
+    /// Push every element of JSON lines, as written by [SyncedList::export_jsonl].
+    ///
+    /// # Notes
+    /// Blank lines are skipped.
+    ///
+    /// # Return
+    /// How many elements were pushed.
+    pub fn import_jsonl(&mut self, reader: impl BufRead) -> Result<usize> {
+        let mut elements = vec![];
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            elements.push(serde_json::from_str(&line)
+                          .map_err(|err| anyhow!("line {}: {err}", i + 1))?);
+        }
+
+        let count = elements.len();
+        self.extend(elements);
+        Ok(count)
+    }
+}
+
+impl<K: MapKey + Serialize, V: MapVal + Serialize> SyncedMap<K, V> {
+    /// Write the map as a JSON object of resolved values.
+    ///
+    /// # Notes
+    /// JSON object keys are strings, so `K` must serialize as a string
+    /// or a number.
+    pub fn export_json(&self, writer: impl Write) -> Result<()> {
+        let resolved: BTreeMap<&K, V> = self.iter().collect();
+        serde_json::to_writer_pretty(writer, &resolved)?;
+        Ok(())
+    }
+}
+
+impl<K: MapKey + DeserializeOwned, V: MapVal + DeserializeOwned> SyncedMap<K, V> {
+    /// Insert every entry of a JSON object, as written by [SyncedMap::export_json].
+    ///
+    /// # Return
+    /// How many entries were inserted.
+    pub fn import_json(&mut self, reader: impl Read) -> Result<usize> {
+        let entries: BTreeMap<K, V> = serde_json::from_reader(reader)?;
+
+        let count = entries.len();
+        for (k, v) in entries {
+            self.insert(k, v);
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,83 @@
+//! Golden Test Vectors
+//!
+//! Canonical CBOR encodings of snapshots and tapes, produced by fixed
+//! scripts of edits. Any implementation of the wire format, or any
+//! refactor of this one, must produce exactly these bytes.
+//!
+//! The vectors themselves live in `golden/` at the root of the
+//! repository, so other languages can read them too.
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use super::list::SyncedList;
+use super::map::SyncedMap;
+use super::taped::Taped;
+
+/// every shipped vector, as `(name, bytes)`
+pub const GOLDEN_VECTORS: &[(&str, &[u8])] = &[
+    ("list-snapshot", include_bytes!("../../golden/list-snapshot.cbor")),
+    ("list-tape", include_bytes!("../../golden/list-tape.cbor")),
+    ("map-tape", include_bytes!("../../golden/map-tape.cbor")),
+];
+
+/// Check this build still encodes every vector byte for byte.
+///
+/// # Return
+/// An error naming the first vector which differs.
+pub fn verify_golden() -> Result<()> {
+    for (name, expected) in GOLDEN_VECTORS {
+        let actual = generate(name)?;
+        if actual != *expected {
+            return Err(anyhow!("golden vector '{name}' differs: expected {} bytes, encoded {}",
+                               expected.len(), actual.len()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Encode a vector from its script.
+///
+/// # Notes
+/// Use this to regenerate the files in `golden/` after a deliberate
+/// change to the wire format.
+pub fn generate(name: &str) -> Result<Vec<u8>> {
+    match name {
+        "list-snapshot" => encode(&list_script().0),
+        "list-tape" => encode(&list_script().1),
+        "map-tape" => encode(&map_script()),
+        _ => Err(anyhow!("no golden vector called '{name}'")),
+    }
+}
+
+/// a list edited by actor 0, pinned so the bytes don't change, and the
+/// tape of those edits
+fn list_script() -> (SyncedList<String>, Vec<<SyncedList<String> as Taped>::Operation>) {
+    let mut list = SyncedList::new().with_actor(0);
+    list.push("a".to_string());
+    list.push("b".to_string());
+    list.insert(1, "c".to_string());
+    list.remove(0);
+    *list.lock(1).unwrap() = "d".to_string();
+
+    let tape = list.tape();
+    (list, tape)
+}
+
+/// the tape of a map edited by actor 0
+fn map_script() -> Vec<<SyncedMap<String, u64> as Taped>::Operation> {
+    let mut map = SyncedMap::new().with_actor(0);
+    map.insert("a".to_string(), 1);
+    map.insert("b".to_string(), 2);
+    map.insert("a".to_string(), 3);
+    map.remove("b".to_string());
+
+    map.tape()
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    ciborium::into_writer(value, &mut buf)?;
+    Ok(buf)
+}
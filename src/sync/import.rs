@@ -0,0 +1,154 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+use super::taped::Taped;
+
+/// default number of records turned into ops per [ImportBatch]
+pub const DEFAULT_IMPORT_BATCH: usize = 256;
+
+/// one batch of an import, ready to publish
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportBatch<O> {
+    /// the ops for this batch's records
+    pub tape: Vec<O>,
+    /// records imported so far, this batch included
+    pub imported: usize,
+    /// records in the whole import, if the source knows
+    pub total: Option<usize>,
+}
+
+impl<O> ImportBatch<O> {
+    /// how far along the import is, from 0 to 1, if the total is known
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|x| if x == 0 { 1.0 } else { self.imported as f64 / x as f64 })
+    }
+}
+
+/// Import of many records into a document, a batch at a time.
+///
+/// # Notes
+/// Give the document the actor the import should be attributed to,
+/// with `with_actor`, so peers can tell imported data from edits. Each
+/// batch takes its ops off the document's tape, so publish every batch
+/// in order; nothing else should edit the document meanwhile.
+///
+/// Iterating gives batches as fast as they are made. To keep a big
+/// import from flooding every peer, use [BatchImport::next_paced]
+/// with [BatchImport::with_pace].
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # use std::time::Duration;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let rows = (0..2500).map(|x| format!("person {x}"));
+/// let mut peer: SyncedList<String> = SyncedList::new();
+/// let people = SyncedList::new();
+/// let mut import = BatchImport::new(people, rows, |doc, row| doc.push(row))
+///     .with_pace(Duration::from_millis(20));
+/// while let Some(batch) = import.next_paced().await {
+///     peer.replay(batch.tape.clone());
+///     println!("{:.0}% imported", 100.0 * batch.fraction().unwrap());
+/// }
+/// let people = import.into_inner();
+/// assert_eq!(people.len(), 2500);
+/// assert_eq!(peer.len(), 2500);
+/// # }
+/// ```
+pub struct BatchImport<D, I, F> {
+    doc: D,
+    records: I,
+    apply: F,
+    batch_size: usize,
+    pace: Duration,
+    imported: usize,
+    total: Option<usize>,
+    last: Option<Instant>,
+}
+
+impl<D, I, F> BatchImport<D, I, F>
+where D: Taped,
+      I: Iterator,
+      F: FnMut(&mut D, I::Item) {
+    /// import `records` into `doc`, with `apply` turning each into an edit
+    pub fn new<R: IntoIterator<IntoIter = I>>(doc: D, records: R, apply: F) -> Self {
+        let records = records.into_iter();
+        let total = match records.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None,
+        };
+
+        BatchImport {
+            doc,
+            records,
+            apply,
+            batch_size: DEFAULT_IMPORT_BATCH,
+            pace: Duration::ZERO,
+            imported: 0,
+            total,
+            last: None,
+        }
+    }
+
+    /// apply `batch_size` records per batch
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// let [BatchImport::next_paced] give at most one batch per `pace`
+    pub fn with_pace(mut self, pace: Duration) -> Self {
+        self.pace = pace;
+        self
+    }
+
+    /// records imported so far
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    /// the document, as far as the import got
+    pub fn get_ref(&self) -> &D {
+        &self.doc
+    }
+
+    /// Stop importing, giving back the document.
+    ///
+    /// # Notes
+    /// Edits of a batch not yet taken are left on the document's tape.
+    pub fn into_inner(self) -> D {
+        self.doc
+    }
+
+    /// Make the next batch, waiting first if the last was made less than the pace ago.
+    pub async fn next_paced(&mut self) -> Option<ImportBatch<D::Operation>> {
+        if let Some(last) = self.last {
+            tokio::time::sleep_until(last + self.pace).await;
+        }
+        self.next()
+    }
+}
+
+impl<D, I, F> Iterator for BatchImport<D, I, F>
+where D: Taped,
+      I: Iterator,
+      F: FnMut(&mut D, I::Item) {
+    type Item = ImportBatch<D::Operation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut applied = 0;
+        for record in self.records.by_ref().take(self.batch_size) {
+            (self.apply)(&mut self.doc, record);
+            applied += 1;
+        }
+        if applied == 0 {
+            return None;
+        }
+
+        self.imported += applied;
+        self.last = Some(Instant::now());
+        Some(ImportBatch { tape: self.doc.tape(), imported: self.imported, total: self.total })
+    }
+}
@@ -0,0 +1,53 @@
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::map::{MapKey, MapVal};
+
+/// An index over a map which can be kept up to date one key at a time
+pub(crate) trait SecondaryIndex<K, V>: Send {
+    /// `key` now holds `value`, or nothing
+    fn observe(&mut self, key: &K, value: Option<&V>);
+
+    /// every key indexed under `by`, or [None] if `by` is the wrong type
+    fn lookup(&self, by: &dyn Any) -> Option<Vec<K>>;
+}
+
+/// An index of map keys by some value extracted from their values
+pub(crate) struct FieldIndex<K, V, I> {
+    extract: Box<dyn Fn(&V) -> I + Send>,
+    forward: BTreeMap<I, BTreeSet<K>>,
+    reverse: BTreeMap<K, I>,
+}
+
+impl<K, V, I> FieldIndex<K, V, I> {
+    pub(crate) fn new(extract: Box<dyn Fn(&V) -> I + Send>) -> Self {
+        FieldIndex { extract, forward: BTreeMap::new(), reverse: BTreeMap::new() }
+    }
+}
+
+impl<K, V, I> SecondaryIndex<K, V> for FieldIndex<K, V, I>
+where K: MapKey + Send,
+      V: MapVal,
+      I: Ord + Clone + Send + 'static {
+    fn observe(&mut self, key: &K, value: Option<&V>) {
+        if let Some(old) = self.reverse.remove(key) {
+            if let Some(keys) = self.forward.get_mut(&old) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.forward.remove(&old);
+                }
+            }
+        }
+
+        if let Some(value) = value {
+            let by = (self.extract)(value);
+            self.forward.entry(by.clone()).or_default().insert(key.clone());
+            self.reverse.insert(key.clone(), by);
+        }
+    }
+
+    fn lookup(&self, by: &dyn Any) -> Option<Vec<K>> {
+        let by = by.downcast_ref::<I>()?;
+        Some(self.forward.get(by).map_or(vec![], |x| x.iter().cloned().collect()))
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+
+use super::taped::Taped;
+
+/// A synced document which is only built when first opened
+///
+/// # Notes
+/// Decoding a snapshot and replaying its tapes is the expensive part of
+/// joining a swarm. `Lazy` keeps the raw snapshot and every tape
+/// recieved until the application first calls [Lazy::open], so apps
+/// with hundreds of documents only pay for the ones they look at.
+///
+/// Snapshots and tapes are CBOR, as produced by [ciborium].
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # fn main() -> anyhow::Result<()> {
+/// let mut peer: SyncedList<String> = vec!["eggs".to_string()].into();
+/// let (mut snapshot, mut tape_from_peer) = (vec![], vec![]);
+/// ciborium::into_writer(&peer, &mut snapshot)?;
+/// peer.tape();
+/// peer.push("bread".into());
+/// ciborium::into_writer(&peer.tape(), &mut tape_from_peer)?;
+///
+/// let mut todo: Lazy<SyncedList<String>> = Lazy::from_snapshot(snapshot);
+/// todo.push_tape(tape_from_peer)?; // just buffered
+/// todo.open()?.push("milk".into()); // decoded and replayed here
+/// assert_eq!(todo.open()?.len(), 3);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Lazy<D: Taped> {
+    snapshot: Option<Vec<u8>>,
+    pending: Vec<Vec<u8>>,
+    doc: Option<D>,
+}
+
+impl<D> Lazy<D>
+where D: Taped + Default + DeserializeOwned,
+      D::Operation: DeserializeOwned {
+    /// a document with no snapshot yet
+    pub fn new() -> Self {
+        Lazy { snapshot: None, pending: vec![], doc: None }
+    }
+
+    /// a document which will be decoded from `snapshot`
+    pub fn from_snapshot(snapshot: Vec<u8>) -> Self {
+        Lazy { snapshot: Some(snapshot), pending: vec![], doc: None }
+    }
+
+    /// whether the document has been built yet
+    pub fn is_hydrated(&self) -> bool {
+        self.doc.is_some()
+    }
+
+    /// number of tapes waiting for the document to be opened
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// recieve an encoded tape, replaying it only if the document is open
+    pub fn push_tape(&mut self, tape: Vec<u8>) -> Result<()> {
+        match self.doc {
+            Some(ref mut doc) => doc.replay(ciborium::from_reader(tape.as_slice())?),
+            None => self.pending.push(tape),
+        }
+
+        Ok(())
+    }
+
+    /// get the document, building it on first access
+    pub fn open(&mut self) -> Result<&mut D> {
+        if self.doc.is_none() {
+            let mut doc: D = match self.snapshot {
+                Some(ref x) => ciborium::from_reader(x.as_slice())?,
+                None => D::default(),
+            };
+            for tape in self.pending.iter() {
+                doc.replay(ciborium::from_reader(tape.as_slice())?);
+            }
+
+            self.snapshot = None;
+            self.pending.clear();
+            self.doc = Some(doc);
+        }
+
+        Ok(self.doc.as_mut().unwrap())
+    }
+}
+
+impl<D> Default for Lazy<D>
+where D: Taped + Default + DeserializeOwned,
+      D::Operation: DeserializeOwned {
+    fn default() -> Self {
+        Self::new()
+    }
+}
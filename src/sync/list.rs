@@ -1,79 +1,200 @@
-use crdts::{CmRDT};
-use crdts::list::{Op, List};
 use serde::{Serialize, Deserialize, Serializer};
 use serde::ser::{SerializeStruct};
 use std::marker::PhantomData;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Index, RangeBounds, Bound};
 use std::fmt::Debug;
+use std::collections::{HashMap, BTreeMap};
+use std::borrow::Cow;
+use anyhow::{Result, anyhow};
+use log::warn;
 
 use super::taped::Taped;
+use super::aggregate::{Aggregate, Aggregator};
+use super::rope::Rope;
+use super::backend::{SeqBackend, SeqId, SeqOp, empty_seq_id};
 
 type PhantomUnsend = PhantomData<std::sync::MutexGuard<'static, ()>>;
+/// Stable handle to one element of a [SyncedList], from [SyncedList::id_of].
+///
+/// # Notes
+/// An element keeps its identifier wherever concurrent inserts and
+/// deletes shift it to, and on every replica, so it can be held on to
+/// where an index can't, and keeps it when moved.
+pub type ElementId = SeqId;
+
+/// which of two concurrent updates to an element wins: the higher
+/// count of updates seen before it, then the higher actor
+type Revision = (u64, usize);
+
+/// an edit to a [SyncedList], as recorded on its tape
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListOp<T> {
+    /// insert or delete an element
+    Seq(SeqOp<T>),
+    /// give an existing element a new value, keeping its identity
+    Update { id: ElementId, val: T, revision: Revision },
+    /// put an existing element at the new place `to`, keeping its
+    /// identity; `val` is its value when moved
+    Move { id: ElementId, to: SeqId, val: T, revision: Revision },
+}
+
+/// how a [SyncedList] changed, as given to [SyncedList::on_change]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListChange<T> {
+    /// `value` now sits at `index`
+    Inserted { index: usize, value: T },
+    /// `value` was at `index`, and is gone
+    Removed { index: usize, value: T },
+    /// the element at `index` is now `value`
+    Updated { index: usize, value: T },
+    /// `value` was at `from`, and now sits at `to`
+    Moved { from: usize, to: usize, value: T },
+}
+
+type Listener<T> = Box<dyn FnMut(&ListChange<T>) + Send>;
+
+/// version of the format written by [SyncedList::export_state] and
+/// [super::map::SyncedMap::export_state]
+pub const STATE_VERSION: u32 = 1;
+
+/// everything a [SyncedList] replica needs to pick up where it left off
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Clone + Serialize, B: Serialize",
+              deserialize = "T: Clone + Deserialize<'de>, B: Deserialize<'de>"))]
+struct ListState<'a, T: Clone, B: Clone> {
+    version: u32,
+    actor: usize,
+    list: Cow<'a, B>,
+    updates: Cow<'a, BTreeMap<ElementId, (Revision, T)>>,
+    #[serde(default)]
+    moves: Cow<'a, BTreeMap<ElementId, (Revision, SeqId)>>,
+    tape: Cow<'a, [ListOp<T>]>,
+}
+
+/// an op [SyncedList::try_replay] refused to apply, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedOp<T> {
+    /// where the op was on the tape
+    pub index: usize,
+    pub op: ListOp<T>,
+    pub reason: String,
+}
+
+impl<T> From<SeqOp<T>> for ListOp<T> {
+    fn from(op: SeqOp<T>) -> Self {
+        ListOp::Seq(op)
+    }
+}
 
 /// List Structure for Syncronized Operations
 ///
 /// # Key Note
 /// **Lists can only be synced if they are `.clone()` of each other.**
 ///
+/// Every new list, clone, and list loaded from a snapshot edits as a
+/// new random actor, so replicas never mint the same identifiers.
+///
 /// # Examples
 ///
 /// ```
+/// # use synch::sync::prelude::*;
 /// let mut amy:SyncedList<u8> = SyncedList::new();
 /// let mut bob:SyncedList<u8> = amy.clone();
-/// 
+///
 /// // push some values
 /// amy.push(5);
 /// bob.push(8);
 /// // get some values
-/// let first = amy.lock(0).expect("no values are here!")
+/// let mut first = amy.lock(0).expect("no values are here!");
 /// // we can even change it
 /// *first = 8;
+/// drop(first);
 /// // we can now syncronize the lists
-/// amy.replay(bob.tape());
-/// bob.replay(amy.tape());
+/// let (from_amy, from_bob) = (amy.tape(), bob.tape());
+/// amy.replay(from_bob);
+/// bob.replay(from_amy);
+/// assert_eq!(Vec::from(amy.clone()), Vec::from(bob.clone()));
 /// // once we call .tape() once, it will no longer be available
-/// assert_eq!(bob.tape().len(), 0)
-/// assert_eq!(amy.tape().len(), 0)
+/// assert_eq!(bob.tape().len(), 0);
+/// assert_eq!(amy.tape().len(), 0);
 /// ```
 #[derive(Deserialize)]
-pub struct SyncedList<T: Clone> {
-    list: List<T, usize>,
+#[serde(from = "Snapshot<T, B>")]
+pub struct SyncedList<T: Clone, B: SeqBackend<T> = Rope<T>> {
+    list: B,
     actor: usize,
-    #[serde(skip)] 
-    tape: Vec<Op<T, usize>>,
-    
+    /// values of elements which were updated in place, by element
+    updates: BTreeMap<ElementId, (Revision, T)>,
+    /// where elements which were moved are now, by element
+    moves: BTreeMap<ElementId, (Revision, SeqId)>,
+    /// which element each place in `moves` holds
+    owners: BTreeMap<SeqId, ElementId>,
+    tape: Vec<ListOp<T>>,
+    aggregates: HashMap<String, Aggregator<ElementId, T>>,
+    listeners: Vec<Listener<T>>,
 }
 
-impl<T: Clone + Serialize> Serialize for SyncedList<T> {
+/// what a [SyncedList] snapshot holds
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de>, B: Deserialize<'de>"))]
+struct Snapshot<T, B> {
+    list: B,
+    #[serde(default)]
+    updates: BTreeMap<ElementId, (Revision, T)>,
+    #[serde(default)]
+    moves: BTreeMap<ElementId, (Revision, SeqId)>,
+}
+
+impl<T: Clone, B: SeqBackend<T>> From<Snapshot<T, B>> for SyncedList<T, B> {
+    fn from(snapshot: Snapshot<T, B>) -> Self {
+        SyncedList {
+            updates: snapshot.updates,
+            owners: owners(&snapshot.moves),
+            moves: snapshot.moves,
+            ..SyncedList::with_backend(snapshot.list)
+        }
+    }
+}
+
+impl<T: Clone + Serialize, B: SeqBackend<T> + Serialize> Serialize for SyncedList<T, B> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
-        let mut pack = serializer.serialize_struct("SyncedList", 2)?;
+        // lists which were never updated in place or moved encode as they always have
+        let fields = 2 + usize::from(!self.updates.is_empty()) + usize::from(!self.moves.is_empty());
+        let mut pack = serializer.serialize_struct("SyncedList", fields)?;
         pack.serialize_field("list", &self.list)?;
-        pack.serialize_field("actor", &(self.actor + 1))?;
+        // no longer read back, but kept so snapshots encode as they always have
+        pack.serialize_field("actor", &self.actor.wrapping_add(1))?;
+        if !self.updates.is_empty() {
+            pack.serialize_field("updates", &self.updates)?;
+        }
+        if !self.moves.is_empty() {
+            pack.serialize_field("moves", &self.moves)?;
+        }
         pack.end()
     }
 }
 
-impl<'a, T: Clone + Debug> Debug for SyncedList<T> {
+impl<T: Clone + Debug, B: SeqBackend<T>> Debug for SyncedList<T, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SyncedList")
-            .field("list", &self.list.read::<Vec<_>>())
+            .field("list", &self.iter().collect::<Vec<_>>())
             .field("actor", &self.actor)
             .finish()
     }
 }
 
-pub struct SyncedListGuard<'a, T: Clone> {
+pub struct SyncedListGuard<'a, T: Clone, B: SeqBackend<T> = Rope<T>> {
     value: T,
     idx: usize,
-    src: &'a mut SyncedList<T>,
+    src: &'a mut SyncedList<T, B>,
     was_mutated: bool,
     _not_send: PhantomUnsend,
 }
 
-impl<'a, T: Clone + Debug> Debug for SyncedListGuard<'a, T> {
+impl<'a, T: Clone + Debug, B: SeqBackend<T>> Debug for SyncedListGuard<'a, T, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SyncedListGuard")
             .field("value", &self.value)
@@ -83,7 +204,7 @@ impl<'a, T: Clone + Debug> Debug for SyncedListGuard<'a, T> {
     }
 }
 
-impl<T:Clone> Deref for SyncedListGuard<'_, T> {
+impl<T: Clone, B: SeqBackend<T>> Deref for SyncedListGuard<'_, T, B> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -91,36 +212,142 @@ impl<T:Clone> Deref for SyncedListGuard<'_, T> {
     }
 }
 
-impl<T:Clone> DerefMut for SyncedListGuard<'_, T> {
+impl<T: Clone, B: SeqBackend<T>> DerefMut for SyncedListGuard<'_, T, B> {
     fn deref_mut (&mut self) -> &mut Self::Target {
         self.was_mutated = true;
         &mut self.value
     }
 }
 
-impl<T:Clone> Drop for SyncedListGuard<'_, T> {
+impl<T: Clone, B: SeqBackend<T>> Drop for SyncedListGuard<'_, T, B> {
     fn drop (&mut self)  {
         if self.was_mutated {
-            let delete_op = self.src.list.delete_index(self.idx, self.src.actor)
-                .expect(
-                    &format!("index out of bounds: length is {} but index is {}",
-                             self.src.len(), self.idx)
-                );
-            self.src.list.apply(delete_op.clone());
-            self.src.tape.push(delete_op);
+            let id = self.src.id_of(self.idx)
+                .unwrap_or_else(|| panic!("index out of bounds: length is {} but index is {}",
+                                          self.src.len(), self.idx));
+            self.src.update_by_id(&id, self.value.clone());
+        }
+    }
+}
 
-            let insert_op = self.src.list.insert_index(self.idx, self.value.clone(),
-                                                       self.src.actor);
-            self.src.list.apply(insert_op.clone());
-            self.src.tape.push(insert_op);
+/// Cursor over guards for every element of a [SyncedList]
+pub struct SyncedListCursor<'a, T: Clone, B: SeqBackend<T> = Rope<T>> {
+    src: &'a mut SyncedList<T, B>,
+    idx: usize,
+}
 
-        }
+impl<T: Clone, B: SeqBackend<T>> SyncedListCursor<'_, T, B> {
+    /// guard for the next element, if there is one
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<SyncedListGuard<'_, T, B>> {
+        let guard = self.src.lock(self.idx)?;
+        self.idx += 1;
+        Some(guard)
     }
 }
 
 impl<T: Clone> SyncedList<T> {
     pub fn new() -> Self {
-        SyncedList { list: List::new(), actor: 0, tape: vec![] } 
+        Self::with_backend(Rope::new())
+    }
+}
+
+impl<T: Clone, B: SeqBackend<T>> SyncedList<T, B> {
+    /// Make an empty list kept in `backend`, rather than the default [Rope].
+    ///
+    /// # Notes
+    /// Replicas may use different backends; see [super::backend].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// // any SeqBackend; this one is the default
+    /// let mut log: SyncedList<String> = SyncedList::with_backend(Default::default());
+    /// log.push("started".into());
+    /// ```
+    pub fn with_backend(backend: B) -> Self {
+        SyncedList {
+            list: backend,
+            actor: rand::random(),
+            updates: BTreeMap::new(),
+            moves: BTreeMap::new(),
+            owners: BTreeMap::new(),
+            tape: vec![],
+            aggregates: HashMap::new(),
+            listeners: vec![]
+        }
+    }
+
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on, such as one handed out by a
+    /// coordinator; it must not be used by any other replica.
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// Save this replica, actor and unsent tape included, to resume it
+    /// later with [SyncedList::import_state].
+    ///
+    /// # Notes
+    /// Unlike serializing the list, which makes a snapshot for new
+    /// replicas to start from, this keeps the replica's identity: the
+    /// imported list edits as the same actor and still has the tape
+    /// which was not yet published. So import each state once, into
+    /// the replica which exported it; clone it to start another.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut todo: SyncedList<String> = SyncedList::new();
+    /// todo.push("milk".into());
+    /// // on shutdown
+    /// let state = todo.export_state()?;
+    /// // on start
+    /// let mut todo: SyncedList<String> = SyncedList::import_state(&state)?;
+    /// assert_eq!(todo.tape().len(), 1); // edits made before the shutdown
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn export_state(&self) -> Result<Vec<u8>>
+    where T: Serialize, B: Serialize {
+        let mut buf = vec![];
+        ciborium::into_writer(&ListState {
+            version: STATE_VERSION,
+            actor: self.actor,
+            list: Cow::Borrowed(&self.list),
+            updates: Cow::Borrowed(&self.updates),
+            moves: Cow::Borrowed(&self.moves),
+            tape: Cow::Borrowed(&self.tape),
+        }, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Resume a replica saved by [SyncedList::export_state].
+    pub fn import_state(buf: &[u8]) -> Result<Self>
+    where T: for<'de> Deserialize<'de>, B: for<'de> Deserialize<'de> {
+        let state: ListState<T, B> = ciborium::from_reader(buf)?;
+        if state.version != STATE_VERSION {
+            return Err(anyhow!("list state is version {}, expected {STATE_VERSION}", state.version));
+        }
+
+        Ok(SyncedList {
+            list: state.list.into_owned(),
+            actor: state.actor,
+            updates: state.updates.into_owned(),
+            owners: owners(&state.moves),
+            moves: state.moves.into_owned(),
+            tape: state.tape.into_owned(),
+            aggregates: HashMap::new(),
+            listeners: vec![]
+        })
     }
 
     /// Get the length of the list.
@@ -128,32 +355,143 @@ impl<T: Clone> SyncedList<T> {
         self.list.len()
     }
 
+    /// Check whether the list has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
     /// Grab the clone of an element from the list
+    ///
+    /// # Notes
+    /// panics if `idx` is out of bounds; see [SyncedList::try_index].
     pub fn index(&self, idx: usize) -> T {
-        if self.len() > idx {
-            self.list.position(idx).unwrap().clone()
-        } else {
-            panic!("index out of bounds: length is {} but index is {}",
-                   self.len(), idx);
+        self.try_index(idx).unwrap_or_else(
+            || panic!("index out of bounds: length is {} but index is {}",
+                      self.len(), idx)
+        )
+    }
+
+    /// Grab the clone of an element from the list, if `idx` is in bounds.
+    pub fn try_index(&self, idx: usize) -> Option<T> {
+        self.get_ref(idx).cloned()
+    }
+
+    /// Borrow an element from the list, if `idx` is in bounds.
+    ///
+    /// # Notes
+    /// Unlike [SyncedList::try_index], this does not clone the element.
+    pub fn get_ref(&self, idx: usize) -> Option<&T> {
+        self.list.entry(idx).map(|(id, x)| self.resolve(id, x))
+    }
+
+    /// Borrow the elements in `range`, if it is in bounds.
+    ///
+    /// # Notes
+    /// The elements are not stored contiguously, so this gives a [Vec]
+    /// of references rather than a slice. Finding the start walks the
+    /// list's chunks, so prefer one call for a range over [SyncedList::get_ref]
+    /// for each of its elements.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let frames: SyncedList<u32> = (0..100).collect();
+    /// let page = frames.read_slice(20..40).unwrap_or_default();
+    /// assert_eq!(page.len(), 20);
+    /// assert_eq!(*page[0], 20);
+    /// ```
+    pub fn read_slice<R: RangeBounds<usize>>(&self, range: R) -> Option<Vec<&T>> {
+        let (start, end) = self.bounds(&range);
+        if start > end || end > self.len() {
+            return None;
         }
+
+        Some(self.list.entries_from(start).take(end - start).map(|(id, x)| self.resolve(id, x)).collect())
+    }
+
+    /// The stable identifier of the element at `idx`, if it is in bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut todos: SyncedList<&str> = vec!["milk", "eggs"].into();
+    /// let mut bob = todos.clone();
+    /// let id = todos.id_of(1).unwrap();
+    /// // ... replay edits from other replicas, which may shift the item
+    /// bob.insert(0, "bread");
+    /// todos.replay(bob.tape());
+    /// todos.update_by_id(&id, "two eggs");
+    /// assert_eq!(Vec::from(todos), ["bread", "milk", "two eggs"]);
+    /// ```
+    pub fn id_of(&self, idx: usize) -> Option<ElementId> {
+        self.list.entry(idx).map(|(place, _)| self.element_of(place).clone())
+    }
+
+    /// Where the element with identifier `id` is now, if it wasn't removed.
+    pub fn position_of(&self, id: &ElementId) -> Option<usize> {
+        self.list.position_entry(self.place_of(id)?)
+    }
+
+    /// Borrow the element with identifier `id`, if it wasn't removed.
+    pub fn get_by_id(&self, id: &ElementId) -> Option<&T> {
+        let place = self.place_of(id)?;
+        self.list.get(place).map(|x| self.resolve(place, x))
+    }
+
+    /// Set the element with identifier `id`, as writing through
+    /// [SyncedList::lock] does.
+    ///
+    /// # Return
+    /// The value it replaced, or [None] if it was removed, in which case
+    /// nothing is recorded.
+    pub fn update_by_id(&mut self, id: &ElementId, element: T) -> Option<T> {
+        let old = self.get_by_id(id)?.clone();
+        let seen = self.updates.get(id).map_or(0, |((count, _), _)| *count);
+
+        self.apply(ListOp::Update {
+            id: id.clone(),
+            val: element,
+            revision: (seen + 1, self.actor)
+        });
+        Some(old)
+    }
+
+    /// Remove the element with identifier `id`.
+    ///
+    /// # Return
+    /// The removed element, or [None] if it was already removed.
+    pub fn remove_by_id(&mut self, id: &ElementId) -> Option<T> {
+        let element = self.get_by_id(id)?.clone();
+        self.apply(self.delete_op(self.position_of(id)?)?);
+
+        Some(element)
     }
 
     /// Get an element from the list, optionally setting it.
     ///
+    /// # Notes
+    /// Writing through the guard records one [ListOp::Update], which
+    /// keeps the element's identity. Of concurrent updates to one
+    /// element, every replica keeps the same one; an update loses to a
+    /// concurrent delete.
+    ///
     /// # Examples
     ///
     /// ```
-    /// let mut list:SyncedList<u32> = SyncedList::new():
+    /// # use synch::sync::prelude::*;
+    /// let mut list:SyncedList<u32> = SyncedList::new();
     /// list.push(1);
     /// assert_eq!(*list.lock(0).unwrap(), 1);
     /// *list.lock(0).unwrap() = 2;
     /// assert_eq!(*list.lock(0).unwrap(), 2);
     /// ```
-    pub fn lock(&mut self, idx: usize) -> Option<SyncedListGuard<T>> {
+    pub fn lock(&mut self, idx: usize) -> Option<SyncedListGuard<'_, T, B>> {
         if self.len() > idx {
             Some(SyncedListGuard {
-                value: self.list.position(idx).unwrap().clone(),
-                idx: idx,
+                value: self.try_index(idx).unwrap(),
+                idx,
                 src: self,
                 was_mutated: false,
                 _not_send: PhantomData
@@ -161,37 +499,686 @@ impl<T: Clone> SyncedList<T> {
         } else { None }
     }
 
+    /// Iterate over the list without consuming or copying it.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.entries().map(|(_, x)| x)
+    }
+
+    /// Check whether the list has an element equal to `element`.
+    pub fn contains(&self, element: &T) -> bool
+    where T: PartialEq {
+        self.iter().any(|x| x == element)
+    }
+
+    /// Walk the list, getting a guard for each element in turn.
+    ///
+    /// # Notes
+    /// Like [SyncedList::lock], only elements which are written through
+    /// their guard generate ops. This is a cursor rather than an
+    /// [Iterator], as each guard borrows the list until it is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut list: SyncedList<u32> = vec![4, 12, 30].into();
+    /// let mut cursor = list.iter_mut();
+    /// while let Some(mut x) = cursor.next() {
+    ///     if *x > 10 {
+    ///         *x = 10;
+    ///     }
+    /// }
+    /// assert_eq!(Vec::from(list), [4, 10, 10]);
+    /// ```
+    pub fn iter_mut(&mut self) -> SyncedListCursor<'_, T, B> {
+        SyncedListCursor { src: self, idx: 0 }
+    }
+
     /// Push an element to the list.
     pub fn push(&mut self, element: T) {
         self.apply(self.list.append(element, self.actor));
     }
 
+    /// Push every element of `elements` to the list, in order.
+    ///
+    /// # Notes
+    /// [SyncedList::push] finds the end of the list by walking its
+    /// chunks, so pushing thousands of elements is slow. This makes the same
+    /// ops as pushing one at a time, but builds each one from the last
+    /// element's identifier directly. Each element is still one op on
+    /// the tape.
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, elements: I) {
+        let mut elements = elements.into_iter();
+        self.tape.reserve(elements.size_hint().0);
+
+        let Some(first) = elements.next() else { return; };
+        let op = self.list.append(first, self.actor);
+        let counters = op.dot().counter + 1..;
+        self.apply(op);
+
+        for (counter, element) in counters.zip(elements) {
+            self.apply(self.list.append_numbered(element, self.actor, counter));
+        }
+    }
+
     /// Remove an element from the list.
+    ///
+    /// # Notes
+    /// panics if `index` is out of bounds; see [SyncedList::try_remove].
+    ///
+    /// Removed elements leave no tombstone: the element, and any value
+    /// it was updated to, are dropped as soon as the delete applies.
+    /// What a list keeps besides its elements is one clock entry per
+    /// actor that ever edited it, so there is nothing to compact.
     pub fn remove(&mut self, index: usize) {
-        self.apply(self.list.delete_index(index, self.actor).expect(
-            &format!("index out of bounds: length is {} but index is {}",
-                     self.len(), index)
-        ));
+        if self.try_remove(index).is_none() {
+            panic!("index out of bounds: length is {} but index is {}",
+                   self.len(), index);
+        }
+    }
+
+    /// Remove an element from the list, if `index` is in bounds.
+    ///
+    /// # Return
+    /// The removed element.
+    pub fn try_remove(&mut self, index: usize) -> Option<T> {
+        let element = self.try_index(index)?;
+        self.apply(self.delete_op(index)?);
+
+        Some(element)
+    }
+
+    /// Remove the last element of the list, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        self.try_remove(self.len().checked_sub(1)?)
+    }
+
+    /// Remove the first element of the list, if there is one.
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.try_remove(0)
+    }
+
+    /// Keep only the elements `keep` is true for.
+    ///
+    /// # Notes
+    /// Each removal is its own op on the tape, so peers filter the
+    /// same elements; ones they insert concurrently are left alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut list: SyncedList<u32> = vec![1, 2, 3, 4].into();
+    /// list.retain(|x| x % 2 == 0);
+    /// assert_eq!(Vec::from(list), [2, 4]);
+    /// ```
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut keep: F) {
+        let dropped: Vec<usize> = self.iter().enumerate()
+            .filter(|(_, x)| !keep(x))
+            .map(|(i, _)| i)
+            .collect();
+
+        // back to front, so the indices ahead stay put
+        self.tape.reserve(dropped.len());
+        for index in dropped.into_iter().rev() {
+            if let Some(op) = self.delete_op(index) {
+                self.apply(op);
+            }
+        }
+    }
+
+    /// Grab the clone of the first element, if there is one.
+    pub fn first(&self) -> Option<T> {
+        self.try_index(0)
+    }
+
+    /// Grab the clone of the last element, if there is one.
+    pub fn last(&self) -> Option<T> {
+        self.list.last_entry().map(|(id, x)| self.resolve(id, x).clone())
     }
 
     /// Insert an element into the list.
+    ///
+    /// # Notes
+    /// panics if `index` is past the end; see [SyncedList::try_insert].
     pub fn insert(&mut self, index: usize, element: T) {
+        if let Err(err) = self.try_insert(index, element) {
+            panic!("{err}");
+        }
+    }
+
+    /// Insert an element into the list, if `index` is at most its length.
+    pub fn try_insert(&mut self, index: usize, element: T) -> Result<()> {
+        if index > self.len() {
+            return Err(anyhow!("insertion index (is {}) should be <= len (is {})",
+                               index, self.len()));
+        }
         self.apply(self.list.insert_index(index, element, self.actor));
+
+        Ok(())
+    }
+
+    /// Move the element at `from` so it ends up at `to`, shifting the
+    /// elements between them.
+    ///
+    /// # Notes
+    /// The element keeps its identity, so a concurrent update to it
+    /// follows it, and a concurrent delete removes it wherever it went.
+    /// Of concurrent moves of one element, every replica keeps the same
+    /// one, as with updates. Panics if either index is out of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut list: SyncedList<char> = vec!['a', 'b', 'c'].into();
+    /// list.move_item(0, 2);
+    /// assert_eq!(Vec::from(list), ['b', 'c', 'a']);
+    /// ```
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from >= self.len() || to >= self.len() {
+            panic!("move from {from} to {to} out of bounds for length {}", self.len());
+        }
+        if from == to {
+            return;
+        }
+
+        let Some(id) = self.id_of(from) else { return; };
+        let val = self.index(from);
+        let seen = self.moves.get(&id).map_or(0, |((count, _), _)| *count);
+
+        // past the element now at `to` when moving right, before it when moving left
+        let SeqOp::Insert { id: place, val } = self.list.insert_index(to + usize::from(from < to), val, self.actor)
+        else { unreachable!("insert_index makes inserts") };
+
+        self.apply(ListOp::Move { id, to: place, val, revision: (seen + 1, self.actor) });
+    }
+
+    /// Swap the values of the elements at `i` and `j`.
+    ///
+    /// # Notes
+    /// This is an update of each element to the other's value, so
+    /// replicas swapping the same pair at once agree, as do concurrent
+    /// updates; see [SyncedList::lock]. Panics if either index is out
+    /// of bounds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut list: SyncedList<char> = vec!['x', 'y'].into();
+    /// list.swap(0, 1);
+    /// assert_eq!(Vec::from(list), ['y', 'x']);
+    /// ```
+    pub fn swap(&mut self, i: usize, j: usize) {
+        if i.max(j) >= self.len() {
+            panic!("swap of {i} and {j} out of bounds for length {}", self.len());
+        }
+        if i == j {
+            return;
+        }
+
+        let (a, b) = (self.index(i), self.index(j));
+        if let (Some(x), Some(y)) = (self.id_of(i), self.id_of(j)) {
+            self.update_by_id(&x, b);
+            self.update_by_id(&y, a);
+        }
     }
 
-    fn apply(&mut self, op: Op<T, usize>) {
-        self.list.apply(op.clone());
+    /// Sort the list with a comparator, recording the result on the tape.
+    ///
+    /// # Notes
+    /// The sort is stable. Elements stay where they are, and each one
+    /// out of order is updated to the value which belongs there, so
+    /// replaying the tape puts every replica in the same order, and
+    /// replicas sorting the same list at once make the same updates.
+    /// Like any update, these lose to concurrent deletes, and a value
+    /// inserted concurrently is not sorted until the list is sorted
+    /// again. Values, not identifiers, move: [SyncedList::id_of] an
+    /// index gives the same identifier after sorting as before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut leaderboard: SyncedList<u32> = vec![3, 1, 2, 0].into();
+    /// leaderboard.sort_by(|a, b| a.cmp(b));
+    /// assert_eq!(Vec::from(leaderboard), [0, 1, 2, 3]);
+    /// ```
+    pub fn sort_by<F>(&mut self, mut compare: F)
+    where F: FnMut(&T, &T) -> std::cmp::Ordering {
+        let values: Vec<T> = self.iter().cloned().collect();
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by(|a, b| compare(&values[*a], &values[*b]));
+
+        let ids: Vec<ElementId> = self.entries().map(|(id, _)| id.clone()).collect();
+        for (id, &index) in ids.iter().zip(order.iter()).filter(|(id, &index)| ids[index] != **id) {
+            self.update_by_id(id, values[index].clone());
+        }
+    }
+
+    /// The operations `other` is missing to catch up with this replica.
+    ///
+    /// # Notes
+    /// These are the inserts of elements only this replica has, and the
+    /// moves and in place updates newer than `other`'s, ready to
+    /// [Taped::replay] on `other`. Deletes are not included: with no
+    /// tombstones, an element only `other` has cannot be told apart
+    /// from one this replica removed, so take the diff both ways to see
+    /// every difference. Elements `other` has already removed stay
+    /// removed when the diff is replayed.
+    ///
+    /// A list ignores operations older than the newest it has seen
+    /// from the same replica, so replay a diff before any tape which
+    /// came after it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut amy: SyncedList<&str> = vec!["milk"].into();
+    /// let mut bob = amy.clone();
+    /// amy.push("eggs"); // a tape bob never got
+    /// let missing = amy.diff(&bob);
+    /// if !missing.is_empty() {
+    ///     eprintln!("bob is missing {} edits", missing.len());
+    ///     bob.replay(missing);
+    /// }
+    /// assert_eq!(Vec::from(bob), ["milk", "eggs"]);
+    /// ```
+    pub fn diff<O: SeqBackend<T>>(&self, other: &SyncedList<T, O>) -> Vec<ListOp<T>> {
+        let inserts = self.list.iter_entries()
+            .map(|(place, val)| (self.element_of(place), val))
+            .filter(|(id, _)| other.place_of(id).is_none())
+            .map(|(id, val)| ListOp::from(SeqOp::Insert { id: id.clone(), val: val.clone() }));
+        let moves = self.moves.iter()
+            .filter(|(id, (revision, _))| {
+                other.moves.get(id).is_none_or(|(x, _)| x < revision)
+            })
+            .filter_map(|(id, (revision, to))| Some(ListOp::Move {
+                id: id.clone(), to: to.clone(), val: self.list.get(to)?.clone(), revision: *revision
+            }));
+
+        // ops from one replica must arrive in the order it made them
+        let mut placed: Vec<_> = inserts.chain(moves).collect();
+        placed.sort_by_key(|x| {
+            let dot = match x {
+                ListOp::Move { to, .. } => to.value().clone(),
+                ListOp::Seq(op) => op.dot().into(),
+                ListOp::Update { .. } => unreachable!("updates are placed after"),
+            };
+            (dot.actor, dot.counter)
+        });
+
+        let updates = self.updates.iter()
+            .filter(|(id, (revision, _))| {
+                other.updates.get(id).is_none_or(|(x, _)| x < revision)
+            })
+            .map(|(id, (revision, val))| {
+                ListOp::Update { id: id.clone(), val: val.clone(), revision: *revision }
+            });
+
+        placed.into_iter().chain(updates).collect()
+    }
+
+    /// Replay a tape from an untrusted peer, skipping malformed ops.
+    ///
+    /// # Notes
+    /// Every op which passes validation is applied, in order, even if
+    /// others are rejected. An op is rejected if it could never have
+    /// been made by a [SyncedList]: an element identifier with no path,
+    /// or a delete or update numbered 0. Ops which are merely stale,
+    /// such as ones already seen or updates to removed elements, are
+    /// not errors and are ignored as in [Taped::replay].
+    ///
+    /// # Return
+    /// Every rejected op, if there were any.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut todo: SyncedList<&str> = SyncedList::new();
+    /// let mut peer = todo.clone();
+    /// peer.push("milk");
+    /// if let Err(rejected) = todo.try_replay(peer.tape()) {
+    ///     for x in rejected {
+    ///         eprintln!("peer sent a bad op at {}: {}", x.index, x.reason);
+    ///     }
+    ///     panic!("ban the peer");
+    /// }
+    /// assert_eq!(Vec::from(todo), ["milk"]);
+    /// ```
+    pub fn try_replay(&mut self, tape: Vec<ListOp<T>>) -> Result<(), Vec<RejectedOp<T>>> {
+        let empty = empty_seq_id();
+        let mut rejected = vec![];
+
+        for (index, op) in tape.into_iter().enumerate() {
+            match invalid(&op, &empty) {
+                Some(reason) => rejected.push(RejectedOp { index, op, reason: reason.to_owned() }),
+                None => self.apply_remote(op),
+            }
+        }
+
+        if rejected.is_empty() { Ok(()) } else { Err(rejected) }
+    }
+
+    /// Replace the elements in `range` with `replacement`.
+    ///
+    /// # Notes
+    /// Elements at either end of the range which `replacement` leaves
+    /// as they are keep their identity, so concurrent edits to them are
+    /// not lost; only the middle is deleted and reinserted. Panics if
+    /// `range` is out of bounds, like [Vec::splice].
+    ///
+    /// # Return
+    /// Every element that was in `range`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut list: SyncedList<u32> = vec![1, 2, 3, 4].into();
+    /// list.tape();
+    /// // only 2 and 3 are deleted
+    /// assert_eq!(list.splice(1..3, [9]), [2, 3]);
+    /// assert_eq!(Vec::from(list.clone()), [1, 9, 4]);
+    /// assert_eq!(list.tape().len(), 3);
+    /// ```
+    pub fn splice<R, I>(&mut self, range: R, replacement: I) -> Vec<T>
+    where R: RangeBounds<usize>,
+          I: IntoIterator<Item = T>,
+          T: PartialEq {
+        let (start, end) = self.bounds(&range);
+        if start > end || end > self.len() {
+            panic!("range {start}..{end} out of bounds for length {}", self.len());
+        }
+
+        let removed: Vec<T> = self.iter().skip(start).take(end - start).cloned().collect();
+        let replacement: Vec<T> = replacement.into_iter().collect();
+
+        // what is already in place at either end stays
+        let prefix = removed.iter().zip(replacement.iter())
+            .take_while(|(a, b)| a == b).count();
+        let suffix = removed[prefix..].iter().rev().zip(replacement[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b).count();
+
+        let deleted = removed.len() - prefix - suffix;
+        let inserted = &replacement[prefix..replacement.len() - suffix];
+        self.tape.reserve(deleted + inserted.len());
+
+        for _ in 0..deleted {
+            if let Some(op) = self.delete_op(start + prefix) {
+                self.apply(op);
+            }
+        }
+        for (i, element) in inserted.iter().enumerate() {
+            self.apply(self.list.insert_index(start + prefix + i, element.clone(), self.actor));
+        }
+
+        removed
+    }
+
+    /// Keep a running [Aggregate] of a number extracted from every element.
+    ///
+    /// # Notes
+    /// The aggregate is built from every element now, then kept up to
+    /// date as local edits and replayed tapes apply, so reading it is
+    /// cheap however long the list is. Aggregates are local to this
+    /// replica, and are not carried over by `.clone()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// #[derive(Clone)]
+    /// struct Reading { celsius: i64 }
+    ///
+    /// let mut readings: SyncedList<Reading> = SyncedList::new();
+    /// readings.push(Reading { celsius: 21 });
+    /// readings.add_aggregate("temperature", |x: &Reading| x.celsius);
+    /// readings.push(Reading { celsius: 25 });
+    /// let hottest = readings.aggregate("temperature").unwrap().max;
+    /// assert_eq!(hottest, Some(25));
+    /// ```
+    pub fn add_aggregate<F>(&mut self, name: &str, extract: F)
+    where F: Fn(&T) -> i64 + Send + 'static {
+        let mut aggregator = Aggregator::new(Box::new(extract));
+        for (id, value) in self.entries() {
+            aggregator.observe(id, Some(value));
+        }
+
+        self.aggregates.insert(name.to_owned(), aggregator);
+    }
+
+    /// stop maintaining an aggregate
+    pub fn drop_aggregate(&mut self, name: &str) {
+        self.aggregates.remove(name);
+    }
+
+    /// read an aggregate, if there is one called `name`
+    pub fn aggregate(&self, name: &str) -> Option<Aggregate> {
+        self.aggregates.get(name).map(|x| x.read())
+    }
+
+    /// Call `listener` with every change to the list from now on.
+    ///
+    /// # Notes
+    /// Changes are reported whether they come from local edits or from
+    /// replayed tapes, and only if they changed something: replaying an
+    /// op twice, or an update which lost to a later one, reports
+    /// nothing. Listeners are local to this replica, and are not
+    /// carried over by `.clone()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # use std::sync::{Arc, Mutex};
+    /// let rows = Arc::new(Mutex::new(Vec::new()));
+    /// let ui = rows.clone();
+    /// let mut todo: SyncedList<&str> = SyncedList::new();
+    /// let mut peer = todo.clone();
+    /// todo.on_change(move |change| {
+    ///     let mut ui = ui.lock().unwrap();
+    ///     match change {
+    ///         ListChange::Inserted { index, value } => ui.insert(*index, *value),
+    ///         ListChange::Removed { index, .. } => { ui.remove(*index); }
+    ///         ListChange::Updated { index, value } => ui[*index] = *value,
+    ///         ListChange::Moved { from, to, value } => {
+    ///             ui.remove(*from);
+    ///             ui.insert(*to, *value);
+    ///         }
+    ///     }
+    /// });
+    /// peer.push("milk");
+    /// todo.replay(peer.tape()); // the ui follows
+    /// assert_eq!(*rows.lock().unwrap(), ["milk"]);
+    /// ```
+    pub fn on_change<F>(&mut self, listener: F)
+    where F: FnMut(&ListChange<T>) + Send + 'static {
+        self.listeners.push(Box::new(listener));
+    }
+
+    /// `range` as `(start, end)`, unchecked
+    fn bounds<R: RangeBounds<usize>>(&self, range: &R) -> (usize, usize) {
+        let start = match range.start_bound() {
+            Bound::Included(&x) => x,
+            Bound::Excluded(&x) => x + 1,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&x) => x + 1,
+            Bound::Excluded(&x) => x,
+            Bound::Unbounded => self.len(),
+        };
+        (start, end)
+    }
+
+    /// where an element is, and its value
+    fn locate(&self, id: &ElementId) -> Option<(usize, T)> {
+        Some((self.position_of(id)?, self.get_by_id(id)?.clone()))
+    }
+
+    /// every element with its identifier, with updates applied
+    fn entries(&self) -> impl Iterator<Item = (&ElementId, &T)> {
+        self.list.iter_entries().map(|(place, x)| (self.element_of(place), self.resolve(place, x)))
+    }
+
+    /// the current value of the element at `place`, given the value it was put there with
+    fn resolve<'a>(&'a self, place: &SeqId, inserted: &'a T) -> &'a T {
+        self.updates.get(self.element_of(place)).map_or(inserted, |(_, x)| x)
+    }
+
+    /// the place in the sequence of the element `id`, if it wasn't removed
+    fn place_of<'a>(&'a self, id: &'a ElementId) -> Option<&'a SeqId> {
+        match self.moves.get(id) {
+            Some((_, place)) => Some(place),
+            // an element which was never moved sits where it was inserted
+            None => (!self.owners.contains_key(id) && self.list.get(id).is_some()).then_some(id),
+        }
+    }
+
+    /// the element at `place` in the sequence
+    fn element_of<'a>(&'a self, place: &'a SeqId) -> &'a ElementId {
+        self.owners.get(place).unwrap_or(place)
+    }
+
+    /// An op deleting the element at `index`, if there is one.
+    ///
+    /// # Notes
+    /// A delete names the element rather than its place, so it removes
+    /// the element wherever a concurrent move put it.
+    fn delete_op(&self, index: usize) -> Option<SeqOp<T>> {
+        let SeqOp::Delete { dot, .. } = self.list.delete_index(index, self.actor)? else { return None; };
+        Some(SeqOp::Delete { id: self.id_of(index)?, dot })
+    }
+
+    fn apply(&mut self, op: impl Into<ListOp<T>>) {
+        let op = op.into();
+        self.apply_remote(op.clone());
         self.tape.push(op);
     }
+
+    /// apply an op without recording it on the tape
+    fn apply_remote(&mut self, op: ListOp<T>) {
+        // only find indices if anyone is listening, as it walks the list
+        let listening = !self.listeners.is_empty();
+
+        let (id, change) = match op {
+            ListOp::Seq(op) => {
+                let id = self.element_of(op.id()).clone();
+                let before = if listening { self.locate(&id) } else { None };
+
+                match op {
+                    SeqOp::Delete { dot, .. } => {
+                        // wherever the element was moved to goes with it
+                        if let Some((_, place)) = self.moves.remove(&id) {
+                            self.owners.remove(&place);
+                            self.list.forget(&place);
+                        }
+                        self.list.apply(SeqOp::Delete { id: id.clone(), dot });
+                    }
+                    op => self.list.apply(op),
+                }
+                if self.place_of(&id).is_none() {
+                    self.updates.remove(&id);
+                }
+
+                let after = if listening { self.locate(&id) } else { None };
+                let change = match (before, after) {
+                    (None, Some((index, value))) => Some(ListChange::Inserted { index, value }),
+                    (Some((index, value)), None) => Some(ListChange::Removed { index, value }),
+                    _ => None,
+                };
+                (id, change)
+            }
+            // an update loses to a delete, and to a later update
+            ListOp::Update { id, val, revision } => {
+                let wins = self.place_of(&id).is_some()
+                    && self.updates.get(&id).is_none_or(|(x, _)| *x < revision);
+                if wins {
+                    self.updates.insert(id.clone(), (revision, val));
+                }
+
+                let change = if wins && listening {
+                    self.locate(&id).map(|(index, value)| ListChange::Updated { index, value })
+                } else { None };
+                (id, change)
+            }
+            // as an update; the place a move loses, or leaves, is forgotten
+            ListOp::Move { id, to, val, revision } => {
+                let before = if listening { self.locate(&id) } else { None };
+                let from = self.place_of(&id).cloned();
+
+                // applied even if it loses, so every replica's clock agrees
+                self.list.apply(SeqOp::Insert { id: to.clone(), val });
+                let wins = from.is_some()
+                    && self.list.get(&to).is_some()
+                    && self.moves.get(&id).is_none_or(|(x, _)| *x < revision);
+
+                match from {
+                    Some(from) if wins => {
+                        self.owners.remove(&from);
+                        self.list.forget(&from);
+                        self.owners.insert(to.clone(), id.clone());
+                        self.moves.insert(id.clone(), (revision, to));
+                    }
+                    // unless it is a replay of the move which put the element there
+                    from if from.as_ref() != Some(&to) => self.list.forget(&to),
+                    _ => {}
+                }
+
+                let after = if wins && listening { self.locate(&id) } else { None };
+                let change = match (before, after) {
+                    (Some((from, _)), Some((to, value))) if from != to => Some(ListChange::Moved { from, to, value }),
+                    _ => None,
+                };
+                (id, change)
+            }
+        };
+
+        let value = self.place_of(&id).and_then(|x| self.list.get(x)).map(|x| self.updates.get(&id).map_or(x, |(_, x)| x));
+        self.aggregates.values_mut().for_each(|x| x.observe(&id, value));
+
+        if let Some(change) = change {
+            self.listeners.iter_mut().for_each(|x| x(&change));
+        }
+    }
 }
 
+/// why an op could not have come from a [SyncedList], if it could not
+fn invalid<T>(op: &ListOp<T>, empty: &ElementId) -> Option<&'static str> {
+    match op {
+        ListOp::Seq(SeqOp::Insert { id, .. }) if id == empty => Some("insert of an empty identifier"),
+        ListOp::Seq(SeqOp::Delete { id, .. }) if id == empty => Some("delete of an empty identifier"),
+        ListOp::Seq(SeqOp::Delete { dot, .. }) if dot.counter == 0 => Some("delete numbered 0"),
+        ListOp::Update { id, .. } if id == empty => Some("update of an empty identifier"),
+        ListOp::Update { revision, .. } if revision.0 == 0 => Some("update numbered 0"),
+        ListOp::Move { id, to, .. } if id == empty || to == empty => Some("move of an empty identifier"),
+        ListOp::Move { revision, .. } if revision.0 == 0 => Some("move numbered 0"),
+        _ => None,
+    }
+}
+
+/// which element each place in `moves` holds
+fn owners(moves: &BTreeMap<ElementId, (Revision, SeqId)>) -> BTreeMap<SeqId, ElementId> {
+    moves.iter().map(|(id, (_, place))| (place.clone(), id.clone())).collect()
+}
 
-impl<T: Clone+Sync> Taped<usize> for SyncedList<T> {
-    type Operation =  Op<T, usize>;
+impl<T: Clone + Sync, B: SeqBackend<T>> Taped<usize> for SyncedList<T, B> {
+    type Operation =  ListOp<T>;
 
     /// Synchronize your list against a tape
-    fn replay(&mut self, tape: Vec<Op<T, usize>>) {
-        tape.into_iter().for_each(|x| self.list.apply(x));
+    ///
+    /// # Notes
+    /// malformed ops are logged and skipped; see [SyncedList::try_replay].
+    fn replay(&mut self, tape: Vec<ListOp<T>>) {
+        if let Err(rejected) = self.try_replay(tape) {
+            for x in rejected {
+                warn!("skipped op {} of tape: {}", x.index, x.reason);
+            }
+        }
     }
 
     /// Grab the tape of the list, removing its tape.
@@ -199,30 +1186,95 @@ impl<T: Clone+Sync> Taped<usize> for SyncedList<T> {
     /// # Note 
     /// If the tape is not published onto the wire, it  will be lost forever
     /// and not recoverable. 
-    fn tape(&mut self) -> Vec<Op<T, usize>> {
+    fn tape(&mut self) -> Vec<ListOp<T>> {
         let mut old_tape = vec![];
         std::mem::swap(&mut self.tape, &mut old_tape);
         old_tape
     }
 }
 
-impl<T: Clone> Clone for SyncedList<T> {
+impl<T: Clone, B: SeqBackend<T>> Default for SyncedList<T, B> {
+    fn default() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+/// A new list holding `elements`, with an insert on the tape for each,
+/// so replaying the tape seeds other replicas.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// let mut seeded: SyncedList<u8> = vec![1, 2, 3].into();
+/// let mut peer: SyncedList<u8> = SyncedList::new();
+/// peer.replay(seeded.tape());
+/// assert_eq!(Vec::from(peer), [1, 2, 3]);
+/// ```
+impl<T: Clone> From<Vec<T>> for SyncedList<T> {
+    fn from(elements: Vec<T>) -> Self {
+        elements.into_iter().collect()
+    }
+}
+
+/// as `From<Vec<T>>`, from any iterator
+impl<T: Clone, B: SeqBackend<T>> FromIterator<T> for SyncedList<T, B> {
+    fn from_iter<I: IntoIterator<Item = T>>(elements: I) -> Self {
+        let mut list = SyncedList::default();
+        list.extend(elements);
+        list
+    }
+}
+
+/// Borrow an element with `list[idx]`.
+///
+/// # Notes
+/// panics if `idx` is out of bounds, like [SyncedList::index]; unlike it,
+/// this does not clone the element. There is no `IndexMut`: write with
+/// [SyncedList::lock], which records the op.
+impl<T: Clone, B: SeqBackend<T>> Index<usize> for SyncedList<T, B> {
+    type Output = T;
+
+    fn index(&self, idx: usize) -> &T {
+        self.get_ref(idx).unwrap_or_else(
+            || panic!("index out of bounds: length is {} but index is {}",
+                      self.len(), idx)
+        )
+    }
+}
+
+impl<T: Clone, B: SeqBackend<T>> Clone for SyncedList<T, B> {
     fn clone(&self) -> Self {
-        SyncedList { list: self.list.clone(), actor: self.actor + 1, tape: vec![] }
+        SyncedList {
+            list: self.list.clone(),
+            actor: rand::random(),
+            updates: self.updates.clone(),
+            moves: self.moves.clone(),
+            owners: self.owners.clone(),
+            tape: vec![],
+            aggregates: HashMap::new(),
+            listeners: vec![]
+        }
+    }
+}
+
+impl<T: Clone, B: SeqBackend<T>> Extend<T> for SyncedList<T, B> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, elements: I) {
+        SyncedList::extend(self, elements)
     }
 }
 
-impl<T: Clone> Into<Vec<T>> for SyncedList<T> {
-    fn into(self) -> Vec<T> {
-        self.list.read_into::<Vec<_>>()
+impl<T: Clone, B: SeqBackend<T>> From<SyncedList<T, B>> for Vec<T> {
+    fn from(list: SyncedList<T, B>) -> Vec<T> {
+        list.iter().cloned().collect()
     }
 }
 
-impl<T: Clone> IntoIterator for SyncedList<T> {
+impl<T: Clone, B: SeqBackend<T>> IntoIterator for SyncedList<T, B> {
     type Item = T;
     type IntoIter = std::vec::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.list.read_into::<Vec<_>>().into_iter()
+        self.iter().cloned().collect::<Vec<_>>().into_iter()
     }
 }
@@ -0,0 +1,236 @@
+use crdts::{CmRDT, LWWReg, ResetRemove, VClock};
+use crdts::map::{Map, Op};
+use std::convert::Infallible;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use log::warn;
+
+use super::taped::Taped;
+use super::map::{MapKey, MapVal};
+
+/// how far ahead of our clock a replayed stamp may move the stamps we make
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60 * 60);
+
+/// when a value of a [SyncedLwwMap] was written, and by whom
+///
+/// # Notes
+/// Later stamps win; two writes at the same millisecond are ordered by
+/// actor, so every replica picks the same one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Stamp {
+    /// milliseconds since the unix epoch, as the writer saw them
+    pub time: u64,
+    pub actor: usize,
+}
+
+/// a last-writer-wins register, fit to be a value of a crdts [Map]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lww<V>(LWWReg<V, Stamp>);
+
+impl<V: PartialEq> CmRDT for Lww<V> {
+    type Op = LWWReg<V, Stamp>;
+    type Validation = Infallible;
+
+    fn validate_op(&self, _op: &Self::Op) -> Result<(), Self::Validation> {
+        Ok(())
+    }
+
+    fn apply(&mut self, op: Self::Op) {
+        self.0.update(op.val, op.marker);
+    }
+}
+
+/// the map tracks which writes a remove saw; the register has nothing to forget
+impl<V> ResetRemove<usize> for Lww<V> {
+    fn reset_remove(&mut self, _clock: &VClock<usize>) {}
+}
+
+type LwwOp<K, V> = Op<K, Lww<V>, usize>;
+
+/// Map whose values are last-writer-wins, for config-style data.
+///
+/// # Notes
+/// Where [super::map::SyncedMap] keeps every value written concurrently
+/// to a key, this keeps only the one with the latest [Stamp]. Stamps
+/// come from the writers' clocks, so a replica whose clock runs ahead
+/// wins more than its share; each replica stamps past every stamp it
+/// has seen, so its own writes supersede what it has read. A stamp more
+/// than [MAX_CLOCK_SKEW] ahead of the local clock still applies, but
+/// only moves our stamps that far, so one bad clock or forged stamp
+/// can't run them to the end of time; a write stamped beyond that wins
+/// over ours until our clock catches up.
+///
+/// Removing a key concurrently with a write to it keeps the write.
+/// Unlike [super::map::SyncedMap], values need a default, which the
+/// registers start from.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// let mut config: SyncedLwwMap<String, String> = SyncedLwwMap::new();
+/// let mut peer: SyncedLwwMap<String, String> = SyncedLwwMap::new();
+/// config.insert("theme".to_string(), "dark".to_string());
+/// peer.replay(config.tape());
+/// assert_eq!(peer.get(&"theme".to_string()), Some("dark".to_string()));
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize, V: Serialize",
+              deserialize = "K: Deserialize<'de>, V: Deserialize<'de>"))]
+pub struct SyncedLwwMap<K: MapKey, V: MapVal + Default> {
+    map: Map<K, Lww<V>, usize>,
+    #[serde(skip, default = "rand::random")]
+    actor: usize,
+    /// the latest stamp time seen
+    #[serde(skip)]
+    latest: u64,
+    #[serde(skip)]
+    tape: Vec<LwwOp<K, V>>,
+}
+
+impl<K: MapKey, V: MapVal + Default> Taped<usize> for SyncedLwwMap<K, V> {
+    type Operation = LwwOp<K, V>;
+
+    /// Synchronize your map against a tape
+    fn replay(&mut self, tape: Vec<Self::Operation>) {
+        tape.into_iter().for_each(|x| {
+            self.witness(&x);
+            self.map.apply(x);
+        });
+    }
+
+    fn tape(&mut self) -> Vec<Self::Operation> {
+        std::mem::take(&mut self.tape)
+    }
+}
+
+impl<K: MapKey, V: MapVal + Default> SyncedLwwMap<K, V> {
+    pub fn new() -> Self {
+        SyncedLwwMap {
+            map: Map::new(),
+            actor: rand::random(),
+            latest: 0,
+            tape: vec![],
+        }
+    }
+
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on; see [super::map::SyncedMap::with_actor].
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.map.get(key).val.map(|x| x.0.val)
+    }
+
+    /// when the value of `key` was written, and by whom
+    pub fn stamp(&self, key: &K) -> Option<Stamp> {
+        self.map.get(key).val.map(|x| x.0.marker)
+    }
+
+    /// upsert a value into the map, returning the old one
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let old = self.get(&k);
+        let marker = self.next_stamp();
+        let ctx = self.map.read_ctx().derive_add_ctx(self.actor);
+        let op = self.map.update(k, ctx, |_, _| LWWReg { val: v, marker });
+        self.apply(op);
+
+        old
+    }
+
+    /// Change a value in place, recording the result on the tape.
+    ///
+    /// # Notes
+    /// A missing key starts from `V::default()`.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, k: K, f: F) {
+        let mut value = self.get(&k).unwrap_or_default();
+        f(&mut value);
+        self.insert(k, value);
+    }
+
+    /// remove an element from the map, returning the old one
+    pub fn remove(&mut self, k: K) -> Option<V> {
+        let reader = self.map.get(&k);
+        let old = reader.val.clone().map(|x| x.0.val);
+        if old.is_some() {
+            let op = self.map.rm(k, reader.derive_rm_ctx());
+            self.apply(op);
+        }
+
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len().val
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.map.get(key).val.is_some()
+    }
+
+    /// every key in the map, in order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.map.iter().map(|x| x.val.0)
+    }
+
+    /// Iterate over every entry of the map, in order of keys.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter().map(|x| (x.val.0, &x.val.1.0.val))
+    }
+
+    fn apply(&mut self, op: LwwOp<K, V>) {
+        self.map.apply(op.clone());
+        self.tape.push(op);
+    }
+
+    /// a stamp later than now and than any seen
+    fn next_stamp(&mut self) -> Stamp {
+        self.latest = now_ms().max(self.latest.saturating_add(1));
+        Stamp { time: self.latest, actor: self.actor }
+    }
+
+    /// keep stamping past the writes in `op`, as far as [MAX_CLOCK_SKEW] allows
+    fn witness(&mut self, op: &LwwOp<K, V>) {
+        if let Op::Up { op, .. } = op {
+            let limit = now_ms().saturating_add(MAX_CLOCK_SKEW.as_millis() as u64);
+            if op.marker.time > limit {
+                warn!("actor {} stamped a write {}ms ahead of our clock", op.marker.actor,
+                      op.marker.time - now_ms());
+            }
+            self.latest = self.latest.max(op.marker.time.min(limit));
+        }
+    }
+}
+
+/// milliseconds since the unix epoch, by our clock
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.as_millis() as u64)
+}
+
+impl<K: MapKey, V: MapVal + Default> Default for SyncedLwwMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: MapKey, V: MapVal + Default> Clone for SyncedLwwMap<K, V> {
+    fn clone(&self) -> Self {
+        SyncedLwwMap {
+            map: self.map.clone(),
+            actor: rand::random(),
+            latest: self.latest,
+            tape: vec![],
+        }
+    }
+}
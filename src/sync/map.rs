@@ -1,92 +1,234 @@
-use crdts::ctx::ReadCtx;
-use crdts::{CmRDT, MVReg};
-use crdts::map::{Map, Op};
 use std::cmp::{Ord, PartialEq};
 use std::default::Default;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Bound, RangeBounds};
+use std::borrow::Borrow;
 use std::fmt::Debug;
+use std::collections::{BTreeMap, HashMap};
+use std::borrow::Cow;
+use anyhow::{Result, anyhow};
+use serde::{Serialize, Deserialize};
 
 use std::marker::PhantomData;
+use futures::Stream;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
 
 type PhantomUnsend = PhantomData<std::sync::MutexGuard<'static, ()>>;
 
 use super::taped::Taped;
+use super::query::{MapChange, QueryDelta, Watcher};
+use super::index::{SecondaryIndex, FieldIndex};
+use super::aggregate::{Aggregate, Aggregator};
+use super::list::STATE_VERSION;
+use super::backend::{MapBackend, CrdtsMap, MapOp, keys_of};
 
 pub trait MapKey: Clone + Ord + Debug {}
-impl<T:?Sized + Clone + Ord + Debug> MapKey for T {}
+impl<T: Clone + Ord + Debug> MapKey for T {}
 
-pub trait MapVal: Clone + PartialEq + Default + Debug {}
-impl<T:?Sized + Clone + PartialEq + Default + Debug> MapVal for T {}
+pub trait MapVal: Clone + PartialEq + Debug {}
+impl<T: Clone + PartialEq + Debug> MapVal for T {}
 
-pub struct SyncedMapElementGuard<'a, K: MapKey, V: MapVal> {
-    key: Option<&'a K>,
-    ctx: Option<ReadCtx<Option<MVReg<V, usize>>, usize>>,
-    value: V,
-    src: &'a mut SyncedMap<K, V>,
+pub struct SyncedMapElementGuard<'a, K: MapKey, V: MapVal, B: MapBackend<K, V> = CrdtsMap<K, V>> {
+    key: &'a K,
+    /// taken when the guard drops
+    value: Option<V>,
+    src: &'a mut SyncedMap<K, V, B>,
     was_mutated: bool,
     _not_send: PhantomUnsend,
 }
 
-impl<K: MapKey, V: MapVal> Debug for SyncedMapElementGuard<'_, K, V> {
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Debug for SyncedMapElementGuard<'_, K, V, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SyncedMapElementGuard")
-            .field("key", self.key.as_ref().unwrap())
-            .field("ctx", &self.ctx.as_ref().unwrap().val)
+            .field("key", self.key)
             .field("value", &self.value)
             .field("was_mutated", &self.was_mutated)
             .finish()
     }
 }
 
-impl<K: MapKey, V: MapVal> Deref for SyncedMapElementGuard<'_, K, V> {
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Deref for SyncedMapElementGuard<'_, K, V, B> {
     type Target = V;
 
     fn deref(&self) -> &Self::Target {
-        &self.value
+        self.value.as_ref().unwrap()
     }
 }
 
-impl<K: MapKey, V: MapVal> DerefMut for SyncedMapElementGuard<'_, K, V> {
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> DerefMut for SyncedMapElementGuard<'_, K, V, B> {
     fn deref_mut (&mut self) -> &mut Self::Target {
         self.was_mutated = true;
-        &mut self.value
+        self.value.as_mut().unwrap()
     }
 }
 
-impl<K: MapKey, V: MapVal> Drop for SyncedMapElementGuard<'_, K, V> {
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Drop for SyncedMapElementGuard<'_, K, V, B> {
     fn drop (&mut self)  {
         if self.was_mutated {
-            let mut read_context = None;
-            std::mem::swap(&mut read_context, &mut self.ctx);
-            let add_ctx = read_context.unwrap().derive_add_ctx(self.src.actor);
+            let dropped_value = self.value.take().unwrap();
 
-            let mut dropped_key = None;
-            std::mem::swap(&mut dropped_key, &mut self.key);
+            let op = self.src.map.write(self.key.clone(), MapCell::Value(dropped_value), self.src.actor);
+            self.src.apply(op);
+        }
+    }
+}
+
+/// A key of a [SyncedMap], which may or may not have a value; see [SyncedMap::entry]
+pub struct SyncedMapEntry<'a, K: MapKey, V: MapVal, B: MapBackend<K, V> = CrdtsMap<K, V>> {
+    key: &'a K,
+    src: &'a mut SyncedMap<K, V, B>,
+}
+
+impl<'a, K: MapKey, V: MapVal, B: MapBackend<K, V>> SyncedMapEntry<'a, K, V, B> {
+    pub fn key(&self) -> &K {
+        self.key
+    }
+
+    /// Write `default` if the key has no value, then give a guard to the value.
+    pub fn or_insert(self, default: V) -> SyncedMapElementGuard<'a, K, V, B> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Write what `default` makes if the key has no value, then give a guard to the value.
+    ///
+    /// # Notes
+    /// The insert is recorded when the guard drops, along with any
+    /// change made through it, as one op.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> SyncedMapElementGuard<'a, K, V, B> {
+        let value = self.src.get(self.key);
+        let vacant = value.is_none();
+        let mut guard = self.src.guard(self.key, value.unwrap_or_else(default));
+        guard.was_mutated = vacant;
+        guard
+    }
 
-            let mut dropped_value = V::default();
-            std::mem::swap(&mut dropped_value, &mut self.value);
+    /// As [SyncedMapEntry::or_insert_with], with `V::default()`.
+    pub fn or_default(self) -> SyncedMapElementGuard<'a, K, V, B>
+    where V: Default {
+        self.or_insert_with(V::default)
+    }
 
-            let op = self.src.map.update(dropped_key.unwrap().clone(), add_ctx, |v,a| v.write(dropped_value, a));
-            self.src.map.apply(op.clone());
-            self.src.tape.push(op);
+    /// Change the value in place if the key has one, recording it at once.
+    ///
+    /// # Notes
+    /// Nothing is recorded if `f` leaves the value as it was.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        if let Some(old) = self.src.get(self.key) {
+            let mut value = old.clone();
+            f(&mut value);
+            if value != old {
+                self.src.guard(self.key, value).was_mutated = true;
+            }
         }
+        self
+    }
+}
+
+/// which of a remove and a concurrent write to the same key wins; see
+/// [SyncedMap::with_remove_policy]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RemovePolicy {
+    /// the write wins, and the key keeps the written value
+    #[default]
+    AddWins,
+    /// the remove wins, and the key is gone
+    RemoveWins,
+}
+
+impl RemovePolicy {
+    fn is_add_wins(&self) -> bool {
+        *self == RemovePolicy::AddWins
     }
 }
 
+/// marks a key removed under [RemovePolicy::RemoveWins]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tombstone {
+    #[serde(rename = "synch:removed")]
+    removed: bool,
+}
+
+/// what a [SyncedMap] stores for a key: a value, or the mark of a remove
+///
+/// # Notes
+/// A value is encoded just as `V` is, so tapes and snapshots from before
+/// tombstones existed still decode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MapCell<V> {
+    Removed(Tombstone),
+    Value(V),
+}
+
+/// everything a [SyncedMap] replica needs to pick up where it left off
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: MapKey + Serialize, V: MapVal + Serialize, B: Serialize",
+              deserialize = "K: MapKey + Deserialize<'de>, V: MapVal + Deserialize<'de>, B: Deserialize<'de>"))]
+struct MapState<'a, K: MapKey, V: MapVal, B: Clone> {
+    version: u32,
+    actor: usize,
+    #[serde(default)]
+    policy: RemovePolicy,
+    map: Cow<'a, B>,
+    tape: Cow<'a, [MapOp<K, V>]>,
+}
+
 /// Map Structure for Syncronized Operations
-pub struct SyncedMap<K: MapKey, V: MapVal> {
-    map: Map<K, MVReg<V, usize>, usize>,
+///
+/// # Notes
+/// Every new map, clone, and map loaded from a snapshot edits as a
+/// new random actor, so replicas never collide. A snapshot holds only the
+/// map's contents; to resume a replica with its actor and unsent tape,
+/// use [SyncedMap::export_state].
+///
+/// A remove and a concurrent write to the same key are settled by the
+/// map's [RemovePolicy], which every replica should share.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # fn main() -> anyhow::Result<()> {
+/// let mut scores: SyncedMap<String, u32> = SyncedMap::new();
+/// scores.insert("amy".into(), 3);
+/// let snapshot = serde_json::to_vec(&scores)?;
+/// scores.tape();
+/// scores.insert("bob".into(), 5);
+/// let tape_since_snapshot = scores.tape();
+/// // ... on a new peer
+/// let mut scores: SyncedMap<String, u32> = serde_json::from_slice(&snapshot)?;
+/// scores.replay(tape_since_snapshot);
+/// assert_eq!(scores.len(), 2);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize, V: Serialize, B: Serialize",
+              deserialize = "K: Deserialize<'de>, V: Deserialize<'de>, B: Deserialize<'de>"))]
+pub struct SyncedMap<K: MapKey, V: MapVal, B: MapBackend<K, V> = CrdtsMap<K, V>> {
+    map: B,
+    #[serde(default, skip_serializing_if = "RemovePolicy::is_add_wins")]
+    policy: RemovePolicy,
+    #[serde(skip, default = "rand::random")]
     actor: usize,
-    // #[serde(skip)] 
-    tape: Vec<Op<K, MVReg<V, usize>, usize>>,
+    #[serde(skip)]
+    tape: Vec<MapOp<K, V>>,
+    #[serde(skip)]
+    watchers: Vec<Watcher<K, V>>,
+    #[serde(skip)]
+    subscribers: Vec<UnboundedSender<MapChange<K, V>>>,
+    #[serde(skip)]
+    indexes: HashMap<String, Box<dyn SecondaryIndex<K, V>>>,
+    #[serde(skip)]
+    aggregates: HashMap<String, Aggregator<K, V>>,
 }
 
-impl<K: MapKey, V: MapVal> Taped<usize> for SyncedMap<K, V> {
-    type Operation = Op<K, MVReg<V, usize>, usize>;
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Taped<usize> for SyncedMap<K, V, B> {
+    type Operation = MapOp<K, V>;
 
     /// Synchronize your list against a tape
     fn replay(&mut self, tape: Vec<Self::Operation>) {
-        tape.into_iter().for_each(|x| self.map.apply(x));
+        tape.into_iter().for_each(|x| self.absorb(x));
     }
 
     /// Grab the tape of the list, removing its tape.
@@ -101,74 +243,685 @@ impl<K: MapKey, V: MapVal> Taped<usize> for SyncedMap<K, V> {
     }
 }
 
-impl<'a, K: MapKey, V: MapVal> SyncedMap<K, V> {
+impl<K: MapKey, V: MapVal> SyncedMap<K, V> {
     pub fn new() -> Self {
+        Self::with_backend(CrdtsMap::default())
+    }
+}
+
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> SyncedMap<K, V, B> {
+    /// Make an empty map kept in `backend`, rather than the default
+    /// [CrdtsMap]; see [super::list::SyncedList::with_backend].
+    pub fn with_backend(backend: B) -> Self {
         SyncedMap {
-            map: Map::new(),
-            actor: 0,
-            tape: vec![]
+            map: backend,
+            policy: RemovePolicy::AddWins,
+            actor: rand::random(),
+            tape: vec![],
+            watchers: vec![],
+            subscribers: vec![],
+            indexes: HashMap::new(),
+            aggregates: HashMap::new()
         }
     }
 
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on, such as one handed out by a
+    /// coordinator; it must not be used by any other replica.
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// how this map settles a remove against a concurrent write
+    pub fn remove_policy(&self) -> RemovePolicy {
+        self.policy
+    }
+
+    /// Settle a remove and a concurrent write to the same key by `policy`.
+    ///
+    /// # Notes
+    /// Under [RemovePolicy::AddWins], the default, a key removed on one
+    /// replica while another writes it keeps the written value. Under
+    /// [RemovePolicy::RemoveWins] it is removed; writes made after
+    /// seeing the remove bring the key back as usual.
+    ///
+    /// Removes under [RemovePolicy::RemoveWins] leave a tombstone in
+    /// the map for each key, which snapshots carry. The policy is saved
+    /// with the map, but every replica should be made with the same
+    /// one; replicas of different policies may disagree on such keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut cart: SyncedMap<String, u32> = SyncedMap::new()
+    ///     .with_remove_policy(RemovePolicy::RemoveWins);
+    /// cart.insert("socks".into(), 2);
+    /// let mut phone = cart.clone();
+    /// phone.replay(cart.tape());
+    ///
+    /// // removed here while added to there
+    /// cart.remove("socks".into());
+    /// phone.update("socks".into(), |x| *x += 1);
+    /// cart.replay(phone.tape());
+    /// phone.replay(cart.tape());
+    /// assert_eq!(cart.get(&"socks".into()), None);
+    /// assert_eq!(phone.get(&"socks".into()), None);
+    /// ```
+    pub fn with_remove_policy(mut self, policy: RemovePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Get the value of `key`.
+    ///
+    /// # Notes
+    /// Of values written concurrently, every replica gives the same
+    /// one, though which is arbitrary; see [SyncedMap::get_all] to see
+    /// them all, and [SyncedMap::resolve] to settle on one.
     pub fn get(&self, key: &K) -> Option<V> {
-        self.map.get(key)
-            .val.and_then(|x| 
-                          x.read().val.first()
-                          .and_then(|y| Some(y.clone())))
+        resolved(&self.map.cells(key), self.policy)
     }
 
-    pub fn lock<'b>(&'b mut self, key: &'b K) -> SyncedMapElementGuard<'b, K, V> {
-        let ctx = self.map.get(key);
+    /// Every value written concurrently to `key`, in an order all replicas agree on.
+    ///
+    /// # Notes
+    /// This has one value unless replicas wrote the key without seeing
+    /// each other's writes, and none if there is no key.
+    pub fn get_all(&self, key: &K) -> Vec<V> {
+        concurrent(&self.map.cells(key), self.policy)
+    }
+
+    /// Settle concurrently written values of `key` on the one `picker` gives.
+    ///
+    /// # Notes
+    /// `picker` is only called if there is more than one value, as given
+    /// by [SyncedMap::get_all]; its pick replaces them all, recording one
+    /// op on the tape. Replicas resolving the same conflict should pick
+    /// alike, or they will conflict again.
+    ///
+    /// # Return
+    /// The value `key` is left with, if there is one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut scores: SyncedMap<String, u32> = SyncedMap::new();
+    /// let mut other = scores.clone();
+    /// scores.insert("amy".into(), 3);
+    /// other.insert("amy".into(), 7);
+    /// scores.replay(other.tape());
+    ///
+    /// let best = scores.resolve(&"amy".to_string(), |xs| xs.into_iter().max().unwrap());
+    /// assert_eq!(best, Some(7));
+    /// assert_eq!(scores.get_all(&"amy".into()), [7]);
+    /// ```
+    pub fn resolve<F: FnOnce(Vec<V>) -> V>(&mut self, key: &K, picker: F) -> Option<V> {
+        let mut values = self.get_all(key);
+        if values.len() <= 1 {
+            return values.pop();
+        }
+
+        let picked = picker(values);
+        self.insert(key.clone(), picked.clone());
+        Some(picked)
+    }
+
+    /// Count the keys in the map.
+    ///
+    /// # Notes
+    /// A key removed on one replica but concurrently written on another
+    /// is counted only if it survives the merge by the map's [RemovePolicy].
+    ///
+    /// Under [RemovePolicy::AddWins] there are no tombstones, so this
+    /// asks the backend without reading any values; under
+    /// [RemovePolicy::RemoveWins] every key's values are read.
+    pub fn len(&self) -> usize {
+        match self.policy {
+            RemovePolicy::AddWins => self.map.len(),
+            RemovePolicy::RemoveWins => self.keys().count(),
+        }
+    }
+
+    /// Check whether the map has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check whether the map has a value for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        match self.policy {
+            RemovePolicy::AddWins => self.map.contains(key),
+            RemovePolicy::RemoveWins => present(&self.map.cells(key), self.policy),
+        }
+    }
+
+    /// every key in the map, in order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.map.keys()
+            .filter(|k| self.policy.is_add_wins() || present(&self.map.cells(k), self.policy))
+    }
+
+    /// every value in the map, in order of their keys
+    ///
+    /// # Notes
+    /// Of concurrently written values for a key, this gives the one
+    /// [SyncedMap::get] does.
+    pub fn values(&self) -> impl Iterator<Item = V> + '_ {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Iterate over every entry of the map, in order of keys.
+    ///
+    /// # Notes
+    /// Values are cloned out of the map, resolved as [SyncedMap::get] does.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut scores: SyncedMap<String, u32> = SyncedMap::new();
+    /// scores.insert("bob".into(), 5);
+    /// scores.insert("amy".into(), 3);
+    /// for (name, score) in scores.iter() {
+    ///     println!("{name}: {score}");
+    /// }
+    /// assert_eq!(scores.iter().next(), Some((&"amy".to_string(), 3)));
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&K, V)> {
+        self.map.entries().filter_map(|(key, cells)| {
+            resolved(&cells, self.policy).map(|v| (key, v))
+        })
+    }
+
+    /// Iterate over the entries whose keys are in `range`, in order of keys.
+    ///
+    /// # Notes
+    /// Values are resolved as [SyncedMap::get] does. The default backend
+    /// walks the keys before the range to find its start; give the map
+    /// a [super::backend::RangeMap] to seek to it instead. A range which
+    /// starts after it ends is empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # use std::ops::Bound;
+    /// let mut readings: SyncedMap<u32, i64> = SyncedMap::new();
+    /// for day in 0..14 {
+    ///     readings.insert(day, 20 + day as i64);
+    /// }
+    /// let (monday, sunday) = (7, 13);
+    /// let week: Vec<i64> = readings.range(monday..=sunday).map(|(_, x)| x).collect();
+    /// assert_eq!(week.len(), 7);
+    ///
+    /// let mut scores: SyncedMap<String, u32> = SyncedMap::new();
+    /// scores.insert("amy".into(), 3);
+    /// scores.insert("bob".into(), 5);
+    /// let after = scores.range::<str, _>((Bound::Excluded("amy"), Bound::Unbounded));
+    /// assert_eq!(after.count(), 1);
+    /// ```
+    pub fn range<Q, R>(&self, range: R) -> impl Iterator<Item = (&K, V)>
+    where K: Borrow<Q>, Q: Ord + ?Sized, R: RangeBounds<Q> {
+        self.map.range(range).filter_map(|(key, cells)| {
+            resolved(&cells, self.policy).map(|v| (key, v))
+        })
+    }
+
+    /// Iterate over the entries whose keys start with `prefix`, in order of keys.
+    ///
+    /// # Notes
+    /// Keys sharing a prefix sit together in order, so this is a
+    /// [SyncedMap::range] which stops at the first key without it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut rooms: SyncedMap<String, String> = SyncedMap::new();
+    /// rooms.insert("room/42/amy".into(), "here".into());
+    /// rooms.insert("room/43/bob".into(), "away".into());
+    /// for (key, user) in rooms.scan_prefix("room/42/") {
+    ///     println!("{key}: {user:?}");
+    /// }
+    /// assert_eq!(rooms.scan_prefix("room/42/").count(), 1);
+    /// ```
+    pub fn scan_prefix<'b>(&'b self, prefix: &'b str) -> impl Iterator<Item = (&'b K, V)>
+    where K: Borrow<str> {
+        self.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| (*key).borrow().starts_with(prefix))
+    }
+
+    /// Get a value from the map, optionally setting it.
+    ///
+    /// # Notes
+    /// Unlike [super::list::SyncedList::lock] this always gives a guard:
+    /// a missing key reads as `V::default()`, and is inserted only if
+    /// written through the guard. Writing records one op on the tape.
+    /// For values without a default, see [SyncedMap::entry].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut map: SyncedMap<String, u32> = SyncedMap::new();
+    /// let key = "amy".to_string();
+    /// *map.lock(&key) = 2;
+    /// assert_eq!(*map.lock(&key), 2);
+    /// ```
+    pub fn lock<'b>(&'b mut self, key: &'b K) -> SyncedMapElementGuard<'b, K, V, B>
+    where V: Default {
+        let value = self.get(key).unwrap_or_default();
+        self.guard(key, value)
+    }
+
+    /// a guard over `key`, holding `value` until written
+    fn guard<'b>(&'b mut self, key: &'b K, value: V) -> SyncedMapElementGuard<'b, K, V, B> {
         SyncedMapElementGuard {
-            key: Some(key),
-            value: match ctx.val {
-                Some(ref x) => x.read()
-                    .val.first()
-                    .map_or(V::default(), |y| y.clone()),
-                None => V::default()
-            },
-            ctx: Some(ctx),
+            key,
+            value: Some(value),
             src: self,
             was_mutated: false,
             _not_send: PhantomData
         }
     }
 
-    /// upsert a value into the map
+    /// Get a key's entry, to read or fill in as with a `HashMap`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let text = "the cat saw the dog";
+    /// let mut counts: SyncedMap<String, u32> = SyncedMap::new();
+    /// for word in text.split_whitespace() {
+    ///     *counts.entry(&word.to_string()).or_insert(0) += 1;
+    /// }
+    /// assert_eq!(counts.get(&"the".into()), Some(2));
+    /// ```
+    pub fn entry<'b>(&'b mut self, key: &'b K) -> SyncedMapEntry<'b, K, V, B> {
+        SyncedMapEntry { key, src: self }
+    }
+
+    /// upsert a value into the map, returning the old one
     pub fn insert(&mut self, k: K, v: V) -> Option<V> {
-        let mut guard = self.lock(&k);
-        let old = guard.clone();
-        *guard = v;
+        let old = self.get(&k);
+        self.guard(&k, v).was_mutated = true;
 
-        Some(old)
+        old
     }
-    /// alias of [SyncedMap::insert]
-    pub fn update(&mut self, k: K, v: V) -> Option<V> {
-        self.insert(k,v)
+
+    /// Change a value in place, recording the result on the tape.
+    ///
+    /// # Notes
+    /// A missing key starts from `V::default()`, as with [SyncedMap::lock].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut scores: SyncedMap<String, u32> = SyncedMap::new();
+    /// scores.update("amy".to_string(), |x| *x += 10);
+    /// assert_eq!(scores.get(&"amy".into()), Some(10));
+    /// ```
+    pub fn update<F: FnOnce(&mut V)>(&mut self, k: K, f: F)
+    where V: Default {
+        f(&mut self.lock(&k));
     }
 
     /// remove an element from the map, returning the old one
+    ///
+    /// # Notes
+    /// The remove covers the writes this replica has seen; a concurrent
+    /// write elsewhere is settled by the map's [RemovePolicy]. Under
+    /// [RemovePolicy::RemoveWins] removing a key with no value records
+    /// nothing.
     pub fn remove(&mut self, k: K) -> Option<V> {
-        let reader = self.map.get(&k);
-        let old_value = self.map.get(&k)
-            .val.and_then(|x| 
-                          x.read().val.first()
-                          .and_then(|y| Some(y.clone())));
-
-        let op = self.map.rm(k, reader.derive_rm_ctx());
-        self.map.apply(op.clone());
-        self.tape.push(op);
+        let old_value = self.get(&k);
+
+        let op = match self.policy {
+            RemovePolicy::AddWins => self.map.remove(k),
+            RemovePolicy::RemoveWins if old_value.is_some() => {
+                self.map.write(k, MapCell::Removed(Tombstone { removed: true }), self.actor)
+            },
+            RemovePolicy::RemoveWins => return None,
+        };
+        self.apply(op);
 
         old_value
     }
+
+    /// Watch the entries matching `predicate` as the map changes.
+    ///
+    /// # Notes
+    /// Every entry which matches now is sent as [QueryDelta::Added] first;
+    /// after that only changes are sent, whether from local edits or from
+    /// replayed tapes. Dropping the stream stops the watch.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # use futures::{FutureExt, StreamExt};
+    /// #[derive(Clone, PartialEq, Debug)]
+    /// struct Task { due: u32 }
+    ///
+    /// let today = 10;
+    /// let mut tasks: SyncedMap<u64, Task> = SyncedMap::new();
+    /// tasks.insert(1, Task { due: 5 });
+    /// let mut overdue = tasks.watch(move |_, task: &Task| task.due < today);
+    /// tasks.insert(2, Task { due: 12 });
+    /// tasks.remove(1);
+    ///
+    /// let mut view = vec![];
+    /// while let Some(Some(delta)) = overdue.next().now_or_never() {
+    ///     match delta {
+    ///         QueryDelta::Added(id, _) => view.push(id),
+    ///         QueryDelta::Updated(_, _) => (),
+    ///         QueryDelta::Removed(id) => view.retain(|x| *x != id),
+    ///     }
+    /// }
+    /// assert!(view.is_empty());
+    /// ```
+    pub fn watch<F>(&mut self, predicate: F) -> impl Stream<Item = QueryDelta<K, V>>
+    where F: Fn(&K, &V) -> bool + Send + 'static {
+        let (sender, reciever) = unbounded();
+        let mut watcher = Watcher::new(Box::new(predicate), sender);
+
+        for (key, cells) in self.map.entries() {
+            watcher.observe(key, resolved(&cells, self.policy).as_ref());
+        }
+
+        self.watchers.push(watcher);
+        reciever
+    }
+
+    /// Follow every change to the map, local or replayed.
+    ///
+    /// # Notes
+    /// Unlike [SyncedMap::watch] nothing is sent for the entries already
+    /// there. A write which leaves a key's value as it was sends
+    /// nothing. Dropping the stream unsubscribes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// # use std::collections::HashMap;
+    /// # use futures::{FutureExt, StreamExt};
+    /// let mut settings: SyncedMap<String, String> = SyncedMap::new();
+    /// let mut changes = settings.on_change();
+    /// settings.insert("theme".into(), "dark".into());
+    /// settings.insert("theme".into(), "light".into());
+    ///
+    /// let mut cache = HashMap::new();
+    /// while let Some(Some(change)) = changes.next().now_or_never() {
+    ///     match change {
+    ///         MapChange::Inserted(key, value) => cache.insert(key, value),
+    ///         MapChange::Updated { key, new, .. } => cache.insert(key, new),
+    ///         MapChange::Removed(key, _) => cache.remove(&key),
+    ///     };
+    /// }
+    /// assert_eq!(cache["theme"], "light");
+    /// ```
+    pub fn on_change(&mut self) -> impl Stream<Item = MapChange<K, V>> {
+        let (sender, reciever) = unbounded();
+        self.subscribers.push(sender);
+        reciever
+    }
+
+    /// Index the map by a value extracted from each entry.
+    ///
+    /// # Notes
+    /// The index is built from every entry now, then kept up to date as
+    /// local edits and replayed tapes apply. Declaring an index under a
+    /// name already in use replaces it. Indexes are local to this
+    /// replica, and are not carried over by `.clone()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// #[derive(Clone, PartialEq, Debug)]
+    /// struct Task { assignee: String }
+    ///
+    /// let mut tasks: SyncedMap<u64, Task> = SyncedMap::new();
+    /// tasks.add_index("assignee", |task: &Task| task.assignee.clone());
+    /// tasks.insert(1, Task { assignee: "amy".into() });
+    /// tasks.insert(2, Task { assignee: "bob".into() });
+    /// let mine = tasks.get_by_index("assignee", &"amy".to_string()).unwrap();
+    /// assert_eq!(mine.len(), 1);
+    /// ```
+    pub fn add_index<I, F>(&mut self, name: &str, extract: F)
+    where K: Send + 'static, V: Send + 'static,
+          I: Ord + Clone + Send + 'static,
+          F: Fn(&V) -> I + Send + 'static {
+        let mut index = FieldIndex::new(Box::new(extract));
+        for (key, cells) in self.map.entries() {
+            index.observe(key, resolved(&cells, self.policy).as_ref());
+        }
+
+        self.indexes.insert(name.to_owned(), Box::new(index));
+    }
+
+    /// stop maintaining an index
+    pub fn drop_index(&mut self, name: &str) {
+        self.indexes.remove(name);
+    }
+
+    /// Every entry whose indexed value is `by`.
+    ///
+    /// # Return
+    /// [None] if there is no index `name`, or it doesn't index values
+    /// of the type of `by`.
+    pub fn get_by_index<I: 'static>(&self, name: &str, by: &I) -> Option<Vec<(K, V)>> {
+        let keys = self.indexes.get(name)?.lookup(by)?;
+        Some(keys.into_iter()
+             .filter_map(|k| self.get(&k).map(|v| (k, v)))
+             .collect())
+    }
+
+    /// Keep a running [Aggregate] of a number extracted from every value.
+    ///
+    /// # Notes
+    /// Like indexes, aggregates are built from every entry now, kept up
+    /// to date as ops apply, and not carried over by `.clone()`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// #[derive(Clone, PartialEq, Debug)]
+    /// struct Order { cents: i64 }
+    ///
+    /// let mut orders: SyncedMap<u64, Order> = SyncedMap::new();
+    /// orders.insert(1, Order { cents: 250 });
+    /// orders.add_aggregate("total", |order: &Order| order.cents);
+    /// orders.insert(2, Order { cents: 100 });
+    /// let revenue = orders.aggregate("total").unwrap().sum;
+    /// assert_eq!(revenue, 350);
+    /// ```
+    pub fn add_aggregate<F>(&mut self, name: &str, extract: F)
+    where F: Fn(&V) -> i64 + Send + 'static {
+        let mut aggregator = Aggregator::new(Box::new(extract));
+        for (key, cells) in self.map.entries() {
+            aggregator.observe(key, resolved(&cells, self.policy).as_ref());
+        }
+
+        self.aggregates.insert(name.to_owned(), aggregator);
+    }
+
+    /// stop maintaining an aggregate
+    pub fn drop_aggregate(&mut self, name: &str) {
+        self.aggregates.remove(name);
+    }
+
+    /// read an aggregate, if there is one called `name`
+    pub fn aggregate(&self, name: &str) -> Option<Aggregate> {
+        self.aggregates.get(name).map(|x| x.read())
+    }
+
+    fn apply(&mut self, op: MapOp<K, V>) {
+        self.absorb(op.clone());
+        self.tape.push(op);
+    }
+
+    /// apply an op to the map, telling everyone following it what changed
+    fn absorb(&mut self, op: MapOp<K, V>) {
+        let keys = keys_of(&op);
+        let before: Vec<Option<V>> = match self.subscribers.is_empty() {
+            true => vec![],
+            false => keys.iter().map(|k| self.get(k)).collect(),
+        };
+
+        self.map.apply(op);
+        keys.iter().for_each(|k| self.changed(k));
+
+        for (key, old) in keys.into_iter().zip(before) {
+            let change = match (old, self.get(&key)) {
+                (None, Some(new)) => MapChange::Inserted(key, new),
+                (Some(old), Some(new)) if old != new => MapChange::Updated { key, old, new },
+                (Some(old), None) => MapChange::Removed(key, old),
+                _ => continue,
+            };
+            self.subscribers.retain(|x| x.unbounded_send(change.clone()).is_ok());
+        }
+    }
+
+    /// tell every watcher, index and aggregate `key` may have changed
+    fn changed(&mut self, key: &K) {
+        if self.watchers.is_empty() && self.indexes.is_empty() && self.aggregates.is_empty() {
+            return;
+        }
+
+        let value = self.get(key);
+        self.aggregates.values_mut().for_each(|x| x.observe(key, value.as_ref()));
+        self.indexes.values_mut().for_each(|x| x.observe(key, value.as_ref()));
+        self.watchers.retain_mut(|x| x.observe(key, value.as_ref()));
+    }
+}
+
+/// the values written to a key, in an order that doesn't depend on
+/// the order writes arrived in
+///
+/// # Notes
+/// concurrent writes can't be ordered by their clocks, which the
+/// backend keeps to itself, so they are ordered by how they print.
+/// Tombstones are dropped, or under [RemovePolicy::RemoveWins] hide
+/// every value written alongside them.
+fn concurrent<V: MapVal>(cells: &[MapCell<V>], policy: RemovePolicy) -> Vec<V> {
+    if !present(cells, policy) {
+        return vec![];
+    }
+
+    let mut values: Vec<V> = cells.iter()
+        .filter_map(|x| match x {
+            MapCell::Value(v) => Some(v.clone()),
+            MapCell::Removed(_) => None,
+        })
+        .collect();
+    if values.len() > 1 {
+        values.sort_by_cached_key(|x| format!("{x:?}"));
+    }
+    values
+}
+
+/// the value [SyncedMap::get] gives from what was written to a key
+fn resolved<V: MapVal>(cells: &[MapCell<V>], policy: RemovePolicy) -> Option<V> {
+    concurrent(cells, policy).pop()
+}
+
+/// whether a key holds a value, as `policy` sees it
+fn present<V>(cells: &[MapCell<V>], policy: RemovePolicy) -> bool {
+    match policy {
+        RemovePolicy::AddWins => cells.iter().any(|x| matches!(x, MapCell::Value(_))),
+        RemovePolicy::RemoveWins => !cells.is_empty() && cells.iter().all(|x| matches!(x, MapCell::Value(_))),
+    }
+}
+
+impl<K, V, B> SyncedMap<K, V, B>
+where K: MapKey + Serialize + for<'de> Deserialize<'de>,
+      V: MapVal + Serialize + for<'de> Deserialize<'de>,
+      B: MapBackend<K, V> + Serialize + for<'de> Deserialize<'de> {
+    /// Save this replica, actor and unsent tape included; see
+    /// [super::list::SyncedList::export_state].
+    pub fn export_state(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        ciborium::into_writer(&MapState {
+            version: STATE_VERSION,
+            actor: self.actor,
+            policy: self.policy,
+            map: Cow::Borrowed(&self.map),
+            tape: Cow::Borrowed(&self.tape),
+        }, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Resume a replica saved by [SyncedMap::export_state].
+    pub fn import_state(buf: &[u8]) -> Result<Self> {
+        let state: MapState<K, V, B> = ciborium::from_reader(buf)?;
+        if state.version != STATE_VERSION {
+            return Err(anyhow!("map state is version {}, expected {STATE_VERSION}", state.version));
+        }
+
+        Ok(SyncedMap {
+            map: state.map.into_owned(),
+            policy: state.policy,
+            actor: state.actor,
+            tape: state.tape.into_owned(),
+            watchers: vec![],
+            subscribers: vec![],
+            indexes: HashMap::new(),
+            aggregates: HashMap::new()
+        })
+    }
+}
+
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Debug for SyncedMap<K, V, B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncedMap")
+            .field("map", &self.iter().collect::<BTreeMap<_, _>>())
+            .finish()
+    }
+}
+
+/// Maps are equal if every key resolves to the same value, as
+/// [SyncedMap::get] gives it.
+///
+/// # Notes
+/// Actors, tapes and the values hidden by a conflict are not compared,
+/// so replicas which have seen the same edits are equal.
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> PartialEq for SyncedMap<K, V, B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<K: MapKey, V: MapVal + Eq, B: MapBackend<K, V>> Eq for SyncedMap<K, V, B> {}
+
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Default for SyncedMap<K, V, B> {
+    fn default() -> Self {
+        Self::with_backend(B::default())
+    }
 }
 
-impl<K: MapKey, V: MapVal> Clone for SyncedMap<K, V> {
+impl<K: MapKey, V: MapVal, B: MapBackend<K, V>> Clone for SyncedMap<K, V, B> {
     fn clone(&self) -> Self {
         SyncedMap {
             map: self.map.clone(),
-            actor: self.actor + 1,
-            tape: vec![]
+            policy: self.policy,
+            actor: rand::random(),
+            tape: vec![],
+            watchers: vec![],
+            subscribers: vec![],
+            indexes: HashMap::new(),
+            aggregates: HashMap::new()
         }
     }
 }
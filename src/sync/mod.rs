@@ -3,10 +3,35 @@
 pub mod taped;
 pub mod list;
 pub mod map;
+pub mod lww;
+pub mod counter;
+pub mod nested;
+pub mod lazy;
+pub mod query;
+mod index;
+mod rope;
+pub mod aggregate;
+pub mod derived;
+pub mod golden;
+mod formats;
+pub mod import;
+pub mod throttle;
+pub mod backend;
+pub mod text;
 
 pub mod prelude {
     pub use super::list::*;
     pub use super::map::*;
+    pub use super::lww::{SyncedLwwMap, Stamp};
+    pub use super::counter::SyncedCounterMap;
+    pub use super::nested::*;
     pub use super::taped::Taped;
+    pub use super::lazy::Lazy;
+    pub use super::query::{MapChange, QueryDelta};
+    pub use super::aggregate::Aggregate;
+    pub use super::derived::Derived;
+    pub use super::import::{BatchImport, ImportBatch};
+    pub use super::throttle::{ReplayQueue, ReplayProgress};
+    pub use super::text::{SyncedText, TextOp, Mark, Anchor, Expand};
 }
 
@@ -0,0 +1,259 @@
+use crdts::{CmRDT, CvRDT, Dot, VClock};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use super::taped::Taped;
+use super::list::SyncedList;
+use super::map::{MapKey, MapVal, SyncedMap};
+
+type PhantomUnsend = PhantomData<std::sync::MutexGuard<'static, ()>>;
+
+/// a synced document which can be the value of a [NestedMap]
+pub trait Nestable: Taped<Operation: Clone> + Default {
+    /// make edits as `actor` from now on
+    fn with_actor(self, actor: usize) -> Self;
+}
+
+impl<T: Clone + Sync> Nestable for SyncedList<T> {
+    fn with_actor(self, actor: usize) -> Self {
+        SyncedList::with_actor(self, actor)
+    }
+}
+
+impl<K: MapKey, V: MapVal> Nestable for SyncedMap<K, V> {
+    fn with_actor(self, actor: usize) -> Self {
+        SyncedMap::with_actor(self, actor)
+    }
+}
+
+/// an edit to a [NestedMap], as recorded on its tape
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NestedOp<K, O> {
+    /// apply the tape of one inner document
+    Edit { key: K, dot: Dot<usize>, ops: Vec<O> },
+    /// drop a key, along with the edits to it seen by the remover
+    Remove { key: K, seen: VClock<usize> },
+}
+
+/// one key of a [NestedMap], removed or not
+#[derive(Clone, Serialize, Deserialize)]
+struct Slot<D> {
+    doc: D,
+    /// every edit applied to `doc`
+    edits: VClock<usize>,
+    /// every edit some remove has seen
+    removed: VClock<usize>,
+}
+
+impl<D> Slot<D> {
+    /// whether some edit was not seen by any remove
+    fn is_live(&self) -> bool {
+        self.edits.dots.iter().any(|(actor, counter)| *counter > self.removed.get(actor))
+    }
+}
+
+/// Map of synced documents, such as a [SyncedMap] of [SyncedList]s.
+///
+/// # Notes
+/// Edits to an inner document, through [NestedMap::lock], are recorded
+/// on the outer tape, so one tape carries the whole structure. Inner
+/// documents merge as they would on their own.
+///
+/// A remove only drops the edits its remover had seen, so an edit
+/// made concurrently brings the key back, along with everything it
+/// held. Removed documents are kept, hidden, for this; a key edited
+/// again after it was removed also comes back as it was.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// let mut boards: NestedMap<String, SyncedList<String>> = NestedMap::new();
+/// let mut peer = boards.clone();
+/// boards.lock(&"todo".to_string()).push("write docs".into());
+/// peer.replay(boards.tape());
+/// let todo = peer.get(&"todo".to_string()).unwrap();
+/// assert_eq!(todo.len(), 1);
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize, D: Serialize",
+              deserialize = "K: Deserialize<'de>, D: Deserialize<'de>"))]
+pub struct NestedMap<K: Ord + Clone, D: Nestable> {
+    slots: BTreeMap<K, Slot<D>>,
+    clock: VClock<usize>,
+    #[serde(skip, default = "rand::random")]
+    actor: usize,
+    #[serde(skip)]
+    tape: Vec<NestedOp<K, D::Operation>>,
+}
+
+/// Write access to one document of a [NestedMap]; its edits are
+/// recorded on the map's tape when the guard drops.
+pub struct NestedMapGuard<'a, K: Ord + Clone, D: Nestable> {
+    key: K,
+    src: &'a mut NestedMap<K, D>,
+    _not_send: PhantomUnsend,
+}
+
+impl<K: Ord + Clone, D: Nestable> Deref for NestedMapGuard<'_, K, D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        &self.src.slots[&self.key].doc
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> DerefMut for NestedMapGuard<'_, K, D> {
+    fn deref_mut(&mut self) -> &mut D {
+        &mut self.src.slots.get_mut(&self.key).expect("locked key has a slot").doc
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> Drop for NestedMapGuard<'_, K, D> {
+    fn drop(&mut self) {
+        let Some(slot) = self.src.slots.get_mut(&self.key) else { return; };
+        let ops = slot.doc.tape();
+        if ops.is_empty() {
+            return;
+        }
+
+        let dot = self.src.clock.inc(self.src.actor);
+        self.src.apply(NestedOp::Edit { key: self.key.clone(), dot, ops });
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> NestedMap<K, D> {
+    pub fn new() -> Self {
+        NestedMap {
+            slots: BTreeMap::new(),
+            clock: VClock::new(),
+            actor: rand::random(),
+            tape: vec![],
+        }
+    }
+
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on; see [SyncedMap::with_actor].
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// the document at `key`, if it has one
+    pub fn get(&self, key: &K) -> Option<&D> {
+        self.slots.get(key).filter(|x| x.is_live()).map(|x| &x.doc)
+    }
+
+    /// Edit the document at `key`, starting an empty one if there is none.
+    ///
+    /// # Notes
+    /// Nothing is recorded unless the document is changed.
+    pub fn lock(&mut self, key: &K) -> NestedMapGuard<'_, K, D> {
+        self.slot(key);
+        NestedMapGuard { key: key.clone(), src: self, _not_send: PhantomData }
+    }
+
+    /// Remove the document at `key`.
+    ///
+    /// # Return
+    /// Whether there was one.
+    pub fn remove(&mut self, key: &K) -> bool {
+        let Some(slot) = self.slots.get(key).filter(|x| x.is_live()) else { return false; };
+        let seen = slot.edits.clone();
+        self.apply(NestedOp::Remove { key: key.clone(), seen });
+        true
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Count the keys with a document.
+    pub fn len(&self) -> usize {
+        self.slots.values().filter(|x| x.is_live()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// every key with a document, in order
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// every document, in order of keys
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &D)> {
+        self.slots.iter().filter(|(_, x)| x.is_live()).map(|(k, x)| (k, &x.doc))
+    }
+
+    fn apply(&mut self, op: NestedOp<K, D::Operation>) {
+        self.apply_remote(op.clone());
+        self.tape.push(op);
+    }
+
+    /// apply an op without recording it on the tape
+    fn apply_remote(&mut self, op: NestedOp<K, D::Operation>) {
+        match op {
+            NestedOp::Edit { key, dot, ops } => {
+                if dot.counter <= self.clock.get(&dot.actor) {
+                    return;
+                }
+                self.clock.apply(dot);
+
+                let slot = self.slot(&key);
+                slot.edits.apply(dot);
+                slot.doc.replay(ops);
+            }
+            NestedOp::Remove { key, seen } => {
+                self.slot(&key).removed.merge(seen);
+            }
+        }
+    }
+
+    /// the slot for `key`, made empty if there is none
+    fn slot(&mut self, key: &K) -> &mut Slot<D> {
+        let actor = self.actor;
+        self.slots.entry(key.clone()).or_insert_with(|| Slot {
+            doc: D::default().with_actor(actor),
+            edits: VClock::new(),
+            removed: VClock::new(),
+        })
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> Taped<usize> for NestedMap<K, D> {
+    type Operation = NestedOp<K, D::Operation>;
+
+    /// Synchronize against a tape, passing inner edits to their documents
+    fn replay(&mut self, tape: Vec<Self::Operation>) {
+        tape.into_iter().for_each(|x| self.apply_remote(x));
+    }
+
+    fn tape(&mut self) -> Vec<Self::Operation> {
+        std::mem::take(&mut self.tape)
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> Default for NestedMap<K, D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Ord + Clone, D: Nestable> Clone for NestedMap<K, D> {
+    fn clone(&self) -> Self {
+        NestedMap {
+            slots: self.slots.clone(),
+            clock: self.clock.clone(),
+            actor: rand::random(),
+            tape: vec![],
+        }
+    }
+}
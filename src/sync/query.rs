@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+use futures::channel::mpsc::UnboundedSender;
+
+use super::map::{MapKey, MapVal};
+
+/// A change to the entries matched by a [super::map::SyncedMap::watch]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryDelta<K, V> {
+    /// the entry now matches
+    Added(K, V),
+    /// the entry still matches, with a new value
+    Updated(K, V),
+    /// the entry no longer matches, or was removed
+    Removed(K),
+}
+
+/// A change to one key of a [super::map::SyncedMap], as sent by
+/// [super::map::SyncedMap::on_change]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapChange<K, V> {
+    Inserted(K, V),
+    Updated { key: K, old: V, new: V },
+    /// the key was removed, with the value it had
+    Removed(K, V),
+}
+
+pub(crate) type Predicate<K, V> = Box<dyn Fn(&K, &V) -> bool + Send>;
+
+/// A live filter over a map, and what it last matched
+pub(crate) struct Watcher<K: MapKey, V: MapVal> {
+    predicate: Predicate<K, V>,
+    matched: BTreeMap<K, V>,
+    sender: UnboundedSender<QueryDelta<K, V>>,
+}
+
+impl<K: MapKey, V: MapVal> Watcher<K, V> {
+    pub(crate) fn new(predicate: Predicate<K, V>,
+                      sender: UnboundedSender<QueryDelta<K, V>>) -> Self {
+        Watcher { predicate, matched: BTreeMap::new(), sender }
+    }
+
+    /// re-evaluate `key`, whose value is now `value`
+    ///
+    /// # Return
+    /// Whether anyone is still listening to this watcher.
+    pub(crate) fn observe(&mut self, key: &K, value: Option<&V>) -> bool {
+        let now = value.filter(|x| (self.predicate)(key, x));
+
+        let delta = match (self.matched.get(key), now) {
+            (None, Some(x)) => QueryDelta::Added(key.clone(), x.clone()),
+            (Some(old), Some(x)) if old != x => QueryDelta::Updated(key.clone(), x.clone()),
+            (Some(_), None) => QueryDelta::Removed(key.clone()),
+            _ => return !self.sender.is_closed(),
+        };
+
+        match now {
+            Some(x) => self.matched.insert(key.clone(), x.clone()),
+            None => self.matched.remove(key),
+        };
+        self.sender.unbounded_send(delta).is_ok()
+    }
+}
@@ -0,0 +1,223 @@
+use crdts::{CmRDT, Identifier, OrdDot, VClock};
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::ser::{SerializeStruct, SerializeMap};
+use std::collections::BTreeMap;
+
+use super::backend::{SeqBackend, SeqId as ElementId, SeqOp as Op};
+
+/// most elements kept in one chunk before it is split in two
+const MAX_CHUNK: usize = 512;
+
+/// The default [SeqBackend] of a [super::list::SyncedList]: a
+/// `crdts::List` whose elements are kept in chunks.
+///
+/// # Notes
+/// `crdts::List` keeps its elements in one map, so finding the element
+/// at an index walks every element before it. Here finding an index
+/// only walks the chunks, and finding an identifier is a binary search,
+/// which keeps edits in the middle of long lists fast.
+///
+/// Ops, their merge behavior and the encoding are exactly those of
+/// `crdts::List<T, usize>`.
+#[derive(Debug, Clone)]
+pub struct Rope<T> {
+    /// elements in identifier order; no chunk is empty
+    chunks: Vec<Vec<(ElementId, T)>>,
+    clock: VClock<usize>,
+    len: usize,
+}
+
+impl<T> Rope<T> {
+    pub fn new() -> Self {
+        Rope { chunks: vec![], clock: VClock::new(), len: 0 }
+    }
+
+    /// `(chunk, offset)` of the element at `ix`, if `ix` is in bounds
+    fn locate_index(&self, mut ix: usize) -> Option<(usize, usize)> {
+        for (chunk, elements) in self.chunks.iter().enumerate() {
+            if ix < elements.len() {
+                return Some((chunk, ix));
+            }
+            ix -= elements.len();
+        }
+        None
+    }
+
+    /// the chunk `id` is in, or belongs in
+    fn locate_id(&self, id: &ElementId) -> usize {
+        let chunk = self.chunks.partition_point(|x| x.last().is_some_and(|(last, _)| last < id));
+        chunk.min(self.chunks.len().saturating_sub(1))
+    }
+
+    /// an insert only has an effect if the identifier is not there yet
+    fn insert(&mut self, id: ElementId, val: T) {
+        if self.chunks.is_empty() {
+            self.chunks.push(vec![]);
+        }
+
+        let chunk = self.locate_id(&id);
+        let elements = &mut self.chunks[chunk];
+        let Err(offset) = elements.binary_search_by(|(x, _)| x.cmp(&id)) else { return; };
+        elements.insert(offset, (id, val));
+        self.len += 1;
+
+        if elements.len() > MAX_CHUNK {
+            let rest = elements.split_off(elements.len() / 2);
+            self.chunks.insert(chunk + 1, rest);
+        }
+    }
+
+    /// a delete only has an effect if the identifier is there
+    fn delete(&mut self, id: &ElementId) {
+        let chunk = self.locate_id(id);
+        let Some(elements) = self.chunks.get_mut(chunk) else { return; };
+        let Ok(offset) = elements.binary_search_by(|(x, _)| x.cmp(id)) else { return; };
+        elements.remove(offset);
+        self.len -= 1;
+
+        if elements.is_empty() {
+            self.chunks.remove(chunk);
+        }
+    }
+}
+
+impl<T: Clone> SeqBackend<T> for Rope<T> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn insert_index(&self, ix: usize, val: T, actor: usize) -> Op<T> {
+        let ix = ix.min(self.len);
+        let prev = ix.checked_sub(1).and_then(|x| self.entry(x)).map(|(id, _)| id);
+        let next = self.entry(ix).map(|(id, _)| id);
+
+        let dot = self.clock.inc(actor);
+        Op::Insert { id: Identifier::between(prev, next, dot.into()), val }
+    }
+
+    fn append_numbered(&self, val: T, actor: usize, counter: u64) -> Op<T> {
+        // what List::append does, without walking to the end
+        let id = Identifier::between(self.last_entry().map(|(id, _)| id), None,
+                                     OrdDot { actor, counter });
+        Op::Insert { id, val }
+    }
+
+    fn delete_index(&self, ix: usize, actor: usize) -> Option<Op<T>> {
+        let (id, _) = self.entry(ix)?;
+        Some(Op::Delete { id: id.clone(), dot: self.clock.inc(actor) })
+    }
+
+    fn delete_id(&self, id: &ElementId, actor: usize) -> Option<Op<T>> {
+        self.get(id)?;
+        Some(Op::Delete { id: id.clone(), dot: self.clock.inc(actor) })
+    }
+
+    /// apply an op, ignoring it if its dot was already seen
+    fn apply(&mut self, op: Op<T>) {
+        let dot = op.dot();
+        if dot.counter <= self.clock.get(&dot.actor) {
+            return;
+        }
+        self.clock.apply(dot);
+
+        match op {
+            Op::Insert { id, val } => self.insert(id, val),
+            Op::Delete { id, .. } => self.delete(&id),
+        }
+    }
+
+    /// the insert's dot stays on the clock, so it is still ignored
+    fn forget(&mut self, id: &ElementId) {
+        self.delete(id)
+    }
+
+    fn iter_entries<'a>(&'a self) -> impl Iterator<Item = (&'a ElementId, &'a T)>
+    where T: 'a {
+        self.chunks.iter().flatten().map(|(id, x)| (id, x))
+    }
+
+    fn entry(&self, ix: usize) -> Option<(&ElementId, &T)> {
+        let (chunk, offset) = self.locate_index(ix)?;
+        self.chunks[chunk].get(offset).map(|(id, x)| (id, x))
+    }
+
+    fn entries_from<'a>(&'a self, ix: usize) -> impl Iterator<Item = (&'a ElementId, &'a T)>
+    where T: 'a {
+        let (chunk, offset) = self.locate_index(ix).unwrap_or((self.chunks.len(), 0));
+        self.chunks.iter().skip(chunk).flatten().skip(offset).map(|(id, x)| (id, x))
+    }
+
+    fn position_entry(&self, id: &ElementId) -> Option<usize> {
+        let chunk = self.locate_id(id);
+        let offset = self.chunks.get(chunk)?.binary_search_by(|(x, _)| x.cmp(id)).ok()?;
+        Some(self.chunks[..chunk].iter().map(|x| x.len()).sum::<usize>() + offset)
+    }
+
+    fn get(&self, id: &ElementId) -> Option<&T> {
+        let chunk = self.chunks.get(self.locate_id(id))?;
+        let offset = chunk.binary_search_by(|(x, _)| x.cmp(id)).ok()?;
+        Some(&chunk[offset].1)
+    }
+
+    fn rank(&self, id: &ElementId) -> usize {
+        let chunk = self.locate_id(id);
+        let Some(elements) = self.chunks.get(chunk) else { return 0; };
+        let offset = elements.partition_point(|(x, _)| x < id);
+        self.chunks[..chunk].iter().map(|x| x.len()).sum::<usize>() + offset
+    }
+
+    fn last_entry(&self) -> Option<(&ElementId, &T)> {
+        self.chunks.last()?.last().map(|(id, x)| (id, x))
+    }
+}
+
+impl<T> Default for Rope<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the elements of a [Rope], encoded as the map `crdts::List` keeps them in
+struct Entries<'a, T>(&'a Rope<T>);
+
+impl<T: Serialize> Serialize for Entries<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len))?;
+        for (id, x) in self.0.chunks.iter().flatten() {
+            map.serialize_entry(id, x)?;
+        }
+        map.end()
+    }
+}
+
+impl<T: Serialize> Serialize for Rope<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut pack = serializer.serialize_struct("List", 2)?;
+        pack.serialize_field("seq", &Entries(self))?;
+        pack.serialize_field("clock", &self.clock)?;
+        pack.end()
+    }
+}
+
+/// a [Rope] as `crdts::List` encodes it
+#[derive(Deserialize)]
+#[serde(rename = "List")]
+struct Stored<T> {
+    seq: BTreeMap<ElementId, T>,
+    clock: VClock<usize>,
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Rope<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let Stored { seq, clock } = Stored::deserialize(deserializer)?;
+        let len = seq.len();
+
+        let mut chunks = vec![];
+        let mut elements = seq.into_iter().peekable();
+        while elements.peek().is_some() {
+            chunks.push(elements.by_ref().take(MAX_CHUNK / 2).collect());
+        }
+
+        Ok(Rope { chunks, clock, len })
+    }
+}
@@ -0,0 +1,588 @@
+use std::fmt::{self, Debug, Display};
+use std::ops::{Bound, Range, RangeBounds};
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{Result, anyhow};
+use ciborium::Value;
+use crdts::{Dot, OrdDot};
+use serde::{Serialize, Deserialize};
+use unicode_segmentation::UnicodeSegmentation;
+use log::warn;
+
+use super::taped::Taped;
+use super::backend::{SeqBackend, SeqId, SeqOp, Rope};
+
+/// which of two overlapping marks of the same name wins: the higher
+/// lamport time, then the higher actor
+type Stamp = (u64, usize);
+
+/// One end of a [Mark], held to the graphemes beside it rather than to
+/// an index, so it stays put as text around it is inserted and deleted.
+///
+/// # Notes
+/// An anchor to a deleted grapheme still sits where that grapheme was.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Anchor {
+    /// the start of the text
+    Start,
+    /// just before the grapheme with this identifier
+    Before(SeqId),
+    /// just after the grapheme with this identifier
+    After(SeqId),
+    /// the end of the text
+    End,
+}
+
+/// whether text inserted at the edges of a marked range takes the mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Expand {
+    /// neither edge grows, as for links and comments
+    Never,
+    /// text typed at the end takes the mark, as for bold or italics
+    #[default]
+    After,
+    /// text typed at either edge takes the mark
+    Both,
+}
+
+/// An annotation of a range of a [SyncedText], like bold, a link or a comment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mark {
+    pub name: String,
+    /// what the mark is set to, or [None] where it was cleared
+    pub value: Option<String>,
+    pub start: Anchor,
+    pub end: Anchor,
+}
+
+/// An edit to a [SyncedText], as recorded on its tape.
+///
+/// # Notes
+/// Text typed or pasted in one go is one op, not one per grapheme:
+/// the identifiers of its graphemes are made again from the run's
+/// neighbours on every replica, just as they were where it was typed,
+/// so only the neighbours' identifiers travel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextOp {
+    /// `text` inserted between `after` and `before`, its graphemes numbered on from `counter`
+    Insert {
+        after: Option<SeqId>,
+        before: Option<SeqId>,
+        actor: usize,
+        counter: u64,
+        text: String,
+        /// byte length of each grapheme of `text`, or empty if each is one `char`
+        lens: Vec<u32>,
+    },
+    /// the graphemes `ids` deleted, numbered on from `counter`
+    Delete { ids: Vec<SeqId>, actor: usize, counter: u64 },
+    /// a range marked, or a mark cleared from it
+    Mark { stamp: Stamp, mark: Mark },
+}
+
+impl TextOp {
+    /// the op for each grapheme, or why this could not have come from a [SyncedText]
+    fn expand(self) -> Result<Vec<SeqOp<String>>> {
+        match self {
+            TextOp::Mark { .. } => Ok(vec![]),
+            TextOp::Insert { counter: 0, .. } | TextOp::Delete { counter: 0, .. } => {
+                Err(anyhow!("op numbered 0"))
+            }
+            TextOp::Insert { after: Some(after), before: Some(before), .. } if after >= before => {
+                Err(anyhow!("insert after {after} but before {before}"))
+            }
+            TextOp::Insert { after, before, actor, counter, text, lens } => {
+                let graphemes = split(&text, &lens)?;
+                if graphemes.is_empty() || counter.checked_add(graphemes.len() as u64).is_none() {
+                    return Err(anyhow!("insert of {} graphemes numbered from {counter}", graphemes.len()));
+                }
+                let dot = |n: usize| OrdDot { actor, counter: counter + n as u64 };
+
+                // the last grapheme goes between the neighbours, and the
+                // rest nest under it in order, which sorts them just
+                // before it and keeps the run from interleaving with
+                // another typed concurrently in the same place
+                let last = graphemes.len() - 1;
+                let root = SeqId::between(after.as_ref(), before.as_ref(), dot(last));
+                let mut step = None;
+                graphemes.into_iter().enumerate().map(|(n, grapheme)| {
+                    let id = if n == last {
+                        root.clone()
+                    } else {
+                        let next = SeqId::between(step.as_ref(), None, dot(n));
+                        step = Some(next.clone());
+                        nest(&root, &next)?
+                    };
+                    Ok(SeqOp::Insert { id, val: grapheme })
+                }).collect()
+            }
+            TextOp::Delete { ids, actor, counter } => {
+                if counter.checked_add(ids.len() as u64).is_none() {
+                    return Err(anyhow!("delete of {} graphemes numbered from {counter}", ids.len()));
+                }
+                Ok(ids.into_iter().zip(counter..)
+                   .map(|(id, counter)| SeqOp::Delete { id, dot: Dot::new(actor, counter) })
+                   .collect())
+            }
+        }
+    }
+}
+
+/// `parent` with the path of `child` appended, which sorts just before `parent`
+///
+/// # Notes
+/// `crdts` has no way to extend an identifier, so this goes through
+/// their encoding, which is the path as an array.
+fn nest(parent: &SeqId, child: &SeqId) -> Result<SeqId> {
+    let mut path: Vec<Value> = Value::serialized(parent)?.deserialized()?;
+    path.extend(Value::serialized(child)?.deserialized::<Vec<Value>>()?);
+    Ok(Value::Array(path).deserialized()?)
+}
+
+/// `s` and the byte length of each of its graphemes, as [TextOp::Insert] keeps them
+fn graphemes(s: &str) -> (String, Vec<u32>) {
+    let lens: Vec<u32> = s.graphemes(true).map(|x| x.len() as u32).collect();
+    if s.chars().count() == lens.len() {
+        (s.to_owned(), vec![])
+    } else {
+        (s.to_owned(), lens)
+    }
+}
+
+/// cut `text` into graphemes by `lens`, as [graphemes] made them
+fn split(text: &str, lens: &[u32]) -> Result<Vec<String>> {
+    if lens.is_empty() {
+        return Ok(text.chars().map(String::from).collect());
+    }
+
+    let mut start = 0;
+    let parts = lens.iter().map(|len| {
+        let end = start + *len as usize;
+        let part = text.get(start..end)
+            .filter(|x| !x.is_empty())
+            .ok_or(anyhow!("grapheme at {start}..{end} is not in the text"))?;
+        start = end;
+        Ok(part.to_owned())
+    }).collect::<Result<Vec<_>>>()?;
+
+    if start != text.len() {
+        return Err(anyhow!("graphemes cover {start} of {} bytes", text.len()));
+    }
+    Ok(parts)
+}
+
+/// Text for collaborative editing, kept one grapheme per element.
+///
+/// # Notes
+/// Positions count grapheme clusters, as a user sees characters, so an
+/// edit never splits an emoji or an accented letter in two. A cluster
+/// is cut where it was typed, though: a combining mark inserted on its
+/// own, or concurrently beside another, stays its own grapheme here
+/// even where it renders as one with its neighbour.
+///
+/// Like [super::list::SyncedList], text can only be synced with its
+/// clones; each new text and clone edits as a new random actor.
+///
+/// Ranges can be marked with [SyncedText::mark], for rich text. Marks of
+/// the same name override each other where they overlap, the latest
+/// winning, so give each comment a name of its own. Marks are never
+/// dropped, even once cleared or left spanning no text.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// let mut amy = SyncedText::from("hello world");
+/// let mut bob = amy.clone();
+/// amy.insert_str(5, ",");
+/// bob.delete_range(6..);
+/// bob.push_str("👋🏽");
+/// amy.replay(bob.tape());
+/// bob.replay(amy.tape());
+/// assert_eq!(amy.to_string(), "hello, 👋🏽");
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(bound(serialize = "B: Serialize", deserialize = "B: Deserialize<'de>"))]
+pub struct SyncedText<B: SeqBackend<String> = Rope<String>> {
+    text: B,
+    #[serde(skip, default = "rand::random")]
+    actor: usize,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    marks: BTreeMap<Stamp, Mark>,
+    #[serde(skip)]
+    tape: Vec<TextOp>,
+}
+
+impl SyncedText {
+    pub fn new() -> Self {
+        Self::with_backend(Rope::new())
+    }
+}
+
+impl<B: SeqBackend<String>> SyncedText<B> {
+    /// Make empty text kept in `backend`; see [super::list::SyncedList::with_backend].
+    pub fn with_backend(backend: B) -> Self {
+        SyncedText {
+            text: backend,
+            actor: rand::random(),
+            marks: BTreeMap::new(),
+            tape: vec![],
+        }
+    }
+
+    /// the actor this replica's edits are made as
+    pub fn actor(&self) -> usize {
+        self.actor
+    }
+
+    /// Make edits as `actor` from now on; see [super::list::SyncedList::with_actor].
+    pub fn with_actor(mut self, actor: usize) -> Self {
+        self.actor = actor;
+        self
+    }
+
+    /// number of graphemes
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the grapheme at `pos`, if there is one
+    pub fn get(&self, pos: usize) -> Option<&str> {
+        self.text.entry(pos).map(|(_, x)| x.as_str())
+    }
+
+    /// every grapheme, in order
+    pub fn graphemes(&self) -> impl Iterator<Item = &str> {
+        self.text.iter_entries().map(|(_, x)| x.as_str())
+    }
+
+    /// the graphemes in `range`, if it is in bounds
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> Option<String> {
+        let (start, end) = bounds(&range, self.len());
+        if start > end || end > self.len() {
+            return None;
+        }
+        Some(self.text.entries_from(start).take(end - start).map(|(_, x)| x.as_str()).collect())
+    }
+
+    /// Stable handle to the grapheme at `pos`; see [super::list::SyncedList::id_of].
+    pub fn id_of(&self, pos: usize) -> Option<SeqId> {
+        self.text.entry(pos).map(|(id, _)| id.clone())
+    }
+
+    /// where the grapheme with `id` is now, if it wasn't deleted
+    pub fn position_of(&self, id: &SeqId) -> Option<usize> {
+        self.text.position_entry(id)
+    }
+
+    /// Insert `s` before the grapheme at `pos`.
+    ///
+    /// # Notes
+    /// panics if `pos` is past the end; see [SyncedText::try_insert_str].
+    pub fn insert_str(&mut self, pos: usize, s: &str) {
+        if let Err(err) = self.try_insert_str(pos, s) {
+            panic!("{err}");
+        }
+    }
+
+    /// Insert `s` before the grapheme at `pos`, if `pos` is at most the length.
+    ///
+    /// # Notes
+    /// The whole of `s` is one op on the tape.
+    pub fn try_insert_str(&mut self, pos: usize, s: &str) -> Result<()> {
+        if pos > self.len() {
+            return Err(anyhow!("insertion index (is {pos}) should be <= len (is {})", self.len()));
+        }
+        if s.is_empty() {
+            return Ok(());
+        }
+
+        let (text, lens) = graphemes(s);
+        self.apply(TextOp::Insert {
+            after: pos.checked_sub(1).and_then(|x| self.id_of(x)),
+            before: self.id_of(pos),
+            actor: self.actor,
+            counter: self.next_counter(),
+            text,
+            lens,
+        });
+        Ok(())
+    }
+
+    /// Append `s` to the end.
+    pub fn push_str(&mut self, s: &str) {
+        self.insert_str(self.len(), s);
+    }
+
+    /// Delete the graphemes in `range`.
+    ///
+    /// # Notes
+    /// The whole range is one op on the tape. Panics if `range` is out
+    /// of bounds, like [String::drain].
+    ///
+    /// # Return
+    /// The text deleted.
+    pub fn delete_range<R: RangeBounds<usize>>(&mut self, range: R) -> String {
+        let (start, end) = bounds(&range, self.len());
+        if start > end || end > self.len() {
+            panic!("range {start}..{end} out of bounds for length {}", self.len());
+        }
+        if start == end {
+            return String::new();
+        }
+
+        let (ids, deleted): (Vec<SeqId>, String) = self.text.entries_from(start)
+            .take(end - start)
+            .map(|(id, x)| (id.clone(), x.as_str()))
+            .unzip();
+        self.apply(TextOp::Delete { ids, actor: self.actor, counter: self.next_counter() });
+
+        deleted
+    }
+
+    /// Mark the graphemes in `range` as `name`, set to `value`.
+    ///
+    /// # Notes
+    /// Panics if `range` is out of bounds; an empty range marks nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut doc = SyncedText::from("hello wrold");
+    /// let id = 7;
+    /// doc.mark(0..5, "bold", "true", Expand::After);
+    /// doc.mark(6..11, "link", "https://example.com", Expand::Never);
+    /// doc.mark(6..11, &format!("comment:{id}"), "typo?", Expand::Never);
+    /// assert_eq!(doc.marks_at(7).len(), 2);
+    /// ```
+    pub fn mark<R: RangeBounds<usize>>(&mut self, range: R, name: &str, value: &str, expand: Expand) {
+        self.annotate(range, name, Some(value.to_owned()), expand);
+    }
+
+    /// Clear `name` from the graphemes in `range`, wherever it was set before.
+    ///
+    /// # Notes
+    /// `expand` is whether text typed at the edges stays clear of it.
+    pub fn unmark<R: RangeBounds<usize>>(&mut self, range: R, name: &str, expand: Expand) {
+        self.annotate(range, name, None, expand);
+    }
+
+    /// every mark set on the grapheme at `pos`, by name
+    pub fn marks_at(&self, pos: usize) -> BTreeMap<&str, &str> {
+        self.spans().into_iter()
+            .find(|(range, _)| range.contains(&pos))
+            .map_or(BTreeMap::new(), |(_, marks)| marks)
+    }
+
+    /// Cut the text into runs of graphemes marked alike, in order.
+    ///
+    /// # Return
+    /// Each run's range, with every mark set on it by name. Together
+    /// the runs cover the whole text.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use synch::sync::prelude::*;
+    /// let mut doc = SyncedText::from("hello world");
+    /// doc.mark(0..5, "bold", "true", Expand::After);
+    /// let mut html = String::new();
+    /// for (range, marks) in doc.spans() {
+    ///     let text = doc.slice(range).unwrap();
+    ///     html.push_str(&if marks.contains_key("bold") { format!("<b>{text}</b>") } else { text });
+    /// }
+    /// assert_eq!(html, "<b>hello</b> world");
+    /// ```
+    pub fn spans(&self) -> Vec<(Range<usize>, BTreeMap<&str, &str>)> {
+        let marks: Vec<(Range<usize>, &Mark)> = self.marks.values()
+            .map(|x| (self.resolve(x), x))
+            .filter(|(range, _)| !range.is_empty())
+            .collect();
+        let cuts: BTreeSet<usize> = marks.iter()
+            .flat_map(|(range, _)| [range.start, range.end])
+            .chain([0, self.len()])
+            .collect();
+
+        let mut spans: Vec<(Range<usize>, BTreeMap<&str, &str>)> = vec![];
+        for (&start, &end) in cuts.iter().zip(cuts.iter().skip(1)) {
+            // marks are in order of their stamps, so the latest wins
+            let mut set = BTreeMap::new();
+            for (_, mark) in marks.iter().filter(|(x, _)| x.start <= start && end <= x.end) {
+                match &mark.value {
+                    Some(value) => set.insert(mark.name.as_str(), value.as_str()),
+                    None => set.remove(mark.name.as_str()),
+                };
+            }
+
+            match spans.last_mut() {
+                Some((last, marks)) if *marks == set => last.end = end,
+                _ => spans.push((start..end, set)),
+            }
+        }
+
+        spans
+    }
+
+    /// where `name` is set, and to what, in order
+    pub fn ranges_of(&self, name: &str) -> Vec<(Range<usize>, &str)> {
+        self.spans().into_iter()
+            .filter_map(|(range, marks)| Some((range, *marks.get(name)?)))
+            .collect()
+    }
+
+    fn annotate<R: RangeBounds<usize>>(&mut self, range: R, name: &str,
+                                       value: Option<String>, expand: Expand) {
+        let (start, end) = bounds(&range, self.len());
+        if start > end || end > self.len() {
+            panic!("range {start}..{end} out of bounds for length {}", self.len());
+        }
+        if start == end {
+            return;
+        }
+
+        let first = self.id_of(start).map(Anchor::Before);
+        let before = start.checked_sub(1).and_then(|x| self.id_of(x)).map(Anchor::After);
+        let last = self.id_of(end - 1).map(Anchor::After);
+        let after = self.id_of(end).map(Anchor::Before);
+        let (start, end) = match expand {
+            Expand::Never => (first, last),
+            Expand::After => (first, after),
+            Expand::Both => (before, after),
+        };
+
+        let time = self.marks.last_key_value().map_or(0, |((x, _), _)| *x) + 1;
+        self.apply(TextOp::Mark {
+            stamp: (time, self.actor),
+            mark: Mark {
+                name: name.to_owned(),
+                value,
+                start: start.unwrap_or(Anchor::Start),
+                end: end.unwrap_or(Anchor::End),
+            },
+        });
+    }
+
+    /// the graphemes a mark spans now
+    fn resolve(&self, mark: &Mark) -> Range<usize> {
+        let start = self.boundary(&mark.start);
+        start..self.boundary(&mark.end).max(start)
+    }
+
+    /// the index an anchor sits at
+    fn boundary(&self, anchor: &Anchor) -> usize {
+        match anchor {
+            Anchor::Start => 0,
+            Anchor::Before(id) => self.text.rank(id),
+            Anchor::After(id) => self.text.rank(id) + self.text.get(id).is_some() as usize,
+            Anchor::End => self.len(),
+        }
+    }
+
+    /// the counter the next op this replica makes is numbered with
+    fn next_counter(&self) -> u64 {
+        self.text.append(String::new(), self.actor).dot().counter
+    }
+
+    fn apply(&mut self, op: TextOp) {
+        if let Err(err) = self.absorb(op.clone()) {
+            panic!("made a broken op: {err}");
+        }
+        self.tape.push(op);
+    }
+
+    /// apply an op without recording it on the tape
+    fn absorb(&mut self, op: TextOp) -> Result<()> {
+        if let TextOp::Mark { stamp, mark } = op {
+            self.marks.entry(stamp).or_insert(mark);
+            return Ok(());
+        }
+        op.expand()?.into_iter().for_each(|x| self.text.apply(x));
+        Ok(())
+    }
+}
+
+/// `range` as `(start, end)`, unchecked
+fn bounds<R: RangeBounds<usize>>(range: &R, len: usize) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(&x) => x,
+        Bound::Excluded(&x) => x + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(&x) => x + 1,
+        Bound::Excluded(&x) => x,
+        Bound::Unbounded => len,
+    };
+    (start, end)
+}
+
+impl<B: SeqBackend<String>> Taped<usize> for SyncedText<B> {
+    type Operation = TextOp;
+
+    /// Synchronize your text against a tape
+    ///
+    /// # Notes
+    /// malformed ops are logged and skipped.
+    fn replay(&mut self, tape: Vec<TextOp>) {
+        for (index, op) in tape.into_iter().enumerate() {
+            if let Err(err) = self.absorb(op) {
+                warn!("skipped op {index} of tape: {err}");
+            }
+        }
+    }
+
+    fn tape(&mut self) -> Vec<TextOp> {
+        std::mem::take(&mut self.tape)
+    }
+}
+
+impl<B: SeqBackend<String>> Display for SyncedText<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.graphemes().try_for_each(|x| f.write_str(x))
+    }
+}
+
+impl<B: SeqBackend<String>> Debug for SyncedText<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SyncedText").field(&self.to_string()).finish()
+    }
+}
+
+/// Texts are equal if they read the same, marks and all; see
+/// [super::map::SyncedMap]'s `PartialEq`.
+impl<B: SeqBackend<String>> PartialEq for SyncedText<B> {
+    fn eq(&self, other: &Self) -> bool {
+        self.graphemes().eq(other.graphemes()) && self.spans() == other.spans()
+    }
+}
+
+impl<B: SeqBackend<String>> Eq for SyncedText<B> {}
+
+impl<B: SeqBackend<String>> Default for SyncedText<B> {
+    fn default() -> Self {
+        Self::with_backend(B::default())
+    }
+}
+
+impl<B: SeqBackend<String>> Clone for SyncedText<B> {
+    fn clone(&self) -> Self {
+        SyncedText {
+            text: self.text.clone(),
+            actor: rand::random(),
+            marks: self.marks.clone(),
+            tape: vec![],
+        }
+    }
+}
+
+impl From<&str> for SyncedText {
+    fn from(s: &str) -> Self {
+        let mut text = SyncedText::new();
+        text.push_str(s);
+        text
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::taped::Taped;
+
+/// default most ops one [ReplayQueue::tick] replays
+pub const DEFAULT_REPLAY_OPS: usize = 512;
+/// default longest one [ReplayQueue::tick] spends replaying, half a frame at 60fps
+pub const DEFAULT_REPLAY_TIME: Duration = Duration::from_millis(8);
+
+/// how far a [ReplayQueue] is through catching up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayProgress {
+    /// ops replayed by this tick
+    pub applied: usize,
+    /// ops replayed since the queue was last caught up, this tick's included
+    pub done: usize,
+    /// ops still waiting
+    pub pending: usize,
+}
+
+impl ReplayProgress {
+    /// how far through the current burst of tapes, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        match self.done + self.pending {
+            0 => 1.0,
+            total => self.done as f64 / total as f64,
+        }
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.pending == 0
+    }
+}
+
+/// A document whose incoming tapes are replayed a budget at a time.
+///
+/// # Notes
+/// Replaying a large burst of tapes at once can freeze the loop it runs
+/// on. Pushed tapes wait here instead, and each [ReplayQueue::tick]
+/// replays only as many ops as the budget allows, oldest first; call it
+/// once per frame, or per turn of the event loop, until caught up.
+///
+/// The document may be edited between ticks. Its reads reflect only
+/// the ops replayed so far.
+///
+/// # Examples
+///
+/// ```
+/// # use synch::sync::prelude::*;
+/// # use std::time::Duration;
+/// let mut peer: SyncedList<String> = (0..5000).map(|x| x.to_string()).collect();
+/// let mut todo = ReplayQueue::new(SyncedList::<String>::new())
+///     .with_max_time(Some(Duration::from_millis(4)));
+/// todo.push(peer.tape());
+/// // every frame
+/// loop {
+///     let progress = todo.tick();
+///     if progress.is_caught_up() {
+///         break;
+///     }
+///     println!("{:.0}% replayed", 100.0 * progress.fraction());
+/// }
+/// assert_eq!(todo.get_ref().len(), 5000);
+/// ```
+pub struct ReplayQueue<D: Taped> {
+    doc: D,
+    pending: VecDeque<D::Operation>,
+    max_ops: usize,
+    max_time: Option<Duration>,
+    done: usize,
+}
+
+impl<D: Taped> ReplayQueue<D> {
+    pub fn new(doc: D) -> Self {
+        ReplayQueue {
+            doc,
+            pending: VecDeque::new(),
+            max_ops: DEFAULT_REPLAY_OPS,
+            max_time: Some(DEFAULT_REPLAY_TIME),
+            done: 0,
+        }
+    }
+
+    /// replay at most `max_ops` ops per tick
+    pub fn with_max_ops(mut self, max_ops: usize) -> Self {
+        self.max_ops = max_ops.max(1);
+        self
+    }
+
+    /// Stop each tick once it has replayed for `max_time`, or never, if [None].
+    ///
+    /// # Notes
+    /// Time is checked between ops, so a tick overruns by up to one op;
+    /// every tick replays at least one.
+    pub fn with_max_time(mut self, max_time: Option<Duration>) -> Self {
+        self.max_time = max_time;
+        self
+    }
+
+    /// queue a tape to replay
+    pub fn push(&mut self, tape: Vec<D::Operation>) {
+        self.pending.extend(tape);
+    }
+
+    /// ops waiting to be replayed
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_caught_up(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Replay waiting ops, oldest first, until caught up or out of budget.
+    pub fn tick(&mut self) -> ReplayProgress {
+        let start = Instant::now();
+        let mut applied = 0;
+        while applied < self.max_ops {
+            if applied > 0 && self.max_time.is_some_and(|x| start.elapsed() >= x) {
+                break;
+            }
+            let Some(op) = self.pending.pop_front() else { break; };
+            self.doc.replay(vec![op]);
+            applied += 1;
+        }
+
+        self.done += applied;
+        let progress = ReplayProgress { applied, done: self.done, pending: self.pending.len() };
+        if self.pending.is_empty() {
+            self.done = 0;
+        }
+        progress
+    }
+
+    /// Replay everything waiting now, whatever the budget.
+    pub fn flush(&mut self) {
+        let tape: Vec<D::Operation> = self.pending.drain(..).collect();
+        self.doc.replay(tape);
+        self.done = 0;
+    }
+
+    /// the document, as far as it has replayed
+    pub fn get_ref(&self) -> &D {
+        &self.doc
+    }
+
+    /// the document, to edit between ticks
+    pub fn get_mut(&mut self) -> &mut D {
+        &mut self.doc
+    }
+
+    /// Replay everything still waiting, then give back the document.
+    pub fn into_inner(mut self) -> D {
+        self.flush();
+        self.doc
+    }
+}
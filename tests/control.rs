@@ -0,0 +1,30 @@
+//! The control stream between a head and a child, secured with Noise
+//! over loopback WebRTC.
+
+use anyhow::Result;
+
+use synch::rtc::*;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn control_stream_is_mutually_authenticated() -> Result<()> {
+    let config = || AgentConfig { stun_servers: vec![], ..Default::default() };
+    let mut head = Agent::new(config())?;
+    let mut child = Agent::new(config())?;
+
+    let mut offer = head.connect_child_with(&["control"]).await?;
+    let answer = child.connect_parent(&offer.get()).await?;
+    offer.answer(&answer.get()).await?;
+    let peer = head.accept(offer)?;
+
+    let (parent, child_side) = tokio::join!(head.secure_control(Some(peer), "control"),
+                                            child.secure_control(None, "control"));
+    let (parent, child_side) = (parent?, child_side?);
+    assert_eq!(parent.remote_static(), child.noise_public_key());
+    assert_eq!(child_side.remote_static(), head.noise_public_key());
+
+    child_side.send_message(&ControlMessage::RotateKeys).await?;
+    let message = parent.recv_message().await.unwrap()?;
+    assert!(matches!(message, ControlMessage::RotateKeys));
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+//! A derived document kept up to date from changes, or recomputed.
+
+use synch::*;
+
+#[test]
+fn updates_agree_with_recomputing() {
+    let sum = |xs: &SyncedList<u32>| xs.iter().sum::<u32>();
+    let mut full = Derived::new(SyncedList::new(), sum);
+    let mut incremental = Derived::new(SyncedList::new(), sum)
+        .with_update(|total, _, pushed: &Vec<u32>| *total += pushed.iter().sum::<u32>());
+    full.get();
+    incremental.get();
+
+    for batch in [vec![1, 2], vec![], vec![10, 20, 30]] {
+        full.apply((), |xs, _| xs.extend(batch.clone()));
+        assert!(full.is_stale());
+        incremental.apply(batch, |xs, pushed| xs.extend(pushed.clone()));
+        assert!(!incremental.is_stale());
+        assert_eq!(full.get(), incremental.get());
+    }
+    assert_eq!(*incremental.get(), 63);
+
+    // anything else falls back to recomputing
+    incremental.sources_mut().push(7);
+    assert!(incremental.is_stale());
+    assert_eq!(*incremental.get(), 70);
+}
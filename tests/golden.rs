@@ -0,0 +1,8 @@
+//! The wire format against the vectors shipped in `golden/`.
+
+use synch::sync::golden::verify_golden;
+
+#[test]
+fn encodings_match_golden_vectors() {
+    verify_golden().unwrap();
+}
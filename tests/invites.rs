@@ -0,0 +1,49 @@
+//! Invites bound to the certificate of the peer redeeming them.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use synch::rtc::*;
+
+/// a config with its own identity, so its fingerprints are known up front
+fn identified(name: &str) -> AgentConfig {
+    let path = std::env::temp_dir().join(format!("synch-{name}-{}.pem", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    AgentConfig { stun_servers: vec![], identity_file: Some(path), ..Default::default() }
+}
+
+/// answer an offer from `head` as a new agent made with `config`
+async fn answer(head: &Agent, config: AgentConfig, token: &str) -> Result<(Agent, Offer)> {
+    let mut offer = head.connect_child_with(&["docs"]).await?;
+    let mut child = Agent::new(config)?;
+    let answer = child.connect_parent(&offer.get()).await?.with_invite(token);
+    offer.answer(&answer.get()).await?;
+    Ok((child, offer))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bound_invites_admit_only_their_peer() -> Result<()> {
+    let mut head = Agent::new(AgentConfig { stun_servers: vec![], ..Default::default() })?;
+    head.require_invites(SecretKey::generate());
+    head.require_bound_invites(true);
+
+    let (amy, bob) = (identified("amy"), identified("bob"));
+    let fingerprints = amy.fingerprints()?;
+    assert!(!fingerprints.is_empty());
+    let bound = head.issue(&Invite::new(Role::Editor, Duration::from_secs(600))
+                           .bound_to(&fingerprints))?;
+    let unbound = head.invite(Role::Editor, Duration::from_secs(600))?;
+
+    // someone else can't redeem amy's invite, nor an unbound one
+    let (_bob, offer) = answer(&head, bob.clone(), &bound).await?;
+    assert!(head.accept(offer).is_err());
+    let (_bob, offer) = answer(&head, bob, &unbound).await?;
+    assert!(head.accept(offer).is_err());
+
+    let (_amy, offer) = answer(&head, amy, &bound).await?;
+    let peer = head.accept(offer)?;
+    assert_eq!(head.fingerprints(peer), Some(fingerprints.as_slice()));
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+//! Stamps of a last-writer-wins map facing a clock far ahead of ours.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crdts::{Dot, LWWReg};
+use crdts::map::Op;
+use synch::sync::lww::MAX_CLOCK_SKEW;
+use synch::*;
+
+#[test]
+fn stamps_from_the_far_future_are_clamped() {
+    let mut config: SyncedLwwMap<String, String> = SyncedLwwMap::new();
+    let forged = Op::Up {
+        dot: Dot::new(7, 1),
+        key: "theme".to_string(),
+        op: LWWReg { val: "light".to_string(), marker: Stamp { time: u64::MAX, actor: 7 } },
+    };
+    config.replay(vec![forged]);
+
+    // stamping past u64::MAX would overflow
+    config.insert("theme".to_string(), "dark".to_string());
+    config.insert("font".to_string(), "serif".to_string());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let stamped = Duration::from_millis(config.stamp(&"font".to_string()).unwrap().time);
+    assert!(stamped <= now + MAX_CLOCK_SKEW + Duration::from_secs(1),
+            "stamped {stamped:?}, now {now:?}");
+
+    // the forged write still applies, and wins until our clock catches up
+    assert_eq!(config.get(&"theme".to_string()), Some("light".to_string()));
+}
@@ -0,0 +1,30 @@
+//! Leases granted by a head's registry.
+
+use std::time::Duration;
+
+use synch::rtc::*;
+
+#[test]
+fn leases_belong_to_the_peer_which_asked() {
+    let mut head = Registry::new();
+    head.set_peer(HEAD_PEER);
+    head.create("todo").unwrap();
+    let mut child = Registry::new();
+    child.create("todo").unwrap();
+    child.handle(HEAD_PEER, RegistryMessage::Assign(1));
+
+    let request = child.acquire_lease("todo", Duration::from_secs(30)).unwrap();
+    for reply in head.handle(1, request) {
+        child.handle(HEAD_PEER, reply);
+    }
+    assert!(child.holds_lease("todo"));
+    assert!(!head.holds_lease("todo"));
+
+    // another peer can neither take nor release it
+    let taken = head.handle(2, RegistryMessage::AcquireLease { name: "todo".into(), ttl_ms: 1000 });
+    assert!(matches!(&taken[..], [RegistryMessage::Lease { holder: Some(1), .. }]));
+    assert!(head.handle(2, RegistryMessage::ReleaseLease { name: "todo".into() }).is_empty());
+
+    let released = head.handle(1, child.release_lease("todo").unwrap());
+    assert!(matches!(&released[..], [RegistryMessage::Lease { holder: None, .. }]));
+}
@@ -0,0 +1,49 @@
+//! A head and two children converging, both simulated and over real
+//! agents connected through loopback WebRTC.
+
+#![cfg(feature = "harness")]
+
+use anyhow::Result;
+
+use synch::harness::{AgentSwarm, three_node_convergence};
+use synch::*;
+
+#[test]
+fn simulated_three_nodes_converge() {
+    three_node_convergence().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn agents_converge_across_a_partition() -> Result<()> {
+    let mut swarm = AgentSwarm::new(SyncedList::<String>::new(), 2).await?;
+    let read = |x: &SyncedList<String>| x.iter().cloned().collect::<Vec<_>>();
+
+    for peer in 0..3 {
+        swarm.peer(peer).push(format!("from {peer}"));
+    }
+    swarm.converge(read).await?;
+    assert_eq!(swarm.views(read)[0].len(), 3);
+
+    // cut the second child off, and keep editing on both sides
+    swarm.partition(2).await?;
+    swarm.peer(2).remove(0);
+    swarm.peer(2).push("offline".into());
+    swarm.peer(1).insert(0, "first".into());
+    swarm.peer(0).push("while away".into());
+    for _ in 0..20 {
+        swarm.step().await?;
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let away = &swarm.views(read)[2];
+    assert!(!away.iter().any(|x| x == "first" || x == "while away"),
+            "a partitioned peer saw edits it shouldn't have: {away:?}");
+
+    swarm.heal(2).await?;
+    swarm.converge(read).await?;
+    let after = &swarm.views(read)[0];
+    for value in ["first", "while away", "offline"] {
+        assert!(after.iter().any(|x| x == value), "{value} was lost: {after:?}");
+    }
+
+    Ok(())
+}